@@ -1,34 +1,2866 @@
+// Every instruction returns Anchor's `Error`, which is large by design
+#![allow(clippy::result_large_err)]
+
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, pubkey::Pubkey,
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, Token, TokenAccount, Mint};
+use anchor_spl::token::spl_token::native_mint;
+use solana_program::pubkey::Pubkey;
+
+use solana_oasis_contracts::dispute::{
+    deposit_id, deposit_key, smt_root, token_balance_key, token_id, withdrawal_hash,
+    withdrawal_key,
 };
-use thiserror::Error;
+use solana_oasis_contracts::{StateAccount, StateRootRecord};
 
-#[derive(Error, Debug)]
-pub enum BridgeError {
-    #[error("Failed to process bridge request")]
-    ProcessingError,
+declare_id!("Bridge1111111111111111111111111111111111111");
+
+/// Number of hourly buckets in the rolling withdrawal window
+pub const VOLUME_BUCKETS: usize = 24;
+/// Length of one bucket of the rolling withdrawal window, in seconds
+pub const BUCKET_SECONDS: i64 = 3_600;
+
+#[program]
+pub mod oasis_bridge {
+    use super::*;
+
+    pub fn initialize_bridge(
+        ctx: Context<InitializeBridge>,
+        params: InitializeBridgeParams,
+    ) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+        bridge.authority = ctx.accounts.authority.key();
+        bridge.bump = *ctx.bumps.get("bridge").unwrap();
+        bridge.paused = false;
+        bridge.withdrawal_nonce = 0;
+        bridge.daily_limit = params.daily_limit;
+        bridge.release_delay = params.release_delay;
+        bridge.volume = RollingVolume::default();
+        require!(params.escape_delay > 0, BridgeError::InvalidEscapeDelay);
+        bridge.oasis_state = ctx.accounts.oasis_state.key();
+        bridge.escape_delay = params.escape_delay;
+        bridge.escape_active = false;
+        bridge.escape_batch = None;
+        bridge.escape_root = [0u8; 32];
+        bridge.deposit_nonce = 0;
+        Ok(())
+    }
+
+    /// Allow deposits of `mint`, locked in a new bridge-owned vault and
+    /// credited on L2 as the asset derived from the mint
+    pub fn register_token(ctx: Context<RegisterToken>, params: TokenParams) -> Result<()> {
+        // Only authority can register tokens
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+        require!(
+            ctx.accounts.mint.key() != native_mint::ID,
+            BridgeError::NativeMint
+        );
+        params.validate()?;
+
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.mint = ctx.accounts.mint.key();
+        token_config.vault = ctx.accounts.vault.key();
+        token_config.l2_token = token_id(&token_config.mint);
+        token_config.enabled = true;
+        token_config.min_withdrawal = params.min_withdrawal;
+        token_config.max_withdrawal = params.max_withdrawal;
+        token_config.daily_limit = params.daily_limit;
+        token_config.total_locked = 0;
+        token_config.volume = RollingVolume::default();
+        token_config.bump = *ctx.bumps.get("token_config").unwrap();
+        token_config.vault_bump = *ctx.bumps.get("vault").unwrap();
+
+        emit!(TokenRegistered {
+            mint: token_config.mint,
+            vault: token_config.vault,
+            l2_token: token_config.l2_token,
+        });
+
+        Ok(())
+    }
+
+    /// Allow native SOL deposits, locked as lamports in the SOL vault and
+    /// credited on L2 as the asset of the native mint
+    pub fn register_native(ctx: Context<RegisterNative>, params: TokenParams) -> Result<()> {
+        // Only authority can register tokens
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+        params.validate()?;
+
+        // Keep the vault rent exempt so it can be drained down to the reserve
+        let reserve = Rent::get()?.minimum_balance(0);
+        let missing = reserve.saturating_sub(ctx.accounts.sol_vault.lamports());
+        if missing > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: ctx.accounts.sol_vault.to_account_info(),
+                    },
+                ),
+                missing,
+            )?;
+        }
+
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.mint = native_mint::ID;
+        token_config.vault = ctx.accounts.sol_vault.key();
+        token_config.l2_token = token_id(&token_config.mint);
+        token_config.enabled = true;
+        token_config.min_withdrawal = params.min_withdrawal;
+        token_config.max_withdrawal = params.max_withdrawal;
+        token_config.daily_limit = params.daily_limit;
+        token_config.total_locked = 0;
+        token_config.volume = RollingVolume::default();
+        token_config.bump = *ctx.bumps.get("token_config").unwrap();
+        token_config.vault_bump = *ctx.bumps.get("sol_vault").unwrap();
+
+        emit!(TokenRegistered {
+            mint: token_config.mint,
+            vault: token_config.vault,
+            l2_token: token_config.l2_token,
+        });
+
+        Ok(())
+    }
+
+    /// Change the limits of a registered token, or stop its deposits.
+    /// Withdrawals of a disabled token stay possible.
+    pub fn update_token(
+        ctx: Context<UpdateToken>,
+        params: TokenParams,
+        enabled: bool,
+    ) -> Result<()> {
+        // Only authority can update tokens
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+        params.validate()?;
+
+        let token_config = &mut ctx.accounts.token_config;
+        token_config.enabled = enabled;
+        token_config.min_withdrawal = params.min_withdrawal;
+        token_config.max_withdrawal = params.max_withdrawal;
+        token_config.daily_limit = params.daily_limit;
+
+        emit!(TokenUpdated {
+            mint: token_config.mint,
+            enabled,
+            min_withdrawal: params.min_withdrawal,
+            max_withdrawal: params.max_withdrawal,
+            daily_limit: params.daily_limit,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit(
+        ctx: Context<Deposit>,
+        amount: u64,
+        l2_recipient: [u8; 32],
+    ) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+        let token_config = &mut ctx.accounts.token_config;
+        
+        // Verify bridge is not paused and the token is accepted. Once in
+        // escape mode, deposits would never be credited.
+        require!(!bridge.paused, BridgeError::BridgePaused);
+        require!(token_config.enabled, BridgeError::TokenDisabled);
+        require!(!bridge.escape_active, BridgeError::EscapeActive);
+
+        // Transfer tokens to bridge vault
+        let cpi_accounts = token::Transfer {
+            from: ctx.accounts.from.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Update token state
+        token_config.total_locked = token_config.total_locked.checked_add(amount)
+            .ok_or(BridgeError::Overflow)?;
+
+        let deposit_id = record_deposit(
+            bridge,
+            &mut ctx.accounts.receipt,
+            ctx.accounts.depositor.key(),
+            token_config.mint,
+            amount,
+            l2_recipient,
+        )?;
+        ctx.accounts.receipt.bump = *ctx.bumps.get("receipt").unwrap();
+
+        emit!(DepositEvent {
+            depositor: ctx.accounts.depositor.key(),
+            mint: token_config.mint,
+            amount,
+            l2_recipient,
+            timestamp: Clock::get()?.unix_timestamp,
+            deposit_id,
+            nonce: ctx.accounts.receipt.nonce,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_sol(
+        ctx: Context<DepositSol>,
+        amount: u64,
+        l2_recipient: [u8; 32],
+    ) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+        let token_config = &mut ctx.accounts.token_config;
+
+        // Verify bridge is not paused and the token is accepted. Once in
+        // escape mode, deposits would never be credited.
+        require!(!bridge.paused, BridgeError::BridgePaused);
+        require!(token_config.enabled, BridgeError::TokenDisabled);
+        require!(!bridge.escape_active, BridgeError::EscapeActive);
+
+        // Transfer lamports to the SOL vault
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.depositor.to_account_info(),
+            to: ctx.accounts.sol_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        system_program::transfer(cpi_ctx, amount)?;
+
+        // Update token state
+        token_config.total_locked = token_config.total_locked.checked_add(amount)
+            .ok_or(BridgeError::Overflow)?;
+
+        let deposit_id = record_deposit(
+            bridge,
+            &mut ctx.accounts.receipt,
+            ctx.accounts.depositor.key(),
+            token_config.mint,
+            amount,
+            l2_recipient,
+        )?;
+        ctx.accounts.receipt.bump = *ctx.bumps.get("receipt").unwrap();
+
+        emit!(DepositEvent {
+            depositor: ctx.accounts.depositor.key(),
+            mint: token_config.mint,
+            amount,
+            l2_recipient,
+            timestamp: Clock::get()?.unix_timestamp,
+            deposit_id,
+            nonce: ctx.accounts.receipt.nonce,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw(
+        ctx: Context<Withdraw>,
+        amount: u64,
+        proof: WithdrawalProof,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let nullifier = &mut ctx.accounts.nullifier;
+        nullifier.bump = *ctx.bumps.get("nullifier").unwrap();
+        let paid_now = accept_withdrawal(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.oasis_state,
+            &ctx.accounts.root_record,
+            nullifier,
+            ctx.accounts.recipient.key(),
+            ctx.accounts.to.key(),
+            amount,
+            &proof,
+            now,
+        )?;
+        if !paid_now {
+            return Ok(());
+        }
+
+        pay_out(
+            &ctx.accounts.bridge,
+            &ctx.accounts.vault,
+            &ctx.accounts.to,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    pub fn withdraw_sol(
+        ctx: Context<WithdrawSol>,
+        amount: u64,
+        proof: WithdrawalProof,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let nullifier = &mut ctx.accounts.nullifier;
+        nullifier.bump = *ctx.bumps.get("nullifier").unwrap();
+        let paid_now = accept_withdrawal(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.oasis_state,
+            &ctx.accounts.root_record,
+            nullifier,
+            ctx.accounts.recipient.key(),
+            ctx.accounts.recipient.key(),
+            amount,
+            &proof,
+            now,
+        )?;
+        if !paid_now {
+            return Ok(());
+        }
+
+        pay_out_sol(
+            &ctx.accounts.sol_vault,
+            ctx.accounts.token_config.vault_bump,
+            &ctx.accounts.recipient.to_account_info(),
+            &ctx.accounts.system_program,
+            amount,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    /// Pay out a queued withdrawal once its release delay passed. Anyone may
+    /// call this; the funds only go to the account named at withdrawal.
+    pub fn release_withdrawal(ctx: Context<ReleaseWithdrawal>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        release(&ctx.accounts.bridge, &mut ctx.accounts.nullifier, now)?;
+
+        pay_out(
+            &ctx.accounts.bridge,
+            &ctx.accounts.vault,
+            &ctx.accounts.to,
+            &ctx.accounts.token_program,
+            ctx.accounts.nullifier.amount,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    /// Native SOL counterpart of `release_withdrawal`
+    pub fn release_sol_withdrawal(ctx: Context<ReleaseSolWithdrawal>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        release(&ctx.accounts.bridge, &mut ctx.accounts.nullifier, now)?;
+
+        pay_out_sol(
+            &ctx.accounts.sol_vault,
+            ctx.accounts.token_config.vault_bump,
+            &ctx.accounts.to.to_account_info(),
+            &ctx.accounts.system_program,
+            ctx.accounts.nullifier.amount,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    pub fn set_withdrawal_limits(
+        ctx: Context<SetWithdrawalLimits>,
+        params: WithdrawalLimitParams,
+    ) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+
+        // Only authority can adjust limits
+        require!(
+            ctx.accounts.authority.key() == bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+        require!(params.release_delay >= 0, BridgeError::InvalidLimits);
+
+        bridge.daily_limit = params.daily_limit;
+        bridge.release_delay = params.release_delay;
+
+        emit!(WithdrawalLimitsUpdated {
+            daily_limit: params.daily_limit,
+            release_delay: params.release_delay,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_escape_delay(ctx: Context<SetEscapeDelay>, escape_delay: i64) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+
+        // Only authority can adjust the escape delay, and only before escape
+        require!(
+            ctx.accounts.authority.key() == bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+        require!(!bridge.escape_active, BridgeError::EscapeActive);
+        require!(escape_delay > 0, BridgeError::InvalidEscapeDelay);
+
+        bridge.escape_delay = escape_delay;
+
+        emit!(EscapeDelayUpdated {
+            escape_delay,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Enter escape mode once no state root was posted for `escape_delay`.
+    /// Anyone may call this. The latest root, which must be finalized, becomes
+    /// the root balances are withdrawn against; later roots are ignored. If no
+    /// root was ever posted, the escape state is the empty tree.
+    pub fn activate_escape(ctx: Context<ActivateEscape>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let bridge = &mut ctx.accounts.bridge;
+        let oasis_state = &ctx.accounts.oasis_state;
+
+        require!(!bridge.escape_active, BridgeError::EscapeActive);
+        let escape_at = oasis_state
+            .last_update
+            .checked_add(bridge.escape_delay)
+            .ok_or(BridgeError::Overflow)?;
+        require!(now >= escape_at, BridgeError::EscapeDelayNotElapsed);
+
+        match &ctx.accounts.root_record {
+            Some(root_record) => {
+                require!(
+                    root_record.batch_index.checked_add(1) == Some(oasis_state.batch_count),
+                    BridgeError::InvalidProof
+                );
+                require!(
+                    root_record.is_finalized(oasis_state.challenge_period, now),
+                    BridgeError::RootNotFinalized
+                );
+                bridge.escape_batch = Some(root_record.batch_index);
+                bridge.escape_root = root_record.state_root;
+            }
+            None => {
+                require!(oasis_state.batch_count == 0, BridgeError::InvalidProof);
+                bridge.escape_batch = None;
+                bridge.escape_root = [0u8; 32];
+            }
+        }
+        bridge.escape_active = true;
+
+        emit!(EscapeActivated {
+            batch_index: bridge.escape_batch,
+            state_root: bridge.escape_root,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw the whole L2 balance of a token proven against the escape
+    /// root. Only the owner of the L2 account may claim it, once.
+    pub fn escape_withdraw(ctx: Context<EscapeWithdraw>, proof: BalanceProof) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let nullifier = &mut ctx.accounts.nullifier;
+        nullifier.bump = *ctx.bumps.get("nullifier").unwrap();
+        let paid_now = accept_escape(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            nullifier,
+            ctx.accounts.owner.key(),
+            ctx.accounts.to.key(),
+            &proof,
+            now,
+        )?;
+        if !paid_now {
+            return Ok(());
+        }
+
+        pay_out(
+            &ctx.accounts.bridge,
+            &ctx.accounts.vault,
+            &ctx.accounts.to,
+            &ctx.accounts.token_program,
+            proof.balance,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    /// Native SOL counterpart of `escape_withdraw`
+    pub fn escape_withdraw_sol(ctx: Context<EscapeWithdrawSol>, proof: BalanceProof) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let nullifier = &mut ctx.accounts.nullifier;
+        nullifier.bump = *ctx.bumps.get("nullifier").unwrap();
+        let paid_now = accept_escape(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            nullifier,
+            ctx.accounts.owner.key(),
+            ctx.accounts.owner.key(),
+            &proof,
+            now,
+        )?;
+        if !paid_now {
+            return Ok(());
+        }
+
+        pay_out_sol(
+            &ctx.accounts.sol_vault,
+            ctx.accounts.token_config.vault_bump,
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.system_program,
+            proof.balance,
+        )?;
+        record_payout(
+            &mut ctx.accounts.bridge,
+            &mut ctx.accounts.token_config,
+            &ctx.accounts.nullifier,
+            now,
+        )
+    }
+
+    /// Return a deposit the escape state never credited, e.g. one made after
+    /// the escape root or before any root was posted. The depositor proves
+    /// the deposit's marker absent from the escape root.
+    pub fn refund_deposit(ctx: Context<RefundDeposit>, proof: AbsenceProof) -> Result<()> {
+        let deposit_id = accept_refund(&ctx.accounts.bridge, &ctx.accounts.receipt, &proof)?;
+        pay_out(
+            &ctx.accounts.bridge,
+            &ctx.accounts.vault,
+            &ctx.accounts.to,
+            &ctx.accounts.token_program,
+            ctx.accounts.receipt.amount,
+        )?;
+        record_refund(&mut ctx.accounts.token_config, &ctx.accounts.receipt, deposit_id)
+    }
+
+    /// Native SOL counterpart of `refund_deposit`
+    pub fn refund_deposit_sol(ctx: Context<RefundDepositSol>, proof: AbsenceProof) -> Result<()> {
+        let deposit_id = accept_refund(&ctx.accounts.bridge, &ctx.accounts.receipt, &proof)?;
+        pay_out_sol(
+            &ctx.accounts.sol_vault,
+            ctx.accounts.token_config.vault_bump,
+            &ctx.accounts.depositor.to_account_info(),
+            &ctx.accounts.system_program,
+            ctx.accounts.receipt.amount,
+        )?;
+        record_refund(&mut ctx.accounts.token_config, &ctx.accounts.receipt, deposit_id)
+    }
+
+    pub fn pause_bridge(ctx: Context<PauseBridge>) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+        
+        // Only authority can pause
+        require!(
+            ctx.accounts.authority.key() == bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+
+        bridge.paused = true;
+
+        emit!(BridgePaused {
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn unpause_bridge(ctx: Context<UnpauseBridge>) -> Result<()> {
+        let bridge = &mut ctx.accounts.bridge;
+        
+        // Only authority can unpause
+        require!(
+            ctx.accounts.authority.key() == bridge.authority,
+            BridgeError::UnauthorizedOperation
+        );
+
+        bridge.paused = false;
+
+        emit!(BridgeUnpaused {
+            authority: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
 }
 
-entrypoint!(process_instruction);
+/// Check a withdrawal against a finalized state root and the withdrawal
+/// limits, and fill in its nullifier. Returns whether it is paid out now;
+/// otherwise it is queued behind the release delay.
+#[allow(clippy::too_many_arguments)]
+fn accept_withdrawal(
+    bridge: &mut BridgeAccount,
+    token_config: &mut TokenConfig,
+    oasis_state: &StateAccount,
+    root_record: &StateRootRecord,
+    nullifier: &mut WithdrawalNullifier,
+    recipient: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+    proof: &WithdrawalProof,
+    now: i64,
+) -> Result<bool> {
+    // Verify bridge is not paused
+    require!(!bridge.paused, BridgeError::BridgePaused);
+
+    // Verify withdrawal limits
+    require!(
+        amount >= token_config.min_withdrawal,
+        BridgeError::WithdrawalTooSmall
+    );
+    require!(
+        amount <= token_config.max_withdrawal,
+        BridgeError::WithdrawalTooLarge
+    );
+
+    // Only roots that survived the challenge period may back a withdrawal.
+    // A record at or past `batch_count` was rolled back by a fraud proof.
+    require!(
+        proof.batch_index < oasis_state.batch_count,
+        BridgeError::InvalidProof
+    );
+    // Balances are withdrawn as of the escape root, so withdrawals made on
+    // L2 after it would be paid twice
+    require!(
+        !bridge.escape_active
+            || matches!(bridge.escape_batch, Some(batch) if proof.batch_index <= batch),
+        BridgeError::EscapeActive
+    );
+    require!(
+        root_record.is_finalized(oasis_state.challenge_period, now),
+        BridgeError::RootNotFinalized
+    );
+
+    // The withdrawal must pay exactly what the L2 sender burned, to whom
+    // and in the token it named
+    let hash = withdrawal_hash(
+        &proof.from,
+        &recipient.to_bytes(),
+        &token_config.mint.to_bytes(),
+        amount,
+        proof.nonce,
+    );
+    require!(hash == proof.withdrawal_hash, BridgeError::InvalidProof);
+
+    // Its marker, holding the amount, must be in the finalized state root
+    let path = hashv(&[&withdrawal_key(&hash)]).to_bytes();
+    require!(
+        smt_root(
+            &path,
+            Some(&amount.to_le_bytes()),
+            &proof.bitmap,
+            &proof.merkle_proof
+        ) == Some(root_record.state_root),
+        BridgeError::InvalidProof
+    );
+
+    // Withdrawals past the rolling daily limits, global or of the token,
+    // wait out the release delay so the authority has time to pause
+    let within_limits = bridge.volume.fits(now, amount, bridge.daily_limit)
+        && token_config.volume.fits(now, amount, token_config.daily_limit);
+    let releasable_at = if within_limits {
+        now
+    } else {
+        require!(bridge.release_delay > 0, BridgeError::DailyLimitExceeded);
+        now.checked_add(bridge.release_delay)
+            .ok_or(BridgeError::Overflow)?
+    };
+
+    // Creating the nullifier fails if it exists, so a withdrawal is paid once
+    nullifier.withdrawal_hash = hash;
+    nullifier.recipient = recipient;
+    nullifier.mint = token_config.mint;
+    nullifier.destination = destination;
+    nullifier.amount = amount;
+    nullifier.batch_index = proof.batch_index;
+    nullifier.claimed_at = now;
+    nullifier.releasable_at = releasable_at;
+    nullifier.released = within_limits;
+
+    if !within_limits {
+        emit!(WithdrawalQueued {
+            recipient,
+            amount,
+            withdrawal_hash: hash,
+            releasable_at,
+        });
+    }
+
+    Ok(within_limits)
+}
+
+/// Check an escape claim against the escape root and fill in its nullifier.
+/// Claims over the daily limits are queued behind the release delay rather
+/// than refused, since a balance may exceed the limits, unless there is no
+/// delay. Returns whether it is paid out now.
+fn accept_escape(
+    bridge: &mut BridgeAccount,
+    token_config: &mut TokenConfig,
+    nullifier: &mut WithdrawalNullifier,
+    owner: Pubkey,
+    destination: Pubkey,
+    proof: &BalanceProof,
+    now: i64,
+) -> Result<bool> {
+    require!(!bridge.paused, BridgeError::BridgePaused);
+    require!(bridge.escape_active, BridgeError::EscapeNotActive);
+    // The empty escape state of a bridge that never saw a root holds no balance
+    let batch_index = bridge.escape_batch.ok_or(BridgeError::InvalidProof)?;
+    require!(proof.balance > 0, BridgeError::WithdrawalTooSmall);
+
+    // The owner's balance of the token must be in the escape root
+    let key = token_balance_key(&token_config.l2_token, &owner.to_bytes());
+    let path = hashv(&[&key]).to_bytes();
+    require!(
+        smt_root(
+            &path,
+            Some(&proof.balance.to_le_bytes()),
+            &proof.bitmap,
+            &proof.merkle_proof
+        ) == Some(bridge.escape_root),
+        BridgeError::InvalidProof
+    );
+
+    let within_limits = bridge.volume.fits(now, proof.balance, bridge.daily_limit)
+        && token_config.volume.fits(now, proof.balance, token_config.daily_limit);
+    let releasable_at = if within_limits {
+        now
+    } else {
+        require!(bridge.release_delay > 0, BridgeError::DailyLimitExceeded);
+        now.checked_add(bridge.release_delay)
+            .ok_or(BridgeError::Overflow)?
+    };
+
+    // Creating the nullifier fails if it exists, so a balance is paid once
+    let hash = escape_hash(&owner, &token_config.mint);
+    nullifier.withdrawal_hash = hash;
+    nullifier.recipient = owner;
+    nullifier.mint = token_config.mint;
+    nullifier.destination = destination;
+    nullifier.amount = proof.balance;
+    nullifier.batch_index = batch_index;
+    nullifier.claimed_at = now;
+    nullifier.releasable_at = releasable_at;
+    nullifier.released = within_limits;
+
+    if !within_limits {
+        emit!(WithdrawalQueued {
+            recipient: owner,
+            amount: proof.balance,
+            withdrawal_hash: hash,
+            releasable_at,
+        });
+    }
+
+    Ok(within_limits)
+}
+
+/// Identifier of the escape claim of `owner`'s balance of `mint`; keys its
+/// nullifier like a withdrawal hash
+pub fn escape_hash(owner: &Pubkey, mint: &Pubkey) -> [u8; 32] {
+    hashv(&[b"oasis:escape", owner.as_ref(), mint.as_ref()]).to_bytes()
+}
+
+/// Fill in the receipt of a new deposit and return its id
+fn record_deposit(
+    bridge: &mut BridgeAccount,
+    receipt: &mut DepositReceipt,
+    depositor: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+    l2_recipient: [u8; 32],
+) -> Result<[u8; 32]> {
+    receipt.depositor = depositor;
+    receipt.mint = mint;
+    receipt.amount = amount;
+    receipt.l2_recipient = l2_recipient;
+    receipt.nonce = bridge.deposit_nonce;
+    bridge.deposit_nonce = bridge.deposit_nonce.checked_add(1)
+        .ok_or(BridgeError::Overflow)?;
+    Ok(deposit_id(receipt.nonce))
+}
+
+/// Check that a deposit was never credited in the escape state. Returns its
+/// id.
+fn accept_refund(
+    bridge: &BridgeAccount,
+    receipt: &DepositReceipt,
+    proof: &AbsenceProof,
+) -> Result<[u8; 32]> {
+    require!(!bridge.paused, BridgeError::BridgePaused);
+    require!(bridge.escape_active, BridgeError::EscapeNotActive);
+
+    // A credited deposit leaves its marker in the state, so the balance it
+    // funded is claimed through `escape_withdraw` instead
+    let id = deposit_id(receipt.nonce);
+    let path = hashv(&[&deposit_key(&id)]).to_bytes();
+    require!(
+        smt_root(&path, None, &proof.bitmap, &proof.merkle_proof) == Some(bridge.escape_root),
+        BridgeError::InvalidProof
+    );
+    Ok(id)
+}
+
+/// Unlock a refunded deposit; its receipt is closed by the instruction
+fn record_refund(
+    token_config: &mut TokenConfig,
+    receipt: &DepositReceipt,
+    deposit_id: [u8; 32],
+) -> Result<()> {
+    token_config.total_locked = token_config.total_locked.checked_sub(receipt.amount)
+        .ok_or(BridgeError::Overflow)?;
+
+    emit!(DepositRefunded {
+        depositor: receipt.depositor,
+        mint: receipt.mint,
+        amount: receipt.amount,
+        deposit_id,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Mark a queued withdrawal as released once its delay passed
+fn release(bridge: &BridgeAccount, nullifier: &mut WithdrawalNullifier, now: i64) -> Result<()> {
+    require!(!bridge.paused, BridgeError::BridgePaused);
+    require!(!nullifier.released, BridgeError::WithdrawalAlreadyReleased);
+    require!(
+        now >= nullifier.releasable_at,
+        BridgeError::WithdrawalNotReleasable
+    );
+    nullifier.released = true;
+    Ok(())
+}
+
+/// Transfer a withdrawal from the token's vault
+fn pay_out<'info>(
+    bridge: &Account<'info, BridgeAccount>,
+    vault: &Account<'info, TokenAccount>,
+    to: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"bridge".as_ref(),
+        &[bridge.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = token::Transfer {
+        from: vault.to_account_info(),
+        to: to.to_account_info(),
+        authority: bridge.to_account_info(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)
+}
+
+/// Transfer a native SOL withdrawal from the SOL vault
+fn pay_out_sol<'info>(
+    sol_vault: &SystemAccount<'info>,
+    sol_vault_bump: u8,
+    to: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"sol_vault".as_ref(),
+        &[sol_vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = system_program::Transfer {
+        from: sol_vault.to_account_info(),
+        to: to.clone(),
+    };
+    let cpi_program = system_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    system_program::transfer(cpi_ctx, amount)
+}
+
+/// Count a paid withdrawal against the rolling limits
+fn record_payout(
+    bridge: &mut BridgeAccount,
+    token_config: &mut TokenConfig,
+    nullifier: &WithdrawalNullifier,
+    now: i64,
+) -> Result<()> {
+    let amount = nullifier.amount;
+
+    // Released withdrawals count too, even when they overshoot the limit
+    bridge.volume.record(now, amount);
+    token_config.volume.record(now, amount);
+
+    // Update bridge and token state
+    token_config.total_locked = token_config.total_locked.checked_sub(amount)
+        .ok_or(BridgeError::Overflow)?;
+    bridge.withdrawal_nonce = bridge.withdrawal_nonce.checked_add(1)
+        .ok_or(BridgeError::Overflow)?;
+
+    emit!(WithdrawEvent {
+        recipient: nullifier.recipient,
+        mint: token_config.mint,
+        amount,
+        nonce: bridge.withdrawal_nonce,
+        withdrawal_hash: nullifier.withdrawal_hash,
+        timestamp: now,
+    });
 
-pub fn process_instruction(
-    _program_id: &Pubkey,
-    _accounts: &[AccountInfo],
-    _instruction_data: &[u8],
-) -> ProgramResult {
     Ok(())
 }
 
+#[derive(Accounts)]
+pub struct InitializeBridge<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + BridgeAccount::LEN,
+        seeds = [b"bridge"],
+        bump
+    )]
+    pub bridge: Account<'info, BridgeAccount>,
+    /// `solana_oasis` state whose roots back withdrawals
+    pub oasis_state: Account<'info, StateAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterToken<'info> {
+    #[account(seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TokenConfig::LEN,
+        seeds = [b"token", mint.key().as_ref()],
+        bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault", mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterNative<'info> {
+    #[account(seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TokenConfig::LEN,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateToken<'info> {
+    #[account(seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", token_config.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", token_config.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, token::mint = token_config.mint)]
+    pub from: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"vault", token_config.mint.as_ref()],
+        bump = token_config.vault_bump,
+        token::mint = token_config.mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = depositor,
+        space = 8 + DepositReceipt::LEN,
+        seeds = [b"deposit".as_ref(), &bridge.deposit_nonce.to_le_bytes()],
+        bump
+    )]
+    pub receipt: Account<'info, DepositReceipt>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump = token_config.vault_bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(
+        init,
+        payer = depositor,
+        space = 8 + DepositReceipt::LEN,
+        seeds = [b"deposit".as_ref(), &bridge.deposit_nonce.to_le_bytes()],
+        bump
+    )]
+    pub receipt: Account<'info, DepositReceipt>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, proof: WithdrawalProof)]
+pub struct Withdraw<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    /// `solana_oasis` state the withdrawal proof is checked against
+    #[account(address = bridge.oasis_state)]
+    pub oasis_state: Account<'info, StateAccount>,
+    #[account(
+        seeds = [b"root", oasis_state.key().as_ref(), &proof.batch_index.to_le_bytes()],
+        bump = root_record.bump,
+        seeds::program = solana_oasis_contracts::ID,
+        constraint = root_record.state == oasis_state.key() @ BridgeError::InvalidProof
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(
+        mut,
+        seeds = [b"token", token_config.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(
+        mut,
+        seeds = [b"vault", token_config.mint.as_ref()],
+        bump = token_config.vault_bump,
+        token::mint = token_config.mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_config.mint, token::authority = recipient)]
+    pub to: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = recipient,
+        space = 8 + WithdrawalNullifier::LEN,
+        seeds = [b"nullifier", proof.withdrawal_hash.as_ref()],
+        bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, proof: WithdrawalProof)]
+pub struct WithdrawSol<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    /// `solana_oasis` state the withdrawal proof is checked against
+    #[account(address = bridge.oasis_state)]
+    pub oasis_state: Account<'info, StateAccount>,
+    #[account(
+        seeds = [b"root", oasis_state.key().as_ref(), &proof.batch_index.to_le_bytes()],
+        bump = root_record.bump,
+        seeds::program = solana_oasis_contracts::ID,
+        constraint = root_record.state == oasis_state.key() @ BridgeError::InvalidProof
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(
+        mut,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump = token_config.vault_bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(
+        init,
+        payer = recipient,
+        space = 8 + WithdrawalNullifier::LEN,
+        seeds = [b"nullifier", proof.withdrawal_hash.as_ref()],
+        bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseWithdrawal<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"nullifier", nullifier.withdrawal_hash.as_ref()],
+        bump = nullifier.bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    #[account(
+        mut,
+        seeds = [b"token", nullifier.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(
+        mut,
+        seeds = [b"vault", token_config.mint.as_ref()],
+        bump = token_config.vault_bump,
+        token::mint = token_config.mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, address = nullifier.destination)]
+    pub to: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseSolWithdrawal<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"nullifier", nullifier.withdrawal_hash.as_ref()],
+        bump = nullifier.bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    #[account(
+        mut,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump = token_config.bump,
+        constraint = nullifier.mint == token_config.mint @ BridgeError::InvalidProof
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump = token_config.vault_bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(mut, address = nullifier.destination)]
+    pub to: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetWithdrawalLimits<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEscapeDelay<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ActivateEscape<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(address = bridge.oasis_state)]
+    pub oasis_state: Account<'info, StateAccount>,
+    /// Record of the latest posted root; omitted if none was posted
+    #[account(
+        seeds = [b"root", oasis_state.key().as_ref(), &root_record.batch_index.to_le_bytes()],
+        bump = root_record.bump,
+        seeds::program = solana_oasis_contracts::ID,
+        constraint = root_record.state == oasis_state.key() @ BridgeError::InvalidProof
+    )]
+    pub root_record: Option<Account<'info, StateRootRecord>>,
+}
+
+#[derive(Accounts)]
+pub struct EscapeWithdraw<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", token_config.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(
+        mut,
+        seeds = [b"vault", token_config.mint.as_ref()],
+        bump = token_config.vault_bump,
+        token::mint = token_config.mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_config.mint, token::authority = owner)]
+    pub to: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + WithdrawalNullifier::LEN,
+        seeds = [b"nullifier", escape_hash(&owner.key(), &token_config.mint).as_ref()],
+        bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    /// Owner of the L2 account, which is keyed by the owner's L1 key
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EscapeWithdrawSol<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump = token_config.vault_bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + WithdrawalNullifier::LEN,
+        seeds = [b"nullifier", escape_hash(&owner.key(), &native_mint::ID).as_ref()],
+        bump
+    )]
+    pub nullifier: Account<'info, WithdrawalNullifier>,
+    /// Owner of the L2 account, which is keyed by the owner's L1 key
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RefundDeposit<'info> {
+    #[account(seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", receipt.mint.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(
+        mut,
+        seeds = [b"vault", token_config.mint.as_ref()],
+        bump = token_config.vault_bump,
+        token::mint = token_config.mint,
+        token::authority = bridge
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_config.mint, token::authority = depositor)]
+    pub to: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"deposit".as_ref(), &receipt.nonce.to_le_bytes()],
+        bump = receipt.bump,
+        has_one = depositor,
+        close = depositor
+    )]
+    pub receipt: Account<'info, DepositReceipt>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RefundDepositSol<'info> {
+    #[account(seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    #[account(
+        mut,
+        seeds = [b"token", native_mint::ID.as_ref()],
+        bump = token_config.bump
+    )]
+    pub token_config: Account<'info, TokenConfig>,
+    #[account(mut, seeds = [b"sol_vault"], bump = token_config.vault_bump)]
+    pub sol_vault: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"deposit".as_ref(), &receipt.nonce.to_le_bytes()],
+        bump = receipt.bump,
+        has_one = depositor,
+        constraint = receipt.mint == native_mint::ID @ BridgeError::InvalidProof,
+        close = depositor
+    )]
+    pub receipt: Account<'info, DepositReceipt>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PauseBridge<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnpauseBridge<'info> {
+    #[account(mut, seeds = [b"bridge"], bump = bridge.bump)]
+    pub bridge: Account<'info, BridgeAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[account]
+pub struct BridgeAccount {
+    pub authority: Pubkey,
+    pub paused: bool,
+    pub withdrawal_nonce: u64,
+    /// Limit across all tokens, in base units
+    pub daily_limit: u64,
+    pub bump: u8,
+    /// Seconds a withdrawal over the daily limits waits before release; zero
+    /// rejects such withdrawals instead
+    pub release_delay: i64,
+    /// Volume paid out across all tokens over the last 24 hours
+    pub volume: RollingVolume,
+    /// `solana_oasis` state whose roots back withdrawals
+    pub oasis_state: Pubkey,
+    /// Seconds without a new state root after which escape mode may start
+    pub escape_delay: i64,
+    /// Batch whose root balances are withdrawn against, once in escape mode;
+    /// `None` if no root was posted before it
+    pub escape_batch: Option<u64>,
+    /// State root of `escape_batch`
+    pub escape_root: [u8; 32],
+    /// Set once escape mode started; deposits are refused from then on
+    pub escape_active: bool,
+    /// Number of deposits made; the next deposit's receipt is keyed by it
+    pub deposit_nonce: u64,
+}
+
+impl BridgeAccount {
+    pub const LEN: usize = 32 + // authority
+        1 + // paused
+        8 + // withdrawal_nonce
+        8 + // daily_limit
+        1 + // bump
+        8 + // release_delay
+        RollingVolume::LEN + // volume
+        32 + // oasis_state
+        8 + // escape_delay
+        1 + 8 + // escape_batch
+        32 + // escape_root
+        1 + // escape_active
+        8; // deposit_nonce
+}
+
+/// Registration of a mint the bridge accepts
+#[account]
+pub struct TokenConfig {
+    pub mint: Pubkey,
+    /// Bridge-owned token account holding the locked tokens
+    pub vault: Pubkey,
+    /// L2 asset deposits of the mint are credited as
+    pub l2_token: [u8; 32],
+    /// Deposits are refused while disabled
+    pub enabled: bool,
+    pub min_withdrawal: u64,
+    pub max_withdrawal: u64,
+    pub daily_limit: u64,
+    pub total_locked: u64,
+    /// Volume of the token paid out over the last 24 hours
+    pub volume: RollingVolume,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+impl TokenConfig {
+    pub const LEN: usize = 32 + // mint
+        32 + // vault
+        32 + // l2_token
+        1 + // enabled
+        8 + // min_withdrawal
+        8 + // max_withdrawal
+        8 + // daily_limit
+        8 + // total_locked
+        RollingVolume::LEN + // volume
+        1 + // bump
+        1; // vault_bump
+}
+
+/// Volume withdrawn over the last 24 hours, kept in hourly buckets
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RollingVolume {
+    /// Volume of each hour, indexed by hour modulo `VOLUME_BUCKETS`
+    pub buckets: [u64; VOLUME_BUCKETS],
+    /// Hour the most recent bucket belongs to
+    pub hour: i64,
+}
+
+impl RollingVolume {
+    pub const LEN: usize = 8 * VOLUME_BUCKETS + // buckets
+        8; // hour
+
+    /// Empty the buckets that fell out of the window ending at `now`
+    fn roll(&mut self, now: i64) {
+        let hour = now.div_euclid(BUCKET_SECONDS);
+        if hour.saturating_sub(self.hour) >= VOLUME_BUCKETS as i64 {
+            self.buckets = [0; VOLUME_BUCKETS];
+        } else {
+            for expired in self.hour + 1..=hour {
+                self.buckets[expired.rem_euclid(VOLUME_BUCKETS as i64) as usize] = 0;
+            }
+        }
+        self.hour = self.hour.max(hour);
+    }
+
+    /// Volume withdrawn in the 24 hours up to `now`
+    pub fn total(&mut self, now: i64) -> u64 {
+        self.roll(now);
+        self.buckets
+            .iter()
+            .fold(0u64, |total, volume| total.saturating_add(*volume))
+    }
+
+    /// Whether withdrawing `amount` at `now` keeps the window within `limit`
+    pub fn fits(&mut self, now: i64, amount: u64, limit: u64) -> bool {
+        self.total(now)
+            .checked_add(amount)
+            .is_some_and(|total| total <= limit)
+    }
+
+    pub fn record(&mut self, now: i64, amount: u64) {
+        self.roll(now);
+        let bucket = &mut self.buckets[self.hour.rem_euclid(VOLUME_BUCKETS as i64) as usize];
+        *bucket = bucket.saturating_add(amount);
+    }
+}
+
+/// Marks a withdrawal as paid; one per withdrawal hash
+#[account]
+pub struct WithdrawalNullifier {
+    pub withdrawal_hash: [u8; 32],
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    /// Token account the withdrawal is paid to
+    pub destination: Pubkey,
+    pub amount: u64,
+    /// Batch whose root the withdrawal was proven against
+    pub batch_index: u64,
+    pub claimed_at: i64,
+    /// Earliest time a withdrawal queued over the daily limits is paid out
+    pub releasable_at: i64,
+    pub released: bool,
+    pub bump: u8,
+}
+
+impl WithdrawalNullifier {
+    pub const LEN: usize = 32 + // withdrawal_hash
+        32 + // recipient
+        32 + // mint
+        32 + // destination
+        8 + // amount
+        8 + // batch_index
+        8 + // claimed_at
+        8 + // releasable_at
+        1 + // released
+        1; // bump
+}
+
+/// Record of a deposit, kept so it can be refunded if the L2 never credits it
+/// and so a dispute can check what the L2 credited for it
+#[account]
+pub struct DepositReceipt {
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub l2_recipient: [u8; 32],
+    /// Position of the deposit; derives its `deposit_id`
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+impl DepositReceipt {
+    pub const LEN: usize = 32 + // depositor
+        32 + // mint
+        8 + // amount
+        32 + // l2_recipient
+        8 + // nonce
+        1; // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct InitializeBridgeParams {
+    pub daily_limit: u64,
+    pub release_delay: i64,
+    pub escape_delay: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct WithdrawalLimitParams {
+    pub daily_limit: u64,
+    pub release_delay: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct TokenParams {
+    pub min_withdrawal: u64,
+    pub max_withdrawal: u64,
+    pub daily_limit: u64,
+}
+
+impl TokenParams {
+    fn validate(&self) -> Result<()> {
+        require!(
+            self.min_withdrawal <= self.max_withdrawal,
+            BridgeError::InvalidLimits
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct WithdrawalProof {
+    pub l2_block_number: u64,
+    /// Batch whose posted root commits to `l2_block_number`
+    pub batch_index: u64,
+    /// L2 account that sent the withdrawal
+    pub from: [u8; 32],
+    /// Nonce the L2 sender gave the withdrawal
+    pub nonce: u64,
+    /// Bit `d` is set if the sibling at depth `d + 1` of the withdrawal marker
+    /// is not empty
+    pub bitmap: [u8; 32],
+    /// Non-empty siblings of the marker in the state tree, from the root down
+    pub merkle_proof: Vec<[u8; 32]>,
+    /// Hash of sender, recipient, token, amount and nonce; keys the marker
+    pub withdrawal_hash: [u8; 32],
+}
+
+/// Proof that a state key is absent from the state tree, as generated by the
+/// node
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct AbsenceProof {
+    /// Bit `d` is set if the sibling at depth `d + 1` of the key is not empty
+    pub bitmap: [u8; 32],
+    /// Non-empty siblings of the key in the state tree, from the root down
+    pub merkle_proof: Vec<[u8; 32]>,
+}
+
+/// Proof of an L2 balance of a bridged token, as generated by the node
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct BalanceProof {
+    pub balance: u64,
+    /// Bit `d` is set if the sibling at depth `d + 1` of the balance is not
+    /// empty
+    pub bitmap: [u8; 32],
+    /// Non-empty siblings of the balance in the state tree, from the root down
+    pub merkle_proof: Vec<[u8; 32]>,
+}
+
+#[event]
+pub struct DepositEvent {
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub l2_recipient: [u8; 32],
+    pub timestamp: i64,
+    /// Id the L2 credits the deposit under; see `deposit_id`
+    pub deposit_id: [u8; 32],
+    /// Position of the deposit, naming its receipt
+    pub nonce: u64,
+}
+
+#[event]
+pub struct DepositRefunded {
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub deposit_id: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub nonce: u64,
+    pub withdrawal_hash: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalQueued {
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub withdrawal_hash: [u8; 32],
+    pub releasable_at: i64,
+}
+
+#[event]
+pub struct WithdrawalLimitsUpdated {
+    pub daily_limit: u64,
+    pub release_delay: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscapeDelayUpdated {
+    pub escape_delay: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscapeActivated {
+    pub batch_index: Option<u64>,
+    pub state_root: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenRegistered {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub l2_token: [u8; 32],
+}
+
+#[event]
+pub struct TokenUpdated {
+    pub mint: Pubkey,
+    pub enabled: bool,
+    pub min_withdrawal: u64,
+    pub max_withdrawal: u64,
+    pub daily_limit: u64,
+}
+
+#[event]
+pub struct BridgePaused {
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BridgeUnpaused {
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[error_code]
+pub enum BridgeError {
+    #[msg("Bridge is paused")]
+    BridgePaused,
+    #[msg("Withdrawal amount too small")]
+    WithdrawalTooSmall,
+    #[msg("Withdrawal amount too large")]
+    WithdrawalTooLarge,
+    #[msg("Daily withdrawal limit exceeded")]
+    DailyLimitExceeded,
+    #[msg("Invalid withdrawal proof")]
+    InvalidProof,
+    #[msg("Unauthorized operation")]
+    UnauthorizedOperation,
+    #[msg("Arithmetic overflow")]
+    Overflow,
+    #[msg("State root is still inside its challenge period")]
+    RootNotFinalized,
+    #[msg("Invalid withdrawal limits")]
+    InvalidLimits,
+    #[msg("Withdrawal was already released")]
+    WithdrawalAlreadyReleased,
+    #[msg("Withdrawal is still inside its release delay")]
+    WithdrawalNotReleasable,
+    #[msg("Token deposits are disabled")]
+    TokenDisabled,
+    #[msg("Native SOL is bridged through the SOL vault")]
+    NativeMint,
+    #[msg("Escape delay must be positive")]
+    InvalidEscapeDelay,
+    #[msg("Bridge is in escape mode")]
+    EscapeActive,
+    #[msg("Bridge is not in escape mode")]
+    EscapeNotActive,
+    #[msg("State roots were posted within the escape delay")]
+    EscapeDelayNotElapsed,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use anchor_lang::error::ErrorCode;
+    use anchor_lang::{Discriminator, InstructionData, ToAccountMetas};
+    use anchor_spl::token::spl_token;
+    use solana_program::{
+        account_info::AccountInfo, entrypoint::ProgramResult, program_pack::Pack,
+    };
+    use solana_program_test::{
+        processor, BanksClientError, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+    };
+    use solana_sdk::{
+        account::Account as SolanaAccount,
+        instruction::{Instruction, InstructionError},
+        signature::{Keypair, Signer},
+        system_instruction,
+        transaction::{Transaction, TransactionError},
+    };
+
+    fn process_instruction(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> ProgramResult {
+        // Anchor's entry ties the account infos to the lifetime of the slice
+        let accounts = Box::leak(Box::new(accounts.to_vec()));
+        entry(program_id, accounts, data)
+    }
+
+    fn program_test() -> ProgramTest {
+        let mut program_test =
+            ProgramTest::new("solana_oasis_bridge", ID, processor!(process_instruction));
+        // The bundled SPL Token build cannot run in this program-test version
+        program_test.add_program(
+            "spl_token",
+            spl_token::ID,
+            processor!(spl_token::processor::Processor::process),
+        );
+
+        // An empty `solana_oasis` state for the bridge to settle against
+        program_test.add_account(
+            oasis_state_address(),
+            oasis_account(&oasis_state(), StateAccount::LEN),
+        );
+        program_test
+    }
+
+    fn oasis_state_address() -> Pubkey {
+        Pubkey::new_from_array([7u8; 32])
+    }
+
+    /// `solana_oasis` state before any root was posted
+    fn oasis_state() -> StateAccount {
+        let mut data = StateAccount::DISCRIMINATOR.to_vec();
+        data.resize(8 + StateAccount::LEN, 0);
+        StateAccount::try_deserialize(&mut &data[..]).unwrap()
+    }
+
+    /// Account of the `solana_oasis` program holding `value`
+    fn oasis_account(value: &impl AccountSerialize, len: usize) -> SolanaAccount {
+        let mut data = Vec::new();
+        value.try_serialize(&mut data).unwrap();
+        data.resize(8 + len, 0);
+        SolanaAccount {
+            lamports: 1_000_000_000,
+            data,
+            owner: solana_oasis_contracts::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn root_record_address(batch_index: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                b"root",
+                oasis_state_address().as_ref(),
+                &batch_index.to_le_bytes(),
+            ],
+            &solana_oasis_contracts::ID,
+        )
+    }
+
+    /// Post `state_root` as the only batch of the `solana_oasis` state,
+    /// recorded at time zero and final once `challenge_period` passed
+    fn post_root(context: &mut ProgramTestContext, state_root: [u8; 32], challenge_period: i64) {
+        let mut state = oasis_state();
+        state.state_root = state_root;
+        state.challenge_period = challenge_period;
+        state.batch_count = 1;
+        context.set_account(
+            &oasis_state_address(),
+            &oasis_account(&state, StateAccount::LEN).into(),
+        );
+
+        let (address, bump) = root_record_address(0);
+        let record = StateRootRecord {
+            state: oasis_state_address(),
+            batch_index: 0,
+            state_root,
+            validator: Pubkey::new_unique(),
+            submitted_at: 0,
+            transactions_root: [0u8; 32],
+            transaction_count: 0,
+            previous_root: [0u8; 32],
+            data_hash: [0u8; 32],
+            open_disputes: 0,
+            revert_count: 0,
+            bump,
+        };
+        context.set_account(
+            &address,
+            &oasis_account(&record, StateRootRecord::LEN).into(),
+        );
+    }
+
+    /// Proof of a withdrawal of `amount` of `mint` to `recipient` against a
+    /// state holding only its marker, and the root of that state
+    fn withdrawal_proof(
+        recipient: &Pubkey,
+        mint: &Pubkey,
+        amount: u64,
+        nonce: u64,
+    ) -> (WithdrawalProof, [u8; 32]) {
+        let from = [3u8; 32];
+        let hash = withdrawal_hash(
+            &from,
+            &recipient.to_bytes(),
+            &mint.to_bytes(),
+            amount,
+            nonce,
+        );
+        let path = hashv(&[&withdrawal_key(&hash)]).to_bytes();
+        let root = smt_root(&path, Some(&amount.to_le_bytes()), &[0u8; 32], &[]).unwrap();
+        let proof = WithdrawalProof {
+            l2_block_number: 1,
+            batch_index: 0,
+            from,
+            nonce,
+            bitmap: [0u8; 32],
+            merkle_proof: vec![],
+            withdrawal_hash: hash,
+        };
+        (proof, root)
+    }
+
+    fn nullifier_address(withdrawal_hash: &[u8; 32]) -> Pubkey {
+        Pubkey::find_program_address(&[b"nullifier", withdrawal_hash.as_ref()], &ID).0
+    }
+
+    fn bridge_address() -> Pubkey {
+        Pubkey::find_program_address(&[b"bridge"], &ID).0
+    }
+
+    fn token_config_address(mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"token", mint.as_ref()], &ID).0
+    }
+
+    fn vault_address(mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"vault", mint.as_ref()], &ID).0
+    }
+
+    fn token_params() -> TokenParams {
+        TokenParams {
+            min_withdrawal: 1,
+            max_withdrawal: u64::MAX,
+            daily_limit: u64::MAX,
+        }
+    }
+
+    async fn send(
+        context: &mut ProgramTestContext,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        // A fresh blockhash keeps a resent transaction from being answered
+        // with the result of an identical earlier one
+        let blockhash = context
+            .banks_client
+            .get_new_latest_blockhash(&context.last_blockhash)
+            .await?;
+        context.last_blockhash = blockhash;
+        let mut all_signers = vec![&context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    fn custom_error(error: BanksClientError) -> Option<u32> {
+        match error.unwrap() {
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    async fn bridge_account(context: &mut ProgramTestContext) -> BridgeAccount {
+        let bridge = context
+            .banks_client
+            .get_account(bridge_address())
+            .await
+            .unwrap()
+            .unwrap();
+        BridgeAccount::try_deserialize(&mut &bridge.data[..]).unwrap()
+    }
+
+    async fn initialize_bridge(context: &mut ProgramTestContext) {
+        let instruction = Instruction {
+            program_id: ID,
+            accounts: accounts::InitializeBridge {
+                bridge: bridge_address(),
+                oasis_state: oasis_state_address(),
+                authority: context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::InitializeBridge {
+                params: InitializeBridgeParams {
+                    daily_limit: u64::MAX,
+                    release_delay: 0,
+                    escape_delay: 7 * 24 * 3_600,
+                },
+            }
+            .data(),
+        };
+        send(context, &[instruction], &[]).await.unwrap();
+    }
+
+    /// Create a mint owned by the payer and register it with the bridge
+    async fn register_mint(context: &mut ProgramTestContext) -> Pubkey {
+        let mint = Keypair::new();
+        let payer = context.payer.pubkey();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let create = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                6,
+            )
+            .unwrap(),
+        ];
+        send(context, &create, &[&mint]).await.unwrap();
+
+        let register = Instruction {
+            program_id: ID,
+            accounts: accounts::RegisterToken {
+                bridge: bridge_address(),
+                mint: mint.pubkey(),
+                token_config: token_config_address(&mint.pubkey()),
+                vault: vault_address(&mint.pubkey()),
+                authority: payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::RegisterToken {
+                params: token_params(),
+            }
+            .data(),
+        };
+        send(context, &[register], &[]).await.unwrap();
+        mint.pubkey()
+    }
+
+    /// Create a token account of `mint` owned by the payer, holding `amount`
+    async fn funded_account(
+        context: &mut ProgramTestContext,
+        mint: &Pubkey,
+        amount: u64,
+    ) -> Pubkey {
+        let account = Keypair::new();
+        let payer = context.payer.pubkey();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                rent.minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_account(
+                &spl_token::ID,
+                &account.pubkey(),
+                mint,
+                &payer,
+            )
+            .unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::ID,
+                mint,
+                &account.pubkey(),
+                &payer,
+                &[],
+                amount,
+            )
+            .unwrap(),
+        ];
+        send(context, &instructions, &[&account]).await.unwrap();
+        account.pubkey()
+    }
+
+    async fn token_balance(context: &mut ProgramTestContext, account: Pubkey) -> u64 {
+        let account = context
+            .banks_client
+            .get_account(account)
+            .await
+            .unwrap()
+            .unwrap();
+        spl_token::state::Account::unpack(&account.data)
+            .unwrap()
+            .amount
+    }
+
+    fn receipt_address(nonce: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"deposit", &nonce.to_le_bytes()], &ID).0
+    }
+
+    /// Deposit of `amount` by the payer, the `nonce`-th made to the bridge
+    fn deposit(
+        context: &ProgramTestContext,
+        mint: &Pubkey,
+        from: Pubkey,
+        vault: Pubkey,
+        amount: u64,
+        nonce: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::Deposit {
+                bridge: bridge_address(),
+                token_config: token_config_address(mint),
+                from,
+                vault,
+                receipt: receipt_address(nonce),
+                depositor: context.payer.pubkey(),
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Deposit {
+                amount,
+                l2_recipient: [1u8; 32],
+            }
+            .data(),
+        }
+    }
+
+    /// Withdrawal by the payer of `amount` of `mint` to `to`
+    fn withdraw(
+        context: &ProgramTestContext,
+        mint: &Pubkey,
+        to: Pubkey,
+        amount: u64,
+        proof: WithdrawalProof,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::Withdraw {
+                bridge: bridge_address(),
+                oasis_state: oasis_state_address(),
+                root_record: root_record_address(proof.batch_index).0,
+                token_config: token_config_address(mint),
+                vault: vault_address(mint),
+                to,
+                nullifier: nullifier_address(&proof.withdrawal_hash),
+                recipient: context.payer.pubkey(),
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Withdraw { amount, proof }.data(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_instruction() {
+        // Instructions reach the program through its entrypoint
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+
+        let bridge = bridge_account(&mut context).await;
+        assert_eq!(bridge.authority, context.payer.pubkey());
+        assert!(!bridge.paused);
+        assert_eq!(bridge.withdrawal_nonce, 0);
+        let (_, bump) = Pubkey::find_program_address(&[b"bridge"], &ID);
+        assert_eq!(bridge.bump, bump);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_rejects_foreign_vaults() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let other_mint = register_mint(&mut context).await;
+        let from = funded_account(&mut context, &mint, 1_000).await;
+
+        // A token account of the right mint that the bridge does not own
+        let foreign = funded_account(&mut context, &mint, 0).await;
+        let instruction = deposit(&context, &mint, from, foreign, 100, 0);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(ErrorCode::ConstraintSeeds.into()));
+
+        // The bridge's own vault of another mint
+        let instruction = deposit(&context, &mint, from, vault_address(&other_mint), 100, 0);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(ErrorCode::ConstraintSeeds.into()));
+
+        let instruction = deposit(&context, &mint, from, vault_address(&mint), 100, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let vault = context
+            .banks_client
+            .get_account(vault_address(&mint))
+            .await
+            .unwrap()
+            .unwrap();
+        let vault = spl_token::state::Account::unpack(&vault.data).unwrap();
+        assert_eq!(vault.amount, 100);
+        assert_eq!(vault.owner, bridge_address());
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_bridge_is_rejected() {
+        // A program-owned account shaped like the bridge, naming an attacker
+        // as its authority
+        let attacker = Keypair::new();
+        let spoofed = Pubkey::new_unique();
+        let mut data = Vec::new();
+        BridgeAccount {
+            authority: attacker.pubkey(),
+            paused: false,
+            withdrawal_nonce: 0,
+            daily_limit: u64::MAX,
+            bump: 255,
+            release_delay: 0,
+            volume: RollingVolume::default(),
+            oasis_state: oasis_state_address(),
+            escape_delay: 1,
+            escape_batch: None,
+            escape_root: [0u8; 32],
+            escape_active: false,
+            deposit_nonce: 0,
+        }
+        .try_serialize(&mut data)
+        .unwrap();
+        data.resize(8 + BridgeAccount::LEN, 0);
+
+        let mut program_test = program_test();
+        program_test.add_account(
+            spoofed,
+            SolanaAccount {
+                lamports: 1_000_000_000,
+                data,
+                owner: ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        program_test.add_account(
+            attacker.pubkey(),
+            SolanaAccount::new(1_000_000_000, 0, &system_program::ID),
+        );
+        let mut context = program_test.start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+
+        let update = |bridge: Pubkey| Instruction {
+            program_id: ID,
+            accounts: accounts::UpdateToken {
+                bridge,
+                token_config: token_config_address(&mint),
+                authority: attacker.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::UpdateToken {
+                params: token_params(),
+                enabled: false,
+            }
+            .data(),
+        };
+
+        let error = send(&mut context, &[update(spoofed)], &[&attacker])
+            .await
+            .unwrap_err();
+        assert_eq!(custom_error(error), Some(ErrorCode::ConstraintSeeds.into()));
+
+        // The real bridge still only answers to its authority
+        let error = send(&mut context, &[update(bridge_address())], &[&attacker])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::UnauthorizedOperation.into())
+        );
+    }
+
+    #[test]
+    fn test_escape_over_limit_needs_release_delay() {
+        let owner = Pubkey::new_unique();
+        let mut token_config = TokenConfig {
+            mint: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            l2_token: [3u8; 32],
+            enabled: true,
+            min_withdrawal: 1,
+            max_withdrawal: u64::MAX,
+            daily_limit: 100,
+            total_locked: 1_000,
+            volume: RollingVolume::default(),
+            bump: 255,
+            vault_bump: 255,
+        };
+
+        // The owner's balance is the only leaf of the escape root
+        let balance = 500u64;
+        let key = token_balance_key(&token_config.l2_token, &owner.to_bytes());
+        let path = hashv(&[&key]).to_bytes();
+        let escape_root = smt_root(&path, Some(&balance.to_le_bytes()), &[0u8; 32], &[]).unwrap();
+        let proof = BalanceProof {
+            balance,
+            bitmap: [0u8; 32],
+            merkle_proof: vec![],
+        };
+        let mut bridge = BridgeAccount {
+            authority: Pubkey::new_unique(),
+            paused: false,
+            withdrawal_nonce: 0,
+            daily_limit: u64::MAX,
+            bump: 255,
+            release_delay: 0,
+            volume: RollingVolume::default(),
+            oasis_state: oasis_state_address(),
+            escape_delay: 1,
+            escape_batch: Some(0),
+            escape_root,
+            escape_active: true,
+            deposit_nonce: 0,
+        };
+        let mut nullifier = WithdrawalNullifier {
+            withdrawal_hash: [0u8; 32],
+            recipient: Pubkey::default(),
+            mint: Pubkey::default(),
+            destination: Pubkey::default(),
+            amount: 0,
+            batch_index: 0,
+            claimed_at: 0,
+            releasable_at: 0,
+            released: false,
+            bump: 255,
+        };
+
+        // Without a release delay, balances over the limits cannot be queued
+        let error = accept_escape(
+            &mut bridge,
+            &mut token_config,
+            &mut nullifier,
+            owner,
+            owner,
+            &proof,
+            1_000,
+        )
+        .unwrap_err();
+        assert_eq!(error, BridgeError::DailyLimitExceeded.into());
+
+        bridge.release_delay = 3_600;
+        let paid_now = accept_escape(
+            &mut bridge,
+            &mut token_config,
+            &mut nullifier,
+            owner,
+            owner,
+            &proof,
+            1_000,
+        )
+        .unwrap();
+        assert!(!paid_now);
+        assert_eq!(nullifier.releasable_at, 4_600);
+        assert!(!nullifier.released);
+    }
+
+    #[tokio::test]
+    async fn test_uncredited_deposit_is_refunded_in_escape() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let from = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, from, vault_address(&mint), 100, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        // The empty tree holds no deposit marker
+        let refund = Instruction {
+            program_id: ID,
+            accounts: accounts::RefundDeposit {
+                bridge: bridge_address(),
+                token_config: token_config_address(&mint),
+                vault: vault_address(&mint),
+                to: from,
+                receipt: receipt_address(0),
+                depositor: context.payer.pubkey(),
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::RefundDeposit {
+                proof: AbsenceProof {
+                    bitmap: [0u8; 32],
+                    merkle_proof: vec![],
+                },
+            }
+            .data(),
+        };
+        let error = send(&mut context, std::slice::from_ref(&refund), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::EscapeNotActive.into())
+        );
+
+        // No root was ever posted and the state went silent long ago
+        let activate = Instruction {
+            program_id: ID,
+            accounts: accounts::ActivateEscape {
+                bridge: bridge_address(),
+                oasis_state: oasis_state_address(),
+                root_record: None,
+            }
+            .to_account_metas(None),
+            data: instruction::ActivateEscape {}.data(),
+        };
+        send(&mut context, &[activate], &[]).await.unwrap();
+
+        let instruction = deposit(&context, &mint, from, vault_address(&mint), 100, 1);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(BridgeError::EscapeActive.into()));
+
+        send(&mut context, std::slice::from_ref(&refund), &[])
+            .await
+            .unwrap();
+        assert_eq!(token_balance(&mut context, from).await, 1_000);
+        assert_eq!(token_balance(&mut context, vault_address(&mint)).await, 0);
+
+        // The receipt is closed, so the deposit is refunded once
+        let error = send(&mut context, &[refund], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(ErrorCode::AccountNotInitialized.into())
+        );
+    }
+
+    #[test]
+    fn test_credited_deposit_is_not_refunded() {
+        let receipt = DepositReceipt {
+            depositor: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            amount: 100,
+            l2_recipient: [4u8; 32],
+            nonce: 3,
+            bump: 255,
+        };
+        let path = hashv(&[&deposit_key(&deposit_id(receipt.nonce))]).to_bytes();
+        let bridge = BridgeAccount {
+            authority: Pubkey::new_unique(),
+            paused: false,
+            withdrawal_nonce: 0,
+            daily_limit: u64::MAX,
+            bump: 255,
+            release_delay: 0,
+            volume: RollingVolume::default(),
+            oasis_state: oasis_state_address(),
+            escape_delay: 1,
+            escape_batch: Some(0),
+            escape_root: smt_root(&path, Some(&100u64.to_le_bytes()), &[0u8; 32], &[]).unwrap(),
+            escape_active: true,
+            deposit_nonce: 4,
+        };
+        let proof = AbsenceProof {
+            bitmap: [0u8; 32],
+            merkle_proof: vec![],
+        };
+
+        // The escape state credited the deposit; its balance is escaped instead
+        assert_eq!(
+            accept_refund(&bridge, &receipt, &proof).unwrap_err(),
+            BridgeError::InvalidProof.into()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_waits_for_finalized_root() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let (proof, root) = withdrawal_proof(&context.payer.pubkey(), &mint, 400, 0);
+        post_root(&mut context, root, i64::MAX);
+        let instruction = withdraw(&context, &mint, to, 400, proof);
+        let error = send(&mut context, std::slice::from_ref(&instruction), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::RootNotFinalized.into())
+        );
+
+        // The same root once its challenge period is over
+        post_root(&mut context, root, 1);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 400);
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_proof_must_match_root() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let recipient = context.payer.pubkey();
+        let (proof, root) = withdrawal_proof(&recipient, &mint, 400, 0);
+        post_root(&mut context, root, 1);
+
+        // More than the L2 sender burned
+        let (inflated, _) = withdrawal_proof(&recipient, &mint, 400, 0);
+        let instruction = withdraw(&context, &mint, to, 900, inflated);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(BridgeError::InvalidProof.into()));
+
+        // The same withdrawal claimed as another L2 sender's
+        let (mut impostor, _) = withdrawal_proof(&recipient, &mint, 400, 0);
+        impostor.from = [4u8; 32];
+        let instruction = withdraw(&context, &mint, to, 400, impostor);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(BridgeError::InvalidProof.into()));
+
+        // A withdrawal the root holds no marker of
+        let (unknown, _) = withdrawal_proof(&recipient, &mint, 400, 1);
+        let instruction = withdraw(&context, &mint, to, 400, unknown);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(BridgeError::InvalidProof.into()));
+
+        let instruction = withdraw(&context, &mint, to, 400, proof);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 400);
+        assert_eq!(token_balance(&mut context, vault_address(&mint)).await, 600);
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_is_paid_once() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let (proof, root) = withdrawal_proof(&context.payer.pubkey(), &mint, 400, 0);
+        let hash = proof.withdrawal_hash;
+        post_root(&mut context, root, 1);
+        let instruction = withdraw(&context, &mint, to, 400, proof);
+        send(&mut context, std::slice::from_ref(&instruction), &[])
+            .await
+            .unwrap();
+
+        let nullifier = context
+            .banks_client
+            .get_account(nullifier_address(&hash))
+            .await
+            .unwrap()
+            .unwrap();
+        let nullifier = WithdrawalNullifier::try_deserialize(&mut &nullifier.data[..]).unwrap();
+        assert_eq!(nullifier.withdrawal_hash, hash);
+        assert_eq!(nullifier.destination, to);
+        assert_eq!(nullifier.amount, 400);
+        assert!(nullifier.released);
+
+        // The nullifier already exists, so the withdrawal cannot be replayed
+        assert!(send(&mut context, &[instruction], &[]).await.is_err());
+        assert_eq!(token_balance(&mut context, to).await, 400);
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_over_daily_limit_is_queued() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let limits = Instruction {
+            program_id: ID,
+            accounts: accounts::SetWithdrawalLimits {
+                bridge: bridge_address(),
+                authority: context.payer.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::SetWithdrawalLimits {
+                params: WithdrawalLimitParams {
+                    daily_limit: 300,
+                    release_delay: 3_600,
+                },
+            }
+            .data(),
+        };
+        send(&mut context, &[limits], &[]).await.unwrap();
+
+        let (proof, root) = withdrawal_proof(&context.payer.pubkey(), &mint, 400, 0);
+        let hash = proof.withdrawal_hash;
+        post_root(&mut context, root, 1);
+        let instruction = withdraw(&context, &mint, to, 400, proof);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 0);
+
+        let release = Instruction {
+            program_id: ID,
+            accounts: accounts::ReleaseWithdrawal {
+                bridge: bridge_address(),
+                nullifier: nullifier_address(&hash),
+                token_config: token_config_address(&mint),
+                vault: vault_address(&mint),
+                to,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ReleaseWithdrawal {}.data(),
+        };
+        let error = send(&mut context, std::slice::from_ref(&release), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::WithdrawalNotReleasable.into())
+        );
+
+        let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += 3_600;
+        context.set_sysvar(&clock);
+        send(&mut context, std::slice::from_ref(&release), &[])
+            .await
+            .unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 400);
+
+        let error = send(&mut context, &[release], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::WithdrawalAlreadyReleased.into())
+        );
+    }
 
     #[test]
-    fn test_process_instruction() {
-        let program_id = Pubkey::new_unique();
-        let accounts = vec![];
-        let instruction_data = vec![];
-        assert!(process_instruction(&program_id, &accounts, &instruction_data).is_ok());
+    fn test_rolling_volume_forgets_old_hours() {
+        let mut volume = RollingVolume::default();
+        volume.record(0, 100);
+        volume.record(BUCKET_SECONDS, 50);
+        assert_eq!(volume.total(BUCKET_SECONDS), 150);
+        assert!(!volume.fits(BUCKET_SECONDS, 1, 150));
+
+        // The first hour leaves the window a day later
+        let day = VOLUME_BUCKETS as i64 * BUCKET_SECONDS;
+        assert_eq!(volume.total(day), 50);
+        assert!(volume.fits(day, 100, 150));
+        assert_eq!(volume.total(2 * day), 0);
+    }
+
+    #[tokio::test]
+    async fn test_token_limits_are_kept_per_mint() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let other_mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let config = context
+            .banks_client
+            .get_account(token_config_address(&mint))
+            .await
+            .unwrap()
+            .unwrap();
+        let config = TokenConfig::try_deserialize(&mut &config.data[..]).unwrap();
+        assert_eq!(config.vault, vault_address(&mint));
+        assert_eq!(config.l2_token, token_id(&mint));
+        assert_eq!(config.total_locked, 1_000);
+
+        // Narrow the limits of one mint and stop its deposits
+        let update = Instruction {
+            program_id: ID,
+            accounts: accounts::UpdateToken {
+                bridge: bridge_address(),
+                token_config: token_config_address(&mint),
+                authority: context.payer.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::UpdateToken {
+                params: TokenParams {
+                    min_withdrawal: 100,
+                    max_withdrawal: 500,
+                    daily_limit: u64::MAX,
+                },
+                enabled: false,
+            }
+            .data(),
+        };
+        send(&mut context, &[update], &[]).await.unwrap();
+
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 100, 1);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(BridgeError::TokenDisabled.into()));
+        let other_from = funded_account(&mut context, &other_mint, 100).await;
+        let instruction = deposit(
+            &context,
+            &other_mint,
+            other_from,
+            vault_address(&other_mint),
+            100,
+            1,
+        );
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let recipient = context.payer.pubkey();
+        let (proof, root) = withdrawal_proof(&recipient, &mint, 600, 0);
+        post_root(&mut context, root, 1);
+        let instruction = withdraw(&context, &mint, to, 600, proof);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::WithdrawalTooLarge.into())
+        );
+
+        // Withdrawals of a disabled mint stay possible within its limits
+        let (proof, root) = withdrawal_proof(&recipient, &mint, 400, 1);
+        post_root(&mut context, root, 1);
+        let instruction = withdraw(&context, &mint, to, 400, proof);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 400);
+    }
+
+    #[tokio::test]
+    async fn test_native_sol_round_trip() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let payer = context.payer.pubkey();
+        let sol_vault = Pubkey::find_program_address(&[b"sol_vault"], &ID).0;
+        let register = Instruction {
+            program_id: ID,
+            accounts: accounts::RegisterNative {
+                bridge: bridge_address(),
+                token_config: token_config_address(&native_mint::ID),
+                sol_vault,
+                authority: payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::RegisterNative {
+                params: token_params(),
+            }
+            .data(),
+        };
+        send(&mut context, &[register], &[]).await.unwrap();
+        let reserve = context
+            .banks_client
+            .get_rent()
+            .await
+            .unwrap()
+            .minimum_balance(0);
+        let balance = context.banks_client.get_balance(sol_vault).await.unwrap();
+        assert_eq!(balance, reserve);
+
+        let deposit = Instruction {
+            program_id: ID,
+            accounts: accounts::DepositSol {
+                bridge: bridge_address(),
+                token_config: token_config_address(&native_mint::ID),
+                sol_vault,
+                receipt: receipt_address(0),
+                depositor: payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::DepositSol {
+                amount: 1_000_000,
+                l2_recipient: [1u8; 32],
+            }
+            .data(),
+        };
+        send(&mut context, &[deposit], &[]).await.unwrap();
+        let balance = context.banks_client.get_balance(sol_vault).await.unwrap();
+        assert_eq!(balance, reserve + 1_000_000);
+
+        // Disputes check what the L2 credits against the receipt
+        let receipt = context
+            .banks_client
+            .get_account(receipt_address(0))
+            .await
+            .unwrap()
+            .unwrap();
+        let receipt = DepositReceipt::try_deserialize(&mut &receipt.data[..]).unwrap();
+        assert_eq!(receipt.mint, native_mint::ID);
+        assert_eq!(receipt.amount, 1_000_000);
+        assert_eq!(receipt.l2_recipient, [1u8; 32]);
+        assert_eq!(receipt.nonce, 0);
+
+        let (proof, root) = withdrawal_proof(&payer, &native_mint::ID, 400_000, 0);
+        post_root(&mut context, root, 1);
+        let withdraw = Instruction {
+            program_id: ID,
+            accounts: accounts::WithdrawSol {
+                bridge: bridge_address(),
+                oasis_state: oasis_state_address(),
+                root_record: root_record_address(0).0,
+                token_config: token_config_address(&native_mint::ID),
+                sol_vault,
+                nullifier: nullifier_address(&proof.withdrawal_hash),
+                recipient: payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawSol {
+                amount: 400_000,
+                proof,
+            }
+            .data(),
+        };
+        send(&mut context, &[withdraw], &[]).await.unwrap();
+        let balance = context.banks_client.get_balance(sol_vault).await.unwrap();
+        assert_eq!(balance, reserve + 600_000);
+    }
+
+    #[tokio::test]
+    async fn test_escape_pays_proven_balance() {
+        let mut context = program_test().start_with_context().await;
+        initialize_bridge(&mut context).await;
+        let mint = register_mint(&mut context).await;
+        let to = funded_account(&mut context, &mint, 1_000).await;
+        let instruction = deposit(&context, &mint, to, vault_address(&mint), 1_000, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        // The payer's L2 balance is the only leaf of the last posted root
+        let owner = context.payer.pubkey();
+        let key = token_balance_key(&token_id(&mint), &owner.to_bytes());
+        let path = hashv(&[&key]).to_bytes();
+        let root = smt_root(&path, Some(&600u64.to_le_bytes()), &[0u8; 32], &[]).unwrap();
+        post_root(&mut context, root, 1);
+
+        let escape = Instruction {
+            program_id: ID,
+            accounts: accounts::EscapeWithdraw {
+                bridge: bridge_address(),
+                token_config: token_config_address(&mint),
+                vault: vault_address(&mint),
+                to,
+                nullifier: nullifier_address(&escape_hash(&owner, &mint)),
+                owner,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::EscapeWithdraw {
+                proof: BalanceProof {
+                    balance: 600,
+                    bitmap: [0u8; 32],
+                    merkle_proof: vec![],
+                },
+            }
+            .data(),
+        };
+        let error = send(&mut context, std::slice::from_ref(&escape), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(BridgeError::EscapeNotActive.into())
+        );
+
+        // The state last moved at time zero, long before the escape delay
+        let activate = Instruction {
+            program_id: ID,
+            accounts: accounts::ActivateEscape {
+                bridge: bridge_address(),
+                oasis_state: oasis_state_address(),
+                root_record: Some(root_record_address(0).0),
+            }
+            .to_account_metas(None),
+            data: instruction::ActivateEscape {}.data(),
+        };
+        send(&mut context, &[activate], &[]).await.unwrap();
+        let bridge = bridge_account(&mut context).await;
+        assert_eq!(bridge.escape_batch, Some(0));
+        assert_eq!(bridge.escape_root, root);
+
+        send(&mut context, std::slice::from_ref(&escape), &[])
+            .await
+            .unwrap();
+        assert_eq!(token_balance(&mut context, to).await, 600);
+        assert_eq!(token_balance(&mut context, vault_address(&mint)).await, 400);
+
+        // The balance is claimed once
+        assert!(send(&mut context, &[escape], &[]).await.is_err());
+        assert_eq!(token_balance(&mut context, to).await, 600);
     }
 }
@@ -0,0 +1,96 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::sysvar::instructions::load_instruction_at_checked;
+
+use crate::OasisError;
+
+/// Size of one signature's offsets in an Ed25519 precompile instruction
+const SIGNATURE_OFFSETS_LEN: usize = 14;
+
+/// Instruction index the precompile uses for "this instruction"
+const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+/// State transition the sequencer claims for one batch.
+///
+/// A quorum of validators signs [`BatchCommitment::message`] after checking
+/// the batch; the program checks the parts it can see itself.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchCommitment {
+    /// Must be the current root of the state account
+    pub previous_root: [u8; 32],
+    pub new_root: [u8; 32],
+    /// SHA-256 of the `data` posted with the commitment
+    pub data_hash: [u8; 32],
+    /// Merkle root of the batch's transactions, bisected over in disputes
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+}
+
+impl BatchCommitment {
+    /// Digest validators sign, bound to one state account and batch index so
+    /// signatures cannot be replayed elsewhere
+    pub fn message(&self, state: &Pubkey, batch_index: u64) -> [u8; 32] {
+        hashv(&[
+            b"oasis:batch",
+            state.as_ref(),
+            &batch_index.to_le_bytes(),
+            &self.previous_root,
+            &self.new_root,
+            &self.data_hash,
+            &self.transactions_root,
+            &self.transaction_count.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+}
+
+/// Signatures needed out of `validators`: strictly more than two thirds
+pub fn quorum(validators: usize) -> usize {
+    validators * 2 / 3 + 1
+}
+
+/// Keys whose signature over `message` was checked by an Ed25519 precompile
+/// instruction of the current transaction.
+///
+/// Only signatures whose key, signature and message all live in the precompile
+/// instruction itself are counted, so the checked bytes are the ones read here.
+pub fn verified_signers(instructions: &AccountInfo, message: &[u8; 32]) -> Result<Vec<Pubkey>> {
+    let mut signers = Vec::new();
+    let mut index = 0;
+    while let Ok(instruction) = load_instruction_at_checked(index, instructions) {
+        index += 1;
+        if instruction.program_id != ed25519_program::ID {
+            continue;
+        }
+
+        let data = &instruction.data;
+        let count = *data.first().ok_or(OasisError::InvalidSignature)? as usize;
+        for offsets in data
+            .get(2..2 + count * SIGNATURE_OFFSETS_LEN)
+            .ok_or(OasisError::InvalidSignature)?
+            .chunks(SIGNATURE_OFFSETS_LEN)
+        {
+            let field = |i: usize| u16::from_le_bytes([offsets[2 * i], offsets[2 * i + 1]]);
+            let (public_key_offset, message_offset, message_len) =
+                (field(2) as usize, field(4) as usize, field(5) as usize);
+            require!(
+                field(1) == CURRENT_INSTRUCTION
+                    && field(3) == CURRENT_INSTRUCTION
+                    && field(6) == CURRENT_INSTRUCTION,
+                OasisError::InvalidSignature
+            );
+
+            if message_len != message.len()
+                || data.get(message_offset..message_offset + message_len) != Some(&message[..])
+            {
+                continue;
+            }
+            let public_key = data
+                .get(public_key_offset..public_key_offset + 32)
+                .ok_or(OasisError::InvalidSignature)?;
+            signers.push(Pubkey::try_from(public_key).map_err(|_| OasisError::InvalidSignature)?);
+        }
+    }
+    Ok(signers)
+}
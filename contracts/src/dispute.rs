@@ -0,0 +1,1236 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
+
+use crate::{
+    FraudProofSubmitted, OasisError, StateAccount, StateRootRecord, ValidatorStake, MAX_BPS,
+};
+
+/// Hash of an empty sparse Merkle subtree at any depth
+const EMPTY: [u8; 32] = [0u8; 32];
+
+/// Depth of the L2 state tree: one level per bit of a key's path
+const STATE_TREE_DEPTH: usize = 256;
+
+/// Whose move a dispute is waiting for
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeTurn {
+    /// The asserter must post the root at the midpoint of the disputed range
+    Asserter,
+    /// The challenger must agree or disagree with the posted midpoint root
+    Challenger,
+    /// The range is a single transaction; anyone may settle it with a step proof
+    OneStep,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Active,
+    ChallengerWon,
+    AsserterWon,
+}
+
+/// Bisection game over the transactions of one batch.
+///
+/// Step `i` is the state after the first `i` transactions of the batch. Both
+/// parties agree on the root at `agreed_step` while the challenger disputes
+/// the asserter's root at `disputed_step`; every round halves that range until
+/// one transaction is left, which [`ProveStep`] re-executes on chain.
+#[account]
+pub struct Dispute {
+    pub state: Pubkey,
+    pub batch_index: u64,
+    /// Validator that posted the disputed root
+    pub asserter: Pubkey,
+    pub challenger: Pubkey,
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+    pub agreed_step: u64,
+    pub agreed_root: [u8; 32],
+    pub disputed_step: u64,
+    pub disputed_root: [u8; 32],
+    /// Asserter's root at the midpoint, while waiting for the challenger
+    pub midpoint_root: [u8; 32],
+    pub turn: DisputeTurn,
+    pub status: DisputeStatus,
+    /// Unix time by which the party to move must act
+    pub deadline: i64,
+    /// `StateRootRecord::revert_count` of the disputed root. The record gets a
+    /// new one if the batch is reverted and posted again.
+    pub revert_count: u64,
+    /// Lamports the challenger locked, paid to the asserter if the challenger
+    /// loses and returned with the account's rent otherwise
+    pub bond: u64,
+    pub bump: u8,
+}
+
+impl Dispute {
+    pub const LEN: usize = 32 + // state
+        8 + // batch_index
+        32 + // asserter
+        32 + // challenger
+        32 + // transactions_root
+        8 + // transaction_count
+        8 + // agreed_step
+        32 + // agreed_root
+        8 + // disputed_step
+        32 + // disputed_root
+        32 + // midpoint_root
+        1 + // turn
+        1 + // status
+        8 + // deadline
+        8 + // revert_count
+        8 + // bond
+        1; // bump
+
+    /// Step the asserter has to post a root for next
+    pub fn midpoint(&self) -> u64 {
+        self.agreed_step + (self.disputed_step - self.agreed_step) / 2
+    }
+
+    fn next_turn(&self) -> DisputeTurn {
+        if self.disputed_step - self.agreed_step == 1 {
+            DisputeTurn::OneStep
+        } else {
+            DisputeTurn::Asserter
+        }
+    }
+
+    /// Whether the disputed root is still part of the chain. A fraud proof on
+    /// a later batch leaves it in place; one on this batch or an earlier one
+    /// voids the dispute, even once the batch is posted again.
+    pub fn is_current(&self, state: &StateAccount, record: &StateRootRecord) -> bool {
+        self.batch_index < state.batch_count && record.revert_count == self.revert_count
+    }
+
+    fn require_move(&self, turn: DisputeTurn, now: i64) -> Result<()> {
+        require!(
+            self.status == DisputeStatus::Active,
+            OasisError::DisputeNotActive
+        );
+        require!(self.turn == turn, OasisError::NotYourTurn);
+        require!(now <= self.deadline, OasisError::MoveDeadlinePassed);
+        Ok(())
+    }
+
+    /// Settle the dispute. A won defence releases the asserter's stake and
+    /// stops holding back the root's finality; a lost one keeps both until the
+    /// fraud proof slashes the stake and reverts the root.
+    fn resolve(
+        &mut self,
+        challenger_won: bool,
+        timed_out: bool,
+        asserter_stake: &mut ValidatorStake,
+        record: &mut StateRootRecord,
+    ) {
+        self.status = if challenger_won {
+            DisputeStatus::ChallengerWon
+        } else {
+            asserter_stake.open_disputes = asserter_stake.open_disputes.saturating_sub(1);
+            record.open_disputes = record.open_disputes.saturating_sub(1);
+            DisputeStatus::AsserterWon
+        };
+        emit!(DisputeResolved {
+            batch_index: self.batch_index,
+            challenger_won,
+            timed_out,
+        });
+    }
+}
+
+/// Pay the bond of a dispute the challenger lost out of the dispute account to
+/// the asserter. A won or stale dispute returns it when the account is closed.
+fn forfeit_bond(dispute: &Account<Dispute>, asserter: &AccountInfo) -> Result<()> {
+    if dispute.status != DisputeStatus::AsserterWon {
+        return Ok(());
+    }
+    let dispute_info = dispute.to_account_info();
+    let mut dispute_lamports = dispute_info.try_borrow_mut_lamports()?;
+    **dispute_lamports = dispute_lamports
+        .checked_sub(dispute.bond)
+        .ok_or(OasisError::InvalidStateProof)?;
+    let mut asserter_lamports = asserter.try_borrow_mut_lamports()?;
+    **asserter_lamports = asserter_lamports
+        .checked_add(dispute.bond)
+        .ok_or(OasisError::InvalidStateProof)?;
+    Ok(())
+}
+
+/// Buffer a step proof is uploaded into, since it does not fit in one
+/// transaction
+#[account]
+pub struct StepProof {
+    pub dispute: Pubkey,
+    pub author: Pubkey,
+    /// Borsh-encoded [`StepProofData`]
+    pub data: Vec<u8>,
+    pub bump: u8,
+}
+
+impl StepProof {
+    pub const MAX_DATA_LEN: usize = 8192;
+
+    pub const LEN: usize = 32 + // dispute
+        32 + // author
+        4 + Self::MAX_DATA_LEN + // data
+        1; // bump
+}
+
+/// Value of one state key before the disputed transaction, with its proof
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct StateAccess {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    /// Bit `d` is set if the sibling at depth `d + 1` is not empty
+    pub bitmap: [u8; 32],
+    /// Non-empty siblings, from the root down
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// Everything needed to re-execute transaction `agreed_step` of a batch.
+///
+/// `accesses` follow the transaction's access list without duplicates. Each
+/// proof is taken after the keys before it were set to their post-transaction
+/// values, so the proofs chain from the agreed root to the resulting root.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct StepProofData {
+    /// Bincode-encoded L2 transaction
+    pub transaction: Vec<u8>,
+    /// Siblings of the transaction in the batch's transactions tree
+    pub transaction_proof: Vec<[u8; 32]>,
+    pub accesses: Vec<StateAccess>,
+}
+
+impl StepProofData {
+    /// Root after executing transaction `index` of the batch on `pre_root`, or
+    /// `None` if the committed transaction is invalid. Errors if the proof
+    /// itself does not check out.
+    ///
+    /// A deposit is checked against the receipt `bridge_program` keeps of it,
+    /// which must be passed as `deposit_receipt`.
+    pub fn verify(
+        &self,
+        index: u64,
+        count: u64,
+        committed_root: [u8; 32],
+        pre_root: [u8; 32],
+        bridge_program: &Pubkey,
+        deposit_receipt: Option<&AccountInfo>,
+    ) -> Result<Option<[u8; 32]>> {
+        let leaf = hashv(&[&[0u8], &self.transaction]).to_bytes();
+        require!(
+            transactions_root(leaf, index, count, &self.transaction_proof) == Some(committed_root),
+            OasisError::InvalidStepProof
+        );
+
+        // The asserter committed to a transaction that is not even decodable
+        let Some(transaction) = decode_transaction(&self.transaction) else {
+            return Ok(None);
+        };
+        // The sequencer credited funds nobody locked in the bridge
+        if let L2Transaction::Deposit {
+            nonce,
+            recipient,
+            token,
+            amount,
+        } = &transaction
+        {
+            let receipt = deposit_receipt.ok_or(error!(OasisError::InvalidStepProof))?;
+            require_keys_eq!(
+                receipt.key(),
+                deposit_receipt_address(bridge_program, *nonce),
+                OasisError::InvalidStepProof
+            );
+            if !receipt_backs(receipt, bridge_program, *nonce, recipient, token, *amount) {
+                return Ok(None);
+            }
+        }
+
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for key in access_list(&transaction) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        require!(
+            keys.len() == self.accesses.len()
+                && keys
+                    .iter()
+                    .zip(&self.accesses)
+                    .all(|(key, access)| *key == access.key),
+            OasisError::InvalidStepProof
+        );
+
+        let read = |key: &[u8]| {
+            self.accesses
+                .iter()
+                .find(|access| access.key == key)
+                .and_then(|access| access.value.clone())
+        };
+        let result = execute(&transaction, &read);
+
+        let mut root = pre_root;
+        for access in &self.accesses {
+            let path = hashv(&[&access.key]).to_bytes();
+            let new_value = match &result {
+                Ok(writes) => writes
+                    .iter()
+                    .rev()
+                    .find(|(key, _)| *key == access.key)
+                    .map(|(_, value)| value.clone())
+                    .or_else(|| access.value.clone()),
+                Err(()) => access.value.clone(),
+            };
+
+            require!(
+                state_root(&path, access.value.as_deref(), access)? == root,
+                OasisError::InvalidStepProof
+            );
+            root = state_root(&path, new_value.as_deref(), access)?;
+        }
+
+        Ok(result.ok().map(|_| root))
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(batch_index: u64)]
+pub struct OpenDispute<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        seeds = [b"root", state.key().as_ref(), &batch_index.to_le_bytes()],
+        bump = root_record.bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    /// Each challenger plays its own game, so a colluding first challenger
+    /// cannot shield the root by losing on purpose
+    #[account(
+        init,
+        payer = challenger,
+        space = 8 + Dispute::LEN,
+        seeds = [
+            b"dispute",
+            state.key().as_ref(),
+            &batch_index.to_le_bytes(),
+            &root_record.revert_count.to_le_bytes(),
+            challenger.key().as_ref(),
+        ],
+        bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+    /// Stake of the asserter, held back until the dispute is settled
+    #[account(
+        mut,
+        seeds = [b"stake", state.key().as_ref(), root_record.validator.as_ref()],
+        bump = asserter_stake.bump
+    )]
+    pub asserter_stake: Account<'info, ValidatorStake>,
+    /// CHECK: receives the bond if the challenger loses
+    #[account(mut, address = root_record.validator)]
+    pub asserter: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub challenger: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> OpenDispute<'info> {
+    pub fn open(&mut self, batch_index: u64, bump: u8) -> Result<()> {
+        let state = &self.state;
+        let record = &mut self.root_record;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(batch_index < state.batch_count, OasisError::RootReverted);
+        require!(
+            !record.challenge_period_ended(state.challenge_period, now),
+            OasisError::ChallengePeriodExpired
+        );
+        record.open_disputes = record
+            .open_disputes
+            .checked_add(1)
+            .ok_or(OasisError::InvalidStateProof)?;
+
+        let pre_root = record.previous_root;
+
+        let dispute = &mut self.dispute;
+        dispute.state = state.key();
+        dispute.batch_index = batch_index;
+        dispute.asserter = record.validator;
+        dispute.challenger = self.challenger.key();
+        dispute.transactions_root = record.transactions_root;
+        dispute.transaction_count = record.transaction_count;
+        dispute.agreed_step = 0;
+        dispute.agreed_root = pre_root;
+        dispute.disputed_step = record.transaction_count;
+        dispute.disputed_root = record.state_root;
+        dispute.midpoint_root = EMPTY;
+        dispute.status = DisputeStatus::Active;
+        dispute.deadline = now
+            .checked_add(state.dispute_move_timeout)
+            .ok_or(OasisError::InvalidStateProof)?;
+        dispute.revert_count = record.revert_count;
+        dispute.bond = state.dispute_bond;
+        dispute.bump = bump;
+        system_program::transfer(
+            CpiContext::new(
+                self.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: self.challenger.to_account_info(),
+                    to: dispute.to_account_info(),
+                },
+            ),
+            dispute.bond,
+        )?;
+
+        emit!(DisputeOpened {
+            batch_index,
+            asserter: dispute.asserter,
+            challenger: dispute.challenger,
+            transaction_count: dispute.transaction_count,
+            deadline: dispute.deadline,
+        });
+
+        // An empty batch has nothing to bisect: it must keep the root
+        let asserter_stake = &mut self.asserter_stake;
+        asserter_stake.open_disputes = asserter_stake
+            .open_disputes
+            .checked_add(1)
+            .ok_or(OasisError::InvalidStateProof)?;
+        if dispute.transaction_count == 0 {
+            let challenger_won = dispute.disputed_root != pre_root;
+            dispute.resolve(challenger_won, false, asserter_stake, record);
+            forfeit_bond(dispute, &self.asserter)?;
+        } else {
+            dispute.turn = dispute.next_turn();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct BisectDispute<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(mut, has_one = state, has_one = asserter)]
+    pub dispute: Account<'info, Dispute>,
+    pub asserter: Signer<'info>,
+}
+
+impl<'info> BisectDispute<'info> {
+    pub fn bisect(&mut self, midpoint_root: [u8; 32]) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let dispute = &mut self.dispute;
+        dispute.require_move(DisputeTurn::Asserter, now)?;
+
+        dispute.midpoint_root = midpoint_root;
+        dispute.turn = DisputeTurn::Challenger;
+        dispute.deadline = now
+            .checked_add(self.state.dispute_move_timeout)
+            .ok_or(OasisError::InvalidStateProof)?;
+
+        emit!(DisputeMoved {
+            batch_index: dispute.batch_index,
+            agreed_step: dispute.agreed_step,
+            disputed_step: dispute.disputed_step,
+            midpoint_root,
+            deadline: dispute.deadline,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct RespondDispute<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(mut, has_one = state, has_one = challenger)]
+    pub dispute: Account<'info, Dispute>,
+    pub challenger: Signer<'info>,
+}
+
+impl<'info> RespondDispute<'info> {
+    pub fn respond(&mut self, agree: bool) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let dispute = &mut self.dispute;
+        dispute.require_move(DisputeTurn::Challenger, now)?;
+
+        let midpoint = dispute.midpoint();
+        if agree {
+            dispute.agreed_step = midpoint;
+            dispute.agreed_root = dispute.midpoint_root;
+        } else {
+            dispute.disputed_step = midpoint;
+            dispute.disputed_root = dispute.midpoint_root;
+        }
+        dispute.turn = dispute.next_turn();
+        dispute.deadline = now
+            .checked_add(self.state.dispute_move_timeout)
+            .ok_or(OasisError::InvalidStateProof)?;
+
+        emit!(DisputeMoved {
+            batch_index: dispute.batch_index,
+            agreed_step: dispute.agreed_step,
+            disputed_step: dispute.disputed_step,
+            midpoint_root: dispute.midpoint_root,
+            deadline: dispute.deadline,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct OpenStepProof<'info> {
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        init,
+        payer = author,
+        space = 8 + StepProof::LEN,
+        seeds = [b"step_proof", dispute.key().as_ref(), author.key().as_ref()],
+        bump
+    )]
+    pub step_proof: Account<'info, StepProof>,
+    #[account(mut)]
+    pub author: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> OpenStepProof<'info> {
+    pub fn open(&mut self, bump: u8) -> Result<()> {
+        let step_proof = &mut self.step_proof;
+        step_proof.dispute = self.dispute.key();
+        step_proof.author = self.author.key();
+        step_proof.data = Vec::new();
+        step_proof.bump = bump;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct WriteStepProof<'info> {
+    #[account(mut, has_one = author)]
+    pub step_proof: Account<'info, StepProof>,
+    pub author: Signer<'info>,
+}
+
+impl<'info> WriteStepProof<'info> {
+    /// Append the next chunk of the encoded proof
+    pub fn write(&mut self, data: Vec<u8>) -> Result<()> {
+        let step_proof = &mut self.step_proof;
+        require!(
+            step_proof.data.len() + data.len() <= StepProof::MAX_DATA_LEN,
+            OasisError::StepProofTooLarge
+        );
+        step_proof.data.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ProveStep<'info> {
+    #[account(address = dispute.state)]
+    pub state: Account<'info, StateAccount>,
+    #[account(mut)]
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        mut,
+        seeds = [b"root", state.key().as_ref(), &dispute.batch_index.to_le_bytes()],
+        bump = root_record.bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(mut, has_one = dispute, has_one = author, close = author)]
+    pub step_proof: Account<'info, StepProof>,
+    #[account(
+        mut,
+        seeds = [b"stake", dispute.state.as_ref(), dispute.asserter.as_ref()],
+        bump = asserter_stake.bump
+    )]
+    pub asserter_stake: Account<'info, ValidatorStake>,
+    /// CHECK: receives the bond if the challenger loses
+    #[account(mut, address = dispute.asserter)]
+    pub asserter: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub author: Signer<'info>,
+}
+
+impl<'info> ProveStep<'info> {
+    /// Re-execute the single disputed transaction. The challenger wins if it
+    /// is invalid or does not lead to the asserter's root. A deposit's bridge
+    /// receipt is passed as the first remaining account.
+    pub fn prove(&mut self, deposit_receipt: Option<&AccountInfo>) -> Result<()> {
+        let dispute = &mut self.dispute;
+        require!(
+            dispute.status == DisputeStatus::Active,
+            OasisError::DisputeNotActive
+        );
+        require!(
+            dispute.turn == DisputeTurn::OneStep,
+            OasisError::NotYourTurn
+        );
+        require!(
+            dispute.is_current(&self.state, &self.root_record),
+            OasisError::RootReverted
+        );
+
+        let proof = StepProofData::try_from_slice(&self.step_proof.data)
+            .map_err(|_| error!(OasisError::InvalidStepProof))?;
+        let post_root = proof.verify(
+            dispute.agreed_step,
+            dispute.transaction_count,
+            dispute.transactions_root,
+            dispute.agreed_root,
+            &self.state.bridge_program,
+            deposit_receipt,
+        )?;
+
+        let challenger_won = post_root != Some(dispute.disputed_root);
+        dispute.resolve(
+            challenger_won,
+            false,
+            &mut self.asserter_stake,
+            &mut self.root_record,
+        );
+        forfeit_bond(dispute, &self.asserter)
+    }
+}
+
+#[derive(Accounts)]
+pub struct TimeoutDispute<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(mut, has_one = state)]
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        mut,
+        seeds = [b"root", state.key().as_ref(), &dispute.batch_index.to_le_bytes()],
+        bump = root_record.bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(
+        mut,
+        seeds = [b"stake", state.key().as_ref(), dispute.asserter.as_ref()],
+        bump = asserter_stake.bump
+    )]
+    pub asserter_stake: Account<'info, ValidatorStake>,
+    /// CHECK: receives the bond if the challenger loses
+    #[account(mut, address = dispute.asserter)]
+    pub asserter: UncheckedAccount<'info>,
+}
+
+impl<'info> TimeoutDispute<'info> {
+    /// Settle a dispute whose party to move missed its deadline. The
+    /// asserter posted the batch and is the one party sure to hold its data,
+    /// so it also loses a one-step range nobody settled.
+    pub fn timeout(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let dispute = &mut self.dispute;
+        require!(
+            dispute.status == DisputeStatus::Active,
+            OasisError::DisputeNotActive
+        );
+        require!(now > dispute.deadline, OasisError::DeadlineNotReached);
+        require!(
+            dispute.is_current(&self.state, &self.root_record),
+            OasisError::RootReverted
+        );
+
+        let challenger_won = dispute.turn != DisputeTurn::Challenger;
+        dispute.resolve(
+            challenger_won,
+            true,
+            &mut self.asserter_stake,
+            &mut self.root_record,
+        );
+        forfeit_bond(dispute, &self.asserter)
+    }
+}
+
+#[derive(Accounts)]
+pub struct SubmitFraudProof<'info> {
+    #[account(mut)]
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        seeds = [b"root", state.key().as_ref(), &dispute.batch_index.to_le_bytes()],
+        bump = root_record.bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(
+        mut,
+        has_one = state,
+        has_one = challenger,
+        close = challenger,
+        seeds = [
+            b"dispute",
+            state.key().as_ref(),
+            &dispute.batch_index.to_le_bytes(),
+            &dispute.revert_count.to_le_bytes(),
+            challenger.key().as_ref(),
+        ],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+    #[account(
+        mut,
+        close = challenger,
+        seeds = [b"stake", state.key().as_ref(), dispute.asserter.as_ref()],
+        bump = validator_stake.bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    #[account(mut, address = state.stake_vault)]
+    pub stake_vault: Account<'info, TokenAccount>,
+    #[account(mut, address = stake_vault.mint)]
+    pub stake_mint: Account<'info, Mint>,
+    /// CHECK: PDA owning the stake vault, only signs
+    #[account(seeds = [b"vault", state.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+    /// Token account receiving the challenger's share of the slashed stake
+    #[account(mut, token::mint = stake_vault.mint)]
+    pub reward_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub challenger: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SubmitFraudProof<'info> {
+    /// The won dispute proves the asserter's root wrong: its stake is split
+    /// between the challenger and a burn, it leaves the validator set, and the
+    /// chain resumes from the root the invalid batch was applied to.
+    pub fn slash(&mut self, vault_bump: u8) -> Result<()> {
+        let dispute = &self.dispute;
+        require!(
+            dispute.status == DisputeStatus::ChallengerWon,
+            OasisError::InvalidFraudProof
+        );
+        require!(
+            dispute.is_current(&self.state, &self.root_record),
+            OasisError::InvalidFraudProof
+        );
+
+        let slashed_amount = self.validator_stake.amount;
+        let reward_amount =
+            (slashed_amount as u128 * self.state.slash_reward_bps as u128 / MAX_BPS as u128) as u64;
+        let state_key = self.state.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[b"vault", state_key.as_ref(), &[vault_bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                token::Transfer {
+                    from: self.stake_vault.to_account_info(),
+                    to: self.reward_account.to_account_info(),
+                    authority: self.vault_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            reward_amount,
+        )?;
+        token::burn(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                token::Burn {
+                    mint: self.stake_mint.to_account_info(),
+                    from: self.stake_vault.to_account_info(),
+                    authority: self.vault_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            slashed_amount - reward_amount,
+        )?;
+        // The slashed stake stops earning; its unclaimed rewards go to the
+        // validators bonded at the next settlement
+        let stake = &mut self.validator_stake;
+        let state = &mut self.state;
+        stake.accrue(state.reward_per_stake)?;
+        if stake.is_bonded() {
+            state.total_stake = state
+                .total_stake
+                .checked_sub(slashed_amount)
+                .ok_or(OasisError::InsufficientStake)?;
+        }
+        state.forfeited_rewards = state
+            .forfeited_rewards
+            .checked_add(stake.unclaimed_rewards)
+            .ok_or(OasisError::RewardOverflow)?;
+        stake.amount = 0;
+        stake.unclaimed_rewards = 0;
+
+        let now = Clock::get()?.unix_timestamp;
+        state
+            .validators
+            .retain(|validator| *validator != dispute.asserter);
+        state.state_root = self.root_record.previous_root;
+        state.batch_count = dispute.batch_index;
+        state.revert_count = state
+            .revert_count
+            .checked_add(1)
+            .ok_or(OasisError::InvalidStateProof)?;
+        state.last_update = now;
+
+        emit!(FraudProofSubmitted {
+            challenger: dispute.challenger,
+            validator: dispute.asserter,
+            batch_index: dispute.batch_index,
+            restored_root: state.state_root,
+            slashed_amount,
+            reward_amount,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SettleStaleDispute<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        seeds = [b"root", state.key().as_ref(), &dispute.batch_index.to_le_bytes()],
+        bump = root_record.bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(mut, has_one = state, has_one = challenger, close = challenger)]
+    pub dispute: Account<'info, Dispute>,
+    /// CHECK: stake of the asserter, which is closed if the asserter was
+    /// slashed since; it is only written to if it still holds a `ValidatorStake`
+    #[account(
+        mut,
+        seeds = [b"stake", state.key().as_ref(), dispute.asserter.as_ref()],
+        bump
+    )]
+    pub asserter_stake: UncheckedAccount<'info>,
+    /// CHECK: receives the rent of the closed dispute
+    #[account(mut)]
+    pub challenger: UncheckedAccount<'info>,
+}
+
+impl<'info> SettleStaleDispute<'info> {
+    /// Close a dispute whose root was reverted. Nobody can win it anymore, so
+    /// a dispute not settled in the asserter's favour stops holding back its
+    /// stake, unless that stake was slashed and registered again since.
+    pub fn settle(&mut self) -> Result<()> {
+        let dispute = &self.dispute;
+        require!(
+            !dispute.is_current(&self.state, &self.root_record),
+            OasisError::DisputeNotStale
+        );
+
+        let stake_info = self.asserter_stake.to_account_info();
+        if dispute.status != DisputeStatus::AsserterWon && !stake_info.data_is_empty() {
+            let mut stake = Account::<ValidatorStake>::try_from(&stake_info)?;
+            if stake.revert_count <= dispute.revert_count {
+                stake.open_disputes = stake.open_disputes.saturating_sub(1);
+                stake.exit(&crate::ID)?;
+            }
+        }
+
+        emit!(StaleDisputeSettled {
+            batch_index: dispute.batch_index,
+            asserter: dispute.asserter,
+            challenger: dispute.challenger,
+        });
+        Ok(())
+    }
+}
+
+/// Root of the transactions tree containing `leaf` at `index`, or `None` if
+/// the proof has the wrong length for `count` transactions
+fn transactions_root(
+    leaf: [u8; 32],
+    index: u64,
+    count: u64,
+    proof: &[[u8; 32]],
+) -> Option<[u8; 32]> {
+    let depth = count.checked_next_power_of_two()?.trailing_zeros() as usize;
+    if index >= count || proof.len() != depth {
+        return None;
+    }
+
+    let mut hash = leaf;
+    let mut index = index;
+    for sibling in proof {
+        hash = if index & 1 == 0 {
+            hashv(&[&[1u8], &hash, sibling]).to_bytes()
+        } else {
+            hashv(&[&[1u8], sibling, &hash]).to_bytes()
+        };
+        index >>= 1;
+    }
+    Some(hash)
+}
+
+/// Root of the state tree in which `path` holds `value`, every other leaf
+/// being as when the proof in `access` was taken
+fn state_root(path: &[u8; 32], value: Option<&[u8]>, access: &StateAccess) -> Result<[u8; 32]> {
+    smt_root(path, value, &access.bitmap, &access.siblings)
+        .ok_or_else(|| error!(OasisError::InvalidStepProof))
+}
+
+/// Root of the sparse Merkle state tree in which `path` holds `value`, or
+/// `None` if `siblings` does not match `bitmap`
+pub fn smt_root(
+    path: &[u8; 32],
+    value: Option<&[u8]>,
+    bitmap: &[u8; 32],
+    siblings: &[[u8; 32]],
+) -> Option<[u8; 32]> {
+    let bit = |bytes: &[u8; 32], depth: usize| bytes[depth / 8] & (0x80 >> (depth % 8)) != 0;
+
+    let mut hash = match value {
+        Some(value) => hashv(&[&[0u8], path, &hashv(&[value]).to_bytes()]).to_bytes(),
+        None => EMPTY,
+    };
+    let mut siblings = siblings.iter().rev();
+    for depth in (0..STATE_TREE_DEPTH).rev() {
+        let sibling = if bit(bitmap, depth) {
+            *siblings.next()?
+        } else {
+            EMPTY
+        };
+        let (left, right) = if bit(path, depth) {
+            (sibling, hash)
+        } else {
+            (hash, sibling)
+        };
+        hash = if left == EMPTY && right == EMPTY {
+            EMPTY
+        } else {
+            hashv(&[&[1u8], &left, &right]).to_bytes()
+        };
+    }
+    siblings.next().is_none().then_some(hash)
+}
+
+/// L2 transaction as executed by the node
+enum L2Transaction {
+    Transfer {
+        from: [u8; 32],
+        to: [u8; 32],
+        amount: u64,
+    },
+    Deposit {
+        nonce: u64,
+        recipient: [u8; 32],
+        token: [u8; 32],
+        amount: u64,
+    },
+    Forced {
+        index: u64,
+        sender: [u8; 32],
+        transaction: Vec<u8>,
+    },
+    Withdraw {
+        from: [u8; 32],
+        recipient: [u8; 32],
+        token: [u8; 32],
+        amount: u64,
+        nonce: u64,
+    },
+}
+
+/// Decode a bincode-encoded L2 transaction; trailing bytes are ignored like
+/// in the node
+fn decode_transaction(data: &[u8]) -> Option<L2Transaction> {
+    let mut data = data;
+    let mut take = |len: usize| -> Option<&[u8]> {
+        if data.len() < len {
+            return None;
+        }
+        let (head, rest) = data.split_at(len);
+        data = rest;
+        Some(head)
+    };
+
+    let tag = u32::from_le_bytes(take(4)?.try_into().ok()?);
+    match tag {
+        0 => Some(L2Transaction::Transfer {
+            from: take(32)?.try_into().ok()?,
+            to: take(32)?.try_into().ok()?,
+            amount: u64::from_le_bytes(take(8)?.try_into().ok()?),
+        }),
+        1 => Some(L2Transaction::Deposit {
+            nonce: u64::from_le_bytes(take(8)?.try_into().ok()?),
+            recipient: take(32)?.try_into().ok()?,
+            token: take(32)?.try_into().ok()?,
+            amount: u64::from_le_bytes(take(8)?.try_into().ok()?),
+        }),
+        2 => {
+            let index = u64::from_le_bytes(take(8)?.try_into().ok()?);
+            let sender = take(32)?.try_into().ok()?;
+            let len = u64::from_le_bytes(take(8)?.try_into().ok()?);
+            let transaction = take(usize::try_from(len).ok()?)?.to_vec();
+            Some(L2Transaction::Forced {
+                index,
+                sender,
+                transaction,
+            })
+        }
+        3 => Some(L2Transaction::Withdraw {
+            from: take(32)?.try_into().ok()?,
+            recipient: take(32)?.try_into().ok()?,
+            token: take(32)?.try_into().ok()?,
+            amount: u64::from_le_bytes(take(8)?.try_into().ok()?),
+            nonce: u64::from_le_bytes(take(8)?.try_into().ok()?),
+        }),
+        _ => None,
+    }
+}
+
+fn balance_key(account: &[u8; 32]) -> Vec<u8> {
+    [&b"balance:"[..], account.as_ref()].concat()
+}
+
+/// State key of `account`'s balance of the L2 asset `token`
+pub fn token_balance_key(token: &[u8; 32], account: &[u8; 32]) -> Vec<u8> {
+    [&b"token_balance:"[..], token.as_ref(), account.as_ref()].concat()
+}
+
+/// L2 asset identifier of the L1 mint `mint`, as derived by the node
+pub fn token_id(mint: &Pubkey) -> [u8; 32] {
+    hashv(&[b"oasis:token", mint.as_ref()]).to_bytes()
+}
+
+fn forced_key(index: u64) -> Vec<u8> {
+    [&b"forced:"[..], &index.to_be_bytes()[..]].concat()
+}
+
+/// State key marking a deposit as credited, holding its amount
+pub fn deposit_key(deposit_id: &[u8; 32]) -> Vec<u8> {
+    [&b"deposit:"[..], deposit_id.as_ref()].concat()
+}
+
+/// Identifier the L2 credits the `nonce`-th bridge deposit under
+pub fn deposit_id(nonce: u64) -> [u8; 32] {
+    hashv(&[b"oasis:deposit", &nonce.to_le_bytes()]).to_bytes()
+}
+
+/// Receipt `bridge_program` keeps of its `nonce`-th deposit
+pub fn deposit_receipt_address(bridge_program: &Pubkey, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"deposit", &nonce.to_le_bytes()], bridge_program).0
+}
+
+/// Whether `receipt` records the deposit of `amount` of `token` to
+/// `recipient`. The bridge's `DepositReceipt` is read field by field, since
+/// this program cannot depend on the bridge.
+fn receipt_backs(
+    receipt: &AccountInfo,
+    bridge_program: &Pubkey,
+    nonce: u64,
+    recipient: &[u8; 32],
+    token: &[u8; 32],
+    amount: u64,
+) -> bool {
+    if receipt.owner != bridge_program {
+        return false;
+    }
+    let data = receipt.data.borrow();
+    let discriminator = &hashv(&[b"account:DepositReceipt"]).to_bytes()[..8];
+    if data.len() < 8 + 32 + 32 + 8 + 32 + 8 || &data[..8] != discriminator {
+        return false;
+    }
+    // depositor, mint, amount, l2_recipient, nonce
+    let mint = Pubkey::new_from_array(data[40..72].try_into().unwrap());
+    token_id(&mint) == *token
+        && data[72..80] == amount.to_le_bytes()
+        && data[80..112] == recipient[..]
+        && data[112..120] == nonce.to_le_bytes()
+}
+
+/// State key marking a withdrawal as made, holding its amount
+pub fn withdrawal_key(withdrawal_hash: &[u8; 32]) -> Vec<u8> {
+    [&b"withdrawal:"[..], withdrawal_hash.as_ref()].concat()
+}
+
+/// Identifier of an L2 withdrawal, as computed by the node
+pub fn withdrawal_hash(
+    from: &[u8; 32],
+    recipient: &[u8; 32],
+    token: &[u8; 32],
+    amount: u64,
+    nonce: u64,
+) -> [u8; 32] {
+    hashv(&[
+        b"oasis:withdrawal",
+        from,
+        recipient,
+        token,
+        &amount.to_le_bytes(),
+        &nonce.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// State keys the transaction reads or writes, as listed by the node
+fn access_list(transaction: &L2Transaction) -> Vec<Vec<u8>> {
+    match transaction {
+        L2Transaction::Transfer { from, to, .. } => vec![balance_key(from), balance_key(to)],
+        L2Transaction::Deposit {
+            nonce,
+            recipient,
+            token,
+            ..
+        } => vec![
+            deposit_key(&deposit_id(*nonce)),
+            token_balance_key(token, recipient),
+        ],
+        L2Transaction::Forced {
+            index, transaction, ..
+        } => {
+            let mut keys = vec![forced_key(*index)];
+            if let Some(inner) = decode_transaction(transaction) {
+                keys.extend(access_list(&inner));
+            }
+            keys
+        }
+        L2Transaction::Withdraw {
+            from,
+            recipient,
+            token,
+            amount,
+            nonce,
+        } => vec![
+            token_balance_key(&token_id(&Pubkey::new_from_array(*token)), from),
+            withdrawal_key(&withdrawal_hash(from, recipient, token, *amount, *nonce)),
+        ],
+    }
+}
+
+fn read_balance(
+    read: &dyn Fn(&[u8]) -> Option<Vec<u8>>,
+    key: &[u8],
+) -> core::result::Result<u64, ()> {
+    match read(key) {
+        Some(data) => Ok(u64::from_le_bytes(
+            data.get(..8).ok_or(())?.try_into().map_err(|_| ())?,
+        )),
+        None => Ok(0),
+    }
+}
+
+/// State keys a transaction writes, with their new values
+type Writes = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Port of the node's transaction executor: the writes of `transaction`, or
+/// `Err` if the transaction is invalid and its block could not be produced
+fn execute(
+    transaction: &L2Transaction,
+    read: &dyn Fn(&[u8]) -> Option<Vec<u8>>,
+) -> core::result::Result<Writes, ()> {
+    match transaction {
+        L2Transaction::Transfer { from, to, amount } => {
+            // Crediting the balance read before the debit would mint `amount`
+            if from == to {
+                return Err(());
+            }
+            let from_key = balance_key(from);
+            let from_balance = read_balance(read, &from_key)?;
+            if from_balance < *amount {
+                return Err(());
+            }
+            let to_key = balance_key(to);
+            let credited = read_balance(read, &to_key)?
+                .checked_add(*amount)
+                .ok_or(())?;
+
+            Ok(vec![
+                (from_key, (from_balance - amount).to_le_bytes().to_vec()),
+                (to_key, credited.to_le_bytes().to_vec()),
+            ])
+        }
+        L2Transaction::Deposit {
+            nonce,
+            recipient,
+            token,
+            amount,
+        } => {
+            let marker = deposit_key(&deposit_id(*nonce));
+            if read(&marker).is_some() {
+                return Err(());
+            }
+            let to_key = token_balance_key(token, recipient);
+            let credited = read_balance(read, &to_key)?
+                .checked_add(*amount)
+                .ok_or(())?;
+
+            Ok(vec![
+                (marker, amount.to_le_bytes().to_vec()),
+                (to_key, credited.to_le_bytes().to_vec()),
+            ])
+        }
+        L2Transaction::Forced {
+            index,
+            sender,
+            transaction,
+        } => {
+            let marker = forced_key(*index);
+            if read(&marker).is_some() {
+                return Err(());
+            }
+
+            let result = match decode_transaction(transaction) {
+                Some(
+                    inner @ (L2Transaction::Transfer { from, .. }
+                    | L2Transaction::Withdraw { from, .. }),
+                ) if from == *sender => execute(&inner, read),
+                _ => Err(()),
+            };
+            match result {
+                Ok(mut writes) => {
+                    writes.insert(0, (marker, vec![1]));
+                    Ok(writes)
+                }
+                Err(()) => Ok(vec![(marker, vec![0])]),
+            }
+        }
+        L2Transaction::Withdraw {
+            from,
+            recipient,
+            token,
+            amount,
+            nonce,
+        } => {
+            let marker = withdrawal_key(&withdrawal_hash(from, recipient, token, *amount, *nonce));
+            if read(&marker).is_some() {
+                return Err(());
+            }
+            let from_key = token_balance_key(&token_id(&Pubkey::new_from_array(*token)), from);
+            let from_balance = read_balance(read, &from_key)?;
+            if from_balance < *amount {
+                return Err(());
+            }
+
+            Ok(vec![
+                (from_key, (from_balance - amount).to_le_bytes().to_vec()),
+                (marker, amount.to_le_bytes().to_vec()),
+            ])
+        }
+    }
+}
+
+#[event]
+pub struct DisputeOpened {
+    pub batch_index: u64,
+    pub asserter: Pubkey,
+    pub challenger: Pubkey,
+    pub transaction_count: u64,
+    pub deadline: i64,
+}
+
+#[event]
+pub struct DisputeMoved {
+    pub batch_index: u64,
+    pub agreed_step: u64,
+    pub disputed_step: u64,
+    pub midpoint_root: [u8; 32],
+    pub deadline: i64,
+}
+
+#[event]
+pub struct DisputeResolved {
+    pub batch_index: u64,
+    pub challenger_won: bool,
+    /// Whether the loser missed a deadline rather than being proven wrong
+    pub timed_out: bool,
+}
+
+#[event]
+pub struct StaleDisputeSettled {
+    pub batch_index: u64,
+    pub asserter: Pubkey,
+    pub challenger: Pubkey,
+}
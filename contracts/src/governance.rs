@@ -0,0 +1,344 @@
+use anchor_lang::prelude::*;
+
+use crate::{OasisError, StateAccount, MAX_BPS, MAX_VALIDATORS};
+
+/// Parameters a governance proposal changes; `None` leaves one as it is
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParameterUpdate {
+    pub challenge_period: Option<i64>,
+    pub min_stake: Option<u64>,
+    pub forced_inclusion_slots: Option<u64>,
+    pub dispute_move_timeout: Option<i64>,
+    pub slash_reward_bps: Option<u16>,
+    pub unbonding_period: Option<i64>,
+    pub leader_timeout: Option<i64>,
+    pub reward_epoch_length: Option<i64>,
+    pub governance_delay: Option<i64>,
+    pub governance_threshold: Option<u8>,
+    pub dispute_bond: Option<u64>,
+}
+
+impl ParameterUpdate {
+    pub const LEN: usize = 9 + // challenge_period
+        9 + // min_stake
+        9 + // forced_inclusion_slots
+        9 + // dispute_move_timeout
+        3 + // slash_reward_bps
+        9 + // unbonding_period
+        9 + // leader_timeout
+        9 + // reward_epoch_length
+        9 + // governance_delay
+        2 + // governance_threshold
+        9; // dispute_bond
+
+    /// Write the changed parameters to `state`, rejecting combinations the
+    /// program could not run with
+    pub fn apply(&self, state: &mut StateAccount) -> Result<()> {
+        if let Some(challenge_period) = self.challenge_period {
+            state.challenge_period = challenge_period;
+        }
+        if let Some(min_stake) = self.min_stake {
+            state.min_stake = min_stake;
+        }
+        if let Some(forced_inclusion_slots) = self.forced_inclusion_slots {
+            state.forced_inclusion_slots = forced_inclusion_slots;
+        }
+        if let Some(dispute_move_timeout) = self.dispute_move_timeout {
+            state.dispute_move_timeout = dispute_move_timeout;
+        }
+        if let Some(slash_reward_bps) = self.slash_reward_bps {
+            state.slash_reward_bps = slash_reward_bps;
+        }
+        if let Some(unbonding_period) = self.unbonding_period {
+            state.unbonding_period = unbonding_period;
+        }
+        if let Some(leader_timeout) = self.leader_timeout {
+            state.leader_timeout = leader_timeout;
+        }
+        if let Some(reward_epoch_length) = self.reward_epoch_length {
+            state.reward_epoch_length = reward_epoch_length;
+        }
+        if let Some(governance_delay) = self.governance_delay {
+            state.governance_delay = governance_delay;
+        }
+        if let Some(governance_threshold) = self.governance_threshold {
+            state.governance_threshold = governance_threshold;
+        }
+        if let Some(dispute_bond) = self.dispute_bond {
+            state.dispute_bond = dispute_bond;
+        }
+
+        require!(
+            state.challenge_period > 0,
+            OasisError::InvalidChallengePeriod
+        );
+        require!(
+            state.dispute_move_timeout > 0,
+            OasisError::InvalidMoveTimeout
+        );
+        require!(
+            state.slash_reward_bps <= MAX_BPS,
+            OasisError::InvalidRewardShare
+        );
+        require!(
+            state.unbonding_period >= state.challenge_period,
+            OasisError::InvalidUnbondingPeriod
+        );
+        require!(state.leader_timeout > 0, OasisError::InvalidLeaderTimeout);
+        require!(
+            state.reward_epoch_length > 0,
+            OasisError::InvalidEpochLength
+        );
+        require!(
+            state.governance_delay >= 0,
+            OasisError::InvalidGovernanceDelay
+        );
+        require!(
+            state.governance_threshold as usize <= MAX_VALIDATORS,
+            OasisError::InvalidGovernanceThreshold
+        );
+        require!(state.dispute_bond > 0, OasisError::InvalidDisputeBond);
+        Ok(())
+    }
+}
+
+/// Parameter change waiting for its timelock and validator approvals
+#[account]
+pub struct GovernanceProposal {
+    pub state: Pubkey,
+    pub id: u64,
+    pub update: ParameterUpdate,
+    /// Earliest time the proposal can be executed
+    pub executable_at: i64,
+    /// Validators that approved the proposal
+    pub approvals: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl GovernanceProposal {
+    pub const LEN: usize = 32 + // state
+        8 + // id
+        ParameterUpdate::LEN + // update
+        8 + // executable_at
+        4 + (32 * MAX_VALIDATORS) + // approvals
+        1; // bump
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(mut, has_one = authority @ OasisError::Unauthorized)]
+    pub state: Account<'info, StateAccount>,
+    pub authority: Signer<'info>,
+}
+
+impl<'info> ProposeAuthority<'info> {
+    /// Offer the authority to `new_authority`, which has to accept it. A new
+    /// offer replaces the previous one; the default key withdraws it.
+    pub fn propose(&mut self, new_authority: Pubkey) -> Result<()> {
+        self.state.pending_authority = new_authority;
+        emit!(AuthorityTransferProposed {
+            authority: self.state.authority,
+            pending_authority: new_authority,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        constraint = state.pending_authority == new_authority.key() @ OasisError::NotPendingAuthority
+    )]
+    pub state: Account<'info, StateAccount>,
+    pub new_authority: Signer<'info>,
+}
+
+impl<'info> AcceptAuthority<'info> {
+    pub fn accept(&mut self) -> Result<()> {
+        let state = &mut self.state;
+        let previous_authority = state.authority;
+        state.authority = state.pending_authority;
+        state.pending_authority = Pubkey::default();
+        emit!(AuthorityTransferred {
+            previous_authority,
+            new_authority: state.authority,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ProposeParameters<'info> {
+    #[account(mut, has_one = authority @ OasisError::Unauthorized)]
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GovernanceProposal::LEN,
+        seeds = [b"proposal", state.key().as_ref(), &state.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, GovernanceProposal>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ProposeParameters<'info> {
+    /// Queue `update` behind the governance timelock
+    pub fn propose(&mut self, update: ParameterUpdate, bump: u8) -> Result<()> {
+        require!(
+            update != ParameterUpdate::default(),
+            OasisError::EmptyProposal
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let state = &mut self.state;
+        let proposal = &mut self.proposal;
+        proposal.state = state.key();
+        proposal.id = state.proposal_count;
+        proposal.update = update;
+        proposal.executable_at = now
+            .checked_add(state.governance_delay)
+            .ok_or(OasisError::InvalidGovernanceDelay)?;
+        proposal.approvals = Vec::new();
+        proposal.bump = bump;
+        state.proposal_count += 1;
+
+        emit!(ProposalCreated {
+            id: proposal.id,
+            update,
+            executable_at: proposal.executable_at,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        seeds = [b"proposal", state.key().as_ref(), &proposal.id.to_le_bytes()],
+        bump = proposal.bump
+    )]
+    pub proposal: Account<'info, GovernanceProposal>,
+    pub validator: Signer<'info>,
+}
+
+impl<'info> ApproveProposal<'info> {
+    pub fn approve(&mut self) -> Result<()> {
+        let validator = self.validator.key();
+        require!(
+            self.state.validators.contains(&validator),
+            OasisError::UnauthorizedValidator
+        );
+        let proposal = &mut self.proposal;
+        require!(
+            !proposal.approvals.contains(&validator),
+            OasisError::AlreadyApproved
+        );
+        proposal.approvals.push(validator);
+
+        emit!(ProposalApproved {
+            id: proposal.id,
+            validator,
+            approvals: proposal.approvals.len() as u32,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ResolveProposal<'info> {
+    #[account(mut, has_one = authority @ OasisError::Unauthorized)]
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        close = authority,
+        seeds = [b"proposal", state.key().as_ref(), &proposal.id.to_le_bytes()],
+        bump = proposal.bump
+    )]
+    pub proposal: Account<'info, GovernanceProposal>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+impl<'info> ResolveProposal<'info> {
+    /// Apply a proposal whose timelock expired, once enough current
+    /// validators approved it
+    pub fn execute(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let proposal = &self.proposal;
+        require!(now >= proposal.executable_at, OasisError::ProposalNotReady);
+
+        // Approvals of validators that left since do not count
+        let state = &mut self.state;
+        let approvals = proposal
+            .approvals
+            .iter()
+            .filter(|validator| state.validators.contains(validator))
+            .count();
+        require!(
+            approvals >= state.governance_threshold as usize,
+            OasisError::ApprovalsNotReached
+        );
+
+        proposal.update.apply(state)?;
+        emit!(ProposalExecuted {
+            id: proposal.id,
+            update: proposal.update,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    /// Drop a proposal without applying it
+    pub fn cancel(&mut self) -> Result<()> {
+        emit!(ProposalCancelled {
+            id: self.proposal.id,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct AuthorityTransferProposed {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub id: u64,
+    pub update: ParameterUpdate,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct ProposalApproved {
+    pub id: u64,
+    pub validator: Pubkey,
+    pub approvals: u32,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub id: u64,
+    pub update: ParameterUpdate,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalCancelled {
+    pub id: u64,
+}
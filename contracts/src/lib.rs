@@ -1,11 +1,28 @@
+// Every instruction returns Anchor's `Error`, which is large by design
+#![allow(clippy::result_large_err)]
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::sysvar;
 use anchor_spl::token::{self, Token, TokenAccount};
-use solana_program::{
-    program_error::ProgramError,
-    pubkey::Pubkey,
-};
+use solana_program::pubkey::Pubkey;
+
+pub mod commitment;
+pub mod dispute;
+pub mod governance;
+pub mod rewards;
+pub mod stake;
+
+pub use commitment::*;
+pub use dispute::*;
+pub use governance::*;
+pub use rewards::*;
+pub use stake::*;
 
-declare_id!("Oasis111111111111111111111111111111111111111");
+declare_id!("oasis11111111111111111111111111111111111111");
+
+/// Basis points in 100%
+pub const MAX_BPS: u16 = 10_000;
 
 #[program]
 pub mod solana_oasis {
@@ -15,39 +32,191 @@ pub mod solana_oasis {
         let state = &mut ctx.accounts.state;
         state.authority = ctx.accounts.authority.key();
         state.validators = Vec::new();
-        state.state_root = [0u8; 32];
+        state.state_root = params.genesis_root;
         state.last_update = Clock::get()?.unix_timestamp;
+        require!(
+            params.challenge_period > 0,
+            OasisError::InvalidChallengePeriod
+        );
         state.challenge_period = params.challenge_period;
         state.min_stake = params.min_stake;
+        state.forced_inclusion_slots = params.forced_inclusion_slots;
+        state.forced_tx_count = 0;
+        state.batch_count = 0;
+        state.genesis_root = params.genesis_root;
+        require!(
+            params.dispute_move_timeout > 0,
+            OasisError::InvalidMoveTimeout
+        );
+        state.dispute_move_timeout = params.dispute_move_timeout;
+        require!(
+            params.slash_reward_bps <= MAX_BPS,
+            OasisError::InvalidRewardShare
+        );
+        require!(
+            params.unbonding_period >= params.challenge_period,
+            OasisError::InvalidUnbondingPeriod
+        );
+        state.unbonding_period = params.unbonding_period;
+        require!(params.leader_timeout > 0, OasisError::InvalidLeaderTimeout);
+        state.leader_timeout = params.leader_timeout;
+        state.stake_vault = ctx.accounts.stake_vault.key();
+        state.slash_reward_bps = params.slash_reward_bps;
+        state.revert_count = 0;
+        require!(
+            params.reward_epoch_length > 0,
+            OasisError::InvalidEpochLength
+        );
+        state.reward_vault = ctx.accounts.reward_vault.key();
+        state.reward_epoch_length = params.reward_epoch_length;
+        state.reward_epoch = 0;
+        state.reward_epoch_start = state.last_update;
+        state.total_stake = 0;
+        state.reward_per_stake = 0;
+        state.forfeited_rewards = 0;
+        require!(
+            params.governance_delay >= 0,
+            OasisError::InvalidGovernanceDelay
+        );
+        require!(
+            params.governance_threshold as usize <= MAX_VALIDATORS,
+            OasisError::InvalidGovernanceThreshold
+        );
+        state.pending_authority = Pubkey::default();
+        state.governance_delay = params.governance_delay;
+        state.governance_threshold = params.governance_threshold;
+        state.proposal_count = 0;
+        state.bridge_program = params.bridge_program;
+        require!(params.dispute_bond > 0, OasisError::InvalidDisputeBond);
+        state.dispute_bond = params.dispute_bond;
         Ok(())
     }
 
+    /// Advance the state root by one batch. The submitting validator counts
+    /// towards the quorum; other validators sign `commitment.message` in Ed25519
+    /// instructions placed earlier in the same transaction.
     pub fn update_state_root(
         ctx: Context<UpdateStateRoot>,
-        new_root: [u8; 32],
-        proof: Vec<u8>,
+        commitment: BatchCommitment,
+        data: Vec<u8>,
     ) -> Result<()> {
         let state = &mut ctx.accounts.state;
         let clock = Clock::get()?;
+        let validator = ctx.accounts.validator.key();
 
         // Verify the validator is authorized
         require!(
-            state.validators.contains(&ctx.accounts.validator.key()),
+            state.validators.contains(&validator),
             OasisError::UnauthorizedValidator
         );
+        require!(
+            state.leader(clock.unix_timestamp) == Some(validator),
+            OasisError::NotLeader
+        );
+
+        // The batch must build on the current root and carry the data it claims
+        require!(
+            commitment.previous_root == state.state_root,
+            OasisError::PreviousRootMismatch
+        );
+        require!(
+            hashv(&[&data]).to_bytes() == commitment.data_hash,
+            OasisError::DataHashMismatch
+        );
+
+        let batch_index = state.batch_count;
+        let message = commitment.message(&state.key(), batch_index);
+        let mut signers = vec![validator];
+        for signer in verified_signers(&ctx.accounts.instructions, &message)? {
+            if state.validators.contains(&signer) && !signers.contains(&signer) {
+                signers.push(signer);
+            }
+        }
+        require!(
+            signers.len() >= quorum(state.validators.len()),
+            OasisError::QuorumNotReached
+        );
 
-        // Verify the proof
-        // TODO: Implement proof verification
-        
         // Update state root
-        state.state_root = new_root;
+        let previous_root = state.state_root;
+        state.state_root = commitment.new_root;
         state.last_update = clock.unix_timestamp;
 
+        // Record the root so it can be referenced once its challenge window ends
+        let record = &mut ctx.accounts.root_record;
+        record.state = state.key();
+        record.batch_index = batch_index;
+        record.state_root = commitment.new_root;
+        record.validator = validator;
+        record.submitted_at = clock.unix_timestamp;
+        record.transactions_root = commitment.transactions_root;
+        record.transaction_count = commitment.transaction_count;
+        record.previous_root = previous_root;
+        record.data_hash = commitment.data_hash;
+        record.open_disputes = 0;
+        record.revert_count = state.revert_count;
+        record.bump = *ctx.bumps.get("root_record").unwrap();
+        state.batch_count = state
+            .batch_count
+            .checked_add(1)
+            .ok_or(OasisError::InvalidStateProof)?;
+
         emit!(StateRootUpdated {
-            previous_root: state.state_root,
-            new_root,
+            previous_root,
+            new_root: commitment.new_root,
             timestamp: clock.unix_timestamp,
-            validator: ctx.accounts.validator.key(),
+            validator,
+            batch_index,
+            transactions_root: commitment.transactions_root,
+            transaction_count: commitment.transaction_count,
+        });
+
+        Ok(())
+    }
+
+    /// Post one chunk of a batch too large for `update_state_root`. The data is
+    /// only kept in the ledger, where validators read it back.
+    pub fn post_batch_data(ctx: Context<PostBatchData>, data: Vec<u8>) -> Result<()> {
+        require!(
+            ctx.accounts.state.validators.contains(&ctx.accounts.validator.key()),
+            OasisError::UnauthorizedValidator
+        );
+        require!(!data.is_empty(), OasisError::EmptyBatchData);
+
+        Ok(())
+    }
+
+    /// Queue an L2 transaction on L1. The sequencer must include it in a batch
+    /// posted within `forced_inclusion_slots`, otherwise that batch is invalid.
+    pub fn force_transaction(ctx: Context<ForceTransaction>, transaction: Vec<u8>) -> Result<()> {
+        require!(
+            !transaction.is_empty() && transaction.len() <= ForcedTransaction::MAX_TRANSACTION_LEN,
+            OasisError::InvalidForcedTransaction
+        );
+
+        let state = &mut ctx.accounts.state;
+        let clock = Clock::get()?;
+        let deadline_slot = clock
+            .slot
+            .checked_add(state.forced_inclusion_slots)
+            .ok_or(OasisError::InvalidForcedTransaction)?;
+
+        let forced = &mut ctx.accounts.forced_transaction;
+        forced.index = state.forced_tx_count;
+        forced.sender = ctx.accounts.sender.key();
+        forced.transaction = transaction.clone();
+        forced.slot = clock.slot;
+        forced.deadline_slot = deadline_slot;
+        forced.bump = *ctx.bumps.get("forced_transaction").unwrap();
+
+        state.forced_tx_count += 1;
+
+        emit!(ForcedTransactionQueued {
+            index: forced.index,
+            sender: forced.sender,
+            transaction,
+            slot: forced.slot,
+            deadline_slot,
         });
 
         Ok(())
@@ -64,6 +233,14 @@ pub mod solana_oasis {
             stake_amount >= state.min_stake,
             OasisError::InsufficientStake
         );
+        require!(
+            !state.validators.contains(&ctx.accounts.validator.key()),
+            OasisError::AlreadyRegistered
+        );
+        require!(
+            state.validators.len() < MAX_VALIDATORS,
+            OasisError::TooManyValidators
+        );
 
         // Transfer stake
         let cpi_accounts = token::Transfer {
@@ -77,6 +254,21 @@ pub mod solana_oasis {
 
         // Register validator
         state.validators.push(ctx.accounts.validator.key());
+        let stake = &mut ctx.accounts.validator_stake;
+        stake.state = state.key();
+        stake.validator = ctx.accounts.validator.key();
+        stake.amount = stake_amount;
+        stake.unbonds_at = 0;
+        stake.open_disputes = 0;
+        stake.bump = *ctx.bumps.get("validator_stake").unwrap();
+        stake.reward_checkpoint = state.reward_per_stake;
+        stake.unclaimed_rewards = 0;
+        stake.claimed_rewards = 0;
+        stake.revert_count = state.revert_count;
+        state.total_stake = state
+            .total_stake
+            .checked_add(stake_amount)
+            .ok_or(OasisError::InsufficientStake)?;
 
         emit!(ValidatorRegistered {
             validator: ctx.accounts.validator.key(),
@@ -87,32 +279,104 @@ pub mod solana_oasis {
         Ok(())
     }
 
-    pub fn submit_fraud_proof(
-        ctx: Context<SubmitFraudProof>,
-        proof_data: Vec<u8>,
+    pub fn add_stake(ctx: Context<AddStake>, amount: u64) -> Result<()> {
+        ctx.accounts.add(amount)
+    }
+
+    /// Leave the validator set and start the unbonding delay
+    pub fn request_unbond(ctx: Context<RequestUnbond>) -> Result<()> {
+        ctx.accounts.request()
+    }
+
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+        let vault_bump = *ctx.bumps.get("vault_authority").unwrap();
+        ctx.accounts.withdraw(vault_bump)
+    }
+
+    /// Close the reward epoch by settling the fees collected on L2 during it
+    pub fn settle_rewards(ctx: Context<SettleRewards>, fees: u64) -> Result<()> {
+        ctx.accounts.settle(fees)
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let vault_bump = *ctx.bumps.get("vault_authority").unwrap();
+        ctx.accounts.claim(vault_bump)
+    }
+
+    /// First step of an authority transfer; `new_authority` must accept
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        ctx.accounts.propose(new_authority)
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        ctx.accounts.accept()
+    }
+
+    /// Queue a parameter change, executable after `governance_delay` seconds
+    /// and `governance_threshold` validator approvals
+    pub fn propose_parameters(
+        ctx: Context<ProposeParameters>,
+        update: ParameterUpdate,
     ) -> Result<()> {
-        let state = &ctx.accounts.state;
-        let clock = Clock::get()?;
+        let bump = *ctx.bumps.get("proposal").unwrap();
+        ctx.accounts.propose(update, bump)
+    }
 
-        // Verify the challenge period hasn't expired
-        require!(
-            clock.unix_timestamp <= state.last_update + state.challenge_period,
-            OasisError::ChallengePeriodExpired
-        );
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        ctx.accounts.approve()
+    }
 
-        // Verify the proof
-        // TODO: Implement fraud proof verification
+    pub fn execute_proposal(ctx: Context<ResolveProposal>) -> Result<()> {
+        ctx.accounts.execute()
+    }
 
-        // If valid, slash the validator and revert state
-        // TODO: Implement slashing and state reversion
+    pub fn cancel_proposal(ctx: Context<ResolveProposal>) -> Result<()> {
+        ctx.accounts.cancel()
+    }
 
-        emit!(FraudProofSubmitted {
-            challenger: ctx.accounts.challenger.key(),
-            proof_data,
-            timestamp: clock.unix_timestamp,
-        });
+    /// Challenge a posted root within its challenge window by opening a
+    /// bisection game against the validator that posted it
+    pub fn open_dispute(ctx: Context<OpenDispute>, batch_index: u64) -> Result<()> {
+        let bump = *ctx.bumps.get("dispute").unwrap();
+        ctx.accounts.open(batch_index, bump)
+    }
 
-        Ok(())
+    pub fn bisect_dispute(ctx: Context<BisectDispute>, midpoint_root: [u8; 32]) -> Result<()> {
+        ctx.accounts.bisect(midpoint_root)
+    }
+
+    pub fn respond_dispute(ctx: Context<RespondDispute>, agree: bool) -> Result<()> {
+        ctx.accounts.respond(agree)
+    }
+
+    pub fn open_step_proof(ctx: Context<OpenStepProof>) -> Result<()> {
+        let bump = *ctx.bumps.get("step_proof").unwrap();
+        ctx.accounts.open(bump)
+    }
+
+    pub fn write_step_proof(ctx: Context<WriteStepProof>, data: Vec<u8>) -> Result<()> {
+        ctx.accounts.write(data)
+    }
+
+    pub fn prove_step(ctx: Context<ProveStep>) -> Result<()> {
+        ctx.accounts.prove(ctx.remaining_accounts.first())
+    }
+
+    pub fn timeout_dispute(ctx: Context<TimeoutDispute>) -> Result<()> {
+        ctx.accounts.timeout()
+    }
+
+    /// Enforce a dispute the challenger won: slash the asserter, reward the
+    /// challenger and roll the state root back to before the disputed batch
+    pub fn submit_fraud_proof(ctx: Context<SubmitFraudProof>) -> Result<()> {
+        let vault_bump = *ctx.bumps.get("vault_authority").unwrap();
+        ctx.accounts.slash(vault_bump)
+    }
+
+    /// Close a dispute over a root a fraud proof reverted, releasing the
+    /// asserter's stake if the dispute still held it back
+    pub fn settle_stale_dispute(ctx: Context<SettleStaleDispute>) -> Result<()> {
+        ctx.accounts.settle()
     }
 }
 
@@ -124,6 +388,15 @@ pub struct Initialize<'info> {
         space = 8 + StateAccount::LEN
     )]
     pub state: Account<'info, StateAccount>,
+    /// Token account holding every validator's stake
+    #[account(constraint = stake_vault.owner == vault_authority.key() @ OasisError::InvalidStakeVault)]
+    pub stake_vault: Account<'info, TokenAccount>,
+    /// Token account validator rewards are paid from
+    #[account(constraint = reward_vault.owner == vault_authority.key() @ OasisError::InvalidRewardVault)]
+    pub reward_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA owning the stake and reward vaults, never read
+    #[account(seeds = [b"vault", state.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
@@ -133,26 +406,66 @@ pub struct Initialize<'info> {
 pub struct UpdateStateRoot<'info> {
     #[account(mut)]
     pub state: Account<'info, StateAccount>,
+    /// Already exists when a fraud proof rolled `batch_count` back over it
+    #[account(
+        init_if_needed,
+        payer = validator,
+        space = 8 + StateRootRecord::LEN,
+        seeds = [b"root", state.key().as_ref(), &state.batch_count.to_le_bytes()],
+        bump
+    )]
+    pub root_record: Account<'info, StateRootRecord>,
+    #[account(mut)]
     pub validator: Signer<'info>,
+    /// CHECK: address is checked; read for the validators' Ed25519 signatures
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct RegisterValidator<'info> {
-    #[account(mut)]
+pub struct PostBatchData<'info> {
     pub state: Account<'info, StateAccount>,
     pub validator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ForceTransaction<'info> {
     #[account(mut)]
-    pub stake_from: Account<'info, TokenAccount>,
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + ForcedTransaction::LEN,
+        seeds = [b"forced", state.key().as_ref(), &state.forced_tx_count.to_le_bytes()],
+        bump
+    )]
+    pub forced_transaction: Account<'info, ForcedTransaction>,
     #[account(mut)]
-    pub stake_vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub sender: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct SubmitFraudProof<'info> {
+pub struct RegisterValidator<'info> {
     #[account(mut)]
     pub state: Account<'info, StateAccount>,
-    pub challenger: Signer<'info>,
+    #[account(
+        init,
+        payer = validator,
+        space = 8 + ValidatorStake::LEN,
+        seeds = [b"stake", state.key().as_ref(), validator.key().as_ref()],
+        bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    #[account(mut)]
+    pub validator: Signer<'info>,
+    #[account(mut)]
+    pub stake_from: Account<'info, TokenAccount>,
+    #[account(mut, address = state.stake_vault)]
+    pub stake_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[account]
@@ -163,6 +476,59 @@ pub struct StateAccount {
     pub last_update: i64,
     pub challenge_period: i64,
     pub min_stake: u64,
+    /// L1 slots the sequencer has to include a forced transaction
+    pub forced_inclusion_slots: u64,
+    /// Number of transactions ever queued for forced inclusion
+    pub forced_tx_count: u64,
+    /// Number of state roots posted; the batch index of the next root
+    pub batch_count: u64,
+    /// Root of the L2 genesis state, the pre-state of the first batch
+    pub genesis_root: [u8; 32],
+    /// Seconds each party of a dispute has to make its move
+    pub dispute_move_timeout: i64,
+    /// Token account holding the stake, owned by the `[b"vault", state]` PDA
+    pub stake_vault: Pubkey,
+    /// Share of a slashed stake paid to the challenger, in basis points; the
+    /// rest is burned
+    pub slash_reward_bps: u16,
+    /// Seconds between leaving the validator set and withdrawing stake, at
+    /// least `challenge_period`
+    pub unbonding_period: i64,
+    /// Seconds the leader of the next batch has before the following
+    /// validator may post it instead
+    pub leader_timeout: i64,
+    /// Number of fraud proofs that rolled the root back. Batch indices from the
+    /// reverted one on are reused, so disputes are scoped to this count.
+    pub revert_count: u64,
+    /// Token account holding settled fees until validators claim them
+    pub reward_vault: Pubkey,
+    /// Minimum seconds between two reward settlements
+    pub reward_epoch_length: i64,
+    /// Number of reward epochs settled so far
+    pub reward_epoch: u64,
+    /// Start of the current reward epoch
+    pub reward_epoch_start: i64,
+    /// Stake of the bonded validators, which shares each epoch's rewards
+    pub total_stake: u64,
+    /// Rewards settled per unit of bonded stake since initialization, scaled
+    /// by `REWARD_PRECISION`
+    pub reward_per_stake: u128,
+    /// Unclaimed rewards of slashed validators, shared at the next settlement
+    pub forfeited_rewards: u64,
+    /// Key offered the authority, or the default key if there is no offer
+    pub pending_authority: Pubkey,
+    /// Seconds a parameter proposal waits before it can be executed
+    pub governance_delay: i64,
+    /// Validator approvals a parameter proposal needs; zero disables the
+    /// multisig
+    pub governance_threshold: u8,
+    /// Number of parameter proposals ever made; the id of the next one
+    pub proposal_count: u64,
+    /// `oasis_bridge` program whose deposit receipts back L2 deposits
+    pub bridge_program: Pubkey,
+    /// Lamports a challenger locks when opening a dispute, paid to the
+    /// asserter if the challenger loses
+    pub dispute_bond: u64,
 }
 
 impl StateAccount {
@@ -171,13 +537,139 @@ impl StateAccount {
         32 + // state_root
         8 + // last_update
         8 + // challenge_period
-        8; // min_stake
+        8 + // min_stake
+        8 + // forced_inclusion_slots
+        8 + // forced_tx_count
+        8 + // batch_count
+        32 + // genesis_root
+        8 + // dispute_move_timeout
+        32 + // stake_vault
+        2 + // slash_reward_bps
+        8 + // unbonding_period
+        8 + // leader_timeout
+        8 + // revert_count
+        32 + // reward_vault
+        8 + // reward_epoch_length
+        8 + // reward_epoch
+        8 + // reward_epoch_start
+        8 + // total_stake
+        16 + // reward_per_stake
+        8 + // forfeited_rewards
+        32 + // pending_authority
+        8 + // governance_delay
+        1 + // governance_threshold
+        8 + // proposal_count
+        32 + // bridge_program
+        8; // dispute_bond
+
+    /// Validator allowed to post the next batch at `now`.
+    ///
+    /// Leadership rotates round-robin by batch index, and passes to the next
+    /// validator for every `leader_timeout` seconds without a new root.
+    pub fn leader(&self, now: i64) -> Option<Pubkey> {
+        if self.validators.is_empty() || self.leader_timeout <= 0 {
+            return None;
+        }
+        let skipped = now.saturating_sub(self.last_update).max(0) / self.leader_timeout;
+        let turn = self.batch_count.wrapping_add(skipped as u64);
+        Some(self.validators[(turn % self.validators.len() as u64) as usize])
+    }
+}
+
+/// State root posted for one batch, kept so that withdrawals can be checked
+/// against roots that outlived their challenge window.
+///
+/// A fraud proof rolls `batch_count` back, so only records below it are part
+/// of the current chain; the others are overwritten as new batches are posted.
+#[account]
+pub struct StateRootRecord {
+    pub state: Pubkey,
+    pub batch_index: u64,
+    pub state_root: [u8; 32],
+    pub validator: Pubkey,
+    pub submitted_at: i64,
+    /// Merkle root of the batch's transactions, bisected over in disputes
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+    /// Root this batch was applied to, the root of batch `batch_index - 1`
+    pub previous_root: [u8; 32],
+    /// Hash of the batch data posted with the root
+    pub data_hash: [u8; 32],
+    /// Disputes not yet settled in the validator's favour; the root cannot
+    /// finalize while any is left
+    pub open_disputes: u32,
+    /// `StateAccount::revert_count` when the root was posted. A batch posted
+    /// again after a revert gets a new count, voiding disputes over the old one.
+    pub revert_count: u64,
+    pub bump: u8,
+}
+
+impl StateRootRecord {
+    pub const LEN: usize = 32 + // state
+        8 + // batch_index
+        32 + // state_root
+        32 + // validator
+        8 + // submitted_at
+        32 + // transactions_root
+        8 + // transaction_count
+        32 + // previous_root
+        32 + // data_hash
+        4 + // open_disputes
+        8 + // revert_count
+        1; // bump
+
+    /// Whether the challenge window has closed, so no dispute can be opened
+    pub fn challenge_period_ended(&self, challenge_period: i64, now: i64) -> bool {
+        self.submitted_at
+            .checked_add(challenge_period)
+            .is_some_and(|finalized_at| now >= finalized_at)
+    }
+
+    /// Whether the root can no longer be challenged or overturned
+    pub fn is_finalized(&self, challenge_period: i64, now: i64) -> bool {
+        self.open_disputes == 0 && self.challenge_period_ended(challenge_period, now)
+    }
+}
+
+/// L2 transaction queued by a user for inclusion without the sequencer's
+/// cooperation
+#[account]
+pub struct ForcedTransaction {
+    pub index: u64,
+    pub sender: Pubkey,
+    /// Bincode-encoded L2 transaction
+    pub transaction: Vec<u8>,
+    pub slot: u64,
+    pub deadline_slot: u64,
+    pub bump: u8,
+}
+
+impl ForcedTransaction {
+    pub const MAX_TRANSACTION_LEN: usize = 512;
+
+    pub const LEN: usize = 8 + // index
+        32 + // sender
+        4 + Self::MAX_TRANSACTION_LEN + // transaction
+        8 + // slot
+        8 + // deadline_slot
+        1; // bump
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct InitializeParams {
     pub challenge_period: i64,
     pub min_stake: u64,
+    pub forced_inclusion_slots: u64,
+    pub genesis_root: [u8; 32],
+    pub dispute_move_timeout: i64,
+    pub slash_reward_bps: u16,
+    pub unbonding_period: i64,
+    pub leader_timeout: i64,
+    pub reward_epoch_length: i64,
+    pub governance_delay: i64,
+    pub governance_threshold: u8,
+    pub bridge_program: Pubkey,
+    pub dispute_bond: u64,
 }
 
 #[event]
@@ -186,6 +678,19 @@ pub struct StateRootUpdated {
     pub new_root: [u8; 32],
     pub timestamp: i64,
     pub validator: Pubkey,
+    /// Index of the `StateRootRecord` holding the root
+    pub batch_index: u64,
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+}
+
+#[event]
+pub struct ForcedTransactionQueued {
+    pub index: u64,
+    pub sender: Pubkey,
+    pub transaction: Vec<u8>,
+    pub slot: u64,
+    pub deadline_slot: u64,
 }
 
 #[event]
@@ -198,7 +703,14 @@ pub struct ValidatorRegistered {
 #[event]
 pub struct FraudProofSubmitted {
     pub challenger: Pubkey,
-    pub proof_data: Vec<u8>,
+    /// Slashed and removed validator that posted the invalid root
+    pub validator: Pubkey,
+    /// Index of the invalid batch, the next batch to be posted
+    pub batch_index: u64,
+    /// Root the state was rolled back to
+    pub restored_root: [u8; 32],
+    pub slashed_amount: u64,
+    pub reward_amount: u64,
     pub timestamp: i64,
 }
 
@@ -214,4 +726,1275 @@ pub enum OasisError {
     InvalidStateProof,
     #[msg("Invalid fraud proof")]
     InvalidFraudProof,
-} 
\ No newline at end of file
+    #[msg("Forced transaction is empty or too large")]
+    InvalidForcedTransaction,
+    #[msg("Dispute is already resolved")]
+    DisputeNotActive,
+    #[msg("Dispute is waiting for the other party")]
+    NotYourTurn,
+    #[msg("Deadline for this dispute move has passed")]
+    MoveDeadlinePassed,
+    #[msg("Dispute move deadline has not passed yet")]
+    DeadlineNotReached,
+    #[msg("Invalid one-step proof")]
+    InvalidStepProof,
+    #[msg("Step proof is too large")]
+    StepProofTooLarge,
+    #[msg("Batch does not build on the current state root")]
+    PreviousRootMismatch,
+    #[msg("Batch data does not match the committed hash")]
+    DataHashMismatch,
+    #[msg("Malformed validator signature instruction")]
+    InvalidSignature,
+    #[msg("Not enough validators signed the batch commitment")]
+    QuorumNotReached,
+    #[msg("Stake vault is not owned by the program's vault authority")]
+    InvalidStakeVault,
+    #[msg("Challenger reward share exceeds 100%")]
+    InvalidRewardShare,
+    #[msg("Validator is already registered")]
+    AlreadyRegistered,
+    #[msg("Validator set is full")]
+    TooManyValidators,
+    #[msg("Unbonding period must cover the challenge period")]
+    InvalidUnbondingPeriod,
+    #[msg("Validator is unbonding")]
+    ValidatorUnbonding,
+    #[msg("Stake is still bonded")]
+    StakeStillBonded,
+    #[msg("Stake backs a root under dispute")]
+    StakeUnderDispute,
+    #[msg("Leader timeout must be positive")]
+    InvalidLeaderTimeout,
+    #[msg("Validator is not the leader for the next batch")]
+    NotLeader,
+    #[msg("Signer is not the state authority")]
+    Unauthorized,
+    #[msg("Reward vault is not owned by the program's vault authority")]
+    InvalidRewardVault,
+    #[msg("Reward epoch length must be positive")]
+    InvalidEpochLength,
+    #[msg("Reward epoch is not over yet")]
+    EpochNotOver,
+    #[msg("No bonded stake to reward")]
+    NoActiveStake,
+    #[msg("No rewards to claim")]
+    NoRewards,
+    #[msg("Rewards must be claimed before withdrawing")]
+    UnclaimedRewards,
+    #[msg("Reward amount overflows")]
+    RewardOverflow,
+    #[msg("Signer is not the pending authority")]
+    NotPendingAuthority,
+    #[msg("Governance delay must not be negative")]
+    InvalidGovernanceDelay,
+    #[msg("Governance threshold exceeds the maximum validator set size")]
+    InvalidGovernanceThreshold,
+    #[msg("Proposal changes no parameter")]
+    EmptyProposal,
+    #[msg("Validator already approved the proposal")]
+    AlreadyApproved,
+    #[msg("Proposal timelock has not expired")]
+    ProposalNotReady,
+    #[msg("Not enough validators approved the proposal")]
+    ApprovalsNotReached,
+    #[msg("Challenge period must be positive")]
+    InvalidChallengePeriod,
+    #[msg("Dispute move timeout must be positive")]
+    InvalidMoveTimeout,
+    #[msg("Batch data chunk is empty")]
+    EmptyBatchData,
+    #[msg("Root was reverted by a fraud proof")]
+    RootReverted,
+    #[msg("Dispute is over a root that is still part of the chain")]
+    DisputeNotStale,
+    #[msg("Dispute bond must be positive")]
+    InvalidDisputeBond,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::error::ErrorCode;
+    use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+    use anchor_spl::token::spl_token;
+    use solana_program::{
+        account_info::AccountInfo, ed25519_program, entrypoint::ProgramResult, program_pack::Pack,
+    };
+    use solana_program_test::{
+        processor, BanksClientError, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+    };
+    use solana_sdk::{
+        instruction::{Instruction, InstructionError},
+        signature::{Keypair, Signer},
+        system_instruction,
+        transaction::{Transaction, TransactionError},
+    };
+
+    /// Lamports a challenger locks per dispute
+    const DISPUTE_BOND: u64 = 10_000_000;
+
+    fn process_instruction(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> ProgramResult {
+        // Anchor's entry ties the account infos to the lifetime of the slice
+        let accounts = Box::leak(Box::new(accounts.to_vec()));
+        entry(program_id, accounts, data)
+    }
+
+    async fn start() -> ProgramTestContext {
+        let mut program_test =
+            ProgramTest::new("solana_oasis", ID, processor!(process_instruction));
+        // The bundled SPL Token build cannot run in this program-test version
+        program_test.add_program(
+            "spl_token",
+            spl_token::ID,
+            processor!(spl_token::processor::Processor::process),
+        );
+        program_test.start_with_context().await
+    }
+
+    async fn send(
+        context: &mut ProgramTestContext,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        // A fresh blockhash keeps a resent transaction from being answered
+        // with the result of an identical earlier one
+        let blockhash = context
+            .banks_client
+            .get_new_latest_blockhash(&context.last_blockhash)
+            .await?;
+        context.last_blockhash = blockhash;
+        let mut all_signers = vec![&context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    fn custom_error(error: BanksClientError) -> Option<u32> {
+        match error.unwrap() {
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    async fn account<T: AccountDeserialize>(
+        context: &mut ProgramTestContext,
+        address: Pubkey,
+    ) -> T {
+        let account = context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        T::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    async fn token_account(
+        context: &mut ProgramTestContext,
+        address: Pubkey,
+    ) -> spl_token::state::Account {
+        let account = context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap()
+    }
+
+    /// Create a mint owned by the payer
+    async fn create_mint(context: &mut ProgramTestContext) -> Pubkey {
+        let mint = Keypair::new();
+        let payer = context.payer.pubkey();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                6,
+            )
+            .unwrap(),
+        ];
+        send(context, &instructions, &[&mint]).await.unwrap();
+        mint.pubkey()
+    }
+
+    /// Create a token account of `mint` owned by `owner`, holding `amount`
+    async fn funded_account(
+        context: &mut ProgramTestContext,
+        mint: &Pubkey,
+        owner: &Pubkey,
+        amount: u64,
+    ) -> Pubkey {
+        let account = Keypair::new();
+        let payer = context.payer.pubkey();
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                rent.minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_account(
+                &spl_token::ID,
+                &account.pubkey(),
+                mint,
+                owner,
+            )
+            .unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::ID,
+                mint,
+                &account.pubkey(),
+                &payer,
+                &[],
+                amount,
+            )
+            .unwrap(),
+        ];
+        send(context, &instructions, &[&account]).await.unwrap();
+        account.pubkey()
+    }
+
+    /// A `solana_oasis` state and the token its validators stake and earn
+    struct Oasis {
+        state: Pubkey,
+        stake_mint: Pubkey,
+        stake_vault: Pubkey,
+        reward_vault: Pubkey,
+    }
+
+    async fn initialize(context: &mut ProgramTestContext, forced_inclusion_slots: u64) -> Oasis {
+        let state = Keypair::new();
+        let vault_authority =
+            Pubkey::find_program_address(&[b"vault", state.pubkey().as_ref()], &ID).0;
+        let stake_mint = create_mint(context).await;
+        let stake_vault = funded_account(context, &stake_mint, &vault_authority, 0).await;
+        let reward_vault = funded_account(context, &stake_mint, &vault_authority, 0).await;
+
+        let instruction = Instruction {
+            program_id: ID,
+            accounts: accounts::Initialize {
+                state: state.pubkey(),
+                stake_vault,
+                reward_vault,
+                vault_authority,
+                authority: context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Initialize {
+                params: InitializeParams {
+                    challenge_period: 3_600,
+                    min_stake: 1,
+                    forced_inclusion_slots,
+                    genesis_root: [0u8; 32],
+                    dispute_move_timeout: 3_600,
+                    slash_reward_bps: 2_500,
+                    unbonding_period: 7_200,
+                    leader_timeout: 600,
+                    reward_epoch_length: 3_600,
+                    governance_delay: 3_600,
+                    governance_threshold: 1,
+                    bridge_program: Pubkey::new_unique(),
+                    dispute_bond: DISPUTE_BOND,
+                },
+            }
+            .data(),
+        };
+        send(context, &[instruction], &[&state]).await.unwrap();
+        Oasis {
+            state: state.pubkey(),
+            stake_mint,
+            stake_vault,
+            reward_vault,
+        }
+    }
+
+    fn stake_address(state: &Pubkey, validator: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"stake", state.as_ref(), validator.as_ref()], &ID).0
+    }
+
+    /// A new validator holding `stake` of the stake token, registered with
+    /// all of it
+    async fn register_validator(
+        context: &mut ProgramTestContext,
+        oasis: &Oasis,
+        stake: u64,
+    ) -> Keypair {
+        let validator = Keypair::new();
+        let fund = system_instruction::transfer(
+            &context.payer.pubkey(),
+            &validator.pubkey(),
+            1_000_000_000,
+        );
+        send(context, &[fund], &[]).await.unwrap();
+        let stake_from =
+            funded_account(context, &oasis.stake_mint, &validator.pubkey(), stake).await;
+
+        let instruction = Instruction {
+            program_id: ID,
+            accounts: accounts::RegisterValidator {
+                state: oasis.state,
+                validator_stake: stake_address(&oasis.state, &validator.pubkey()),
+                validator: validator.pubkey(),
+                stake_from,
+                stake_vault: oasis.stake_vault,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::RegisterValidator {
+                stake_amount: stake,
+            }
+            .data(),
+        };
+        send(context, &[instruction], &[&validator]).await.unwrap();
+        validator
+    }
+
+    fn root_address(state: &Pubkey, batch_index: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"root", state.as_ref(), &batch_index.to_le_bytes()], &ID).0
+    }
+
+    /// Ed25519 precompile instruction checking each of `signers`' signature
+    /// over `message`, laid out the way `update_state_root` reads it
+    fn ed25519_signatures(signers: &[&Keypair], message: &[u8; 32]) -> Instruction {
+        const OFFSETS_LEN: usize = 14;
+        const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+        let header_len = 2 + signers.len() * OFFSETS_LEN;
+        let message_offset = header_len + signers.len() * (32 + 64);
+        let mut offsets = vec![signers.len() as u8, 0];
+        let mut keys_and_signatures = Vec::new();
+        for signer in signers {
+            let public_key_offset = header_len + keys_and_signatures.len();
+            keys_and_signatures.extend_from_slice(&signer.pubkey().to_bytes());
+            keys_and_signatures.extend_from_slice(signer.sign_message(message).as_ref());
+
+            for field in [
+                public_key_offset + 32,
+                CURRENT_INSTRUCTION as usize,
+                public_key_offset,
+                CURRENT_INSTRUCTION as usize,
+                message_offset,
+                message.len(),
+                CURRENT_INSTRUCTION as usize,
+            ] {
+                offsets.extend_from_slice(&(field as u16).to_le_bytes());
+            }
+        }
+
+        let data = [&offsets[..], &keys_and_signatures, message].concat();
+        Instruction::new_with_bytes(ed25519_program::ID, &data, vec![])
+    }
+
+    /// Post an empty batch from the current root to `new_root`, submitted by
+    /// the first of `signers` and signed by the others
+    async fn post_batch(
+        context: &mut ProgramTestContext,
+        oasis: &Oasis,
+        signers: &[&Keypair],
+        new_root: [u8; 32],
+    ) -> std::result::Result<(), BanksClientError> {
+        let state: StateAccount = account(context, oasis.state).await;
+        let data = vec![1u8];
+        let commitment = BatchCommitment {
+            previous_root: state.state_root,
+            new_root,
+            data_hash: hashv(&[&data]).to_bytes(),
+            transactions_root: [0u8; 32],
+            transaction_count: 0,
+        };
+        let mut instructions = Vec::new();
+        if signers.len() > 1 {
+            let message = commitment.message(&oasis.state, state.batch_count);
+            instructions.push(ed25519_signatures(&signers[1..], &message));
+        }
+        instructions.push(Instruction {
+            program_id: ID,
+            accounts: accounts::UpdateStateRoot {
+                state: oasis.state,
+                root_record: root_address(&oasis.state, state.batch_count),
+                validator: signers[0].pubkey(),
+                instructions: sysvar::instructions::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::UpdateStateRoot { commitment, data }.data(),
+        });
+        send(context, &instructions, &signers[..1]).await
+    }
+
+    fn dispute_address(
+        state: &Pubkey,
+        batch_index: u64,
+        revert_count: u64,
+        challenger: &Pubkey,
+    ) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"dispute",
+                state.as_ref(),
+                &batch_index.to_le_bytes(),
+                &revert_count.to_le_bytes(),
+                challenger.as_ref(),
+            ],
+            &ID,
+        )
+        .0
+    }
+
+    fn open_dispute(
+        oasis: &Oasis,
+        asserter: &Pubkey,
+        challenger: &Pubkey,
+        batch_index: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::OpenDispute {
+                state: oasis.state,
+                root_record: root_address(&oasis.state, batch_index),
+                dispute: dispute_address(&oasis.state, batch_index, 0, challenger),
+                asserter_stake: stake_address(&oasis.state, asserter),
+                asserter: *asserter,
+                challenger: *challenger,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::OpenDispute { batch_index }.data(),
+        }
+    }
+
+    fn submit_fraud_proof(
+        oasis: &Oasis,
+        asserter: &Pubkey,
+        challenger: &Pubkey,
+        reward_account: Pubkey,
+        batch_index: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::SubmitFraudProof {
+                state: oasis.state,
+                root_record: root_address(&oasis.state, batch_index),
+                dispute: dispute_address(&oasis.state, batch_index, 0, challenger),
+                validator_stake: stake_address(&oasis.state, asserter),
+                stake_vault: oasis.stake_vault,
+                stake_mint: oasis.stake_mint,
+                vault_authority: Pubkey::find_program_address(
+                    &[b"vault", oasis.state.as_ref()],
+                    &ID,
+                )
+                .0,
+                reward_account,
+                challenger: *challenger,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::SubmitFraudProof {}.data(),
+        }
+    }
+
+    fn settle_stale_dispute(
+        oasis: &Oasis,
+        asserter: &Pubkey,
+        challenger: &Pubkey,
+        batch_index: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::SettleStaleDispute {
+                state: oasis.state,
+                root_record: root_address(&oasis.state, batch_index),
+                dispute: dispute_address(&oasis.state, batch_index, 0, challenger),
+                asserter_stake: stake_address(&oasis.state, asserter),
+                challenger: *challenger,
+            }
+            .to_account_metas(None),
+            data: instruction::SettleStaleDispute {}.data(),
+        }
+    }
+
+    fn withdraw_stake(oasis: &Oasis, validator: &Pubkey, destination: Pubkey) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::WithdrawStake {
+                state: oasis.state,
+                validator_stake: stake_address(&oasis.state, validator),
+                validator: *validator,
+                stake_vault: oasis.stake_vault,
+                vault_authority: Pubkey::find_program_address(
+                    &[b"vault", oasis.state.as_ref()],
+                    &ID,
+                )
+                .0,
+                destination,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::WithdrawStake {}.data(),
+        }
+    }
+
+    fn request_unbond(oasis: &Oasis, validator: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::RequestUnbond {
+                state: oasis.state,
+                validator_stake: stake_address(&oasis.state, validator),
+                validator: *validator,
+            }
+            .to_account_metas(None),
+            data: instruction::RequestUnbond {}.data(),
+        }
+    }
+
+    async fn advance_clock(context: &mut ProgramTestContext, seconds: i64) {
+        let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += seconds;
+        context.set_sysvar(&clock);
+    }
+
+    fn forced_address(state: &Pubkey, index: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"forced", state.as_ref(), &index.to_le_bytes()], &ID).0
+    }
+
+    fn force(
+        context: &ProgramTestContext,
+        state: &Pubkey,
+        index: u64,
+        transaction: Vec<u8>,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::ForceTransaction {
+                state: *state,
+                forced_transaction: forced_address(state, index),
+                sender: context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ForceTransaction { transaction }.data(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forced_transactions_are_queued_with_a_deadline() {
+        let mut context = start().await;
+        let state = initialize(&mut context, 100).await.state;
+
+        for index in 0..2u64 {
+            let instruction = force(&context, &state, index, vec![index as u8 + 1; 16]);
+            send(&mut context, &[instruction], &[]).await.unwrap();
+
+            let forced: ForcedTransaction =
+                account(&mut context, forced_address(&state, index)).await;
+            assert_eq!(forced.index, index);
+            assert_eq!(forced.sender, context.payer.pubkey());
+            assert_eq!(forced.transaction, vec![index as u8 + 1; 16]);
+            assert_eq!(forced.deadline_slot, forced.slot + 100);
+        }
+
+        let state: StateAccount = account(&mut context, state).await;
+        assert_eq!(state.forced_tx_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_forced_transaction_must_fit_the_queue() {
+        let mut context = start().await;
+        let state = initialize(&mut context, 100).await.state;
+
+        for transaction in [
+            vec![],
+            vec![1u8; ForcedTransaction::MAX_TRANSACTION_LEN + 1],
+        ] {
+            let instruction = force(&context, &state, 0, transaction);
+            let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+            assert_eq!(
+                custom_error(error),
+                Some(OasisError::InvalidForcedTransaction.into())
+            );
+        }
+
+        let instruction = force(&context, &state, 1, vec![1u8; 16]);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(ErrorCode::ConstraintSeeds.into()));
+    }
+
+    #[tokio::test]
+    async fn test_won_dispute_slashes_the_asserter() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let asserter = register_validator(&mut context, &oasis, 1_000).await;
+
+        // An empty batch cannot change the root
+        post_batch(&mut context, &oasis, &[&asserter], [9u8; 32])
+            .await
+            .unwrap();
+
+        let challenger = context.payer.pubkey();
+        let reward_account = funded_account(&mut context, &oasis.stake_mint, &challenger, 0).await;
+        let instruction = open_dispute(&oasis, &asserter.pubkey(), &challenger, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let dispute: Dispute = account(
+            &mut context,
+            dispute_address(&oasis.state, 0, 0, &challenger),
+        )
+        .await;
+        assert_eq!(dispute.status, DisputeStatus::ChallengerWon);
+
+        // The bond stays locked until the fraud proof closes the dispute
+        let rent = context.banks_client.get_rent().await.unwrap();
+        assert_eq!(
+            context
+                .banks_client
+                .get_balance(dispute_address(&oasis.state, 0, 0, &challenger))
+                .await
+                .unwrap(),
+            rent.minimum_balance(8 + Dispute::LEN) + DISPUTE_BOND
+        );
+
+        let instruction =
+            submit_fraud_proof(&oasis, &asserter.pubkey(), &challenger, reward_account, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        // A quarter goes to the challenger and the rest is burned
+        assert_eq!(
+            token_account(&mut context, reward_account).await.amount,
+            250
+        );
+        assert_eq!(
+            token_account(&mut context, oasis.stake_vault).await.amount,
+            0
+        );
+        let stake = context
+            .banks_client
+            .get_account(stake_address(&oasis.state, &asserter.pubkey()))
+            .await
+            .unwrap();
+        assert!(stake.is_none());
+
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert!(state.validators.is_empty());
+        assert_eq!(state.state_root, [0u8; 32]);
+        assert_eq!(state.batch_count, 0);
+        assert_eq!(state.revert_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_lost_dispute_slashes_nobody() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let asserter = register_validator(&mut context, &oasis, 1_000).await;
+        post_batch(&mut context, &oasis, &[&asserter], [0u8; 32])
+            .await
+            .unwrap();
+
+        let challenger = context.payer.pubkey();
+        let reward_account = funded_account(&mut context, &oasis.stake_mint, &challenger, 0).await;
+        let asserter_balance = context
+            .banks_client
+            .get_balance(asserter.pubkey())
+            .await
+            .unwrap();
+        let instruction = open_dispute(&oasis, &asserter.pubkey(), &challenger, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let dispute: Dispute = account(
+            &mut context,
+            dispute_address(&oasis.state, 0, 0, &challenger),
+        )
+        .await;
+        assert_eq!(dispute.status, DisputeStatus::AsserterWon);
+
+        // The challenger's bond goes to the asserter
+        assert_eq!(
+            context
+                .banks_client
+                .get_balance(asserter.pubkey())
+                .await
+                .unwrap(),
+            asserter_balance + DISPUTE_BOND
+        );
+
+        let instruction =
+            submit_fraud_proof(&oasis, &asserter.pubkey(), &challenger, reward_account, 0);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::InvalidFraudProof.into())
+        );
+        assert_eq!(
+            token_account(&mut context, oasis.stake_vault).await.amount,
+            1_000
+        );
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert_eq!(state.validators, vec![asserter.pubkey()]);
+        assert_eq!(state.batch_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stake_is_returned_after_unbonding() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let validator = register_validator(&mut context, &oasis, 1_000).await;
+
+        let stake_from =
+            funded_account(&mut context, &oasis.stake_mint, &validator.pubkey(), 500).await;
+        let add_stake = Instruction {
+            program_id: ID,
+            accounts: accounts::AddStake {
+                state: oasis.state,
+                validator_stake: stake_address(&oasis.state, &validator.pubkey()),
+                validator: validator.pubkey(),
+                stake_from,
+                stake_vault: oasis.stake_vault,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::AddStake { amount: 500 }.data(),
+        };
+        send(
+            &mut context,
+            std::slice::from_ref(&add_stake),
+            &[&validator],
+        )
+        .await
+        .unwrap();
+        let stake: ValidatorStake = account(
+            &mut context,
+            stake_address(&oasis.state, &validator.pubkey()),
+        )
+        .await;
+        assert_eq!(stake.amount, 1_500);
+
+        let instruction = request_unbond(&oasis, &validator.pubkey());
+        send(&mut context, &[instruction], &[&validator])
+            .await
+            .unwrap();
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert!(state.validators.is_empty());
+
+        // Unbonding stake can neither grow nor leave before the delay
+        let error = send(&mut context, &[add_stake], &[&validator])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::ValidatorUnbonding.into())
+        );
+        let destination =
+            funded_account(&mut context, &oasis.stake_mint, &validator.pubkey(), 0).await;
+        let withdraw = withdraw_stake(&oasis, &validator.pubkey(), destination);
+        let error = send(&mut context, std::slice::from_ref(&withdraw), &[&validator])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::StakeStillBonded.into())
+        );
+
+        advance_clock(&mut context, 7_200).await;
+        send(&mut context, &[withdraw], &[&validator])
+            .await
+            .unwrap();
+        assert_eq!(token_account(&mut context, destination).await.amount, 1_500);
+        assert_eq!(
+            token_account(&mut context, oasis.stake_vault).await.amount,
+            0
+        );
+        let stake = context
+            .banks_client
+            .get_account(stake_address(&oasis.state, &validator.pubkey()))
+            .await
+            .unwrap();
+        assert!(stake.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disputed_stake_cannot_be_withdrawn() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let asserter = register_validator(&mut context, &oasis, 1_000).await;
+        post_batch(&mut context, &oasis, &[&asserter], [9u8; 32])
+            .await
+            .unwrap();
+
+        let challenger = context.payer.pubkey();
+        let instruction = open_dispute(&oasis, &asserter.pubkey(), &challenger, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let stake: ValidatorStake = account(
+            &mut context,
+            stake_address(&oasis.state, &asserter.pubkey()),
+        )
+        .await;
+        assert_eq!(stake.open_disputes, 1);
+
+        // The lost dispute keeps the stake locked for the fraud proof
+        let instruction = request_unbond(&oasis, &asserter.pubkey());
+        send(&mut context, &[instruction], &[&asserter])
+            .await
+            .unwrap();
+        advance_clock(&mut context, 7_200).await;
+        let destination =
+            funded_account(&mut context, &oasis.stake_mint, &asserter.pubkey(), 0).await;
+        let instruction = withdraw_stake(&oasis, &asserter.pubkey(), destination);
+        let error = send(&mut context, &[instruction], &[&asserter])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::StakeUnderDispute.into())
+        );
+        assert_eq!(
+            token_account(&mut context, oasis.stake_vault).await.amount,
+            1_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revert_voids_only_disputes_from_the_reverted_batch_on() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let first = register_validator(&mut context, &oasis, 1_000).await;
+        let second = register_validator(&mut context, &oasis, 1_000).await;
+
+        // Three invalid batches, the middle one posted by the second validator
+        let challenger = context.payer.pubkey();
+        let batches = [(&first, &second), (&second, &first), (&first, &second)];
+        for (batch_index, (leader, other)) in batches.into_iter().enumerate() {
+            post_batch(
+                &mut context,
+                &oasis,
+                &[leader, other],
+                [batch_index as u8 + 1; 32],
+            )
+            .await
+            .unwrap();
+            let instruction =
+                open_dispute(&oasis, &leader.pubkey(), &challenger, batch_index as u64);
+            send(&mut context, &[instruction], &[]).await.unwrap();
+        }
+        let instruction = settle_stale_dispute(&oasis, &first.pubkey(), &challenger, 2);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::DisputeNotStale.into())
+        );
+
+        let reward_account = funded_account(&mut context, &oasis.stake_mint, &challenger, 0).await;
+        let instruction =
+            submit_fraud_proof(&oasis, &second.pubkey(), &challenger, reward_account, 1);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        // The dispute over the reverted batch 2 can only be settled, which
+        // stops it from holding back the first validator's stake
+        let instruction =
+            submit_fraud_proof(&oasis, &first.pubkey(), &challenger, reward_account, 2);
+        let error = send(&mut context, &[instruction], &[]).await.unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::InvalidFraudProof.into())
+        );
+        let instruction = settle_stale_dispute(&oasis, &first.pubkey(), &challenger, 2);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let dispute = context
+            .banks_client
+            .get_account(dispute_address(&oasis.state, 2, 0, &challenger))
+            .await
+            .unwrap();
+        assert!(dispute.is_none());
+        let stake: ValidatorStake =
+            account(&mut context, stake_address(&oasis.state, &first.pubkey())).await;
+        assert_eq!(stake.open_disputes, 1);
+
+        // The dispute over batch 0 outlives the revert of a later batch
+        let instruction =
+            submit_fraud_proof(&oasis, &first.pubkey(), &challenger, reward_account, 0);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert!(state.validators.is_empty());
+        assert_eq!(state.batch_count, 0);
+        assert_eq!(state.revert_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_leader_rotates_by_batch() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let first = register_validator(&mut context, &oasis, 1_000).await;
+        let second = register_validator(&mut context, &oasis, 1_000).await;
+
+        // Batch 0 belongs to the first validator and batch 1 to the second
+        for (leader, other) in [(&first, &second), (&second, &first)] {
+            let error = post_batch(&mut context, &oasis, &[other, leader], [0u8; 32])
+                .await
+                .unwrap_err();
+            assert_eq!(custom_error(error), Some(OasisError::NotLeader.into()));
+            post_batch(&mut context, &oasis, &[leader, other], [0u8; 32])
+                .await
+                .unwrap();
+        }
+
+        // The second validator takes batch 2 over once the first misses its
+        // turn for the leader timeout
+        let error = post_batch(&mut context, &oasis, &[&second, &first], [0u8; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(custom_error(error), Some(OasisError::NotLeader.into()));
+        advance_clock(&mut context, 600).await;
+        post_batch(&mut context, &oasis, &[&second, &first], [0u8; 32])
+            .await
+            .unwrap();
+
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert_eq!(state.batch_count, 3);
+    }
+
+    fn claim_rewards(oasis: &Oasis, validator: &Pubkey, destination: Pubkey) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::ClaimRewards {
+                state: oasis.state,
+                validator_stake: stake_address(&oasis.state, validator),
+                validator: *validator,
+                reward_vault: oasis.reward_vault,
+                vault_authority: Pubkey::find_program_address(
+                    &[b"vault", oasis.state.as_ref()],
+                    &ID,
+                )
+                .0,
+                destination,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClaimRewards {}.data(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rewards_are_shared_by_bonded_stake() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let small = register_validator(&mut context, &oasis, 1_000).await;
+        let large = register_validator(&mut context, &oasis, 3_000).await;
+        let payer = context.payer.pubkey();
+        let fee_source = funded_account(&mut context, &oasis.stake_mint, &payer, 1_000).await;
+        let settle = |fees: u64| Instruction {
+            program_id: ID,
+            accounts: accounts::SettleRewards {
+                state: oasis.state,
+                reward_vault: oasis.reward_vault,
+                fee_source,
+                authority: payer,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::SettleRewards { fees }.data(),
+        };
+
+        let error = send(&mut context, &[settle(400)], &[]).await.unwrap_err();
+        assert_eq!(custom_error(error), Some(OasisError::EpochNotOver.into()));
+        advance_clock(&mut context, 3_600).await;
+        send(&mut context, &[settle(400)], &[]).await.unwrap();
+
+        let mut destinations = Vec::new();
+        for (validator, reward) in [(&small, 100), (&large, 300)] {
+            let destination =
+                funded_account(&mut context, &oasis.stake_mint, &validator.pubkey(), 0).await;
+            let instruction = claim_rewards(&oasis, &validator.pubkey(), destination);
+            send(&mut context, &[instruction], &[validator])
+                .await
+                .unwrap();
+            assert_eq!(
+                token_account(&mut context, destination).await.amount,
+                reward
+            );
+            destinations.push(destination);
+        }
+        let instruction = claim_rewards(&oasis, &small.pubkey(), destinations[0]);
+        let error = send(&mut context, &[instruction], &[&small])
+            .await
+            .unwrap_err();
+        assert_eq!(custom_error(error), Some(OasisError::NoRewards.into()));
+
+        // Unbonding stake earns nothing from later epochs
+        let instruction = request_unbond(&oasis, &small.pubkey());
+        send(&mut context, &[instruction], &[&small]).await.unwrap();
+        advance_clock(&mut context, 3_600).await;
+        send(&mut context, &[settle(300)], &[]).await.unwrap();
+        let instruction = claim_rewards(&oasis, &small.pubkey(), destinations[0]);
+        let error = send(&mut context, &[instruction], &[&small])
+            .await
+            .unwrap_err();
+        assert_eq!(custom_error(error), Some(OasisError::NoRewards.into()));
+        let instruction = claim_rewards(&oasis, &large.pubkey(), destinations[1]);
+        send(&mut context, &[instruction], &[&large]).await.unwrap();
+        assert_eq!(
+            token_account(&mut context, destinations[1]).await.amount,
+            600
+        );
+    }
+
+    fn proposal_address(state: &Pubkey, id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"proposal", state.as_ref(), &id.to_le_bytes()], &ID).0
+    }
+
+    fn propose_parameters(
+        context: &ProgramTestContext,
+        oasis: &Oasis,
+        id: u64,
+        update: ParameterUpdate,
+    ) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::ProposeParameters {
+                state: oasis.state,
+                proposal: proposal_address(&oasis.state, id),
+                authority: context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ProposeParameters { update }.data(),
+        }
+    }
+
+    fn approve_proposal(oasis: &Oasis, id: u64, validator: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::ApproveProposal {
+                state: oasis.state,
+                proposal: proposal_address(&oasis.state, id),
+                validator: *validator,
+            }
+            .to_account_metas(None),
+            data: instruction::ApproveProposal {}.data(),
+        }
+    }
+
+    fn execute_proposal(context: &ProgramTestContext, oasis: &Oasis, id: u64) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts::ResolveProposal {
+                state: oasis.state,
+                proposal: proposal_address(&oasis.state, id),
+                authority: context.payer.pubkey(),
+            }
+            .to_account_metas(None),
+            data: instruction::ExecuteProposal {}.data(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proposal_needs_timelock_and_approvals() {
+        let mut context = start().await;
+        let oasis = initialize(&mut context, 100).await;
+        let first = register_validator(&mut context, &oasis, 1_000).await;
+        let second = register_validator(&mut context, &oasis, 1_000).await;
+
+        let update = ParameterUpdate {
+            governance_threshold: Some(2),
+            ..ParameterUpdate::default()
+        };
+        let instruction = propose_parameters(&context, &oasis, 0, update);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let instruction = approve_proposal(&oasis, 0, &first.pubkey());
+        send(&mut context, &[instruction], &[&first]).await.unwrap();
+        let instruction = execute_proposal(&context, &oasis, 0);
+        let error = send(&mut context, std::slice::from_ref(&instruction), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::ProposalNotReady.into())
+        );
+        advance_clock(&mut context, 3_600).await;
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert_eq!(state.governance_threshold, 2);
+
+        let update = ParameterUpdate {
+            min_stake: Some(5),
+            ..ParameterUpdate::default()
+        };
+        let instruction = propose_parameters(&context, &oasis, 1, update);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        let instruction = approve_proposal(&oasis, 1, &first.pubkey());
+        send(&mut context, std::slice::from_ref(&instruction), &[&first])
+            .await
+            .unwrap();
+        let error = send(&mut context, &[instruction], &[&first])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::AlreadyApproved.into())
+        );
+
+        advance_clock(&mut context, 3_600).await;
+        let instruction = execute_proposal(&context, &oasis, 1);
+        let error = send(&mut context, std::slice::from_ref(&instruction), &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            custom_error(error),
+            Some(OasisError::ApprovalsNotReached.into())
+        );
+        let approve = approve_proposal(&oasis, 1, &second.pubkey());
+        send(&mut context, &[approve], &[&second]).await.unwrap();
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert_eq!(state.min_stake, 5);
+        assert_eq!(state.proposal_count, 2);
+        let proposal = context
+            .banks_client
+            .get_account(proposal_address(&oasis.state, 1))
+            .await
+            .unwrap();
+        assert!(proposal.is_none());
+
+        // A threshold of zero disables the multisig, leaving only the timelock
+        let update = ParameterUpdate {
+            governance_threshold: Some(0),
+            ..ParameterUpdate::default()
+        };
+        let instruction = propose_parameters(&context, &oasis, 2, update);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        for validator in [&first, &second] {
+            let approve = approve_proposal(&oasis, 2, &validator.pubkey());
+            send(&mut context, &[approve], &[validator]).await.unwrap();
+        }
+        advance_clock(&mut context, 3_600).await;
+        let instruction = execute_proposal(&context, &oasis, 2);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let update = ParameterUpdate {
+            min_stake: Some(7),
+            ..ParameterUpdate::default()
+        };
+        let instruction = propose_parameters(&context, &oasis, 3, update);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+        advance_clock(&mut context, 3_600).await;
+        let instruction = execute_proposal(&context, &oasis, 3);
+        send(&mut context, &[instruction], &[]).await.unwrap();
+
+        let state: StateAccount = account(&mut context, oasis.state).await;
+        assert_eq!(state.governance_threshold, 0);
+        assert_eq!(state.min_stake, 7);
+    }
+
+    #[test]
+    fn test_transfer_to_self_is_invalid() {
+        let account = [5u8; 32];
+        // Bincode encoding of the node's `Transaction::Transfer`
+        let mut transaction = 0u32.to_le_bytes().to_vec();
+        transaction.extend_from_slice(&account);
+        transaction.extend_from_slice(&account);
+        transaction.extend_from_slice(&100u64.to_le_bytes());
+        let leaf = hashv(&[&[0u8], &transaction]).to_bytes();
+
+        // A state holding only the sender's balance
+        let key = [&b"balance:"[..], &account].concat();
+        let value = 100u64.to_le_bytes().to_vec();
+        let path = hashv(&[&key]).to_bytes();
+        let pre_root = smt_root(&path, Some(&value), &[0u8; 32], &[]).unwrap();
+        let proof = StepProofData {
+            transaction,
+            transaction_proof: Vec::new(),
+            accesses: vec![StateAccess {
+                key,
+                value: Some(value),
+                bitmap: [0u8; 32],
+                siblings: Vec::new(),
+            }],
+        };
+
+        let result = proof.verify(0, 1, leaf, pre_root, &Pubkey::new_unique(), None);
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn test_deposit_needs_a_matching_receipt() {
+        let bridge_program = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let nonce = 7u64;
+        let recipient = [4u8; 32];
+
+        // Bincode encoding of the node's `Transaction::Deposit`
+        let mut transaction = 1u32.to_le_bytes().to_vec();
+        transaction.extend_from_slice(&nonce.to_le_bytes());
+        transaction.extend_from_slice(&recipient);
+        transaction.extend_from_slice(&token_id(&mint));
+        transaction.extend_from_slice(&100u64.to_le_bytes());
+        let leaf = hashv(&[&[0u8], &transaction]).to_bytes();
+        let proof = StepProofData {
+            transaction,
+            transaction_proof: Vec::new(),
+            accesses: Vec::new(),
+        };
+
+        let verify = |key: Pubkey, owner: Pubkey, amount: u64| {
+            let mut data = hashv(&[b"account:DepositReceipt"]).to_bytes()[..8].to_vec();
+            data.extend_from_slice(Pubkey::new_unique().as_ref());
+            data.extend_from_slice(mint.as_ref());
+            data.extend_from_slice(&amount.to_le_bytes());
+            data.extend_from_slice(&recipient);
+            data.extend_from_slice(&nonce.to_le_bytes());
+            data.push(255);
+            let mut lamports = 1_000_000;
+            let receipt = AccountInfo::new(
+                &key,
+                false,
+                false,
+                &mut lamports,
+                &mut data,
+                &owner,
+                false,
+                0,
+            );
+            proof.verify(0, 1, leaf, [0u8; 32], &bridge_program, Some(&receipt))
+        };
+        let receipt = deposit_receipt_address(&bridge_program, nonce);
+
+        // Crediting more than was locked, or funds no bridge receipt records,
+        // is an invalid transaction
+        assert!(matches!(verify(receipt, bridge_program, 200), Ok(None)));
+        assert!(matches!(verify(receipt, system_program::ID, 100), Ok(None)));
+        // Any other account, or none, is a bad proof
+        for result in [
+            verify(Pubkey::new_unique(), bridge_program, 100),
+            proof.verify(0, 1, leaf, [0u8; 32], &bridge_program, None),
+        ] {
+            assert_eq!(result.unwrap_err(), error!(OasisError::InvalidStepProof));
+        }
+        // With the matching receipt the proof goes on to the state accesses,
+        // which this one leaves out
+        assert_eq!(
+            verify(receipt, bridge_program, 100).unwrap_err(),
+            error!(OasisError::InvalidStepProof)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount};
+
+use crate::{OasisError, StateAccount, ValidatorStake};
+
+/// Fixed-point scale of `StateAccount::reward_per_stake`
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
+#[derive(Accounts)]
+pub struct SettleRewards<'info> {
+    #[account(mut, has_one = authority @ OasisError::Unauthorized)]
+    pub state: Account<'info, StateAccount>,
+    #[account(mut, address = state.reward_vault)]
+    pub reward_vault: Account<'info, TokenAccount>,
+    /// Account the L2 fees of the epoch were bridged to
+    #[account(mut, token::mint = reward_vault.mint)]
+    pub fee_source: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SettleRewards<'info> {
+    /// Close the current reward epoch: move the fees collected on L2 during it
+    /// into the reward vault and share them, together with rewards forfeited
+    /// by slashed validators, pro rata over the bonded stake.
+    pub fn settle(&mut self, fees: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &mut self.state;
+        require!(
+            now >= state
+                .reward_epoch_start
+                .saturating_add(state.reward_epoch_length),
+            OasisError::EpochNotOver
+        );
+        require!(state.total_stake > 0, OasisError::NoActiveStake);
+
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                token::Transfer {
+                    from: self.fee_source.to_account_info(),
+                    to: self.reward_vault.to_account_info(),
+                    authority: self.authority.to_account_info(),
+                },
+            ),
+            fees,
+        )?;
+
+        // Rounding leaves dust in the vault rather than over-promising
+        let amount = fees
+            .checked_add(state.forfeited_rewards)
+            .ok_or(OasisError::RewardOverflow)?;
+        state.reward_per_stake = (amount as u128)
+            .checked_mul(REWARD_PRECISION)
+            .map(|scaled| scaled / state.total_stake as u128)
+            .and_then(|increase| state.reward_per_stake.checked_add(increase))
+            .ok_or(OasisError::RewardOverflow)?;
+        state.forfeited_rewards = 0;
+        state.reward_epoch += 1;
+        state.reward_epoch_start = now;
+
+        emit!(EpochRewardsSettled {
+            epoch: state.reward_epoch,
+            amount,
+            total_stake: state.total_stake,
+            reward_per_stake: state.reward_per_stake,
+            timestamp: now,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        has_one = validator,
+        seeds = [b"stake", state.key().as_ref(), validator.key().as_ref()],
+        bump = validator_stake.bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    pub validator: Signer<'info>,
+    #[account(mut, address = state.reward_vault)]
+    pub reward_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA owning the reward vault, only signs
+    #[account(seeds = [b"vault", state.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(mut, token::mint = reward_vault.mint)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> ClaimRewards<'info> {
+    /// Pay out every reward the validator earned in settled epochs
+    pub fn claim(&mut self, vault_bump: u8) -> Result<()> {
+        let stake = &mut self.validator_stake;
+        stake.accrue(self.state.reward_per_stake)?;
+        let amount = stake.unclaimed_rewards;
+        require!(amount > 0, OasisError::NoRewards);
+
+        let state_key = self.state.key();
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                token::Transfer {
+                    from: self.reward_vault.to_account_info(),
+                    to: self.destination.to_account_info(),
+                    authority: self.vault_authority.to_account_info(),
+                },
+                &[&[b"vault", state_key.as_ref(), &[vault_bump]]],
+            ),
+            amount,
+        )?;
+        stake.unclaimed_rewards = 0;
+        stake.claimed_rewards = stake
+            .claimed_rewards
+            .checked_add(amount)
+            .ok_or(OasisError::RewardOverflow)?;
+
+        emit!(RewardsClaimed {
+            validator: stake.validator,
+            epoch: self.state.reward_epoch,
+            amount,
+            total_claimed: stake.claimed_rewards,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct EpochRewardsSettled {
+    /// Number of the epoch just closed, starting at 1
+    pub epoch: u64,
+    /// Fees settled plus forfeited rewards, shared over `total_stake`
+    pub amount: u64,
+    pub total_stake: u64,
+    pub reward_per_stake: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub validator: Pubkey,
+    /// Last settled epoch included in the claim
+    pub epoch: u64,
+    pub amount: u64,
+    pub total_claimed: u64,
+}
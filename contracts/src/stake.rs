@@ -0,0 +1,245 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount};
+
+use crate::{OasisError, StateAccount, REWARD_PRECISION};
+
+/// Largest validator set, matching the space reserved in `StateAccount::LEN`
+pub const MAX_VALIDATORS: usize = 100;
+
+/// Stake a validator locked in the stake vault
+#[account]
+pub struct ValidatorStake {
+    pub state: Pubkey,
+    pub validator: Pubkey,
+    pub amount: u64,
+    /// When the stake can be withdrawn; zero while the validator is bonded
+    pub unbonds_at: i64,
+    /// Disputes over this validator's roots that are not settled in its favour
+    /// yet. Stake cannot be withdrawn while any is left.
+    pub open_disputes: u32,
+    pub bump: u8,
+    /// `StateAccount::reward_per_stake` up to which rewards were accrued
+    pub reward_checkpoint: u128,
+    /// Rewards accrued but not claimed yet
+    pub unclaimed_rewards: u64,
+    /// Rewards claimed over the lifetime of the stake account
+    pub claimed_rewards: u64,
+    /// `StateAccount::revert_count` at registration. A validator registering
+    /// again after being slashed gets a higher one than its earlier disputes.
+    pub revert_count: u64,
+}
+
+impl ValidatorStake {
+    pub const LEN: usize = 32 + // state
+        32 + // validator
+        8 + // amount
+        8 + // unbonds_at
+        4 + // open_disputes
+        1 + // bump
+        16 + // reward_checkpoint
+        8 + // unclaimed_rewards
+        8 + // claimed_rewards
+        8; // revert_count
+
+    pub fn is_bonded(&self) -> bool {
+        self.unbonds_at == 0
+    }
+
+    /// Credit the rewards settled since the last checkpoint. Only bonded stake
+    /// earns, so this must run before `amount` or the bonding status change.
+    pub fn accrue(&mut self, reward_per_stake: u128) -> Result<()> {
+        if self.is_bonded() {
+            let earned = (self.amount as u128)
+                .checked_mul(reward_per_stake - self.reward_checkpoint)
+                .map(|scaled| scaled / REWARD_PRECISION)
+                .and_then(|earned| u64::try_from(earned).ok())
+                .and_then(|earned| self.unclaimed_rewards.checked_add(earned))
+                .ok_or(OasisError::RewardOverflow)?;
+            self.unclaimed_rewards = earned;
+        }
+        self.reward_checkpoint = reward_per_stake;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct AddStake<'info> {
+    #[account(mut)]
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        has_one = validator,
+        seeds = [b"stake", state.key().as_ref(), validator.key().as_ref()],
+        bump = validator_stake.bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    pub validator: Signer<'info>,
+    #[account(mut)]
+    pub stake_from: Account<'info, TokenAccount>,
+    #[account(mut, address = state.stake_vault)]
+    pub stake_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> AddStake<'info> {
+    /// Top up the stake of a bonded validator
+    pub fn add(&mut self, amount: u64) -> Result<()> {
+        let stake = &mut self.validator_stake;
+        require!(stake.is_bonded(), OasisError::ValidatorUnbonding);
+        stake.accrue(self.state.reward_per_stake)?;
+
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                token::Transfer {
+                    from: self.stake_from.to_account_info(),
+                    to: self.stake_vault.to_account_info(),
+                    authority: self.validator.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        stake.amount = stake
+            .amount
+            .checked_add(amount)
+            .ok_or(OasisError::InsufficientStake)?;
+        self.state.total_stake = self
+            .state
+            .total_stake
+            .checked_add(amount)
+            .ok_or(OasisError::InsufficientStake)?;
+
+        emit!(StakeAdded {
+            validator: stake.validator,
+            amount,
+            total: stake.amount,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct RequestUnbond<'info> {
+    #[account(mut)]
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        has_one = validator,
+        seeds = [b"stake", state.key().as_ref(), validator.key().as_ref()],
+        bump = validator_stake.bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    pub validator: Signer<'info>,
+}
+
+impl<'info> RequestUnbond<'info> {
+    /// Leave the validator set. The stake stays slashable for
+    /// `unbonding_period`, which covers the challenge window of every root the
+    /// validator posted.
+    pub fn request(&mut self) -> Result<()> {
+        let state = &mut self.state;
+        let stake = &mut self.validator_stake;
+        require!(stake.is_bonded(), OasisError::ValidatorUnbonding);
+        stake.accrue(state.reward_per_stake)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        state
+            .validators
+            .retain(|validator| *validator != stake.validator);
+        state.total_stake = state
+            .total_stake
+            .checked_sub(stake.amount)
+            .ok_or(OasisError::InsufficientStake)?;
+        stake.unbonds_at = now
+            .checked_add(state.unbonding_period)
+            .ok_or(OasisError::InvalidUnbondingPeriod)?;
+
+        emit!(ValidatorUnbonding {
+            validator: stake.validator,
+            amount: stake.amount,
+            unbonds_at: stake.unbonds_at,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    pub state: Account<'info, StateAccount>,
+    #[account(
+        mut,
+        has_one = state,
+        has_one = validator,
+        close = validator,
+        seeds = [b"stake", state.key().as_ref(), validator.key().as_ref()],
+        bump = validator_stake.bump
+    )]
+    pub validator_stake: Account<'info, ValidatorStake>,
+    #[account(mut)]
+    pub validator: Signer<'info>,
+    #[account(mut, address = state.stake_vault)]
+    pub stake_vault: Account<'info, TokenAccount>,
+    /// CHECK: PDA owning the stake vault, only signs
+    #[account(seeds = [b"vault", state.key().as_ref()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(mut, token::mint = stake_vault.mint)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> WithdrawStake<'info> {
+    /// Return the stake of an unbonded validator and close its stake account.
+    /// Rewards must be claimed first, as closing the account forgets them.
+    pub fn withdraw(&mut self, vault_bump: u8) -> Result<()> {
+        let stake = &self.validator_stake;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            !stake.is_bonded() && now >= stake.unbonds_at,
+            OasisError::StakeStillBonded
+        );
+        require!(stake.open_disputes == 0, OasisError::StakeUnderDispute);
+        require!(stake.unclaimed_rewards == 0, OasisError::UnclaimedRewards);
+
+        let state_key = self.state.key();
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                token::Transfer {
+                    from: self.stake_vault.to_account_info(),
+                    to: self.destination.to_account_info(),
+                    authority: self.vault_authority.to_account_info(),
+                },
+                &[&[b"vault", state_key.as_ref(), &[vault_bump]]],
+            ),
+            stake.amount,
+        )?;
+
+        emit!(StakeWithdrawn {
+            validator: stake.validator,
+            amount: stake.amount,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct StakeAdded {
+    pub validator: Pubkey,
+    pub amount: u64,
+    pub total: u64,
+}
+
+#[event]
+pub struct ValidatorUnbonding {
+    pub validator: Pubkey,
+    pub amount: u64,
+    pub unbonds_at: i64,
+}
+
+#[event]
+pub struct StakeWithdrawn {
+    pub validator: Pubkey,
+    pub amount: u64,
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use solana_oasis_node::{
+    rollup::Rollup,
+    state::StateManager,
+    types::{balance_key, Block, Transaction},
+};
+use tempfile::TempDir;
+use tokio::{runtime::Runtime, sync::RwLock};
+
+const ACCOUNTS: usize = 1024;
+const BLOCK_SIZE: usize = 2000;
+
+/// Block of transfers between `ACCOUNTS` accounts; `conflict_every` controls how
+/// often a transfer touches the shared hot account.
+fn build_block(conflict_every: usize) -> Block {
+    let transactions = (0..BLOCK_SIZE)
+        .map(|i| {
+            let from = (i * 2) % ACCOUNTS;
+            let to = if i % conflict_every == 0 {
+                0
+            } else {
+                (i * 2 + 1) % ACCOUNTS
+            };
+            Transaction::Transfer {
+                from: account(from),
+                to: account(to),
+                amount: 1,
+            }
+        })
+        .collect();
+    Block::new(1, [0u8; 32], transactions, 0)
+}
+
+fn account(index: usize) -> [u8; 32] {
+    let mut account = [0u8; 32];
+    account[..8].copy_from_slice(&(index as u64).to_be_bytes());
+    account
+}
+
+fn setup(runtime: &Runtime) -> (TempDir, Rollup) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state_manager = Arc::new(RwLock::new(StateManager::new(&temp_dir).unwrap()));
+    runtime.block_on(async {
+        let mut state = state_manager.write().await;
+        for index in 0..ACCOUNTS {
+            state
+                .set_value(
+                    &balance_key(&account(index)),
+                    bincode::serialize(&1_000_000u64).unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+    });
+    let rollup = Rollup::new(state_manager).unwrap();
+    (temp_dir, rollup)
+}
+
+fn bench_execution(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("block_execution");
+    group.sample_size(10);
+
+    for conflict_every in [BLOCK_SIZE, 10, 2] {
+        let block = build_block(conflict_every);
+
+        group.bench_with_input(
+            BenchmarkId::new("sequential", conflict_every),
+            &block,
+            |b, block| {
+                b.iter_batched(
+                    || setup(&runtime),
+                    |(_dir, mut rollup)| {
+                        runtime.block_on(rollup.process_block(block.clone())).unwrap()
+                    },
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("parallel", conflict_every),
+            &block,
+            |b, block| {
+                b.iter_batched(
+                    || setup(&runtime),
+                    |(_dir, mut rollup)| {
+                        runtime
+                            .block_on(rollup.process_block_parallel(block.clone()))
+                            .unwrap()
+                    },
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_execution);
+criterion_main!(benches);
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bincode::Options as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::{KeyChange, StateManager};
+use crate::types::{Block, Transaction};
+
+/// Current batch encoding version, written as the first byte of every payload
+pub const BATCH_VERSION: u8 = 1;
+
+/// Upper bound on a decompressed batch, guarding decoders against zstd bombs
+pub const MAX_DECODED_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// Batch bytes carried by one chunk; leaves room for the chunk header and the
+/// link to the previous chunk inside a single Solana transaction
+pub const MAX_CHUNK_DATA_BYTES: usize = 700;
+
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// Range and state transition covered by a batch
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchHeader {
+    pub start_height: u64,
+    pub end_height: u64,
+    /// Root at `start_height - 1`
+    pub previous_root: [u8; 32],
+    /// Root at `end_height`
+    pub state_root: [u8; 32],
+}
+
+/// Block fields that cannot be derived from the batch position
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchBlock {
+    pub previous_hash: [u8; 32],
+    pub timestamp: i64,
+    pub transactions: Vec<Transaction>,
+}
+
+/// A range of L2 blocks as posted to L1: everything a validator needs to
+/// rebuild the blocks and check the claimed state transition.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Batch {
+    pub header: BatchHeader,
+    pub blocks: Vec<BatchBlock>,
+    /// Net value of every key changed by the batch, in order of first write;
+    /// `None` marks a deleted key
+    pub state_diff: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl Batch {
+    /// Assemble the batch of committed blocks `start..=end`
+    pub fn from_state(state: &StateManager, start: u64, end: u64) -> Result<Self> {
+        if start == 0 || start > end {
+            return Err(anyhow::anyhow!("Invalid batch range {}..={}", start, end));
+        }
+
+        let previous_root = state
+            .get_root_at(start - 1)?
+            .ok_or_else(|| anyhow::anyhow!("No state root stored for height {}", start - 1))?;
+
+        let mut blocks = Vec::new();
+        let mut changes: Vec<KeyChange> = Vec::new();
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut state_root = previous_root.root;
+
+        for height in start..=end {
+            let block = state
+                .get_block(height)?
+                .ok_or_else(|| anyhow::anyhow!("Block {} is not stored", height))?;
+            let diff = state
+                .get_state_diff(height)?
+                .ok_or_else(|| anyhow::anyhow!("No state diff stored for height {}", height))?;
+
+            for change in diff.changes {
+                match positions.get(&change.key) {
+                    Some(&index) => changes[index].new = change.new,
+                    None => {
+                        positions.insert(change.key.clone(), changes.len());
+                        changes.push(change);
+                    }
+                }
+            }
+            state_root = diff.root.root;
+
+            blocks.push(BatchBlock {
+                previous_hash: block.previous_hash,
+                timestamp: block.timestamp,
+                transactions: block.transactions,
+            });
+        }
+
+        Ok(Self {
+            header: BatchHeader {
+                start_height: start,
+                end_height: end,
+                previous_root: previous_root.root,
+                state_root,
+            },
+            blocks,
+            state_diff: changes
+                .into_iter()
+                .filter(|change| change.old != change.new)
+                .map(|change| (change.key, change.new))
+                .collect(),
+        })
+    }
+
+    /// Version byte followed by the zstd-compressed varint encoding
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let body = bincode::DefaultOptions::new().serialize(self)?;
+        let mut data = vec![BATCH_VERSION];
+        data.extend(zstd::bulk::compress(&body, COMPRESSION_LEVEL)?);
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (&version, compressed) = data
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Empty batch payload"))?;
+        if version != BATCH_VERSION {
+            return Err(anyhow::anyhow!("Unsupported batch version {}", version));
+        }
+
+        let body = zstd::bulk::decompress(compressed, MAX_DECODED_BATCH_BYTES)?;
+        let batch: Self = bincode::DefaultOptions::new()
+            .with_limit(MAX_DECODED_BATCH_BYTES as u64)
+            .deserialize(&body)?;
+
+        let expected = batch
+            .header
+            .end_height
+            .checked_sub(batch.header.start_height)
+            .map(|n| n + 1);
+        if batch.header.start_height == 0 || expected != Some(batch.blocks.len() as u64) {
+            return Err(anyhow::anyhow!(
+                "Batch header {}..={} does not match its {} blocks",
+                batch.header.start_height,
+                batch.header.end_height,
+                batch.blocks.len()
+            ));
+        }
+        Ok(batch)
+    }
+
+    /// Rebuild the L2 blocks, including their transactions roots
+    pub fn to_blocks(&self) -> Vec<Block> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(offset, block)| {
+                Block::new(
+                    self.header.start_height + offset as u64,
+                    block.previous_hash,
+                    block.transactions.clone(),
+                    block.timestamp,
+                )
+            })
+            .collect()
+    }
+
+    /// Transactions of every block, in execution order
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.blocks.iter().flat_map(|block| &block.transactions)
+    }
+
+    /// What `update_state_root` commits to on L1 for this batch, `data` being
+    /// the batch data posted alongside it
+    pub fn commitment(&self, data: &[u8]) -> Result<BatchCommitment> {
+        let transactions: Vec<Transaction> = self.transactions().cloned().collect();
+        Ok(BatchCommitment {
+            previous_root: self.header.previous_root,
+            new_root: self.header.state_root,
+            data_hash: data_hash(data),
+            transactions_root: transactions_root(&transactions)?,
+            transaction_count: transactions.len() as u64,
+        })
+    }
+}
+
+/// Claim posted with a batch. Disputes bisect over the committed transactions,
+/// step `i` being the state after the first `i` of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchCommitment {
+    pub previous_root: [u8; 32],
+    pub new_root: [u8; 32],
+    /// Hash of the `update_state_root` payload posted with the commitment,
+    /// which for a chunked batch covers every chunk through their chain hash
+    pub data_hash: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+}
+
+impl BatchCommitment {
+    /// Digest a quorum of validators signs for batch `batch_index` of the
+    /// program state account `state_account`
+    pub fn message(&self, state_account: &[u8; 32], batch_index: u64) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"oasis:batch");
+        hasher.update(state_account);
+        hasher.update(batch_index.to_le_bytes());
+        hasher.update(self.previous_root);
+        hasher.update(self.new_root);
+        hasher.update(self.data_hash);
+        hasher.update(self.transactions_root);
+        hasher.update(self.transaction_count.to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Levels of the transactions tree from the leaves up, padded with empty leaves
+/// to a power of two
+fn transaction_tree(transactions: &[Transaction]) -> Result<Vec<Vec<[u8; 32]>>> {
+    let mut leaves = Vec::with_capacity(transactions.len().next_power_of_two());
+    for transaction in transactions {
+        let mut hasher = Sha256::new();
+        hasher.update([0u8]);
+        hasher.update(bincode::serialize(transaction)?);
+        leaves.push(hasher.finalize().into());
+    }
+    leaves.resize(transactions.len().next_power_of_two(), [0u8; 32]);
+
+    let mut levels = vec![leaves];
+    while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+        let parents = level
+            .chunks(2)
+            .map(|pair| {
+                let mut hasher = Sha256::new();
+                hasher.update([1u8]);
+                hasher.update(pair[0]);
+                hasher.update(pair[1]);
+                hasher.finalize().into()
+            })
+            .collect();
+        levels.push(parents);
+    }
+    Ok(levels)
+}
+
+/// Merkle root over the bincode encoding of `transactions`
+pub fn transactions_root(transactions: &[Transaction]) -> Result<[u8; 32]> {
+    let levels = transaction_tree(transactions)?;
+    Ok(levels[levels.len() - 1][0])
+}
+
+/// Siblings of transaction `index` in the transactions tree, from the leaf up
+pub fn transaction_proof(transactions: &[Transaction], index: usize) -> Result<Vec<[u8; 32]>> {
+    if index >= transactions.len() {
+        return Err(anyhow::anyhow!(
+            "Transaction {} is out of range for {} transactions",
+            index,
+            transactions.len()
+        ));
+    }
+    let levels = transaction_tree(transactions)?;
+    Ok(levels[..levels.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(depth, level)| level[(index >> depth) ^ 1])
+        .collect())
+}
+
+/// Hash of an encoded batch, committed to on L1
+pub fn data_hash(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Link of the hash chain over a chunked batch: `data` chained onto
+/// `previous`, the link of the chunks before it
+pub fn chunk_link(previous: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"oasis:chunk");
+    hasher.update(previous);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Slice of an encoded batch too large for a single transaction.
+///
+/// Chunks are posted in order and each links to the signature of the chunk
+/// before it, so the whole batch can be recovered from the last signature.
+/// They also form a hash chain whose last link the batch commitment covers,
+/// so every chunk is checked as soon as it is fetched.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchChunk {
+    /// Chain link of the chunks before this one, zero for the first
+    pub previous_link: [u8; 32],
+    pub index: u16,
+    pub count: u16,
+    /// Signature of the L1 transaction carrying chunk `index - 1`
+    pub previous: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl BatchChunk {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::DefaultOptions::new().serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(bincode::DefaultOptions::new()
+            .with_limit(MAX_DECODED_BATCH_BYTES as u64)
+            .deserialize(data)?)
+    }
+
+    /// Chain link up to and including this chunk
+    pub fn link(&self) -> [u8; 32] {
+        chunk_link(&self.previous_link, &self.data)
+    }
+}
+
+/// Split `data` into unlinked chunks of at most `chunk_size` bytes; the
+/// submitter fills in `previous` as it posts them.
+pub fn split_payload(data: &[u8], chunk_size: usize) -> Result<Vec<BatchChunk>> {
+    let pieces: Vec<&[u8]> = data.chunks(chunk_size.max(1)).collect();
+    let count = u16::try_from(pieces.len())
+        .map_err(|_| anyhow::anyhow!("Batch of {} bytes needs too many chunks", data.len()))?;
+
+    let mut link = [0u8; 32];
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            let chunk = BatchChunk {
+                previous_link: link,
+                index: index as u16,
+                count,
+                previous: None,
+                data: piece.to_vec(),
+            };
+            link = chunk.link();
+            chunk
+        })
+        .collect())
+}
+
+/// Concatenate chunks given in posting order and check that they chain up to
+/// the committed `chain_hash`
+pub fn reassemble(chunks: &[BatchChunk], chain_hash: &[u8; 32]) -> Result<Vec<u8>> {
+    if chunks.is_empty() {
+        return Err(anyhow::anyhow!("No batch chunks"));
+    }
+
+    let mut data = Vec::new();
+    let mut link = [0u8; 32];
+    for (index, chunk) in chunks.iter().enumerate() {
+        if chunk.index as usize != index
+            || chunk.count as usize != chunks.len()
+            || chunk.previous_link != link
+        {
+            return Err(anyhow::anyhow!("Batch chunk {} is out of sequence", index));
+        }
+        link = chunk.link();
+        data.extend_from_slice(&chunk.data);
+    }
+
+    if link != *chain_hash {
+        return Err(anyhow::anyhow!(
+            "Batch chunks do not match their chain hash"
+        ));
+    }
+    Ok(data)
+}
+
+/// Payload of the `update_state_root` instruction
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchData {
+    /// The encoded batch itself
+    Inline(Vec<u8>),
+    /// The encoded batch was posted as linked `post_batch_data` chunks
+    Chunked {
+        /// Last link of the chunks' hash chain, see [`chunk_link`]
+        chain_hash: [u8; 32],
+        last_chunk: String,
+    },
+}
+
+impl BatchData {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::DefaultOptions::new().serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(bincode::DefaultOptions::new()
+            .with_limit(MAX_DECODED_BATCH_BYTES as u64)
+            .deserialize(data)?)
+    }
+}
+
+/// Read access to data posted to the `solana_oasis` program
+#[async_trait]
+pub trait PostedDataSource: Send + Sync {
+    /// Byte argument of the `update_state_root` or `post_batch_data`
+    /// instruction in transaction `signature`
+    async fn posted_data(&self, signature: &str) -> Result<Vec<u8>>;
+}
+
+/// Reconstruct the batch committed by the `update_state_root` transaction
+/// `signature`, using nothing but L1 data
+pub async fn fetch_batch(source: &impl PostedDataSource, signature: &str) -> Result<Batch> {
+    batch_from_payload(source, &source.posted_data(signature).await?).await
+}
+
+/// Reconstruct the batch an `update_state_root` payload carries inline or
+/// points to on L1
+pub async fn batch_from_payload(source: &impl PostedDataSource, payload: &[u8]) -> Result<Batch> {
+    let data = match BatchData::decode(payload)? {
+        BatchData::Inline(data) => data,
+        BatchData::Chunked {
+            chain_hash,
+            last_chunk,
+        } => {
+            // Walking back from the last chunk, each one must produce the link
+            // the chunk after it builds on
+            let mut chunks = Vec::new();
+            let mut link = chain_hash;
+            let mut next = Some(last_chunk);
+            while let Some(signature) = next {
+                let chunk = BatchChunk::decode(&source.posted_data(&signature).await?)?;
+                if chunk.link() != link || chunks.len() >= chunk.count as usize {
+                    return Err(anyhow::anyhow!(
+                        "Chunk {} does not belong to the batch",
+                        signature
+                    ));
+                }
+                link = chunk.previous_link;
+                next = chunk.previous.clone();
+                chunks.push(chunk);
+            }
+            chunks.reverse();
+            reassemble(&chunks, &chain_hash)?
+        }
+    };
+    Batch::decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollup::Rollup;
+    use crate::types::balance_key;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::sync::RwLock;
+
+    struct MockPosted(HashMap<String, Vec<u8>>);
+
+    #[async_trait]
+    impl PostedDataSource for MockPosted {
+        async fn posted_data(&self, signature: &str) -> Result<Vec<u8>> {
+            self.0
+                .get(signature)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Unknown transaction {}", signature))
+        }
+    }
+
+    async fn build_batch(blocks: u64) -> Result<(Batch, Vec<Block>)> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        state
+            .write()
+            .await
+            .set_value(&balance_key(&[1u8; 32]), bincode::serialize(&1_000_000u64)?)
+            .await?;
+        state
+            .write()
+            .await
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?;
+
+        let mut rollup = Rollup::new(state.clone())?;
+        let mut produced = Vec::new();
+        for number in 2..=blocks + 1 {
+            let transactions = (0..20)
+                .map(|i| Transaction::Transfer {
+                    from: [1u8; 32],
+                    to: [(number + i) as u8; 32],
+                    amount: 1,
+                })
+                .collect();
+            let block = Block::new(number, [number as u8; 32], transactions, number as i64);
+            rollup.process_block(block.clone()).await?;
+            produced.push(block);
+        }
+
+        let batch = Batch::from_state(&*state.read().await, 2, blocks + 1)?;
+        assert_eq!(
+            batch.header.state_root,
+            state.read().await.get_current_root().root
+        );
+        Ok((batch, produced))
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_reconstructs_blocks() -> Result<()> {
+        let (batch, produced) = build_batch(4).await?;
+        let encoded = batch.encode()?;
+        assert_eq!(encoded[0], BATCH_VERSION);
+        assert!(encoded.len() < bincode::serialize(&produced)?.len());
+
+        let decoded = Batch::decode(&encoded)?;
+        assert_eq!(decoded, batch);
+        let hashes: Vec<_> = decoded.to_blocks().iter().map(Block::hash).collect();
+        let expected: Vec<_> = produced.iter().map(Block::hash).collect();
+        assert_eq!(hashes, expected);
+
+        // Unknown versions are rejected rather than misparsed
+        let mut future = encoded.clone();
+        future[0] = BATCH_VERSION + 1;
+        assert!(Batch::decode(&future).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_chunked_batch() -> Result<()> {
+        let (batch, _) = build_batch(8).await?;
+        let encoded = batch.encode()?;
+
+        let mut posted = HashMap::new();
+        let mut previous = None;
+        let chunks = split_payload(&encoded, 64)?;
+        assert!(chunks.len() > 1);
+        let chain_hash = chunks.last().unwrap().link();
+        assert_eq!(reassemble(&chunks, &chain_hash)?, encoded);
+        for (index, mut chunk) in chunks.clone().into_iter().enumerate() {
+            chunk.previous = previous.take();
+            let signature = format!("chunk{}", index);
+            posted.insert(signature.clone(), chunk.encode()?);
+            previous = Some(signature);
+        }
+        posted.insert(
+            "update".to_string(),
+            BatchData::Chunked {
+                chain_hash,
+                last_chunk: previous.clone().unwrap(),
+            }
+            .encode()?,
+        );
+
+        let mut source = MockPosted(posted);
+        assert_eq!(fetch_batch(&source, "update").await?, batch);
+
+        // A chunk swapped for other data breaks the chain, even if its
+        // successor is rewritten to link to it
+        let mut forged = chunks[1].clone();
+        forged.data[0] ^= 1;
+        forged.previous = Some("chunk0".to_string());
+        source.0.insert("chunk1".to_string(), forged.encode()?);
+        assert!(fetch_batch(&source, "update").await.is_err());
+        let mut relinked = chunks[2].clone();
+        relinked.previous_link = forged.link();
+        relinked.previous = Some("chunk1".to_string());
+        source.0.insert("chunk2".to_string(), relinked.encode()?);
+        assert!(fetch_batch(&source, "update").await.is_err());
+        assert!(reassemble(&chunks[..1], &chain_hash).is_err());
+
+        Ok(())
+    }
+}
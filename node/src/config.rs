@@ -7,6 +7,60 @@ pub struct NetworkConfig {
     pub listen_addresses: Vec<String>,
     pub bootstrap_peers: Vec<String>,
     pub state_db_path: String,
+    /// Genesis file used on first start; the default devnet genesis if unset
+    pub genesis_path: Option<String>,
+    /// Settlement layer connection; batches are not posted to L1 if unset
+    pub l1: Option<L1Config>,
+}
+
+/// Connection to the `solana_oasis` program on Solana
+#[derive(Clone, Debug)]
+pub struct L1Config {
+    pub rpc_url: String,
+    pub program_id: String,
+    pub state_account: String,
+    /// Keypair of the validator signing L1 transactions
+    pub keypair_path: String,
+    /// Co-signing endpoints (`host:port`) of other validators, asked to sign
+    /// each batch commitment so the submitted roots reach the program's quorum
+    pub cosigners: Vec<String>,
+    /// Address to answer other validators' co-signing requests on; requires
+    /// `verify_batches`, since only verified batches are signed
+    pub cosign_listen_address: Option<String>,
+    /// Token account of the stake mint receiving rewards for won disputes
+    pub reward_token_account: Option<String>,
+    /// `oasis_bridge` program whose deposits are credited on L2, if watched
+    pub bridge_program_id: Option<String>,
+    /// Re-execute posted batches, dispute invalid roots and defend this node's
+    /// own roots against disputes
+    pub verify_batches: bool,
+}
+
+impl L1Config {
+    /// Read the L1 connection from `OASIS_L1_*` environment variables, if set
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            rpc_url: std::env::var("OASIS_L1_RPC_URL").ok()?,
+            program_id: std::env::var("OASIS_L1_PROGRAM_ID").ok()?,
+            state_account: std::env::var("OASIS_L1_STATE_ACCOUNT").ok()?,
+            keypair_path: std::env::var("OASIS_L1_KEYPAIR").ok()?,
+            cosigners: std::env::var("OASIS_L1_COSIGNERS")
+                .map(|endpoints| {
+                    endpoints
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|endpoint| !endpoint.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            cosign_listen_address: std::env::var("OASIS_L1_COSIGN_ADDRESS").ok(),
+            reward_token_account: std::env::var("OASIS_L1_REWARD_TOKEN_ACCOUNT").ok(),
+            bridge_program_id: std::env::var("OASIS_L1_BRIDGE_PROGRAM_ID").ok(),
+            verify_batches: std::env::var("OASIS_L1_VERIFY_BATCHES")
+                .map_or(false, |value| value == "1" || value == "true"),
+        })
+    }
 }
 
 impl fmt::Debug for NetworkConfig {
@@ -15,6 +69,8 @@ impl fmt::Debug for NetworkConfig {
             .field("listen_addresses", &self.listen_addresses)
             .field("bootstrap_peers", &self.bootstrap_peers)
             .field("state_db_path", &self.state_db_path)
+            .field("genesis_path", &self.genesis_path)
+            .field("l1", &self.l1)
             .field("identity", &"<keypair>")
             .finish()
     }
@@ -32,8 +88,20 @@ impl NetworkConfig {
             listen_addresses,
             bootstrap_peers,
             state_db_path,
+            genesis_path: None,
+            l1: None,
         }
     }
+
+    pub fn with_genesis_path(mut self, genesis_path: String) -> Self {
+        self.genesis_path = Some(genesis_path);
+        self
+    }
+
+    pub fn with_l1(mut self, l1: L1Config) -> Self {
+        self.l1 = Some(l1);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -53,5 +121,7 @@ mod tests {
         assert_eq!(config.listen_addresses.len(), 1);
         assert_eq!(config.bootstrap_peers.len(), 0);
         assert_eq!(config.state_db_path, "test_db");
+        assert!(config.genesis_path.is_none());
+        assert!(config.l1.is_none());
     }
 }
@@ -0,0 +1,323 @@
+//! Co-signing of batch commitments.
+//!
+//! `update_state_root` only accepts a commitment signed by a quorum of
+//! validators. The leader sends each other validator a [`SignRequest`] with the
+//! payload it is about to post, and a validator only signs after rebuilding the
+//! batch from L1 data and re-executing it on its own L1-derived state. Requests
+//! and responses are single JSON lines over TCP.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+use crate::batch::{batch_from_payload, BatchCommitment, PostedDataSource};
+use crate::dispute::build_trace;
+use crate::state::StateManager;
+use crate::validator::{next_batch_index, unbacked_deposit};
+use crate::watcher::DepositReceiptSource;
+
+/// How long the leader waits for a validator to check and sign a batch
+const SIGN_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Upper bound of a request or response line. Payloads fit in one L1
+/// transaction, so this is far above any honest message.
+const MAX_MESSAGE_BYTES: u64 = 64 * 1024;
+
+/// Request to sign the commitment of batch `batch_index`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignRequest {
+    pub batch_index: u64,
+    pub commitment: BatchCommitment,
+    /// `update_state_root` payload posted with the commitment; chunks it
+    /// points to must already be on L1
+    pub data: Vec<u8>,
+}
+
+/// Answer to a [`SignRequest`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignResponse {
+    /// Signature of the validator's L1 key over the commitment message
+    Signed {
+        validator: Pubkey,
+        signature: Signature,
+    },
+    /// The batch did not check out
+    Rejected(String),
+}
+
+/// Answers sign requests for batches that re-execute to their committed root
+/// on top of `state`, the state a [`crate::validator::BatchValidator`] builds
+/// from posted batches
+pub struct BatchSigner<C> {
+    client: C,
+    keypair: Keypair,
+    state_account: [u8; 32],
+    state: Arc<RwLock<StateManager>>,
+}
+
+impl<C> BatchSigner<C>
+where
+    C: PostedDataSource + DepositReceiptSource + 'static,
+{
+    pub fn new(
+        client: C,
+        keypair: Keypair,
+        state_account: [u8; 32],
+        state: Arc<RwLock<StateManager>>,
+    ) -> Self {
+        Self {
+            client,
+            keypair,
+            state_account,
+            state,
+        }
+    }
+
+    /// Answer sign requests on `listener` until the task is dropped
+    pub async fn run(self, listener: TcpListener) {
+        let signer = Arc::new(self);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::error!("Failed to accept sign request: {}", e);
+                    continue;
+                }
+            };
+            let signer = signer.clone();
+            tokio::spawn(async move {
+                if let Err(e) = signer.answer(stream).await {
+                    log::warn!("Sign request from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn answer(&self, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(reader)
+            .take(MAX_MESSAGE_BYTES)
+            .read_line(&mut line)
+            .await?;
+        let request: SignRequest = serde_json::from_str(&line)?;
+
+        let response = match self.sign(&request).await {
+            Ok(signature) => SignResponse::Signed {
+                validator: self.keypair.pubkey(),
+                signature,
+            },
+            Err(e) => {
+                log::warn!("Refusing to sign batch {}: {}", request.batch_index, e);
+                SignResponse::Rejected(e.to_string())
+            }
+        };
+        let mut line = serde_json::to_vec(&response)?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        Ok(())
+    }
+
+    /// Sign `request` if its batch is the next one after the validated head
+    /// and re-executes to the committed root
+    pub async fn sign(&self, request: &SignRequest) -> Result<Signature> {
+        let batch = batch_from_payload(&self.client, &request.data).await?;
+        if batch.commitment(&request.data)? != request.commitment {
+            return Err(anyhow::anyhow!("Commitment does not match the batch data"));
+        }
+        let transactions: Vec<_> = batch.transactions().cloned().collect();
+        if let Some(nonce) = unbacked_deposit(&self.client, &transactions).await? {
+            return Err(anyhow::anyhow!(
+                "Deposit {} does not match its bridge receipt",
+                nonce
+            ));
+        }
+
+        let state = self.state.read().await;
+        let next = next_batch_index(&state)?;
+        if request.batch_index != next {
+            return Err(anyhow::anyhow!(
+                "Validated up to batch {}, not {}",
+                next,
+                request.batch_index
+            ));
+        }
+        let head = state.get_current_root();
+        if batch.header.start_height != head.height + 1 || batch.header.previous_root != head.root {
+            return Err(anyhow::anyhow!(
+                "Batch does not build on the validated head"
+            ));
+        }
+        let trace = build_trace(&state, &batch, &tempfile::tempdir()?).await?;
+        if trace.failed_at.is_some()
+            || trace.root(request.commitment.transaction_count) != Some(request.commitment.new_root)
+        {
+            return Err(anyhow::anyhow!(
+                "Batch does not execute to root {}",
+                hex::encode(request.commitment.new_root)
+            ));
+        }
+
+        let message = request
+            .commitment
+            .message(&self.state_account, request.batch_index);
+        Ok(self.keypair.sign_message(&message))
+    }
+}
+
+/// Ask the validator answering at `endpoint` to sign `request`, returning its
+/// key and a signature checked against the commitment message
+pub async fn request_signature(
+    endpoint: &str,
+    state_account: &[u8; 32],
+    request: &SignRequest,
+) -> Result<(Pubkey, Signature)> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(endpoint).await?;
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        stream.write_all(&line).await?;
+
+        let mut line = String::new();
+        BufReader::new(stream)
+            .take(MAX_MESSAGE_BYTES)
+            .read_line(&mut line)
+            .await?;
+        Ok::<_, anyhow::Error>(serde_json::from_str(&line)?)
+    };
+    let response = tokio::time::timeout(SIGN_REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow::anyhow!("{} did not answer in time", endpoint))??;
+
+    match response {
+        SignResponse::Signed {
+            validator,
+            signature,
+        } => {
+            let message = request
+                .commitment
+                .message(state_account, request.batch_index);
+            if !signature.verify(validator.as_ref(), &message) {
+                return Err(anyhow::anyhow!(
+                    "{} returned an invalid signature",
+                    endpoint
+                ));
+            }
+            Ok((validator, signature))
+        }
+        SignResponse::Rejected(reason) => {
+            Err(anyhow::anyhow!("{} refused to sign: {}", endpoint, reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, BatchData};
+    use crate::genesis::Genesis;
+    use crate::rollup::Rollup;
+    use crate::types::{Block, Transaction};
+    use crate::watcher::DepositReceipt;
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    struct MockL1;
+
+    #[async_trait]
+    impl PostedDataSource for MockL1 {
+        async fn posted_data(&self, signature: &str) -> Result<Vec<u8>> {
+            Err(anyhow::anyhow!("Unknown signature {}", signature))
+        }
+    }
+
+    #[async_trait]
+    impl DepositReceiptSource for MockL1 {
+        async fn deposit_receipt(&self, _nonce: u64) -> Result<Option<DepositReceipt>> {
+            Ok(None)
+        }
+    }
+
+    fn request(batch_index: u64, batch: &Batch) -> Result<SignRequest> {
+        let data = BatchData::Inline(batch.encode()?).encode()?;
+        Ok(SignRequest {
+            batch_index,
+            commitment: batch.commitment(&data)?,
+            data,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_signs_only_batches_that_check_out() -> Result<()> {
+        let alice = [1u8; 32];
+        let mut genesis = Genesis::default();
+        genesis.balances.insert(hex::encode(alice), 1_000);
+
+        let sequencer_dir = tempdir()?;
+        let mut manager = StateManager::new(&sequencer_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let sequencer = Arc::new(RwLock::new(manager));
+        let tx = Transaction::Transfer {
+            from: alice,
+            to: [2u8; 32],
+            amount: 10,
+        };
+        Rollup::new(sequencer.clone())?
+            .process_block(Block::new(1, [0u8; 32], vec![tx], 0))
+            .await?;
+        let batch = Batch::from_state(&*sequencer.read().await, 1, 1)?;
+
+        let validator_dir = tempdir()?;
+        let mut manager = StateManager::new(&validator_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let keypair = Keypair::new();
+        let validator = keypair.pubkey();
+        let state_account = [4u8; 32];
+        let signer = BatchSigner::new(
+            MockL1,
+            keypair,
+            state_account,
+            Arc::new(RwLock::new(manager)),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = listener.local_addr()?.to_string();
+        let server = tokio::spawn(signer.run(listener));
+
+        let valid = request(0, &batch)?;
+        let (signer_key, signature) = request_signature(&endpoint, &state_account, &valid).await?;
+        assert_eq!(signer_key, validator);
+        let message = valid.commitment.message(&state_account, 0);
+        assert!(signature.verify(validator.as_ref(), &message));
+
+        // The batch does not execute to the root it claims
+        let mut invalid = batch.clone();
+        invalid.header.state_root = [9u8; 32];
+        let error = request_signature(&endpoint, &state_account, &request(0, &invalid)?)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("does not execute"));
+
+        // Batch 0 has not been validated, so nothing builds on batch 1 yet
+        assert!(
+            request_signature(&endpoint, &state_account, &request(1, &batch)?)
+                .await
+                .is_err()
+        );
+
+        // The commitment does not match the posted data
+        let mut tampered = valid.clone();
+        tampered.commitment.transaction_count += 1;
+        assert!(request_signature(&endpoint, &state_account, &tampered)
+            .await
+            .is_err());
+
+        server.abort();
+        Ok(())
+    }
+}
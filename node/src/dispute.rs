@@ -0,0 +1,638 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::batch::{transaction_proof, Batch};
+use crate::executor::execute_transaction;
+use crate::l1::AccountReader;
+use crate::smt::{key_path, SmtProof};
+use crate::state::StateManager;
+use crate::types::Transaction;
+use crate::watcher::event_discriminator;
+
+/// Whose move a dispute is waiting for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeTurn {
+    Asserter,
+    Challenger,
+    /// A single transaction is left; anyone may settle it with a step proof
+    OneStep,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Active,
+    ChallengerWon,
+    AsserterWon,
+}
+
+/// Fields of the `solana_oasis` `Dispute` account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisputeAccount {
+    pub state: [u8; 32],
+    pub batch_index: u64,
+    pub asserter: [u8; 32],
+    pub challenger: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+    pub agreed_step: u64,
+    pub agreed_root: [u8; 32],
+    pub disputed_step: u64,
+    pub disputed_root: [u8; 32],
+    pub midpoint_root: [u8; 32],
+    pub turn: DisputeTurn,
+    pub status: DisputeStatus,
+    pub deadline: i64,
+    /// `revert_count` of the disputed root record, part of the dispute's
+    /// address
+    pub revert_count: u64,
+}
+
+impl DisputeAccount {
+    /// Decode the Borsh account data, including the 8-byte discriminator
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = AccountReader::new(data);
+        reader.take(8)?;
+
+        Ok(Self {
+            state: reader.bytes32()?,
+            batch_index: reader.u64()?,
+            asserter: reader.bytes32()?,
+            challenger: reader.bytes32()?,
+            transactions_root: reader.bytes32()?,
+            transaction_count: reader.u64()?,
+            agreed_step: reader.u64()?,
+            agreed_root: reader.bytes32()?,
+            disputed_step: reader.u64()?,
+            disputed_root: reader.bytes32()?,
+            midpoint_root: reader.bytes32()?,
+            turn: match reader.take(1)?[0] {
+                0 => DisputeTurn::Asserter,
+                1 => DisputeTurn::Challenger,
+                2 => DisputeTurn::OneStep,
+                turn => return Err(anyhow::anyhow!("Unknown dispute turn {}", turn)),
+            },
+            status: match reader.take(1)?[0] {
+                0 => DisputeStatus::Active,
+                1 => DisputeStatus::ChallengerWon,
+                2 => DisputeStatus::AsserterWon,
+                status => return Err(anyhow::anyhow!("Unknown dispute status {}", status)),
+            },
+            deadline: reader.u64()? as i64,
+            revert_count: reader.u64()?,
+        })
+    }
+
+    /// Step the asserter has to post a root for next
+    pub fn midpoint(&self) -> u64 {
+        self.agreed_step + (self.disputed_step - self.agreed_step) / 2
+    }
+}
+
+/// `DisputeOpened` event emitted by `open_dispute`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisputeOpened {
+    pub batch_index: u64,
+    pub asserter: [u8; 32],
+    pub challenger: [u8; 32],
+    pub transaction_count: u64,
+    pub deadline: i64,
+}
+
+impl DisputeOpened {
+    /// Decode a `DisputeOpened` Anchor event payload
+    pub fn decode_event(data: &[u8]) -> Option<Self> {
+        let fields = data.strip_prefix(&event_discriminator("DisputeOpened")[..])?;
+        if fields.len() != 88 {
+            return None;
+        }
+
+        Some(Self {
+            batch_index: u64::from_le_bytes(fields[0..8].try_into().ok()?),
+            asserter: fields[8..40].try_into().ok()?,
+            challenger: fields[40..72].try_into().ok()?,
+            transaction_count: u64::from_le_bytes(fields[72..80].try_into().ok()?),
+            deadline: i64::from_le_bytes(fields[80..88].try_into().ok()?),
+        })
+    }
+}
+
+/// `FraudProofSubmitted` event emitted when a won dispute rolls the root back
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FraudProofSubmitted {
+    pub challenger: [u8; 32],
+    /// Slashed validator
+    pub validator: [u8; 32],
+    /// Invalid batch; the next batch posted reuses its index
+    pub batch_index: u64,
+    pub restored_root: [u8; 32],
+    pub slashed_amount: u64,
+    pub reward_amount: u64,
+    pub timestamp: i64,
+}
+
+impl FraudProofSubmitted {
+    /// Decode a `FraudProofSubmitted` Anchor event payload
+    pub fn decode_event(data: &[u8]) -> Option<Self> {
+        let fields = data.strip_prefix(&event_discriminator("FraudProofSubmitted")[..])?;
+        if fields.len() != 128 {
+            return None;
+        }
+
+        Some(Self {
+            challenger: fields[0..32].try_into().ok()?,
+            validator: fields[32..64].try_into().ok()?,
+            batch_index: u64::from_le_bytes(fields[64..72].try_into().ok()?),
+            restored_root: fields[72..104].try_into().ok()?,
+            slashed_amount: u64::from_le_bytes(fields[104..112].try_into().ok()?),
+            reward_amount: u64::from_le_bytes(fields[112..120].try_into().ok()?),
+            timestamp: i64::from_le_bytes(fields[120..128].try_into().ok()?),
+        })
+    }
+}
+
+/// Roots this node computes for the steps of a batch
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// Root after each number of transactions, starting with the pre-state
+    pub roots: Vec<[u8; 32]>,
+    /// Index of the first transaction that fails to execute; no step after it
+    /// has a valid root
+    pub failed_at: Option<u64>,
+}
+
+impl Trace {
+    /// Root after the first `step` transactions, `None` if one of them fails
+    pub fn root(&self, step: u64) -> Option<[u8; 32]> {
+        self.roots.get(step as usize).copied()
+    }
+}
+
+/// Pre-transaction value of one state key with its proof
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateAccess {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    pub proof: SmtProof,
+}
+
+/// Evidence settling a one-step dispute: a transaction of the batch and every
+/// state value it accesses.
+///
+/// Access proofs are taken one after the other, each after the keys before it
+/// were set to their post-transaction values, so they chain from the agreed
+/// root to the root after the transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepProof {
+    /// Bincode-encoded transaction, as committed in the transactions root
+    pub transaction: Vec<u8>,
+    /// Siblings of the transaction in the transactions tree, from the leaf up
+    pub transaction_proof: Vec<[u8; 32]>,
+    pub accesses: Vec<StateAccess>,
+}
+
+impl StepProof {
+    /// Nonce of the bridge deposit the transaction credits, if it is one; the
+    /// program checks it against that deposit's receipt
+    pub fn deposit_nonce(&self) -> Option<u64> {
+        match bincode::deserialize(&self.transaction) {
+            Ok(Transaction::Deposit { nonce, .. }) => Some(nonce),
+            _ => None,
+        }
+    }
+
+    /// Borsh encoding of the program's `StepProofData`
+    pub fn encode(&self) -> Vec<u8> {
+        fn bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+            data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            data.extend_from_slice(bytes);
+        }
+        fn hashes(data: &mut Vec<u8>, hashes: &[[u8; 32]]) {
+            data.extend_from_slice(&(hashes.len() as u32).to_le_bytes());
+            data.extend(hashes.concat());
+        }
+
+        let mut data = Vec::new();
+        bytes(&mut data, &self.transaction);
+        hashes(&mut data, &self.transaction_proof);
+        data.extend_from_slice(&(self.accesses.len() as u32).to_le_bytes());
+        for access in &self.accesses {
+            bytes(&mut data, &access.key);
+            match &access.value {
+                Some(value) => {
+                    data.push(1);
+                    bytes(&mut data, value);
+                }
+                None => data.push(0),
+            }
+            data.extend_from_slice(&access.proof.bitmap);
+            hashes(&mut data, &access.proof.siblings);
+        }
+        data
+    }
+
+    /// Root after the transaction, checking the access proofs against
+    /// `pre_root` the way the program does; `None` if the transaction fails
+    pub fn post_root(&self, pre_root: [u8; 32]) -> Result<Option<[u8; 32]>> {
+        let transaction: Transaction = bincode::deserialize(&self.transaction)?;
+        let result = execute_transaction(&transaction, |key| {
+            self.accesses
+                .iter()
+                .find(|access| access.key == key)
+                .and_then(|access| access.value.clone())
+        });
+
+        let mut root = pre_root;
+        for access in &self.accesses {
+            let path = key_path(&access.key);
+            if access.proof.root(&path, access.value.as_deref())? != root {
+                return Err(anyhow::anyhow!(
+                    "Proof of {} does not match the root",
+                    hex::encode(&access.key)
+                ));
+            }
+            let value = match &result {
+                Ok(writes) => last_write(writes, &access.key).or_else(|| access.value.clone()),
+                Err(_) => access.value.clone(),
+            };
+            root = access.proof.root(&path, value.as_deref())?;
+        }
+        Ok(result.is_ok().then_some(root))
+    }
+}
+
+fn last_write(writes: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> Option<Vec<u8>> {
+    writes
+        .iter()
+        .rev()
+        .find(|(written, _)| written == key)
+        .map(|(_, value)| value.clone())
+}
+
+/// Re-execute `batch` transaction by transaction, recording the root after each.
+///
+/// Execution happens in a checkpoint of `source` under `workdir`, rolled back to
+/// the parent of the batch, so the source database is never modified.
+pub async fn build_trace(
+    source: &StateManager,
+    batch: &Batch,
+    workdir: &impl AsRef<Path>,
+) -> Result<Trace> {
+    Ok(replay(source, batch, None, workdir).await?.0)
+}
+
+/// Proof of transaction `step` of `batch`, executed on top of the state after
+/// the transactions before it
+pub async fn step_proof(
+    source: &StateManager,
+    batch: &Batch,
+    step: u64,
+    workdir: &impl AsRef<Path>,
+) -> Result<StepProof> {
+    replay(source, batch, Some(step), workdir)
+        .await?
+        .1
+        .ok_or_else(|| anyhow::anyhow!("Step {} is beyond the batch", step))
+}
+
+async fn replay(
+    source: &StateManager,
+    batch: &Batch,
+    prove: Option<u64>,
+    workdir: &impl AsRef<Path>,
+) -> Result<(Trace, Option<StepProof>)> {
+    let parent = batch.header.start_height - 1;
+    let mut state = source.checkpoint(&workdir.as_ref().join("dispute-state"))?;
+    state.rollback_to(parent).await?;
+    if state.get_current_root().root != batch.header.previous_root {
+        return Err(anyhow::anyhow!(
+            "Batch does not build on the root at height {}",
+            parent
+        ));
+    }
+
+    let transactions: Vec<Transaction> = batch.transactions().cloned().collect();
+    let mut trace = Trace {
+        roots: vec![batch.header.previous_root],
+        failed_at: None,
+    };
+    let mut step = 0;
+
+    for block in batch.to_blocks() {
+        // Writes stay in an overlay so the tree can read the committed nodes
+        let mut tree = state.tree();
+        let mut overlay: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut writes = Vec::new();
+
+        for transaction in &block.transactions {
+            let mut keys: Vec<Vec<u8>> = Vec::new();
+            let mut values = HashMap::new();
+            for key in transaction.access_list() {
+                if values.contains_key(&key) {
+                    continue;
+                }
+                let value = match overlay.get(&key) {
+                    Some(value) => Some(value.clone()),
+                    None => state.get_value(&key).await?,
+                };
+                values.insert(key.clone(), value);
+                keys.push(key);
+            }
+            let result = execute_transaction(transaction, |key| values.get(key).cloned().flatten());
+
+            if prove == Some(step) {
+                let mut accesses = Vec::with_capacity(keys.len());
+                for key in keys {
+                    let value = values.remove(&key).flatten();
+                    let proof = tree.proof(&key)?;
+                    let new_value = match &result {
+                        Ok(writes) => last_write(writes, &key).or_else(|| value.clone()),
+                        Err(_) => value.clone(),
+                    };
+                    tree.update(&key, new_value.as_deref())?;
+                    accesses.push(StateAccess { key, value, proof });
+                }
+
+                let proof = StepProof {
+                    transaction: bincode::serialize(transaction)?,
+                    transaction_proof: transaction_proof(&transactions, step as usize)?,
+                    accesses,
+                };
+                return Ok((trace, Some(proof)));
+            }
+
+            match result {
+                Ok(transaction_writes) => {
+                    for (key, value) in transaction_writes {
+                        tree.update(&key, Some(&value))?;
+                        overlay.insert(key.clone(), value.clone());
+                        writes.push((key, value));
+                    }
+                    trace.roots.push(tree.root()?);
+                }
+                Err(e) => {
+                    log::debug!("Transaction {} of the batch fails: {}", step, e);
+                    trace.failed_at = Some(step);
+                    return Ok((trace, None));
+                }
+            }
+            step += 1;
+        }
+
+        drop(tree);
+        for (key, value) in writes {
+            state.set_value(&key, value).await?;
+        }
+        state.commit_block(&block).await?;
+    }
+
+    Ok((trace, None))
+}
+
+/// Move a party makes in a dispute
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisputeMove {
+    /// Post the asserter's root at the midpoint
+    Bisect([u8; 32]),
+    /// Accept or reject the asserter's midpoint root
+    Respond { agree: bool },
+    /// Settle the remaining transaction on chain
+    ProveStep,
+    /// Claim the win because the other party missed its deadline
+    Timeout,
+}
+
+/// Move `signer` should make in `dispute` given its own `trace` of the batch,
+/// if any. A challenger without the batch data plays with an empty trace: it
+/// rejects every midpoint and leaves the last step for the asserter to prove.
+pub fn next_move(
+    dispute: &DisputeAccount,
+    signer: &[u8; 32],
+    trace: &Trace,
+    now: i64,
+) -> Option<DisputeMove> {
+    if dispute.status != DisputeStatus::Active {
+        return None;
+    }
+    let is_asserter = dispute.asserter == *signer;
+    let is_challenger = dispute.challenger == *signer;
+    let expired = now > dispute.deadline;
+
+    match dispute.turn {
+        DisputeTurn::Asserter if is_asserter && !expired => {
+            trace.root(dispute.midpoint()).map(DisputeMove::Bisect)
+        }
+        DisputeTurn::Challenger if is_challenger && !expired => Some(DisputeMove::Respond {
+            agree: trace.root(dispute.midpoint()) == Some(dispute.midpoint_root),
+        }),
+        DisputeTurn::Asserter if is_challenger && expired => Some(DisputeMove::Timeout),
+        DisputeTurn::Challenger if is_asserter && expired => Some(DisputeMove::Timeout),
+        DisputeTurn::OneStep if is_challenger && expired => Some(DisputeMove::Timeout),
+        DisputeTurn::OneStep
+            if (is_asserter || is_challenger) && trace.root(dispute.agreed_step).is_some() =>
+        {
+            Some(DisputeMove::ProveStep)
+        }
+        _ => None,
+    }
+}
+
+/// L1 interaction needed to play disputes
+#[async_trait]
+pub trait DisputeClient: Send + Sync {
+    /// Account signing this node's moves
+    fn signer(&self) -> [u8; 32];
+
+    /// Challenge period configured in the `solana_oasis` state account
+    async fn challenge_period(&self) -> Result<i64>;
+
+    /// Dispute `challenger` opened over the root recorded for batch
+    /// `batch_index`, if any. Every challenger plays its own dispute over a
+    /// batch.
+    async fn dispute(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+    ) -> Result<Option<DisputeAccount>>;
+
+    /// Send `open_dispute` with this node as challenger, locking its bond, and
+    /// return the transaction signature
+    async fn open_dispute(&self, batch_index: u64) -> Result<String>;
+
+    async fn bisect_dispute(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+        midpoint_root: [u8; 32],
+    ) -> Result<String>;
+
+    async fn respond_dispute(&self, batch_index: u64, agree: bool) -> Result<String>;
+
+    /// Upload the encoded `proof` and send `prove_step`
+    async fn prove_step(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+        proof: &StepProof,
+    ) -> Result<String>;
+
+    async fn timeout_dispute(&self, batch_index: u64, challenger: &[u8; 32]) -> Result<String>;
+
+    /// Send `submit_fraud_proof` for a dispute this node won as challenger
+    async fn submit_fraud_proof(&self, batch_index: u64) -> Result<String>;
+
+    /// Send `settle_stale_dispute` for the dispute `challenger` opened over
+    /// batch `batch_index` before a fraud proof reverted it
+    async fn settle_stale_dispute(&self, batch_index: u64, challenger: &[u8; 32])
+        -> Result<String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollup::Rollup;
+    use crate::types::{balance_key, Block};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_bisection_reaches_the_invalid_step() -> Result<()> {
+        let alice = [1u8; 32];
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        state
+            .write()
+            .await
+            .set_value(&balance_key(&alice), bincode::serialize(&100u64)?)
+            .await?;
+        state
+            .write()
+            .await
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?;
+
+        let mut rollup = Rollup::new(state.clone())?;
+        for number in 2..=3 {
+            let transactions = (0..3)
+                .map(|i| Transaction::Transfer {
+                    from: alice,
+                    to: [number as u8 * 10 + i; 32],
+                    amount: 5,
+                })
+                .collect();
+            rollup
+                .process_block(Block::new(number, [0u8; 32], transactions, 0))
+                .await?;
+        }
+
+        let source = state.read().await;
+        let batch = Batch::from_state(&source, 2, 3)?;
+        let transaction_count = batch.transactions().count() as u64;
+
+        let trace = build_trace(&source, &batch, &tempdir()?).await?;
+        assert_eq!(trace.roots.len(), 7);
+        assert_eq!(trace.root(0), Some(batch.header.previous_root));
+        assert_eq!(trace.root(3), Some(source.get_root_at(2)?.unwrap().root));
+        assert_eq!(trace.root(6), Some(batch.header.state_root));
+
+        // The asserter's claims diverge after the fourth transaction
+        let claim = |step: u64| match step {
+            0..=4 => trace.root(step).unwrap(),
+            _ => [step as u8; 32],
+        };
+        let challenger = [2u8; 32];
+        let mut dispute = DisputeAccount {
+            state: [0u8; 32],
+            batch_index: 0,
+            asserter: [3u8; 32],
+            challenger,
+            transactions_root: [0u8; 32],
+            transaction_count,
+            agreed_step: 0,
+            agreed_root: trace.root(0).unwrap(),
+            disputed_step: transaction_count,
+            disputed_root: claim(transaction_count),
+            midpoint_root: [0u8; 32],
+            turn: DisputeTurn::Asserter,
+            status: DisputeStatus::Active,
+            deadline: 100,
+            revert_count: 0,
+        };
+        while dispute.disputed_step - dispute.agreed_step > 1 {
+            let midpoint = dispute.midpoint();
+            dispute.midpoint_root = claim(midpoint);
+            dispute.turn = DisputeTurn::Challenger;
+            let Some(DisputeMove::Respond { agree }) = next_move(&dispute, &challenger, &trace, 0)
+            else {
+                panic!("Expected a response");
+            };
+            if agree {
+                dispute.agreed_step = midpoint;
+                dispute.agreed_root = dispute.midpoint_root;
+            } else {
+                dispute.disputed_step = midpoint;
+                dispute.disputed_root = dispute.midpoint_root;
+            }
+        }
+        assert_eq!((dispute.agreed_step, dispute.disputed_step), (4, 5));
+
+        dispute.turn = DisputeTurn::OneStep;
+        assert_eq!(
+            next_move(&dispute, &challenger, &trace, 0),
+            Some(DisputeMove::ProveStep)
+        );
+        let proof = step_proof(&source, &batch, 4, &tempdir()?).await?;
+        assert_eq!(proof.post_root(dispute.agreed_root)?, trace.root(5));
+        assert_ne!(trace.root(5), Some(dispute.disputed_root));
+
+        Ok(())
+    }
+    #[test]
+    fn test_challenger_without_data_leaves_the_last_step_to_the_asserter() {
+        let asserter = [3u8; 32];
+        let challenger = [2u8; 32];
+        let mut dispute = DisputeAccount {
+            state: [0u8; 32],
+            batch_index: 0,
+            asserter,
+            challenger,
+            transactions_root: [0u8; 32],
+            transaction_count: 2,
+            agreed_step: 0,
+            agreed_root: [0u8; 32],
+            disputed_step: 2,
+            disputed_root: [2u8; 32],
+            midpoint_root: [1u8; 32],
+            turn: DisputeTurn::Challenger,
+            status: DisputeStatus::Active,
+            deadline: 100,
+            revert_count: 0,
+        };
+        let blind = Trace::default();
+        assert_eq!(
+            next_move(&dispute, &challenger, &blind, 0),
+            Some(DisputeMove::Respond { agree: false })
+        );
+
+        dispute.disputed_step = 1;
+        dispute.turn = DisputeTurn::OneStep;
+        assert_eq!(next_move(&dispute, &challenger, &blind, 0), None);
+        assert_eq!(
+            next_move(&dispute, &challenger, &blind, 101),
+            Some(DisputeMove::Timeout)
+        );
+
+        // The asserter cannot run out the clock on its own proof
+        let trace = Trace {
+            roots: vec![[0u8; 32], [1u8; 32]],
+            failed_at: None,
+        };
+        assert_eq!(
+            next_move(&dispute, &asserter, &trace, 101),
+            Some(DisputeMove::ProveStep)
+        );
+    }
+}
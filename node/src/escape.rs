@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::smt::SmtProof;
+use crate::state::StateManager;
+use crate::types::{token_balance_key, token_id};
+
+/// Balance of an account in one bridged token, provable against the state root
+/// of `height`. The bridge pays it out through its escape hatch once the L2
+/// stopped posting roots.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceProof {
+    pub height: u64,
+    pub state_root: [u8; 32],
+    pub account: [u8; 32],
+    /// L1 mint of the token
+    pub mint: [u8; 32],
+    pub balance: u64,
+    pub proof: SmtProof,
+}
+
+/// Prove `account`'s balance of `mint` as of block `height` of `source`.
+///
+/// Only local state is read, so proofs can be made while the L2 and L1 are
+/// unreachable. Past heights are proven in a checkpoint of `source` under
+/// `workdir`, rolled back to `height`, so the source database is never modified.
+pub async fn balance_proof(
+    source: &StateManager,
+    account: &[u8; 32],
+    mint: &[u8; 32],
+    height: u64,
+    workdir: &impl AsRef<Path>,
+) -> Result<BalanceProof> {
+    if height == source.get_current_root().height {
+        return prove_balance(source, account, mint).await;
+    }
+
+    let mut copy = source.checkpoint(&workdir.as_ref().join("escape-state"))?;
+    copy.rollback_to(height).await?;
+    prove_balance(&copy, account, mint).await
+}
+
+async fn prove_balance(
+    state: &StateManager,
+    account: &[u8; 32],
+    mint: &[u8; 32],
+) -> Result<BalanceProof> {
+    let root = state.get_current_root();
+    let key = token_balance_key(&token_id(mint), account);
+    let balance: u64 = match state.get_value(&key).await? {
+        Some(data) => bincode::deserialize(&data)?,
+        None => {
+            return Err(anyhow::anyhow!(
+                "Account {} holds no balance of {} at height {}",
+                hex::encode(account),
+                hex::encode(mint),
+                root.height
+            ))
+        }
+    };
+
+    Ok(BalanceProof {
+        height: root.height,
+        state_root: root.root,
+        account: *account,
+        mint: *mint,
+        balance,
+        proof: state.prove(&key)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smt::key_path;
+    use crate::types::Block;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_past_balance_is_proven_against_its_root() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut state = StateManager::new(&temp_dir.path().join("state"))?;
+        let (account, mint) = ([1u8; 32], [2u8; 32]);
+        let key = token_balance_key(&token_id(&mint), &account);
+
+        state.set_value(&key, bincode::serialize(&100u64)?).await?;
+        let first = state
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?
+            .root;
+        state.set_value(&key, bincode::serialize(&40u64)?).await?;
+        state
+            .commit_block(&Block::new(2, [0u8; 32], vec![], 0))
+            .await?;
+
+        let proof = balance_proof(&state, &account, &mint, 1, &temp_dir).await?;
+        assert_eq!(proof.balance, 100);
+        assert_eq!(proof.state_root, first.root);
+        assert_eq!(
+            proof
+                .proof
+                .root(&key_path(&key), Some(&100u64.to_le_bytes()))?,
+            first.root
+        );
+
+        // The source keeps its head
+        assert_eq!(state.get_current_root().height, 2);
+        let head = balance_proof(&state, &account, &mint, 2, &temp_dir).await?;
+        assert_eq!(head.balance, 40);
+
+        assert!(balance_proof(&state, &[9u8; 32], &mint, 2, &temp_dir)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
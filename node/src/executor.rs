@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::thread;
+
+use anyhow::Result;
+
+use crate::types::{
+    balance_key, deposit_id, deposit_key, forced_key, token_balance_key, token_id, withdrawal_hash,
+    withdrawal_key, Transaction,
+};
+
+/// Key/value pairs written by a single transaction, in write order
+pub type WriteSet = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Pre-block values of every key a block may touch; `None` marks an absent key
+pub type Snapshot = HashMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Execute a transaction, reading state through `read`.
+///
+/// Execution is pure so the sequential and parallel paths share the exact same
+/// semantics and therefore produce identical state.
+pub fn execute_transaction(
+    transaction: &Transaction,
+    read: impl Fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<WriteSet> {
+    execute(transaction, &read)
+}
+
+fn execute(transaction: &Transaction, read: &dyn Fn(&[u8]) -> Option<Vec<u8>>) -> Result<WriteSet> {
+    match transaction {
+        Transaction::Transfer { from, to, amount } => {
+            // Crediting the balance read before the debit would mint `amount`
+            if from == to {
+                return Err(anyhow::anyhow!("Transfer to the sender itself"));
+            }
+            let from_key = balance_key(from);
+            let from_balance: u64 = match read(&from_key) {
+                Some(data) => bincode::deserialize(&data)?,
+                None => 0,
+            };
+
+            if from_balance < *amount {
+                return Err(anyhow::anyhow!("Insufficient balance"));
+            }
+
+            let to_key = balance_key(to);
+            let to_balance: u64 = match read(&to_key) {
+                Some(data) => bincode::deserialize(&data)?,
+                None => 0,
+            };
+            let credited = to_balance
+                .checked_add(*amount)
+                .ok_or_else(|| anyhow::anyhow!("Balance overflow"))?;
+
+            Ok(vec![
+                (from_key, bincode::serialize(&(from_balance - amount))?),
+                (to_key, bincode::serialize(&credited)?),
+            ])
+        }
+        Transaction::Deposit {
+            nonce,
+            recipient,
+            token,
+            amount,
+        } => {
+            let marker = deposit_key(&deposit_id(*nonce));
+            if read(&marker).is_some() {
+                return Err(anyhow::anyhow!("Deposit {} was already credited", nonce));
+            }
+
+            let to_key = token_balance_key(token, recipient);
+            let to_balance: u64 = match read(&to_key) {
+                Some(data) => bincode::deserialize(&data)?,
+                None => 0,
+            };
+            let credited = to_balance
+                .checked_add(*amount)
+                .ok_or_else(|| anyhow::anyhow!("Balance overflow"))?;
+
+            Ok(vec![
+                (marker, bincode::serialize(amount)?),
+                (to_key, bincode::serialize(&credited)?),
+            ])
+        }
+        Transaction::Forced {
+            index,
+            sender,
+            transaction,
+        } => {
+            let marker = forced_key(*index);
+            if read(&marker).is_some() {
+                return Err(anyhow::anyhow!(
+                    "Forced transaction {} was already included",
+                    index
+                ));
+            }
+
+            // Only transfers and withdrawals out of the sender's own account
+            // may be forced
+            let result = match bincode::deserialize::<Transaction>(transaction) {
+                Ok(
+                    inner @ (Transaction::Transfer { from, .. }
+                    | Transaction::Withdraw { from, .. }),
+                ) if from == *sender => execute(&inner, read),
+                Ok(_) => Err(anyhow::anyhow!("Forced transaction is not authorized")),
+                Err(e) => Err(e.into()),
+            };
+
+            match result {
+                Ok(mut writes) => {
+                    writes.insert(0, (marker, vec![1]));
+                    Ok(writes)
+                }
+                Err(e) => {
+                    log::debug!("Forced transaction {} failed: {}", index, e);
+                    Ok(vec![(marker, vec![0])])
+                }
+            }
+        }
+        Transaction::Withdraw {
+            from,
+            recipient,
+            token,
+            amount,
+            nonce,
+        } => {
+            let hash = withdrawal_hash(from, recipient, token, *amount, *nonce);
+            let marker = withdrawal_key(&hash);
+            if read(&marker).is_some() {
+                return Err(anyhow::anyhow!(
+                    "Withdrawal {} was already made",
+                    hex::encode(hash)
+                ));
+            }
+
+            let from_key = token_balance_key(&token_id(token), from);
+            let from_balance: u64 = match read(&from_key) {
+                Some(data) => bincode::deserialize(&data)?,
+                None => 0,
+            };
+            if from_balance < *amount {
+                return Err(anyhow::anyhow!("Insufficient balance"));
+            }
+
+            Ok(vec![
+                (from_key, bincode::serialize(&(from_balance - amount))?),
+                (marker, bincode::serialize(amount)?),
+            ])
+        }
+    }
+}
+
+/// Outcome of executing a block
+#[derive(Debug)]
+pub struct BlockExecution {
+    /// Write set of each transaction, in block order
+    pub write_sets: Vec<WriteSet>,
+    /// Transactions whose optimistic result was discarded because of a conflict
+    pub reexecuted: usize,
+}
+
+/// Optimistic parallel executor.
+///
+/// Every transaction first runs concurrently against the pre-block snapshot.
+/// Results are then validated in block order: a transaction whose access list
+/// overlaps a key written by an earlier transaction of the same block is
+/// re-executed on top of those writes, so the outcome always matches
+/// sequential execution.
+pub struct ParallelExecutor {
+    threads: usize,
+}
+
+impl ParallelExecutor {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn execute(
+        &self,
+        transactions: &[Transaction],
+        snapshot: &Snapshot,
+    ) -> Result<BlockExecution> {
+        if transactions.is_empty() {
+            return Ok(BlockExecution {
+                write_sets: Vec::new(),
+                reexecuted: 0,
+            });
+        }
+
+        let chunk_size = transactions.len().div_ceil(self.threads);
+        let speculative: Vec<Result<WriteSet>> = thread::scope(|scope| {
+            let handles: Vec<_> = transactions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|tx| execute_transaction(tx, |key| read_snapshot(snapshot, key)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Execution thread panicked"))
+                .collect()
+        });
+
+        let mut overlay: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut write_sets = Vec::with_capacity(transactions.len());
+        let mut reexecuted = 0;
+
+        for (tx, result) in transactions.iter().zip(speculative) {
+            let conflicted = tx.access_list().iter().any(|key| overlay.contains_key(key));
+            let writes = if conflicted {
+                reexecuted += 1;
+                execute_transaction(tx, |key| {
+                    overlay
+                        .get(key)
+                        .cloned()
+                        .or_else(|| read_snapshot(snapshot, key))
+                })?
+            } else {
+                result?
+            };
+
+            for (key, value) in &writes {
+                overlay.insert(key.clone(), value.clone());
+            }
+            write_sets.push(writes);
+        }
+
+        Ok(BlockExecution {
+            write_sets,
+            reexecuted,
+        })
+    }
+}
+
+impl Default for ParallelExecutor {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+fn read_snapshot(snapshot: &Snapshot, key: &[u8]) -> Option<Vec<u8>> {
+    snapshot.get(key).cloned().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn funded_snapshot(accounts: &[[u8; 32]], balance: u64) -> Snapshot {
+        accounts
+            .iter()
+            .map(|account| {
+                (
+                    balance_key(account),
+                    Some(bincode::serialize(&balance).unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_conflicting_transactions_match_sequential() -> Result<()> {
+        let accounts = [[1u8; 32], [2u8; 32], [3u8; 32], [4u8; 32]];
+        let snapshot = funded_snapshot(&accounts, 100);
+
+        let transactions = vec![
+            Transaction::Transfer { from: accounts[0], to: accounts[1], amount: 60 },
+            Transaction::Transfer { from: accounts[2], to: accounts[3], amount: 10 },
+            // Only affordable after the first transfer credits accounts[1]
+            Transaction::Transfer { from: accounts[1], to: accounts[2], amount: 150 },
+        ];
+
+        let execution = ParallelExecutor::new(3).execute(&transactions, &snapshot)?;
+        assert_eq!(execution.reexecuted, 1);
+
+        let mut sequential = snapshot.clone();
+        let mut expected = Vec::new();
+        for tx in &transactions {
+            let writes = execute_transaction(tx, |key| read_snapshot(&sequential, key))?;
+            for (key, value) in &writes {
+                sequential.insert(key.clone(), Some(value.clone()));
+            }
+            expected.push(writes);
+        }
+        assert_eq!(execution.write_sets, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_transaction_fails_block() {
+        let snapshot = funded_snapshot(&[[1u8; 32]], 10);
+        let transactions = vec![Transaction::Transfer {
+            from: [1u8; 32],
+            to: [2u8; 32],
+            amount: 11,
+        }];
+
+        assert!(ParallelExecutor::default()
+            .execute(&transactions, &snapshot)
+            .is_err());
+    }
+
+    #[test]
+    fn test_transfer_to_self_is_rejected() {
+        let snapshot = funded_snapshot(&[[1u8; 32]], 10);
+        let transfer = Transaction::Transfer {
+            from: [1u8; 32],
+            to: [1u8; 32],
+            amount: 10,
+        };
+
+        assert!(execute_transaction(&transfer, |key| read_snapshot(&snapshot, key)).is_err());
+        assert!(ParallelExecutor::default()
+            .execute(&[transfer], &snapshot)
+            .is_err());
+    }
+
+    #[test]
+    fn test_withdrawal_is_made_once() -> Result<()> {
+        let balance = token_balance_key(&token_id(&[7u8; 32]), &[1u8; 32]);
+        let mut snapshot: Snapshot = [(balance.clone(), Some(bincode::serialize(&100u64)?))].into();
+        let withdrawal = Transaction::Withdraw {
+            from: [1u8; 32],
+            recipient: [9u8; 32],
+            token: [7u8; 32],
+            amount: 40,
+            nonce: 0,
+        };
+
+        let writes = execute_transaction(&withdrawal, |key| read_snapshot(&snapshot, key))?;
+        let marker = withdrawal_key(&withdrawal_hash(&[1u8; 32], &[9u8; 32], &[7u8; 32], 40, 0));
+        assert_eq!(
+            writes,
+            vec![
+                (balance, bincode::serialize(&60u64)?),
+                (marker, bincode::serialize(&40u64)?),
+            ]
+        );
+
+        for (key, value) in writes {
+            snapshot.insert(key, Some(value));
+        }
+        assert!(execute_transaction(&withdrawal, |key| read_snapshot(&snapshot, key)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::genesis::{BridgeParams, Genesis};
+use crate::state::StateManager;
+use crate::submitter::{covering_batch, BatchRecord};
+
+/// How settled an L2 block is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockStatus {
+    /// Produced by the sequencer but its root is not on L1 yet
+    Pending,
+    /// Root posted to L1 and still inside the challenge period
+    Posted,
+    /// Root posted and its challenge period has elapsed
+    Finalized,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockFinality {
+    pub height: u64,
+    pub status: BlockStatus,
+    /// Batch that posted the block's root, if any
+    pub batch: Option<BatchRecord>,
+    /// Unix time the block becomes final, once posted
+    pub finalizes_at: Option<i64>,
+}
+
+/// Challenge period mirrored from L1 in the genesis bridge parameters
+pub async fn challenge_period(state: &StateManager) -> Result<i64> {
+    match state.get_value(b"genesis:bridge").await? {
+        Some(data) => Ok(bincode::deserialize::<BridgeParams>(&data)?.challenge_period),
+        None => Ok(Genesis::default().bridge.challenge_period),
+    }
+}
+
+/// Finality of block `height` at unix time `now`, or `None` if the block does
+/// not exist
+pub async fn block_finality(
+    state: &StateManager,
+    height: u64,
+    now: i64,
+) -> Result<Option<BlockFinality>> {
+    if height > state.get_current_root().height {
+        return Ok(None);
+    }
+    // Genesis is final by definition
+    if height == 0 {
+        return Ok(Some(BlockFinality {
+            height,
+            status: BlockStatus::Finalized,
+            batch: None,
+            finalizes_at: None,
+        }));
+    }
+
+    let Some(batch) = covering_batch(state, height)? else {
+        return Ok(Some(BlockFinality {
+            height,
+            status: BlockStatus::Pending,
+            batch: None,
+            finalizes_at: None,
+        }));
+    };
+
+    let finalizes_at = batch
+        .posted_at
+        .saturating_add(challenge_period(state).await?);
+    let status = if now >= finalizes_at {
+        BlockStatus::Finalized
+    } else {
+        BlockStatus::Posted
+    };
+
+    Ok(Some(BlockFinality {
+        height,
+        status,
+        batch: Some(batch),
+        finalizes_at: Some(finalizes_at),
+    }))
+}
+
+/// Highest block whose root is final at unix time `now`
+pub async fn finalized_height(state: &StateManager, now: i64) -> Result<u64> {
+    let period = challenge_period(state).await?;
+
+    // Batches are contiguous and posted in order, so walk them from the start
+    // until one is still challengeable
+    let mut finalized = 0;
+    while let Some(batch) = covering_batch(state, finalized + 1)? {
+        if now < batch.posted_at.saturating_add(period) {
+            break;
+        }
+        finalized = batch.end_height;
+    }
+    Ok(finalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::BatchCommitment;
+    use crate::rollup::Rollup;
+    use crate::submitter::{
+        BatchSubmitter, BatchSubmitterConfig, ConfirmationStatus, L1Client, PostedRoot,
+    };
+    use crate::types::Block;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::sync::RwLock;
+
+    /// L1 that records every root at cluster time `L1_TIME`
+    #[derive(Default)]
+    struct ConfirmingL1 {
+        root: std::sync::Mutex<Option<PostedRoot>>,
+    }
+
+    const L1_TIME: i64 = 1_000;
+
+    #[async_trait]
+    impl L1Client for ConfirmingL1 {
+        async fn submit_state_root(
+            &self,
+            commitment: BatchCommitment,
+            _data: Vec<u8>,
+        ) -> Result<String> {
+            *self.root.lock().unwrap() = Some(PostedRoot {
+                batch_index: 0,
+                state_root: commitment.new_root,
+                data_hash: commitment.data_hash,
+                submitted_at: L1_TIME,
+            });
+            Ok("sig".to_string())
+        }
+
+        async fn post_batch_data(&self, _data: Vec<u8>) -> Result<String> {
+            Ok("chunk".to_string())
+        }
+
+        async fn is_leader(&self) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn confirmation_status(&self, _signature: &str) -> Result<ConfirmationStatus> {
+            Ok(ConfirmationStatus::Confirmed)
+        }
+
+        async fn latest_root(&self) -> Result<Option<PostedRoot>> {
+            Ok(self.root.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_status_progression() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut manager = StateManager::new(&temp_dir)?;
+        Genesis::default().initialize(&mut manager).await?;
+        let state = Arc::new(RwLock::new(manager));
+
+        let mut rollup = Rollup::new(state.clone())?;
+        for number in 1..=3 {
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![], 0))
+                .await?;
+        }
+
+        let config = BatchSubmitterConfig {
+            max_blocks_per_batch: 2,
+            confirmation_poll_interval: Duration::from_millis(1),
+            ..BatchSubmitterConfig::default()
+        };
+        let mut submitter = BatchSubmitter::new(ConfirmingL1::default(), state.clone(), config);
+        let batch = submitter.submit_next_batch().await?.unwrap();
+
+        let state = state.read().await;
+        let period = challenge_period(&state).await?;
+        // The window starts at the L1 time of the root, not the local clock
+        assert_eq!(batch.posted_at, L1_TIME);
+        let now = batch.posted_at;
+
+        let posted = block_finality(&state, 2, now).await?.unwrap();
+        assert_eq!(posted.status, BlockStatus::Posted);
+        assert_eq!(posted.finalizes_at, Some(batch.posted_at + period));
+        assert_eq!(
+            block_finality(&state, 3, now).await?.unwrap().status,
+            BlockStatus::Pending
+        );
+        assert!(block_finality(&state, 4, now).await?.is_none());
+        assert_eq!(finalized_height(&state, now).await?, 0);
+
+        let later = batch.posted_at + period;
+        assert_eq!(
+            block_finality(&state, 1, later).await?.unwrap().status,
+            BlockStatus::Finalized
+        );
+        assert_eq!(finalized_height(&state, later).await?, 2);
+
+        Ok(())
+    }
+}
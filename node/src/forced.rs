@@ -0,0 +1,359 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::rollup::Rollup;
+use crate::state::StateManager;
+use crate::types::{forced_key, Transaction};
+use crate::watcher::{event_discriminator, program_event_data, L1EventSource, L1WatcherConfig};
+
+/// Metadata key holding the highest L1 slot scanned for queued transactions
+const FORCED_SLOT_KEY: &[u8] = b"l1:forced_slot";
+/// Metadata key holding the number of queued transactions seen so far
+const FORCED_COUNT_KEY: &[u8] = b"l1:forced_count";
+/// Prefix of queued transaction records, keyed by queue index
+const FORCED_RECORD_PREFIX: &[u8] = b"l1:forced:";
+
+/// Transaction queued in the `solana_oasis` forced-inclusion queue
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForcedTransaction {
+    pub index: u64,
+    pub sender: [u8; 32],
+    /// Bincode-encoded L2 transaction
+    pub transaction: Vec<u8>,
+    /// L1 slot the transaction was queued in
+    pub slot: u64,
+    /// Last L1 slot by which a posted batch must include the transaction
+    pub deadline_slot: u64,
+}
+
+impl ForcedTransaction {
+    /// Decode a `ForcedTransactionQueued` Anchor event payload
+    pub fn decode_event(data: &[u8]) -> Option<Self> {
+        let fields = data.strip_prefix(&event_discriminator("ForcedTransactionQueued")[..])?;
+        let index = u64::from_le_bytes(fields.get(0..8)?.try_into().ok()?);
+        let sender = fields.get(8..40)?.try_into().ok()?;
+        let len = u32::from_le_bytes(fields.get(40..44)?.try_into().ok()?) as usize;
+        let transaction = fields.get(44..44 + len)?.to_vec();
+        let rest = &fields[44 + len..];
+        if rest.len() != 16 {
+            return None;
+        }
+
+        Some(Self {
+            index,
+            sender,
+            transaction,
+            slot: u64::from_le_bytes(rest[0..8].try_into().ok()?),
+            deadline_slot: u64::from_le_bytes(rest[8..16].try_into().ok()?),
+        })
+    }
+
+    /// The L2 transaction that includes this queue item
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction::Forced {
+            index: self.index,
+            sender: self.sender,
+            transaction: self.transaction.clone(),
+        }
+    }
+}
+
+/// Queued transactions not yet included in the L2 state, in queue order.
+///
+/// Every recorded item is checked against the current state rather than
+/// tracking a low-water mark, since a rollback can un-include any of them.
+pub async fn pending_forced(state: &StateManager) -> Result<Vec<ForcedTransaction>> {
+    let count = read_u64(state, FORCED_COUNT_KEY)?;
+
+    let mut pending = Vec::new();
+    for index in 0..count {
+        if state.get_value(&forced_key(index)).await?.is_some() {
+            continue;
+        }
+        let key = [FORCED_RECORD_PREFIX, &index.to_be_bytes()[..]].concat();
+        let record = state
+            .get_metadata(&key)?
+            .ok_or_else(|| anyhow::anyhow!("Forced transaction {} is not recorded", index))?;
+        pending.push(bincode::deserialize(&record)?);
+    }
+    Ok(pending)
+}
+
+/// Forced-inclusion rule: a batch posted at `l1_slot` is invalid, and can be
+/// challenged, if the state it produces is missing a queued transaction whose
+/// deadline already passed.
+///
+/// `state` must be the state at the end of the batch.
+pub async fn check_forced_inclusion(state: &StateManager, l1_slot: u64) -> Result<()> {
+    let overdue: Vec<u64> = pending_forced(state)
+        .await?
+        .iter()
+        .filter(|forced| forced.deadline_slot < l1_slot)
+        .map(|forced| forced.index)
+        .collect();
+
+    if overdue.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Forced transactions {:?} were not included before slot {}",
+            overdue,
+            l1_slot
+        ))
+    }
+}
+
+/// Follows the `solana_oasis` forced-inclusion queue: records every queued
+/// transaction and puts those not yet included at the front of the mempool.
+pub struct ForcedInclusionWatcher<S: L1EventSource> {
+    source: S,
+    program_id: String,
+    state: Arc<RwLock<StateManager>>,
+    rollup: Arc<Mutex<Rollup>>,
+    config: L1WatcherConfig,
+    /// Queue indices handed to the mempool but not yet included
+    queued: HashSet<u64>,
+}
+
+impl<S: L1EventSource> ForcedInclusionWatcher<S> {
+    pub fn new(
+        source: S,
+        program_id: String,
+        state: Arc<RwLock<StateManager>>,
+        rollup: Arc<Mutex<Rollup>>,
+        config: L1WatcherConfig,
+    ) -> Self {
+        Self {
+            source,
+            program_id,
+            state,
+            rollup,
+            config,
+            queued: HashSet::new(),
+        }
+    }
+
+    /// Poll for queued transactions until the task is dropped
+    pub async fn run(&mut self) {
+        loop {
+            if let Err(e) = self.poll().await {
+                log::error!("Forced inclusion poll failed: {}", e);
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Record newly queued transactions and hand every pending one to the
+    /// sequencer. Returns the number of transactions added to the mempool.
+    pub async fn poll(&mut self) -> Result<usize> {
+        self.sync_queue().await?;
+
+        let pending = pending_forced(&*self.state.read().await).await?;
+
+        let mut rollup = self.rollup.lock().await;
+        let mut count = 0;
+        // Reverse so the oldest item ends up at the front of the mempool
+        for forced in pending.iter().rev() {
+            if self.queued.insert(forced.index) {
+                rollup.submit_priority_transaction(forced.to_transaction());
+                count += 1;
+            }
+        }
+        self.queued
+            .retain(|index| pending.iter().any(|forced| forced.index == *index));
+
+        Ok(count)
+    }
+
+    /// Record queue events from confirmed slots after the cursor
+    async fn sync_queue(&mut self) -> Result<()> {
+        let latest = self.source.latest_slot().await?;
+        let Some(confirmed) = latest.checked_sub(self.config.confirmations) else {
+            return Ok(());
+        };
+
+        let cursor = read_u64(&*self.state.read().await, FORCED_SLOT_KEY)?;
+        if confirmed <= cursor {
+            return Ok(());
+        }
+        let to = confirmed.min(cursor + self.config.max_slots_per_poll);
+        let transactions = self
+            .source
+            .program_logs(&self.program_id, cursor + 1, to)
+            .await?;
+
+        let mut state = self.state.write().await;
+        for logs in &transactions {
+            for data in program_event_data(&self.program_id, &logs.logs) {
+                let Some(forced) = ForcedTransaction::decode_event(&data) else {
+                    continue;
+                };
+                log::info!(
+                    "Forced transaction {} queued at slot {}, due by slot {}",
+                    forced.index,
+                    forced.slot,
+                    forced.deadline_slot
+                );
+                record_forced(&mut state, &forced)?;
+            }
+        }
+        // Records are written before the cursor so a restart never skips one
+        state.set_metadata(FORCED_SLOT_KEY, bincode::serialize(&to)?)?;
+        Ok(())
+    }
+}
+
+/// Record a queued transaction so [`pending_forced`] reports it until the
+/// state includes it
+pub fn record_forced(state: &mut StateManager, forced: &ForcedTransaction) -> Result<()> {
+    let key = [FORCED_RECORD_PREFIX, &forced.index.to_be_bytes()[..]].concat();
+    state.set_metadata(&key, bincode::serialize(forced)?)?;
+    let count = read_u64(state, FORCED_COUNT_KEY)?.max(forced.index + 1);
+    state.set_metadata(FORCED_COUNT_KEY, bincode::serialize(&count)?)
+}
+
+fn read_u64(state: &StateManager, key: &[u8]) -> Result<u64> {
+    match state.get_metadata(key)? {
+        Some(data) => Ok(bincode::deserialize(&data)?),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{balance_key, Block};
+    use crate::watcher::{ProgramLogs, PROGRAM_DATA_PREFIX};
+    use async_trait::async_trait;
+    use base64::Engine;
+    use tempfile::tempdir;
+
+    const OASIS: &str = "oasis11111111111111111111111111111111111111";
+
+    struct MockSource(Vec<ProgramLogs>);
+
+    #[async_trait]
+    impl L1EventSource for MockSource {
+        async fn latest_slot(&self) -> Result<u64> {
+            Ok(100)
+        }
+
+        async fn program_logs(
+            &self,
+            _program_id: &str,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<ProgramLogs>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|logs| logs.slot >= from && logs.slot <= to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn queued_logs(index: u64, sender: [u8; 32], transaction: &Transaction) -> Result<ProgramLogs> {
+        let transaction = bincode::serialize(transaction)?;
+        let mut data = event_discriminator("ForcedTransactionQueued").to_vec();
+        data.extend_from_slice(&index.to_le_bytes());
+        data.extend_from_slice(&sender);
+        data.extend_from_slice(&(transaction.len() as u32).to_le_bytes());
+        data.extend_from_slice(&transaction);
+        data.extend_from_slice(&10u64.to_le_bytes());
+        data.extend_from_slice(&60u64.to_le_bytes());
+
+        Ok(ProgramLogs {
+            slot: 10,
+            signature: format!("sig{}", index),
+            logs: vec![
+                format!("Program {} invoke [1]", OASIS),
+                format!(
+                    "{}{}",
+                    PROGRAM_DATA_PREFIX,
+                    base64::engine::general_purpose::STANDARD.encode(data)
+                ),
+                format!("Program {} success", OASIS),
+            ],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_forced_transactions_must_be_included() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        let rollup = Arc::new(Mutex::new(Rollup::new(state.clone())?));
+        let alice = [1u8; 32];
+        state
+            .write()
+            .await
+            .set_value(&balance_key(&alice), bincode::serialize(&100u64)?)
+            .await?;
+        rollup
+            .lock()
+            .await
+            .submit_transaction(Transaction::Transfer {
+                from: [9u8; 32],
+                to: alice,
+                amount: 0,
+            });
+
+        let own = Transaction::Transfer {
+            from: alice,
+            to: [2u8; 32],
+            amount: 40,
+        };
+        // Spends from an account the sender does not own
+        let theft = Transaction::Transfer {
+            from: [3u8; 32],
+            to: alice,
+            amount: 1,
+        };
+        let source = MockSource(vec![
+            queued_logs(0, alice, &own)?,
+            queued_logs(1, alice, &theft)?,
+        ]);
+        let mut watcher = ForcedInclusionWatcher::new(
+            source,
+            OASIS.to_string(),
+            state.clone(),
+            rollup.clone(),
+            L1WatcherConfig {
+                confirmations: 10,
+                ..L1WatcherConfig::default()
+            },
+        );
+
+        assert_eq!(watcher.poll().await?, 2);
+        assert_eq!(watcher.poll().await?, 0);
+
+        check_forced_inclusion(&*state.read().await, 60).await?;
+        assert!(check_forced_inclusion(&*state.read().await, 61)
+            .await
+            .is_err());
+
+        // Forced transactions were put ahead of the existing mempool
+        let transactions = rollup.lock().await.take_pending(2);
+        assert!(matches!(
+            transactions[0],
+            Transaction::Forced { index: 0, .. }
+        ));
+        rollup
+            .lock()
+            .await
+            .process_block(Block::new(1, [0u8; 32], transactions, 0))
+            .await?;
+
+        let state = state.read().await;
+        check_forced_inclusion(&state, 61).await?;
+        let balance = state.get_value(&balance_key(&alice)).await?;
+        assert_eq!(balance, Some(bincode::serialize(&60u64)?));
+        assert_eq!(state.get_value(&forced_key(1)).await?, Some(vec![0]));
+
+        Ok(())
+    }
+}
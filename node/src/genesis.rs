@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::{StateManager, StateRoot};
+use crate::types::{balance_key, Block};
+
+/// Initial chain configuration, loaded from a JSON file on first start
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    /// Identifier distinguishing this chain from other deployments
+    pub chain_id: String,
+    /// Timestamp of block 0
+    pub timestamp: i64,
+    /// Initial L2 balances keyed by hex-encoded account
+    #[serde(default)]
+    pub balances: BTreeMap<String, u64>,
+    /// Initial validator set
+    #[serde(default)]
+    pub validators: Vec<GenesisValidator>,
+    /// Models registered at launch
+    #[serde(default)]
+    pub models: Vec<GenesisModel>,
+    /// Bridge and settlement parameters mirrored from L1
+    pub bridge: BridgeParams,
+}
+
+/// Genesis with its keys decoded, as committed to by [`Genesis::hash`]
+#[derive(Serialize)]
+struct CanonicalGenesis<'a> {
+    chain_id: &'a str,
+    timestamp: i64,
+    balances: BTreeMap<[u8; 32], u64>,
+    validators: Vec<([u8; 32], u64)>,
+    models: &'a [GenesisModel],
+    bridge: &'a BridgeParams,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// Hex-encoded validator key
+    pub address: String,
+    pub stake: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisModel {
+    pub id: String,
+    pub version: String,
+    pub model_type: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeParams {
+    pub challenge_period: i64,
+    pub min_stake: u64,
+    pub min_withdrawal: u64,
+    pub max_withdrawal: u64,
+    pub daily_limit: u64,
+    /// Seconds a withdrawal over the daily limit waits before release
+    #[serde(default)]
+    pub release_delay: i64,
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Self {
+            chain_id: "oasis-devnet".to_string(),
+            timestamp: 0,
+            balances: BTreeMap::new(),
+            validators: Vec::new(),
+            models: Vec::new(),
+            bridge: BridgeParams {
+                challenge_period: 7 * 24 * 60 * 60,
+                min_stake: 1_000_000_000,
+                min_withdrawal: 1,
+                max_withdrawal: u64::MAX,
+                daily_limit: u64::MAX,
+                release_delay: 24 * 60 * 60,
+            },
+        }
+    }
+}
+
+impl Genesis {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read genesis file {}", path.display()))?;
+        let genesis: Self = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse genesis file {}", path.display()))?;
+        genesis.validate()?;
+        Ok(genesis)
+    }
+
+    /// Check that every account and validator key is well formed and that no
+    /// account is listed twice under different spellings
+    pub fn validate(&self) -> Result<()> {
+        if self.chain_id.is_empty() {
+            return Err(anyhow::anyhow!("Genesis chain id must not be empty"));
+        }
+        self.canonical()?;
+        Ok(())
+    }
+
+    /// Hash committing to the whole configuration, independent of file
+    /// formatting and of how keys are spelled
+    pub fn hash(&self) -> Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        hasher.update(bincode::serialize(&self.canonical()?)?);
+        Ok(hasher.finalize().into())
+    }
+
+    fn canonical(&self) -> Result<CanonicalGenesis<'_>> {
+        let mut balances = BTreeMap::new();
+        for (account, balance) in &self.balances {
+            if balances.insert(parse_account(account)?, *balance).is_some() {
+                return Err(anyhow::anyhow!("Account {} is listed twice", account));
+            }
+        }
+        let validators = self
+            .validators
+            .iter()
+            .map(|validator| Ok((parse_account(&validator.address)?, validator.stake)))
+            .collect::<Result<_>>()?;
+
+        Ok(CanonicalGenesis {
+            chain_id: &self.chain_id,
+            timestamp: self.timestamp,
+            balances,
+            validators,
+            models: &self.models,
+            bridge: &self.bridge,
+        })
+    }
+
+    /// Block 0; its `previous_hash` is the genesis hash so the block hash is
+    /// deterministic for a given configuration.
+    pub fn block(&self) -> Result<Block> {
+        Ok(Block::new(0, self.hash()?, Vec::new(), self.timestamp))
+    }
+
+    /// Write the genesis state into an empty database, or check that an existing
+    /// database was created from this same genesis.
+    pub async fn initialize(&self, state: &mut StateManager) -> Result<StateRoot> {
+        let hash = self.hash()?;
+
+        if let Some(existing) = state.get_genesis_hash()? {
+            if existing != hash {
+                return Err(anyhow::anyhow!(
+                    "State database was created from genesis {} but {} was configured",
+                    hex::encode(existing),
+                    hex::encode(hash)
+                ));
+            }
+            return Ok(state.get_current_root().clone());
+        }
+
+        if state.get_current_root().height != 0 {
+            return Err(anyhow::anyhow!(
+                "State database has blocks but no genesis record"
+            ));
+        }
+
+        for (account, balance) in &self.balances {
+            state
+                .set_value(&balance_key(&parse_account(account)?), bincode::serialize(balance)?)
+                .await?;
+        }
+        for validator in &self.validators {
+            let key = [&b"validator:"[..], &parse_account(&validator.address)?[..]].concat();
+            state.set_value(&key, bincode::serialize(&validator.stake)?).await?;
+        }
+        for model in &self.models {
+            let key = [&b"model:"[..], model.id.as_bytes()].concat();
+            state.set_value(&key, bincode::serialize(model)?).await?;
+        }
+        state
+            .set_value(b"genesis:chain_id", self.chain_id.as_bytes().to_vec())
+            .await?;
+        state
+            .set_value(b"genesis:bridge", bincode::serialize(&self.bridge)?)
+            .await?;
+
+        let root = state.commit_genesis(&self.block()?, hash).await?.root;
+
+        log::info!(
+            "Initialized chain {} from genesis {}",
+            self.chain_id,
+            hex::encode(hash)
+        );
+        Ok(root)
+    }
+}
+
+fn parse_account(account: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(account.trim_start_matches("0x"))
+        .with_context(|| format!("Invalid account {}", account))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Account {} is not 32 bytes", account))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn test_genesis() -> Genesis {
+        let mut genesis = Genesis::default();
+        genesis.balances.insert(hex::encode([1u8; 32]), 1_000);
+        genesis.validators.push(GenesisValidator {
+            address: hex::encode([9u8; 32]),
+            stake: 5_000,
+        });
+        genesis
+    }
+
+    #[tokio::test]
+    async fn test_genesis_initialization() -> Result<()> {
+        let genesis = test_genesis();
+
+        let first_dir = tempdir()?;
+        let mut first = StateManager::new(&first_dir)?;
+        let root = genesis.initialize(&mut first).await?;
+        assert_eq!(root.height, 0);
+
+        let balance = first.get_value(&balance_key(&[1u8; 32])).await?.unwrap();
+        assert_eq!(bincode::deserialize::<u64>(&balance)?, 1_000);
+        assert_eq!(first.get_block(0)?.unwrap().hash(), genesis.block()?.hash());
+
+        // Same genesis on another database produces the same root
+        let second_dir = tempdir()?;
+        let mut second = StateManager::new(&second_dir)?;
+        assert_eq!(genesis.initialize(&mut second).await?, root);
+
+        // Re-initializing is a no-op
+        assert_eq!(genesis.initialize(&mut first).await?, root);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_different_genesis() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut state = StateManager::new(&temp_dir)?;
+        test_genesis().initialize(&mut state).await?;
+
+        let mut other = test_genesis();
+        other.chain_id = "oasis-testnet".to_string();
+        assert!(other.initialize(&mut state).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_ignores_key_spelling() -> Result<()> {
+        let mut respelled = test_genesis();
+        respelled.balances = [(format!("0x{}", hex::encode_upper([1u8; 32])), 1_000)].into();
+        assert_eq!(respelled.hash()?, test_genesis().hash()?);
+
+        // The same account under two spellings is ambiguous
+        respelled.balances.insert(hex::encode([1u8; 32]), 1);
+        assert!(respelled.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_from_file() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("genesis.json");
+        std::fs::write(&path, serde_json::to_string_pretty(&test_genesis())?)?;
+        assert_eq!(Genesis::load(&path)?, test_genesis());
+
+        let mut invalid = test_genesis();
+        invalid.balances.insert("abcd".to_string(), 1);
+        std::fs::write(&path, serde_json::to_string(&invalid)?)?;
+        assert!(Genesis::load(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1338 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{
+    clock::Clock,
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    ed25519_program,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    system_program, sysvar,
+    transaction::Transaction as SolanaTransaction,
+};
+use solana_transaction_status::UiTransactionEncoding;
+
+use crate::batch::{BatchCommitment, PostedDataSource};
+use crate::config::L1Config;
+use crate::cosign::{request_signature, SignRequest};
+use crate::dispute::{DisputeAccount, DisputeClient, StepProof};
+use crate::submitter::{ConfirmationStatus, L1Client, PostedRoot};
+use crate::watcher::{DepositReceipt, DepositReceiptSource, L1EventSource, ProgramLogs};
+
+/// Page size of `getSignaturesForAddress`, the RPC maximum
+const SIGNATURES_PAGE_LIMIT: usize = 1_000;
+
+/// Borsh size of a `BatchCommitment` argument
+const COMMITMENT_LEN: usize = 4 * 32 + 8;
+
+/// Step proof bytes uploaded per `write_step_proof` transaction
+const STEP_PROOF_CHUNK_BYTES: usize = 900;
+
+/// SPL Token program, which holds the stake vault
+const TOKEN_PROGRAM_ID: Pubkey = solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+/// Compute units requested for `prove_step`, which hashes a few hundred times
+/// per state access
+const PROVE_STEP_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Anchor instruction discriminator: first 8 bytes of `sha256("global:<name>")`
+pub fn instruction_discriminator(name: &str) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("global:{}", name).as_bytes());
+    let hash = hasher.finalize();
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+/// Fields of the `solana_oasis` `StateAccount`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OasisState {
+    pub authority: Pubkey,
+    pub validators: Vec<Pubkey>,
+    pub state_root: [u8; 32],
+    pub last_update: i64,
+    pub challenge_period: i64,
+    pub min_stake: u64,
+    pub forced_inclusion_slots: u64,
+    pub forced_tx_count: u64,
+    pub batch_count: u64,
+    pub genesis_root: [u8; 32],
+    pub dispute_move_timeout: i64,
+    pub stake_vault: Pubkey,
+    pub slash_reward_bps: u16,
+    pub unbonding_period: i64,
+    pub leader_timeout: i64,
+    /// Fraud proofs accepted so far; dispute addresses depend on it
+    pub revert_count: u64,
+    pub reward_vault: Pubkey,
+    pub reward_epoch_length: i64,
+    pub reward_epoch: u64,
+    pub reward_epoch_start: i64,
+    /// Stake of the bonded validators
+    pub total_stake: u64,
+    pub reward_per_stake: u128,
+    pub forfeited_rewards: u64,
+    /// Key offered the authority, or the default key
+    pub pending_authority: Pubkey,
+    pub governance_delay: i64,
+    pub governance_threshold: u8,
+    pub proposal_count: u64,
+    /// `oasis_bridge` program whose receipts back L2 deposits
+    pub bridge_program: Pubkey,
+    /// Lamports a challenger locks per dispute, lost if it loses the dispute
+    pub dispute_bond: u64,
+}
+
+impl OasisState {
+    /// Decode the Borsh account data, including the 8-byte discriminator
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = AccountReader::new(data);
+        reader.take(8)?;
+        let authority = reader.pubkey()?;
+        let validators = (0..reader.u32()?)
+            .map(|_| reader.pubkey())
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            authority,
+            validators,
+            state_root: reader.bytes32()?,
+            last_update: reader.u64()? as i64,
+            challenge_period: reader.u64()? as i64,
+            min_stake: reader.u64()?,
+            forced_inclusion_slots: reader.u64()?,
+            forced_tx_count: reader.u64()?,
+            batch_count: reader.u64()?,
+            genesis_root: reader.bytes32()?,
+            dispute_move_timeout: reader.u64()? as i64,
+            stake_vault: reader.pubkey()?,
+            slash_reward_bps: reader.u16()?,
+            unbonding_period: reader.u64()? as i64,
+            leader_timeout: reader.u64()? as i64,
+            revert_count: reader.u64()?,
+            reward_vault: reader.pubkey()?,
+            reward_epoch_length: reader.u64()? as i64,
+            reward_epoch: reader.u64()?,
+            reward_epoch_start: reader.u64()? as i64,
+            total_stake: reader.u64()?,
+            reward_per_stake: reader.u128()?,
+            forfeited_rewards: reader.u64()?,
+            pending_authority: reader.pubkey()?,
+            governance_delay: reader.u64()? as i64,
+            governance_threshold: reader.u8()?,
+            proposal_count: reader.u64()?,
+            bridge_program: reader.pubkey()?,
+            dispute_bond: reader.u64()?,
+        })
+    }
+
+    /// Validator allowed to post the next batch at `now`, mirroring the
+    /// program's round-robin schedule: the turn of batch `batch_count` passes
+    /// on every `leader_timeout` seconds without a new root.
+    pub fn leader(&self, now: i64) -> Option<Pubkey> {
+        if self.validators.is_empty() || self.leader_timeout <= 0 {
+            return None;
+        }
+        let skipped = now.saturating_sub(self.last_update).max(0) / self.leader_timeout;
+        let turn = self.batch_count.wrapping_add(skipped as u64);
+        Some(self.validators[(turn % self.validators.len() as u64) as usize])
+    }
+}
+
+/// Sequential reader over Borsh-encoded account data
+pub(crate) struct AccountReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AccountReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(anyhow::anyhow!("Account data is truncated"));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub(crate) fn u128(&mut self) -> Result<u128> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into()?))
+    }
+
+    pub(crate) fn bytes32(&mut self) -> Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into()?)
+    }
+
+    pub(crate) fn pubkey(&mut self) -> Result<Pubkey> {
+        Ok(Pubkey::new_from_array(self.bytes32()?))
+    }
+}
+
+/// Address of the `StateRootRecord` PDA for `batch_index`
+pub fn root_record_address(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"root", state_account.as_ref(), &batch_index.to_le_bytes()],
+        program_id,
+    )
+    .0
+}
+
+/// Address of the `Dispute` PDA `challenger` opened over the root of batch
+/// `batch_index` posted after `revert_count` fraud proofs
+pub fn dispute_address(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+    revert_count: u64,
+    challenger: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"dispute",
+            state_account.as_ref(),
+            &batch_index.to_le_bytes(),
+            &revert_count.to_le_bytes(),
+            challenger.as_ref(),
+        ],
+        program_id,
+    )
+    .0
+}
+
+/// Address of the `ValidatorStake` PDA of `validator`
+pub fn validator_stake_address(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    validator: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"stake", state_account.as_ref(), validator.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+/// Address of the PDA owning the stake vault
+pub fn vault_authority_address(program_id: &Pubkey, state_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", state_account.as_ref()], program_id).0
+}
+
+/// Address of the `StepProof` buffer `author` uploads into for `dispute`
+pub fn step_proof_address(program_id: &Pubkey, dispute: &Pubkey, author: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"step_proof", dispute.as_ref(), author.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+/// Build the `solana_oasis::update_state_root` instruction posting the
+/// commitment of batch `batch_index` along with its batch data
+pub fn update_state_root_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    validator: &Pubkey,
+    batch_index: u64,
+    commitment: &BatchCommitment,
+    batch_data: &[u8],
+) -> Instruction {
+    // Borsh layout: discriminator, the commitment's four [u8; 32] and u64,
+    // Vec<u8> as u32 length + bytes
+    let mut data = Vec::with_capacity(8 + COMMITMENT_LEN + 4 + batch_data.len());
+    data.extend_from_slice(&instruction_discriminator("update_state_root"));
+    data.extend_from_slice(&commitment.previous_root);
+    data.extend_from_slice(&commitment.new_root);
+    data.extend_from_slice(&commitment.data_hash);
+    data.extend_from_slice(&commitment.transactions_root);
+    data.extend_from_slice(&commitment.transaction_count.to_le_bytes());
+    data.extend_from_slice(&(batch_data.len() as u32).to_le_bytes());
+    data.extend_from_slice(batch_data);
+
+    Instruction::new_with_bytes(
+        *program_id,
+        &data,
+        vec![
+            AccountMeta::new(*state_account, false),
+            AccountMeta::new(
+                root_record_address(program_id, state_account, batch_index),
+                false,
+            ),
+            AccountMeta::new(*validator, true),
+            AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Build an Ed25519 precompile instruction checking each of `signatures`, made
+/// by the paired key, over `message`.
+///
+/// Every signature, key and the shared message live in the instruction itself,
+/// which is what `update_state_root` requires to count them towards its quorum.
+pub fn ed25519_signatures_instruction(
+    signatures: &[(Pubkey, Signature)],
+    message: &[u8; 32],
+) -> Instruction {
+    const OFFSETS_LEN: usize = 14;
+    const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+    let header_len = 2 + signatures.len() * OFFSETS_LEN;
+    let message_offset = header_len + signatures.len() * (32 + 64);
+    let mut offsets = Vec::with_capacity(header_len);
+    offsets.extend_from_slice(&[signatures.len() as u8, 0]);
+    let mut keys_and_signatures = Vec::with_capacity(signatures.len() * (32 + 64));
+    for (signer, signature) in signatures {
+        let public_key_offset = header_len + keys_and_signatures.len();
+        keys_and_signatures.extend_from_slice(&signer.to_bytes());
+        keys_and_signatures.extend_from_slice(signature.as_ref());
+
+        for field in [
+            public_key_offset + 32,
+            CURRENT_INSTRUCTION as usize,
+            public_key_offset,
+            CURRENT_INSTRUCTION as usize,
+            message_offset,
+            message.len(),
+            CURRENT_INSTRUCTION as usize,
+        ] {
+            offsets.extend_from_slice(&(field as u16).to_le_bytes());
+        }
+    }
+
+    let data = [&offsets[..], &keys_and_signatures, message].concat();
+    Instruction::new_with_bytes(ed25519_program::id(), &data, vec![])
+}
+
+/// Build the `solana_oasis::post_batch_data` instruction carrying one chunk of
+/// an encoded batch
+pub fn post_batch_data_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    validator: &Pubkey,
+    data: &[u8],
+) -> Instruction {
+    let mut payload = Vec::with_capacity(8 + 4 + data.len());
+    payload.extend_from_slice(&instruction_discriminator("post_batch_data"));
+    payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    payload.extend_from_slice(data);
+
+    Instruction::new_with_bytes(
+        *program_id,
+        &payload,
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new_readonly(*validator, true),
+        ],
+    )
+}
+
+/// Accounts of a won dispute that `submit_fraud_proof` settles
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FraudProofAccounts {
+    pub batch_index: u64,
+    pub revert_count: u64,
+    /// Validator that posted the disputed root
+    pub asserter: Pubkey,
+    pub stake_vault: Pubkey,
+    pub stake_mint: Pubkey,
+    /// Token account receiving the challenger's reward
+    pub reward_account: Pubkey,
+}
+
+/// Build the `solana_oasis::submit_fraud_proof` instruction slashing the
+/// asserter of a dispute `challenger` won and rolling the root back
+pub fn submit_fraud_proof_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    challenger: &Pubkey,
+    accounts: &FraudProofAccounts,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "submit_fraud_proof",
+        &[],
+        vec![
+            AccountMeta::new(*state_account, false),
+            AccountMeta::new_readonly(
+                root_record_address(program_id, state_account, accounts.batch_index),
+                false,
+            ),
+            AccountMeta::new(
+                dispute_address(
+                    program_id,
+                    state_account,
+                    accounts.batch_index,
+                    accounts.revert_count,
+                    challenger,
+                ),
+                false,
+            ),
+            AccountMeta::new(
+                validator_stake_address(program_id, state_account, &accounts.asserter),
+                false,
+            ),
+            AccountMeta::new(accounts.stake_vault, false),
+            AccountMeta::new(accounts.stake_mint, false),
+            AccountMeta::new_readonly(vault_authority_address(program_id, state_account), false),
+            AccountMeta::new(accounts.reward_account, false),
+            AccountMeta::new(*challenger, true),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ],
+    )
+}
+
+/// Anchor instruction calling `name` with Borsh-encoded `args`
+fn program_instruction(
+    program_id: &Pubkey,
+    name: &str,
+    args: &[u8],
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    let data = [&instruction_discriminator(name)[..], args].concat();
+    Instruction::new_with_bytes(*program_id, &data, accounts)
+}
+
+/// Build the `solana_oasis::open_dispute` instruction challenging the root of
+/// batch `batch_index` and locking the challenger's bond
+pub fn open_dispute_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    challenger: &Pubkey,
+    asserter: &Pubkey,
+    batch_index: u64,
+    revert_count: u64,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "open_dispute",
+        &batch_index.to_le_bytes(),
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new(
+                root_record_address(program_id, state_account, batch_index),
+                false,
+            ),
+            AccountMeta::new(
+                dispute_address(
+                    program_id,
+                    state_account,
+                    batch_index,
+                    revert_count,
+                    challenger,
+                ),
+                false,
+            ),
+            AccountMeta::new(
+                validator_stake_address(program_id, state_account, asserter),
+                false,
+            ),
+            AccountMeta::new(*asserter, false),
+            AccountMeta::new(*challenger, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::bisect_dispute` instruction posting the asserter's
+/// midpoint root
+pub fn bisect_dispute_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    asserter: &Pubkey,
+    dispute: &Pubkey,
+    midpoint_root: [u8; 32],
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "bisect_dispute",
+        &midpoint_root,
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new(*dispute, false),
+            AccountMeta::new_readonly(*asserter, true),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::respond_dispute` instruction accepting or
+/// rejecting the asserter's midpoint root
+pub fn respond_dispute_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    challenger: &Pubkey,
+    dispute: &Pubkey,
+    agree: bool,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "respond_dispute",
+        &[agree as u8],
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new(*dispute, false),
+            AccountMeta::new_readonly(*challenger, true),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::open_step_proof` instruction creating the proof
+/// buffer of `author`
+pub fn open_step_proof_instruction(
+    program_id: &Pubkey,
+    dispute: &Pubkey,
+    author: &Pubkey,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "open_step_proof",
+        &[],
+        vec![
+            AccountMeta::new_readonly(*dispute, false),
+            AccountMeta::new(step_proof_address(program_id, dispute, author), false),
+            AccountMeta::new(*author, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::write_step_proof` instruction appending one chunk
+/// of an encoded step proof
+pub fn write_step_proof_instruction(
+    program_id: &Pubkey,
+    dispute: &Pubkey,
+    author: &Pubkey,
+    data: &[u8],
+) -> Instruction {
+    let mut args = Vec::with_capacity(4 + data.len());
+    args.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    args.extend_from_slice(data);
+
+    program_instruction(
+        program_id,
+        "write_step_proof",
+        &args,
+        vec![
+            AccountMeta::new(step_proof_address(program_id, dispute, author), false),
+            AccountMeta::new_readonly(*author, true),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::prove_step` instruction settling a one-step
+/// dispute over batch `batch_index` with the uploaded proof. A disputed
+/// deposit also needs the bridge's `deposit_receipt` of it.
+pub fn prove_step_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+    dispute: &Pubkey,
+    asserter: &Pubkey,
+    author: &Pubkey,
+    deposit_receipt: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*state_account, false),
+        AccountMeta::new(*dispute, false),
+        AccountMeta::new(
+            root_record_address(program_id, state_account, batch_index),
+            false,
+        ),
+        AccountMeta::new(step_proof_address(program_id, dispute, author), false),
+        AccountMeta::new(
+            validator_stake_address(program_id, state_account, asserter),
+            false,
+        ),
+        AccountMeta::new(*asserter, false),
+        AccountMeta::new(*author, true),
+    ];
+    accounts.extend(deposit_receipt.map(|receipt| AccountMeta::new_readonly(*receipt, false)));
+    program_instruction(program_id, "prove_step", &[], accounts)
+}
+
+/// Address of the `DepositReceipt` PDA of the `nonce`-th bridge deposit
+pub fn deposit_receipt_address(bridge_program: &Pubkey, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"deposit", &nonce.to_le_bytes()], bridge_program).0
+}
+
+/// Build the `solana_oasis::settle_stale_dispute` instruction closing a dispute
+/// over a reverted root of batch `batch_index`
+pub fn settle_stale_dispute_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+    dispute: &Pubkey,
+    asserter: &Pubkey,
+    challenger: &Pubkey,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "settle_stale_dispute",
+        &[],
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new_readonly(
+                root_record_address(program_id, state_account, batch_index),
+                false,
+            ),
+            AccountMeta::new(*dispute, false),
+            AccountMeta::new(
+                validator_stake_address(program_id, state_account, asserter),
+                false,
+            ),
+            AccountMeta::new(*challenger, false),
+        ],
+    )
+}
+
+/// Build the `solana_oasis::timeout_dispute` instruction settling the dispute
+/// over batch `batch_index`
+pub fn timeout_dispute_instruction(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+    dispute: &Pubkey,
+    asserter: &Pubkey,
+) -> Instruction {
+    program_instruction(
+        program_id,
+        "timeout_dispute",
+        &[],
+        vec![
+            AccountMeta::new_readonly(*state_account, false),
+            AccountMeta::new(*dispute, false),
+            AccountMeta::new(
+                root_record_address(program_id, state_account, batch_index),
+                false,
+            ),
+            AccountMeta::new(
+                validator_stake_address(program_id, state_account, asserter),
+                false,
+            ),
+            AccountMeta::new(*asserter, false),
+        ],
+    )
+}
+
+/// Decode a Borsh `Vec<u8>` argument at the start of `data`
+fn bytes_argument(data: &[u8]) -> Result<Vec<u8>> {
+    let len: [u8; 4] = data
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("Truncated instruction data"))?;
+    let len = u32::from_le_bytes(len) as usize;
+    data.get(4..4 + len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow::anyhow!("Truncated instruction data"))
+}
+
+/// [`L1Client`] talking to the `solana_oasis` program over JSON-RPC
+pub struct SolanaL1Client {
+    rpc: RpcClient,
+    program_id: Pubkey,
+    state_account: Pubkey,
+    validator: Keypair,
+    /// Co-signing endpoints of the validators whose signatures complete the
+    /// commitment quorum
+    cosigners: Vec<String>,
+    /// Token account receiving rewards for won disputes
+    reward_account: Option<Pubkey>,
+}
+
+impl SolanaL1Client {
+    pub fn new(config: &L1Config) -> Result<Self> {
+        let validator = read_keypair_file(&config.keypair_path).map_err(|e| {
+            anyhow::anyhow!("Failed to read keypair {}: {}", config.keypair_path, e)
+        })?;
+
+        Ok(Self {
+            rpc: RpcClient::new_with_commitment(
+                config.rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            ),
+            program_id: Pubkey::from_str(&config.program_id).context("Invalid program id")?,
+            state_account: Pubkey::from_str(&config.state_account)
+                .context("Invalid state account")?,
+            validator,
+            cosigners: config.cosigners.clone(),
+            reward_account: config
+                .reward_token_account
+                .as_deref()
+                .map(Pubkey::from_str)
+                .transpose()
+                .context("Invalid reward token account")?,
+        })
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub fn program_id(&self) -> &Pubkey {
+        &self.program_id
+    }
+
+    pub fn state_account(&self) -> &Pubkey {
+        &self.state_account
+    }
+
+    pub fn validator(&self) -> &Keypair {
+        &self.validator
+    }
+
+    /// Address of the dispute `challenger` opened over the root recorded for
+    /// `batch_index`
+    async fn current_dispute_address(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+    ) -> Result<Pubkey> {
+        let record = self
+            .rpc
+            .get_account_data(&root_record_address(
+                &self.program_id,
+                &self.state_account,
+                batch_index,
+            ))
+            .await?;
+        // `StateRootRecord` fields before its revert count
+        let mut reader = AccountReader::new(&record);
+        reader.take(8 + 32 + 8 + 32 + 32 + 8 + 32 + 8 + 32 + 32 + 4)?;
+        let revert_count = reader.u64()?;
+        Ok(dispute_address(
+            &self.program_id,
+            &self.state_account,
+            batch_index,
+            revert_count,
+            &Pubkey::new_from_array(*challenger),
+        ))
+    }
+
+    /// Validator defending the dispute `challenger` opened over `batch_index`
+    async fn asserter(&self, batch_index: u64, challenger: &[u8; 32]) -> Result<Pubkey> {
+        let dispute = self
+            .dispute(batch_index, challenger)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No dispute over batch {}", batch_index))?;
+        Ok(Pubkey::new_from_array(dispute.asserter))
+    }
+
+    /// Fetch and decode the `solana_oasis` state account
+    pub async fn oasis_state(&self) -> Result<OasisState> {
+        OasisState::decode(&self.rpc.get_account_data(&self.state_account).await?)
+    }
+
+    /// Sign `instructions` with the validator key and send them in one transaction
+    pub async fn send_instructions(&self, instructions: &[Instruction]) -> Result<String> {
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let transaction = SolanaTransaction::new_signed_with_payer(
+            instructions,
+            Some(&self.validator.pubkey()),
+            &[&self.validator],
+            blockhash,
+        );
+        let signature = self.rpc.send_transaction(&transaction).await?;
+        Ok(signature.to_string())
+    }
+}
+
+#[async_trait]
+impl L1Client for SolanaL1Client {
+    async fn submit_state_root(
+        &self,
+        commitment: BatchCommitment,
+        data: Vec<u8>,
+    ) -> Result<String> {
+        let batch_index = self.oasis_state().await?.batch_count;
+        let mut instructions = Vec::new();
+        if !self.cosigners.is_empty() {
+            let request = SignRequest {
+                batch_index,
+                commitment,
+                data: data.clone(),
+            };
+            let state_account = self.state_account.to_bytes();
+            let mut signatures = Vec::new();
+            for endpoint in &self.cosigners {
+                match request_signature(endpoint, &state_account, &request).await {
+                    Ok(signature) => signatures.push(signature),
+                    Err(e) => log::warn!("No signature for batch {}: {}", batch_index, e),
+                }
+            }
+            if !signatures.is_empty() {
+                let message = commitment.message(&state_account, batch_index);
+                instructions.push(ed25519_signatures_instruction(&signatures, &message));
+            }
+        }
+        instructions.push(update_state_root_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            batch_index,
+            &commitment,
+            &data,
+        ));
+        self.send_instructions(&instructions).await
+    }
+
+    async fn post_batch_data(&self, data: Vec<u8>) -> Result<String> {
+        let instruction = post_batch_data_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            &data,
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn is_leader(&self) -> Result<bool> {
+        // The program checks the schedule against the cluster clock
+        let clock: Clock =
+            bincode::deserialize(&self.rpc.get_account_data(&sysvar::clock::id()).await?)?;
+        let leader = self.oasis_state().await?.leader(clock.unix_timestamp);
+        Ok(leader == Some(self.validator.pubkey()))
+    }
+
+    async fn confirmation_status(&self, signature: &str) -> Result<ConfirmationStatus> {
+        let signature = Signature::from_str(signature)?;
+        let status = self
+            .rpc
+            .get_signature_status_with_commitment(&signature, CommitmentConfig::finalized())
+            .await?;
+
+        Ok(match status {
+            None => ConfirmationStatus::Pending,
+            Some(Ok(())) => ConfirmationStatus::Confirmed,
+            Some(Err(e)) => ConfirmationStatus::Failed(e.to_string()),
+        })
+    }
+
+    async fn latest_root(&self) -> Result<Option<PostedRoot>> {
+        let batch_count = self.oasis_state().await?.batch_count;
+        if batch_count == 0 {
+            return Ok(None);
+        }
+        let record = self
+            .rpc
+            .get_account_data(&root_record_address(
+                &self.program_id,
+                &self.state_account,
+                batch_count - 1,
+            ))
+            .await?;
+
+        // `StateRootRecord` fields after the discriminator and state account
+        let mut reader = AccountReader::new(&record);
+        reader.take(8 + 32)?;
+        let batch_index = reader.u64()?;
+        let state_root = reader.bytes32()?;
+        reader.take(32)?;
+        let submitted_at = reader.u64()? as i64;
+        // Transactions root and count, previous root
+        reader.take(32 + 8 + 32)?;
+        Ok(Some(PostedRoot {
+            batch_index,
+            state_root,
+            data_hash: reader.bytes32()?,
+            submitted_at,
+        }))
+    }
+}
+
+#[async_trait]
+impl DisputeClient for SolanaL1Client {
+    fn signer(&self) -> [u8; 32] {
+        self.validator.pubkey().to_bytes()
+    }
+
+    async fn challenge_period(&self) -> Result<i64> {
+        Ok(self.oasis_state().await?.challenge_period)
+    }
+
+    async fn dispute(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+    ) -> Result<Option<DisputeAccount>> {
+        let address = self
+            .current_dispute_address(batch_index, challenger)
+            .await?;
+        let account = self
+            .rpc
+            .get_account_with_commitment(&address, CommitmentConfig::confirmed())
+            .await?
+            .value;
+        account
+            .map(|account| DisputeAccount::decode(&account.data))
+            .transpose()
+    }
+
+    async fn open_dispute(&self, batch_index: u64) -> Result<String> {
+        // `StateRootRecord` starts with its state, batch index and root, and
+        // its revert count follows the open dispute count
+        let record = self
+            .rpc
+            .get_account_data(&root_record_address(
+                &self.program_id,
+                &self.state_account,
+                batch_index,
+            ))
+            .await?;
+        let mut reader = AccountReader::new(&record);
+        reader.take(8 + 32 + 8 + 32)?;
+        let asserter = reader.pubkey()?;
+        reader.take(8 + 32 + 8 + 32 + 32 + 4)?;
+        let revert_count = reader.u64()?;
+
+        let instruction = open_dispute_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            &asserter,
+            batch_index,
+            revert_count,
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn bisect_dispute(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+        midpoint_root: [u8; 32],
+    ) -> Result<String> {
+        let instruction = bisect_dispute_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            &self
+                .current_dispute_address(batch_index, challenger)
+                .await?,
+            midpoint_root,
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn respond_dispute(&self, batch_index: u64, agree: bool) -> Result<String> {
+        let instruction = respond_dispute_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            &self
+                .current_dispute_address(batch_index, &self.signer())
+                .await?,
+            agree,
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn prove_step(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+        proof: &StepProof,
+    ) -> Result<String> {
+        let author = self.validator.pubkey();
+        let deposit_receipt = match proof.deposit_nonce() {
+            Some(nonce) => Some(deposit_receipt_address(
+                &self.oasis_state().await?.bridge_program,
+                nonce,
+            )),
+            None => None,
+        };
+        let proof = proof.encode();
+        let dispute = self
+            .current_dispute_address(batch_index, challenger)
+            .await?;
+        let buffer = step_proof_address(&self.program_id, &dispute, &author);
+
+        // Resume an upload interrupted by an earlier attempt
+        let uploaded = match self
+            .rpc
+            .get_account_with_commitment(&buffer, CommitmentConfig::confirmed())
+            .await?
+            .value
+        {
+            Some(account) => {
+                let mut reader = AccountReader::new(&account.data);
+                reader.take(8 + 32 + 32)?;
+                let len = reader.u32()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            None => {
+                let instruction = open_step_proof_instruction(&self.program_id, &dispute, &author);
+                self.send_instructions(&[instruction]).await?;
+                Vec::new()
+            }
+        };
+        let remaining = proof.strip_prefix(uploaded.as_slice()).ok_or_else(|| {
+            anyhow::anyhow!("Step proof buffer {} holds a different proof", buffer)
+        })?;
+
+        for chunk in remaining.chunks(STEP_PROOF_CHUNK_BYTES) {
+            let instruction =
+                write_step_proof_instruction(&self.program_id, &dispute, &author, chunk);
+            self.send_instructions(&[instruction]).await?;
+        }
+
+        self.send_instructions(&[
+            ComputeBudgetInstruction::set_compute_unit_limit(PROVE_STEP_COMPUTE_UNITS),
+            prove_step_instruction(
+                &self.program_id,
+                &self.state_account,
+                batch_index,
+                &dispute,
+                &self.asserter(batch_index, challenger).await?,
+                &author,
+                deposit_receipt.as_ref(),
+            ),
+        ])
+        .await
+    }
+
+    async fn timeout_dispute(&self, batch_index: u64, challenger: &[u8; 32]) -> Result<String> {
+        let instruction = timeout_dispute_instruction(
+            &self.program_id,
+            &self.state_account,
+            batch_index,
+            &self
+                .current_dispute_address(batch_index, challenger)
+                .await?,
+            &self.asserter(batch_index, challenger).await?,
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn submit_fraud_proof(&self, batch_index: u64) -> Result<String> {
+        let reward_account = self
+            .reward_account
+            .ok_or_else(|| anyhow::anyhow!("No reward token account configured"))?;
+        let state = self.oasis_state().await?;
+        let dispute = self
+            .dispute(batch_index, &self.signer())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No dispute over batch {}", batch_index))?;
+        // SPL token accounts start with their mint
+        let vault = self.rpc.get_account_data(&state.stake_vault).await?;
+        let stake_mint = AccountReader::new(&vault).pubkey()?;
+
+        let instruction = submit_fraud_proof_instruction(
+            &self.program_id,
+            &self.state_account,
+            &self.validator.pubkey(),
+            &FraudProofAccounts {
+                batch_index,
+                revert_count: dispute.revert_count,
+                asserter: Pubkey::new_from_array(dispute.asserter),
+                stake_vault: state.stake_vault,
+                stake_mint,
+                reward_account,
+            },
+        );
+        self.send_instructions(&[instruction]).await
+    }
+
+    async fn settle_stale_dispute(
+        &self,
+        batch_index: u64,
+        challenger: &[u8; 32],
+    ) -> Result<String> {
+        let instruction = settle_stale_dispute_instruction(
+            &self.program_id,
+            &self.state_account,
+            batch_index,
+            &self
+                .current_dispute_address(batch_index, challenger)
+                .await?,
+            &self.asserter(batch_index, challenger).await?,
+            &Pubkey::new_from_array(*challenger),
+        );
+        self.send_instructions(&[instruction]).await
+    }
+}
+
+#[async_trait]
+impl L1EventSource for SolanaL1Client {
+    async fn latest_slot(&self) -> Result<u64> {
+        Ok(self.rpc.get_slot().await?)
+    }
+
+    async fn program_logs(&self, program_id: &str, from: u64, to: u64) -> Result<Vec<ProgramLogs>> {
+        let address = Pubkey::from_str(program_id).context("Invalid program id")?;
+
+        // Signatures are returned newest first; page backwards until `from`
+        let mut signatures = Vec::new();
+        let mut before = None;
+        'pages: loop {
+            let page = self
+                .rpc
+                .get_signatures_for_address_with_config(
+                    &address,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(SIGNATURES_PAGE_LIMIT),
+                        commitment: Some(CommitmentConfig::confirmed()),
+                    },
+                )
+                .await?;
+            let full_page = page.len() == SIGNATURES_PAGE_LIMIT;
+
+            for status in page {
+                if status.slot < from {
+                    break 'pages;
+                }
+                before = Some(Signature::from_str(&status.signature)?);
+                // Failed transactions emit logs but their events never happened
+                if status.slot <= to && status.err.is_none() {
+                    signatures.push((status.slot, status.signature));
+                }
+            }
+            if !full_page {
+                break;
+            }
+        }
+        signatures.reverse();
+
+        let mut transactions = Vec::with_capacity(signatures.len());
+        for (slot, signature) in signatures {
+            let transaction = self
+                .rpc
+                .get_transaction_with_config(
+                    &Signature::from_str(&signature)?,
+                    RpcTransactionConfig {
+                        encoding: None,
+                        commitment: Some(CommitmentConfig::confirmed()),
+                        max_supported_transaction_version: Some(0),
+                    },
+                )
+                .await?;
+            let logs = transaction
+                .transaction
+                .meta
+                .and_then(|meta| Option::<Vec<String>>::from(meta.log_messages))
+                .unwrap_or_default();
+
+            transactions.push(ProgramLogs {
+                slot,
+                signature,
+                logs,
+            });
+        }
+
+        Ok(transactions)
+    }
+}
+
+#[async_trait]
+impl PostedDataSource for SolanaL1Client {
+    async fn posted_data(&self, signature: &str) -> Result<Vec<u8>> {
+        let transaction = self
+            .rpc
+            .get_transaction_with_config(
+                &Signature::from_str(signature)?,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await?;
+        let transaction = transaction
+            .transaction
+            .transaction
+            .decode()
+            .ok_or_else(|| anyhow::anyhow!("Failed to decode transaction {}", signature))?;
+
+        let account_keys = transaction.message.static_account_keys();
+        for instruction in transaction.message.instructions() {
+            if account_keys.get(instruction.program_id_index as usize) != Some(&self.program_id) {
+                continue;
+            }
+            if instruction.data.len() < 8 {
+                continue;
+            }
+            let (discriminator, args) = instruction.data.split_at(8);
+            if discriminator == instruction_discriminator("update_state_root") {
+                return bytes_argument(args.get(COMMITMENT_LEN..).unwrap_or_default());
+            }
+            if discriminator == instruction_discriminator("post_batch_data") {
+                return bytes_argument(args);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Transaction {} carries no batch data",
+            signature
+        ))
+    }
+}
+
+#[async_trait]
+impl DepositReceiptSource for SolanaL1Client {
+    async fn deposit_receipt(&self, nonce: u64) -> Result<Option<DepositReceipt>> {
+        let bridge_program = self.oasis_state().await?.bridge_program;
+        let account = self
+            .rpc
+            .get_account_with_commitment(
+                &deposit_receipt_address(&bridge_program, nonce),
+                CommitmentConfig::confirmed(),
+            )
+            .await?
+            .value;
+        Ok(account
+            .filter(|account| account.owner == bridge_program)
+            .and_then(|account| DepositReceipt::decode(&account.data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_state_root_instruction_layout() {
+        let program_id = Pubkey::new_unique();
+        let state_account = Pubkey::new_unique();
+        let validator = Pubkey::new_unique();
+
+        let instruction = update_state_root_instruction(
+            &program_id,
+            &state_account,
+            &validator,
+            4,
+            &BatchCommitment {
+                previous_root: [5u8; 32],
+                new_root: [6u8; 32],
+                data_hash: [7u8; 32],
+                transactions_root: [8u8; 32],
+                transaction_count: 9,
+            },
+            &[1, 2, 3],
+        );
+
+        assert_eq!(instruction.program_id, program_id);
+        assert_eq!(
+            &instruction.data[..8],
+            &instruction_discriminator("update_state_root")
+        );
+        assert_eq!(&instruction.data[8..40], &[5u8; 32]);
+        assert_eq!(&instruction.data[40..72], &[6u8; 32]);
+        assert_eq!(&instruction.data[72..104], &[7u8; 32]);
+        assert_eq!(&instruction.data[104..136], &[8u8; 32]);
+        assert_eq!(&instruction.data[136..144], &9u64.to_le_bytes());
+        assert_eq!(&instruction.data[144..148], &3u32.to_le_bytes());
+        assert_eq!(&instruction.data[148..], &[1, 2, 3]);
+        assert!(instruction.accounts[0].is_writable);
+        assert_eq!(
+            instruction.accounts[1].pubkey,
+            root_record_address(&program_id, &state_account, 4)
+        );
+        assert!(instruction.accounts[2].is_signer);
+        assert_eq!(instruction.accounts[3].pubkey, sysvar::instructions::id());
+    }
+
+    #[test]
+    fn test_ed25519_signatures_verify() {
+        let message = [3u8; 32];
+        let signatures: Vec<_> = [Keypair::new(), Keypair::new()]
+            .iter()
+            .map(|signer| (signer.pubkey(), signer.sign_message(&message)))
+            .collect();
+        let instruction = ed25519_signatures_instruction(&signatures, &message);
+
+        assert_eq!(instruction.program_id, ed25519_program::id());
+        let feature_set = solana_sdk::feature_set::FeatureSet::all_enabled();
+        solana_sdk::ed25519_instruction::verify(&instruction.data, &[], &feature_set).unwrap();
+
+        // A tampered message no longer verifies
+        let mut data = instruction.data;
+        *data.last_mut().unwrap() ^= 1;
+        assert!(solana_sdk::ed25519_instruction::verify(&data, &[], &feature_set).is_err());
+    }
+
+    #[test]
+    fn test_leader_rotates_and_times_out() {
+        let validators = vec![
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let state = OasisState {
+            authority: Pubkey::new_unique(),
+            validators: validators.clone(),
+            state_root: [0; 32],
+            last_update: 1_000,
+            challenge_period: 100,
+            min_stake: 1,
+            forced_inclusion_slots: 10,
+            forced_tx_count: 0,
+            batch_count: 4,
+            genesis_root: [0; 32],
+            dispute_move_timeout: 60,
+            stake_vault: Pubkey::new_unique(),
+            slash_reward_bps: 5_000,
+            unbonding_period: 100,
+            leader_timeout: 30,
+            revert_count: 0,
+            reward_vault: Pubkey::new_unique(),
+            reward_epoch_length: 86_400,
+            reward_epoch: 0,
+            reward_epoch_start: 0,
+            total_stake: 0,
+            reward_per_stake: 0,
+            forfeited_rewards: 0,
+            pending_authority: Pubkey::default(),
+            governance_delay: 0,
+            governance_threshold: 0,
+            proposal_count: 0,
+            bridge_program: Pubkey::new_unique(),
+            dispute_bond: 10_000_000,
+        };
+
+        // Batch 4 belongs to validator 1 until it misses its window
+        assert_eq!(state.leader(1_000), Some(validators[1]));
+        assert_eq!(state.leader(1_029), Some(validators[1]));
+        assert_eq!(state.leader(1_030), Some(validators[2]));
+        assert_eq!(state.leader(1_065), Some(validators[0]));
+        // Clock skew before the last update keeps the scheduled leader
+        assert_eq!(state.leader(900), Some(validators[1]));
+
+        let empty = OasisState {
+            validators: Vec::new(),
+            ..state
+        };
+        assert_eq!(empty.leader(1_000), None);
+    }
+}
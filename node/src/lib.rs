@@ -1,29 +1,161 @@
+pub mod batch;
 pub mod config;
+pub mod cosign;
+pub mod dispute;
+pub mod escape;
+pub mod executor;
+pub mod finality;
+pub mod forced;
+pub mod genesis;
+pub mod l1;
 pub mod network;
+pub mod replay;
+pub mod rewards;
+pub mod rollup;
+pub mod smt;
 pub mod state;
+pub mod submitter;
 pub mod types;
+pub mod validator;
+pub mod watcher;
 
 use anyhow::Result;
 use config::NetworkConfig;
+use cosign::BatchSigner;
+use finality::BlockFinality;
+use forced::ForcedInclusionWatcher;
+use genesis::Genesis;
+use l1::SolanaL1Client;
 use network::Network;
+use rewards::RewardTracker;
+use rollup::Rollup;
+use solana_sdk::signature::{read_keypair_file, Signer};
 use state::StateManager;
+use std::sync::Arc;
+use submitter::{BatchSubmitter, BatchSubmitterConfig};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+use validator::BatchValidator;
+use watcher::{DepositWatcher, L1WatcherConfig};
 
 #[allow(dead_code)]
 pub struct Node {
+    config: NetworkConfig,
     network: Network,
-    state: StateManager,
+    genesis: Genesis,
+    state: Arc<RwLock<StateManager>>,
+    rollup: Arc<Mutex<Rollup>>,
+    tasks: Vec<JoinHandle<()>>,
 }
 
 impl Node {
     pub async fn new(config: NetworkConfig) -> Result<Self> {
         let network = Network::new(config.clone()).await?;
-        let state = StateManager::new(&config.state_db_path)?;
+        let mut state = StateManager::new(&config.state_db_path)?;
 
-        Ok(Self { network, state })
+        let genesis = match &config.genesis_path {
+            Some(path) => Genesis::load(path)?,
+            None => Genesis::default(),
+        };
+        genesis.initialize(&mut state).await?;
+
+        let state = Arc::new(RwLock::new(state));
+        let rollup = Arc::new(Mutex::new(Rollup::new(state.clone())?));
+
+        Ok(Self {
+            config,
+            network,
+            genesis,
+            state,
+            rollup,
+            tasks: Vec::new(),
+        })
+    }
+
+    /// Settlement status of block `height`, or `None` if it does not exist
+    pub async fn block_finality(&self, height: u64) -> Result<Option<BlockFinality>> {
+        let state = self.state.read().await;
+        finality::block_finality(&state, height, chrono::Utc::now().timestamp()).await
     }
 
     pub async fn start(&mut self) -> Result<()> {
-        // TODO: Implement node startup logic
+        if let Some(l1) = &self.config.l1 {
+            let client = SolanaL1Client::new(l1)?;
+            let mut submitter =
+                BatchSubmitter::new(client, self.state.clone(), BatchSubmitterConfig::default());
+            self.tasks
+                .push(tokio::spawn(async move { submitter.run().await }));
+
+            let mut forced = ForcedInclusionWatcher::new(
+                SolanaL1Client::new(l1)?,
+                l1.program_id.clone(),
+                self.state.clone(),
+                self.rollup.clone(),
+                L1WatcherConfig::default(),
+            );
+            self.tasks
+                .push(tokio::spawn(async move { forced.run().await }));
+
+            let rewards_client = SolanaL1Client::new(l1)?;
+            let validator = rewards_client.validator().pubkey().to_bytes();
+            let mut rewards = RewardTracker::new(
+                rewards_client,
+                l1.program_id.clone(),
+                validator,
+                self.state.clone(),
+                L1WatcherConfig::default(),
+            );
+            self.tasks
+                .push(tokio::spawn(async move { rewards.run().await }));
+
+            if let Some(bridge_program_id) = &l1.bridge_program_id {
+                let mut watcher = DepositWatcher::new(
+                    SolanaL1Client::new(l1)?,
+                    bridge_program_id.clone(),
+                    self.state.clone(),
+                    self.rollup.clone(),
+                    L1WatcherConfig::default(),
+                );
+                self.tasks
+                    .push(tokio::spawn(async move { watcher.run().await }));
+            }
+
+            if l1.verify_batches {
+                // Built from L1 data alone, independently of the local chain
+                let mut state =
+                    StateManager::new(&format!("{}.validator", self.config.state_db_path))?;
+                self.genesis.initialize(&mut state).await?;
+                let state = Arc::new(RwLock::new(state));
+                let mut validator = BatchValidator::new(
+                    SolanaL1Client::new(l1)?,
+                    l1.program_id.clone(),
+                    state.clone(),
+                    L1WatcherConfig::default(),
+                )?;
+                self.tasks
+                    .push(tokio::spawn(async move { validator.run().await }));
+
+                // Other leaders' batches are only signed once they check out
+                // against the state verified above
+                if let Some(address) = &l1.cosign_listen_address {
+                    let client = SolanaL1Client::new(l1)?;
+                    let keypair = read_keypair_file(&l1.keypair_path).map_err(|e| {
+                        anyhow::anyhow!("Failed to read keypair {}: {}", l1.keypair_path, e)
+                    })?;
+                    let state_account = client.state_account().to_bytes();
+                    let signer = BatchSigner::new(client, keypair, state_account, state);
+                    let listener = TcpListener::bind(address).await?;
+                    self.tasks.push(tokio::spawn(signer.run(listener)));
+                }
+            } else if l1.cosign_listen_address.is_some() {
+                return Err(anyhow::anyhow!(
+                    "Co-signing requires verifying batches (OASIS_L1_VERIFY_BATCHES)"
+                ));
+            }
+        }
         Ok(())
     }
 }
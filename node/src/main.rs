@@ -1,24 +1,161 @@
 use anyhow::Result;
 use libp2p::identity::Keypair;
-use solana_oasis_node::{config::NetworkConfig, Node};
-use std::path::PathBuf;
+use solana_oasis_node::{
+    config::{L1Config, NetworkConfig},
+    escape::balance_proof,
+    finality::{block_finality, finalized_height},
+    replay::replay_blocks,
+    state::StateManager,
+    Node,
+};
+use solana_sdk::pubkey::Pubkey;
+use std::path::{Path, PathBuf};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
-    let identity = Keypair::generate_ed25519();
     let state_db_path = PathBuf::from("state.db");
+    let genesis_path = PathBuf::from("genesis.json");
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("replay") {
+        return replay(&state_db_path, &args[2..]).await;
+    }
+    if args.get(1).map(String::as_str) == Some("status") {
+        return status(&state_db_path, &args[2..]).await;
+    }
+    if args.get(1).map(String::as_str) == Some("escape-proof") {
+        return escape_proof(&state_db_path, &args[2..]).await;
+    }
+
+    let identity = Keypair::generate_ed25519();
 
     let config = NetworkConfig {
         identity,
         state_db_path: state_db_path.to_str().unwrap().to_string(),
         listen_addresses: vec!["/ip4/127.0.0.1/tcp/0".to_string()],
         bootstrap_peers: vec![],
+        genesis_path: genesis_path
+            .exists()
+            .then(|| genesis_path.to_str().unwrap().to_string()),
+        l1: L1Config::from_env(),
     };
 
     let mut node = Node::new(config).await?;
     node.start().await?;
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// `status <height>`: print the settlement status of a block as JSON
+async fn status(state_db_path: &Path, args: &[String]) -> Result<()> {
+    let height: u64 = match args {
+        [height] => height.parse()?,
+        _ => return Err(anyhow::anyhow!("Usage: solana-oasis-node status <height>")),
+    };
 
+    let state = StateManager::new(state_db_path)?;
+    let now = chrono::Utc::now().timestamp();
+    let finality = block_finality(&state, height, now)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Block {} does not exist", height))?;
+
+    println!("{}", serde_json::to_string_pretty(&finality)?);
+    println!("finalized height: {}", finalized_height(&state, now).await?);
     Ok(())
 }
+
+/// `escape-proof <account> <mint> [height]`: print the proof of an account's
+/// balance of a bridged token as JSON, for withdrawing through the bridge's
+/// escape hatch. Defaults to the last finalized height and needs no network.
+async fn escape_proof(state_db_path: &Path, args: &[String]) -> Result<()> {
+    let (account, mint, height) = match args {
+        [account, mint] => (account.parse::<Pubkey>()?, mint.parse::<Pubkey>()?, None),
+        [account, mint, height] => (
+            account.parse::<Pubkey>()?,
+            mint.parse::<Pubkey>()?,
+            Some(height.parse()?),
+        ),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Usage: solana-oasis-node escape-proof <account> <mint> [height]"
+            ))
+        }
+    };
+
+    let state = StateManager::new(state_db_path)?;
+    let height = match height {
+        Some(height) => height,
+        None => finalized_height(&state, chrono::Utc::now().timestamp()).await?,
+    };
+    let workdir = tempfile::tempdir()?;
+    let proof = balance_proof(
+        &state,
+        &account.to_bytes(),
+        &mint.to_bytes(),
+        height,
+        &workdir,
+    )
+    .await?;
+
+    println!("{}", serde_json::to_string_pretty(&proof)?);
+    Ok(())
+}
+
+/// `replay <from> <to>`: re-execute stored blocks and print per-transaction diffs
+async fn replay(state_db_path: &Path, args: &[String]) -> Result<()> {
+    let (from, to) = match args {
+        [from, to] => (from.parse()?, to.parse()?),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Usage: solana-oasis-node replay <from> <to>"
+            ))
+        }
+    };
+
+    let state = StateManager::new(state_db_path)?;
+    let workdir = tempfile::tempdir()?;
+    let report = replay_blocks(&state, from, to, &workdir).await?;
+
+    for block in &report.blocks {
+        println!(
+            "block {} expected {} replayed {}",
+            block.number,
+            hex::encode(block.expected_root.root),
+            block
+                .replayed_root
+                .as_ref()
+                .map_or("<failed>".to_string(), |root| hex::encode(root.root))
+        );
+        for tx in &block.transactions {
+            if let Some(error) = &tx.error {
+                println!("  tx {} failed: {}", tx.index, error);
+            }
+            for change in &tx.changes {
+                println!(
+                    "  tx {} {}: {} -> {}",
+                    tx.index,
+                    hex::encode(&change.key),
+                    change
+                        .old
+                        .as_ref()
+                        .map_or("<none>".to_string(), hex::encode),
+                    change
+                        .new
+                        .as_ref()
+                        .map_or("<none>".to_string(), hex::encode)
+                );
+            }
+        }
+    }
+
+    match report.first_divergence {
+        Some(number) => Err(anyhow::anyhow!("State diverged at block {}", number)),
+        None => {
+            println!("Replayed blocks {}..={} without divergence", from, to);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::rollup::Rollup;
+use crate::state::{KeyChange, StateManager, StateRoot};
+
+/// Keys written by one transaction during replay
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionDiff {
+    pub index: usize,
+    pub changes: Vec<KeyChange>,
+    /// Execution error, if the transaction failed on replay
+    pub error: Option<String>,
+}
+
+/// Outcome of re-executing a single block
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockReplay {
+    pub number: u64,
+    /// Root stored by the node that produced the block
+    pub expected_root: StateRoot,
+    /// Root obtained by re-executing the block, if execution succeeded
+    pub replayed_root: Option<StateRoot>,
+    pub transactions: Vec<TransactionDiff>,
+}
+
+impl BlockReplay {
+    pub fn diverged(&self) -> bool {
+        self.replayed_root.as_ref() != Some(&self.expected_root)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub blocks: Vec<BlockReplay>,
+    /// First block whose replayed root differs from the stored one
+    pub first_divergence: Option<u64>,
+}
+
+/// Re-execute stored blocks `from..=to` of `source`.
+///
+/// Execution happens in a checkpoint of `source` under `workdir`, rolled back to
+/// the parent of `from`, so the source database is never modified. Replay stops
+/// at the first divergent block since later roots cannot be compared.
+pub async fn replay_blocks(
+    source: &StateManager,
+    from: u64,
+    to: u64,
+    workdir: &impl AsRef<Path>,
+) -> Result<ReplayReport> {
+    if from == 0 || from > to {
+        return Err(anyhow::anyhow!("Invalid replay range {}..={}", from, to));
+    }
+    if to > source.get_current_root().height {
+        return Err(anyhow::anyhow!(
+            "Replay range ends at {} beyond head {}",
+            to,
+            source.get_current_root().height
+        ));
+    }
+
+    let mut copy = source.checkpoint(&workdir.as_ref().join("replay-state"))?;
+    copy.rollback_to(from - 1).await?;
+    let state = Arc::new(RwLock::new(copy));
+    let mut rollup = Rollup::new(state.clone())?;
+
+    let mut report = ReplayReport {
+        blocks: Vec::new(),
+        first_divergence: None,
+    };
+
+    for number in from..=to {
+        let block = source
+            .get_block(number)?
+            .ok_or_else(|| anyhow::anyhow!("Block {} is not stored", number))?;
+        let expected_root = source
+            .get_root_at(number)?
+            .ok_or_else(|| anyhow::anyhow!("No state root stored for height {}", number))?;
+
+        rollup.validate_block(&block).await?;
+
+        let mut transactions = Vec::with_capacity(block.transactions.len());
+        let mut failed = false;
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let start = state.read().await.pending_journal().len();
+            let error = rollup.add_transaction(tx.clone()).await.err();
+
+            let guard = state.read().await;
+            let mut changes = Vec::new();
+            for entry in &guard.pending_journal()[start..] {
+                changes.push(KeyChange {
+                    key: entry.key.clone(),
+                    old: entry.previous.clone(),
+                    new: guard.get_value(&entry.key).await?,
+                });
+            }
+            drop(guard);
+
+            failed |= error.is_some();
+            transactions.push(TransactionDiff {
+                index,
+                changes,
+                error: error.map(|e| e.to_string()),
+            });
+            if failed {
+                break;
+            }
+        }
+
+        let replayed_root = if failed {
+            None
+        } else {
+            Some(state.write().await.commit_block(&block).await?.root)
+        };
+
+        let replay = BlockReplay {
+            number,
+            expected_root,
+            replayed_root,
+            transactions,
+        };
+        let diverged = replay.diverged();
+        report.blocks.push(replay);
+
+        if diverged {
+            report.first_divergence = Some(number);
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{balance_key, Block, Transaction};
+    use tempfile::tempdir;
+
+    async fn build_chain(
+        state_manager: Arc<RwLock<StateManager>>,
+        tamper_at: Option<u64>,
+    ) -> Result<()> {
+        state_manager
+            .write()
+            .await
+            .set_value(&balance_key(&[1u8; 32]), bincode::serialize(&1_000u64)?)
+            .await?;
+        state_manager
+            .write()
+            .await
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?;
+
+        let mut rollup = Rollup::new(state_manager.clone())?;
+        for number in 2..=4 {
+            if tamper_at == Some(number) {
+                // A sequencer crediting itself outside of any transaction
+                state_manager
+                    .write()
+                    .await
+                    .set_value(&balance_key(&[7u8; 32]), bincode::serialize(&500u64)?)
+                    .await?;
+            }
+            let tx = Transaction::Transfer {
+                from: [1u8; 32],
+                to: [2u8; 32],
+                amount: 10,
+            };
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![tx], 0))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_matches() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state_manager = Arc::new(RwLock::new(StateManager::new(&temp_dir.path().join("db"))?));
+        build_chain(state_manager.clone(), None).await?;
+
+        let workdir = tempdir()?;
+        let report = replay_blocks(&*state_manager.read().await, 2, 4, &workdir).await?;
+        assert_eq!(report.first_divergence, None);
+        assert_eq!(report.blocks.len(), 3);
+
+        let diff = &report.blocks[0].transactions[0];
+        assert_eq!(diff.changes.len(), 2);
+        assert_eq!(diff.changes[0].key, balance_key(&[1u8; 32]));
+        assert_eq!(diff.changes[0].new, Some(bincode::serialize(&990u64)?));
+
+        // Source database is untouched
+        assert_eq!(state_manager.read().await.get_current_root().height, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_first_divergence() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state_manager = Arc::new(RwLock::new(StateManager::new(&temp_dir.path().join("db"))?));
+        build_chain(state_manager.clone(), Some(3)).await?;
+
+        let workdir = tempdir()?;
+        let report = replay_blocks(&*state_manager.read().await, 2, 4, &workdir).await?;
+        assert_eq!(report.first_divergence, Some(3));
+        assert!(!report.blocks[0].diverged());
+        assert!(report.blocks[1].diverged());
+        assert_eq!(report.blocks.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::dispute::FraudProofSubmitted;
+use crate::state::StateManager;
+use crate::watcher::{event_discriminator, program_event_data, L1EventSource, L1WatcherConfig};
+
+/// Metadata key holding the highest L1 slot scanned for reward events
+const REWARDS_SLOT_KEY: &[u8] = b"rewards:slot";
+/// Metadata key holding the [`RewardReport`] built so far
+const REWARDS_REPORT_KEY: &[u8] = b"rewards:report";
+
+/// Fixed-point scale of the program's `reward_per_stake`
+const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
+/// Event of the `solana_oasis` program that changes what a validator earns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RewardEvent {
+    Registered {
+        validator: [u8; 32],
+        stake: u64,
+    },
+    StakeAdded {
+        validator: [u8; 32],
+        total: u64,
+    },
+    Unbonding {
+        validator: [u8; 32],
+    },
+    Slashed {
+        validator: [u8; 32],
+    },
+    /// An epoch's fees were shared over the bonded stake
+    Settled {
+        epoch: u64,
+        reward_per_stake: u128,
+    },
+    Claimed {
+        validator: [u8; 32],
+        amount: u64,
+    },
+}
+
+impl RewardEvent {
+    /// Decode an Anchor event payload, or `None` for unrelated events
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if let Some(slashed) = FraudProofSubmitted::decode_event(data) {
+            return Some(Self::Slashed {
+                validator: slashed.validator,
+            });
+        }
+        if data.len() < 8 {
+            return None;
+        }
+        let (discriminator, fields) = data.split_at(8);
+        let u64_at = |at: usize| Some(u64::from_le_bytes(fields[at..at + 8].try_into().ok()?));
+
+        if discriminator == event_discriminator("ValidatorRegistered") && fields.len() == 48 {
+            Some(Self::Registered {
+                validator: fields[0..32].try_into().ok()?,
+                stake: u64_at(32)?,
+            })
+        } else if discriminator == event_discriminator("StakeAdded") && fields.len() == 48 {
+            Some(Self::StakeAdded {
+                validator: fields[0..32].try_into().ok()?,
+                total: u64_at(40)?,
+            })
+        } else if discriminator == event_discriminator("ValidatorUnbonding") && fields.len() == 48 {
+            Some(Self::Unbonding {
+                validator: fields[0..32].try_into().ok()?,
+            })
+        } else if discriminator == event_discriminator("EpochRewardsSettled") && fields.len() == 48
+        {
+            Some(Self::Settled {
+                epoch: u64_at(0)?,
+                reward_per_stake: u128::from_le_bytes(fields[24..40].try_into().ok()?),
+            })
+        } else if discriminator == event_discriminator("RewardsClaimed") && fields.len() == 56 {
+            Some(Self::Claimed {
+                validator: fields[0..32].try_into().ok()?,
+                amount: u64_at(40)?,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Rewards one validator should have received according to the settled
+/// epochs, next to what it actually claimed.
+///
+/// Accrual mirrors the program's: bonded stake earns `reward_per_stake` growth
+/// since the last stake change, rounded down at every change.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RewardReport {
+    /// Last reward epoch settled
+    pub epoch: u64,
+    /// Bonded stake, zero while unbonding or after being slashed
+    pub stake: u64,
+    /// Rewards earned in settled epochs
+    pub expected: u64,
+    /// Rewards paid out by `claim_rewards`
+    pub claimed: u64,
+    /// Rewards lost to a slash before they were claimed
+    pub forfeited: u64,
+    reward_per_stake: u128,
+    checkpoint: u128,
+    accrued: u64,
+}
+
+impl RewardReport {
+    /// Rewards earned but neither claimed nor forfeited
+    pub fn unclaimed(&self) -> u64 {
+        self.expected.saturating_sub(self.claimed + self.forfeited)
+    }
+
+    /// Update the report with an event; returns whether it concerned
+    /// `validator`'s rewards
+    pub fn apply(&mut self, validator: &[u8; 32], event: &RewardEvent) -> bool {
+        match event {
+            RewardEvent::Settled {
+                epoch,
+                reward_per_stake,
+            } => {
+                self.epoch = *epoch;
+                self.reward_per_stake = *reward_per_stake;
+                self.expected = self.accrued + self.pending();
+                self.stake > 0
+            }
+            RewardEvent::Registered {
+                validator: v,
+                stake,
+            } if v == validator => {
+                self.checkpoint = self.reward_per_stake;
+                self.stake = *stake;
+                true
+            }
+            RewardEvent::StakeAdded {
+                validator: v,
+                total,
+            } if v == validator => {
+                self.accrue();
+                self.stake = *total;
+                true
+            }
+            RewardEvent::Unbonding { validator: v } if v == validator => {
+                self.accrue();
+                self.stake = 0;
+                true
+            }
+            RewardEvent::Slashed { validator: v } if v == validator => {
+                self.accrue();
+                self.stake = 0;
+                self.forfeited += self.unclaimed();
+                true
+            }
+            RewardEvent::Claimed {
+                validator: v,
+                amount,
+            } if v == validator => {
+                self.claimed += amount;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn pending(&self) -> u64 {
+        (self.stake as u128 * (self.reward_per_stake - self.checkpoint) / REWARD_PRECISION) as u64
+    }
+
+    fn accrue(&mut self) {
+        self.accrued += self.pending();
+        self.checkpoint = self.reward_per_stake;
+        self.expected = self.accrued;
+    }
+}
+
+/// Follows the reward events of the `solana_oasis` program and keeps a
+/// [`RewardReport`] for this node's validator, so operators can see rewards
+/// it has not claimed and notice claims that do not match the settlements.
+pub struct RewardTracker<S: L1EventSource> {
+    source: S,
+    program_id: String,
+    validator: [u8; 32],
+    state: Arc<RwLock<StateManager>>,
+    config: L1WatcherConfig,
+}
+
+impl<S: L1EventSource> RewardTracker<S> {
+    pub fn new(
+        source: S,
+        program_id: String,
+        validator: [u8; 32],
+        state: Arc<RwLock<StateManager>>,
+        config: L1WatcherConfig,
+    ) -> Self {
+        Self {
+            source,
+            program_id,
+            validator,
+            state,
+            config,
+        }
+    }
+
+    /// Track rewards until the task is dropped, logging the report whenever
+    /// it changes
+    pub async fn run(&mut self) {
+        loop {
+            match self.poll().await {
+                Ok(Some(report)) if report.claimed > report.expected => log::warn!(
+                    "Validator claimed {} rewards but only {} were settled to it",
+                    report.claimed,
+                    report.expected
+                ),
+                Ok(Some(report)) => log::info!(
+                    "Rewards through epoch {}: expected {}, claimed {}, unclaimed {}",
+                    report.epoch,
+                    report.expected,
+                    report.claimed,
+                    report.unclaimed()
+                ),
+                Ok(None) => {}
+                Err(e) => log::error!("Reward tracking failed: {}", e),
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Current report, as of the last poll
+    pub async fn report(&self) -> Result<RewardReport> {
+        match self.state.read().await.get_metadata(REWARDS_REPORT_KEY)? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Ok(RewardReport::default()),
+        }
+    }
+
+    /// Apply the reward events of confirmed slots after the cursor. Returns
+    /// the report if any of them concerned this validator.
+    pub async fn poll(&mut self) -> Result<Option<RewardReport>> {
+        let latest = self.source.latest_slot().await?;
+        let Some(confirmed) = latest.checked_sub(self.config.confirmations) else {
+            return Ok(None);
+        };
+
+        let cursor = match self.state.read().await.get_metadata(REWARDS_SLOT_KEY)? {
+            Some(data) => bincode::deserialize(&data)?,
+            None => 0,
+        };
+        if confirmed <= cursor {
+            return Ok(None);
+        }
+        let to = confirmed.min(cursor + self.config.max_slots_per_poll);
+        let transactions = self
+            .source
+            .program_logs(&self.program_id, cursor + 1, to)
+            .await?;
+
+        let mut report = self.report().await?;
+        let mut changed = false;
+        for logs in &transactions {
+            for data in program_event_data(&self.program_id, &logs.logs) {
+                if let Some(event) = RewardEvent::decode(&data) {
+                    changed |= report.apply(&self.validator, &event);
+                }
+            }
+        }
+
+        // Report and cursor are written together so no event is applied twice
+        let mut state = self.state.write().await;
+        state.set_metadata(REWARDS_REPORT_KEY, bincode::serialize(&report)?)?;
+        state.set_metadata(REWARDS_SLOT_KEY, bincode::serialize(&to)?)?;
+        Ok(changed.then_some(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watcher::{ProgramLogs, PROGRAM_DATA_PREFIX};
+    use async_trait::async_trait;
+    use base64::Engine;
+    use tempfile::tempdir;
+
+    const OASIS: &str = "oasis11111111111111111111111111111111111111";
+
+    #[derive(Default)]
+    struct MockL1 {
+        logs: Vec<ProgramLogs>,
+    }
+
+    #[async_trait]
+    impl L1EventSource for MockL1 {
+        async fn latest_slot(&self) -> Result<u64> {
+            // Every emitted transaction is confirmed, later ones are not yet
+            Ok(10 * self.logs.len() as u64 + 15)
+        }
+
+        async fn program_logs(
+            &self,
+            _program_id: &str,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<ProgramLogs>> {
+            Ok(self
+                .logs
+                .iter()
+                .filter(|logs| logs.slot >= from && logs.slot <= to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    impl MockL1 {
+        fn emit(&mut self, name: &str, fields: &[&[u8]]) {
+            let data = [&event_discriminator(name)[..], &fields.concat()].concat();
+            self.logs.push(ProgramLogs {
+                slot: 10 * (self.logs.len() as u64 + 1),
+                signature: format!("{}{}", name, self.logs.len()),
+                logs: vec![
+                    format!("Program {} invoke [1]", OASIS),
+                    format!(
+                        "{}{}",
+                        PROGRAM_DATA_PREFIX,
+                        base64::engine::general_purpose::STANDARD.encode(data)
+                    ),
+                    format!("Program {} success", OASIS),
+                ],
+            });
+        }
+
+        fn settle(&mut self, epoch: u64, amount: u64, total_stake: u64, reward_per_stake: u128) {
+            self.emit(
+                "EpochRewardsSettled",
+                &[
+                    &epoch.to_le_bytes(),
+                    &amount.to_le_bytes(),
+                    &total_stake.to_le_bytes(),
+                    &reward_per_stake.to_le_bytes(),
+                    &0i64.to_le_bytes(),
+                ],
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_follows_settlements_and_claims() -> Result<()> {
+        let validator = [1u8; 32];
+        let other = [2u8; 32];
+        let mut l1 = MockL1::default();
+
+        // Rewards settled before registering are not ours
+        l1.emit(
+            "ValidatorRegistered",
+            &[&other, &300u64.to_le_bytes(), &[0; 8]],
+        );
+        l1.settle(1, 600, 300, 2 * REWARD_PRECISION);
+        l1.emit(
+            "ValidatorRegistered",
+            &[&validator, &100u64.to_le_bytes(), &[0; 8]],
+        );
+        // 1000 over 400 staked: 250 for us
+        l1.settle(2, 1_000, 400, 4_500_000_000_000);
+        l1.emit(
+            "StakeAdded",
+            &[&validator, &200u64.to_le_bytes(), &300u64.to_le_bytes()],
+        );
+        // 1200 over 600 staked: 600 more
+        l1.settle(3, 1_200, 600, 6_500_000_000_000);
+        l1.emit(
+            "RewardsClaimed",
+            &[
+                &validator,
+                &3u64.to_le_bytes(),
+                &250u64.to_le_bytes(),
+                &250u64.to_le_bytes(),
+            ],
+        );
+
+        let dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&dir)?));
+        let config = L1WatcherConfig {
+            confirmations: 10,
+            ..L1WatcherConfig::default()
+        };
+        let mut tracker = RewardTracker::new(l1, OASIS.to_string(), validator, state, config);
+
+        let report = tracker.poll().await?.unwrap();
+        assert_eq!(report.epoch, 3);
+        assert_eq!(report.stake, 300);
+        assert_eq!(report.expected, 850);
+        assert_eq!(report.claimed, 250);
+        assert_eq!(report.unclaimed(), 600);
+
+        // Nothing new: the persisted report is unchanged
+        assert!(tracker.poll().await?.is_none());
+        assert_eq!(tracker.report().await?, report);
+
+        // Unbonded stake stops earning
+        tracker
+            .source
+            .emit("ValidatorUnbonding", &[&validator, &[0; 16]]);
+        tracker.source.settle(4, 1_000, 300, 9_833_333_333_333);
+        let report = tracker.poll().await?.unwrap();
+        assert_eq!(report.expected, 850);
+        assert_eq!(report.stake, 0);
+        Ok(())
+    }
+}
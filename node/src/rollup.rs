@@ -1,50 +1,145 @@
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 use anyhow::Result;
 use sha2::{Sha256, Digest};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
-use crate::state::StateManager;
+use crate::executor::{execute_transaction, ParallelExecutor, Snapshot};
+use crate::state::{StateDiff, StateManager, StateRoot};
 use crate::types::{Block, Transaction};
 
+/// Capacity of the reorg notification channel
+const REORG_CHANNEL_CAPACITY: usize = 16;
+
+/// Notification that the canonical head moved backwards
+#[derive(Clone, Debug)]
+pub struct ReorgEvent {
+    /// Head before the rollback
+    pub old_head: StateRoot,
+    /// Head after the rollback
+    pub new_head: StateRoot,
+    /// Numbers of the blocks that were reverted, in ascending order
+    pub reverted_blocks: Vec<u64>,
+    /// Number of transactions returned to the mempool
+    pub requeued_transactions: usize,
+}
+
 pub struct Rollup {
     state_manager: Arc<RwLock<StateManager>>,
+    mempool: VecDeque<Transaction>,
+    reorg_sender: broadcast::Sender<ReorgEvent>,
+    executor: ParallelExecutor,
 }
 
 impl Rollup {
     pub fn new(state_manager: Arc<RwLock<StateManager>>) -> Result<Self> {
-        Ok(Self { state_manager })
+        let (reorg_sender, _) = broadcast::channel(REORG_CHANNEL_CAPACITY);
+        Ok(Self {
+            state_manager,
+            mempool: VecDeque::new(),
+            reorg_sender,
+            executor: ParallelExecutor::default(),
+        })
+    }
+
+    /// Subscribe to reorg notifications
+    pub fn subscribe_reorgs(&self) -> broadcast::Receiver<ReorgEvent> {
+        self.reorg_sender.subscribe()
+    }
+
+    /// Queue a transaction for inclusion in a future block
+    pub fn submit_transaction(&mut self, transaction: Transaction) {
+        self.mempool.push_back(transaction);
+    }
+
+    /// Queue a transaction ahead of everything already waiting, e.g. a forced
+    /// transaction close to its inclusion deadline
+    pub fn submit_priority_transaction(&mut self, transaction: Transaction) {
+        self.mempool.push_front(transaction);
+    }
+
+    /// Transactions waiting to be included, oldest first
+    pub fn pending_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.mempool.iter()
+    }
+
+    /// Remove up to `max` transactions from the front of the mempool
+    pub fn take_pending(&mut self, max: usize) -> Vec<Transaction> {
+        let count = max.min(self.mempool.len());
+        self.mempool.drain(..count).collect()
     }
 
     pub async fn add_transaction(&mut self, transaction: Transaction) -> Result<()> {
-        match transaction {
-            Transaction::Transfer { from, to, amount } => {
-                let mut state = self.state_manager.write().await;
-                let from_key = [&b"balance:"[..], from.as_ref()].concat();
-                let from_balance = match state.get_value(&from_key).await? {
-                    Some(data) => bincode::deserialize(&data)?,
-                    None => 0u64,
-                };
-
-                if from_balance < amount {
-                    return Err(anyhow::anyhow!("Insufficient balance"));
-                }
+        let mut state = self.state_manager.write().await;
+        let mut snapshot = Snapshot::new();
+        for key in transaction.access_list() {
+            let value = state.get_value(&key).await?;
+            snapshot.insert(key, value);
+        }
+
+        let writes = execute_transaction(&transaction, |key| snapshot.get(key).cloned().flatten())?;
+        for (key, value) in writes {
+            state.set_value(&key, value).await?;
+        }
+        Ok(())
+    }
 
-                let to_key = [&b"balance:"[..], to.as_ref()].concat();
-                let to_balance = match state.get_value(&to_key).await? {
-                    Some(data) => bincode::deserialize(&data)?,
-                    None => 0u64,
-                };
+    pub async fn process_block(&mut self, block: Block) -> Result<StateDiff> {
+        self.validate_block(&block).await?;
 
-                state.set_value(&from_key, bincode::serialize(&(from_balance - amount))?)
-                    .await?;
-                state.set_value(&to_key, bincode::serialize(&(to_balance + amount))?)
-                    .await?;
+        for tx in block.transactions.clone() {
+            if let Err(e) = self.add_transaction(tx).await {
+                self.state_manager.write().await.discard_pending().await?;
+                return Err(e);
             }
         }
-        Ok(())
+
+        self.state_manager.write().await.commit_block(&block).await
     }
 
-    pub async fn process_block(&mut self, block: Block) -> Result<()> {
+    /// Process a block with optimistic parallel execution.
+    ///
+    /// The state lock is only held to snapshot the block's access lists and to
+    /// apply the validated writes, and the resulting diff and root are identical
+    /// to [`Rollup::process_block`].
+    pub async fn process_block_parallel(&mut self, block: Block) -> Result<StateDiff> {
+        self.validate_block(&block).await?;
+
+        let keys: HashSet<Vec<u8>> = block
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.access_list())
+            .collect();
+        let mut snapshot = Snapshot::new();
+        {
+            let state = self.state_manager.read().await;
+            for key in keys {
+                let value = state.get_value(&key).await?;
+                snapshot.insert(key, value);
+            }
+        }
+
+        let execution = self.executor.execute(&block.transactions, &snapshot)?;
+        if execution.reexecuted > 0 {
+            log::debug!(
+                "Re-executed {} conflicting transactions in block {}",
+                execution.reexecuted,
+                block.number
+            );
+        }
+
+        let mut state = self.state_manager.write().await;
+        for (key, value) in execution.write_sets.into_iter().flatten() {
+            if let Err(e) = state.set_value(&key, value).await {
+                state.discard_pending().await?;
+                return Err(e);
+            }
+        }
+        state.commit_block(&block).await
+    }
+
+    /// Check the transactions root and that `block` extends the current head
+    pub async fn validate_block(&self, block: &Block) -> Result<()> {
         let mut hasher = Sha256::new();
         hasher.update(&bincode::serialize(&block.transactions)?);
         let transactions_root = hasher.finalize();
@@ -53,17 +148,64 @@ impl Rollup {
             return Err(anyhow::anyhow!("Invalid transactions root"));
         }
 
-        for tx in block.transactions {
-            self.add_transaction(tx).await?;
+        let expected = self.state_manager.read().await.get_current_root().height + 1;
+        if block.number != expected {
+            return Err(anyhow::anyhow!(
+                "Unexpected block number {}, expected {}",
+                block.number,
+                expected
+            ));
         }
 
         Ok(())
     }
+
+    /// Revert the canonical chain to `height`, e.g. after its successor root was
+    /// successfully challenged on L1.
+    ///
+    /// Transactions from the reverted blocks are put back at the front of the
+    /// mempool in their original order and subscribers receive a [`ReorgEvent`].
+    pub async fn rollback_to(&mut self, height: u64) -> Result<ReorgEvent> {
+        let mut state = self.state_manager.write().await;
+        let old_head = state.get_current_root().clone();
+        let reverted = state.rollback_to(height).await?;
+        let new_head = state.get_current_root().clone();
+        drop(state);
+
+        let transactions: Vec<Transaction> = reverted
+            .iter()
+            .flat_map(|block| block.transactions.iter().cloned())
+            .collect();
+        let requeued_transactions = transactions.len();
+        for tx in transactions.into_iter().rev() {
+            self.mempool.push_front(tx);
+        }
+
+        let event = ReorgEvent {
+            old_head,
+            new_head,
+            reverted_blocks: reverted.iter().map(|block| block.number).collect(),
+            requeued_transactions,
+        };
+
+        if !event.reverted_blocks.is_empty() {
+            log::warn!(
+                "Rolled back from height {} to {}",
+                event.old_head.height,
+                event.new_head.height
+            );
+            // Having no subscribers is not an error
+            let _ = self.reorg_sender.send(event.clone());
+        }
+
+        Ok(event)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::balance_key;
     use tempfile::tempdir;
 
     #[tokio::test]
@@ -80,12 +222,14 @@ mod tests {
         let mut state = state_manager.write().await;
         let from_key = [&b"balance:"[..], &from[..]].concat();
         state.set_value(&from_key, bincode::serialize(&200u64)?).await?;
+        drop(state);
 
         // Process transaction
         let tx = Transaction::Transfer { from, to, amount };
         rollup.add_transaction(tx.clone()).await?;
 
         // Verify balances
+        let state = state_manager.read().await;
         let from_balance: u64 = bincode::deserialize(
             &state.get_value(&from_key).await?.unwrap()
         )?;
@@ -99,4 +243,107 @@ mod tests {
 
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_multi_block_rollback() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state_manager = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        let mut rollup = Rollup::new(state_manager.clone())?;
+        let mut reorgs = rollup.subscribe_reorgs();
+
+        let alice = [1u8; 32];
+        let bob = [2u8; 32];
+        let alice_key = [&b"balance:"[..], &alice[..]].concat();
+        let bob_key = [&b"balance:"[..], &bob[..]].concat();
+
+        // Fund alice in block 1
+        state_manager
+            .write()
+            .await
+            .set_value(&alice_key, bincode::serialize(&300u64)?)
+            .await?;
+        let genesis = state_manager
+            .write()
+            .await
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?
+            .root;
+
+        let mut previous_hash = [0u8; 32];
+        for number in 2..=4 {
+            let tx = Transaction::Transfer {
+                from: alice,
+                to: bob,
+                amount: 50,
+            };
+            let block = Block::new(number, previous_hash, vec![tx], 0);
+            previous_hash.copy_from_slice(&block.hash());
+            rollup.process_block(block).await?;
+        }
+
+        let event = rollup.rollback_to(1).await?;
+        assert_eq!(event.reverted_blocks, vec![2, 3, 4]);
+        assert_eq!(event.requeued_transactions, 3);
+        assert_eq!(event.old_head.height, 4);
+        assert_eq!(event.new_head, genesis);
+
+        let received = reorgs.recv().await?;
+        assert_eq!(received.reverted_blocks, event.reverted_blocks);
+
+        let state = state_manager.read().await;
+        assert_eq!(state.get_current_root(), &genesis);
+        let alice_balance: u64 = bincode::deserialize(&state.get_value(&alice_key).await?.unwrap())?;
+        assert_eq!(alice_balance, 300);
+        assert!(state.get_value(&bob_key).await?.is_none());
+        drop(state);
+
+        assert_eq!(rollup.pending_transactions().count(), 3);
+
+        // Re-applying the requeued transactions reaches the same balances again
+        let block = Block::new(2, [0u8; 32], rollup.take_pending(10), 0);
+        rollup.process_block(block).await?;
+        let state = state_manager.read().await;
+        let bob_balance: u64 = bincode::deserialize(&state.get_value(&bob_key).await?.unwrap())?;
+        assert_eq!(bob_balance, 150);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_matches_sequential_diff() -> Result<()> {
+        let accounts: Vec<[u8; 32]> = (0..8u8).map(|i| [i; 32]).collect();
+        let transactions: Vec<Transaction> = (0..32usize)
+            .map(|i| Transaction::Transfer {
+                from: accounts[i % 8],
+                to: accounts[(i * 3 + 1) % 8],
+                amount: 5,
+            })
+            .collect();
+        let block = Block::new(1, [0u8; 32], transactions, 0);
+
+        let mut diffs = Vec::new();
+        for parallel in [false, true] {
+            let temp_dir = tempdir()?;
+            let state_manager = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+            {
+                let mut state = state_manager.write().await;
+                for account in &accounts {
+                    state
+                        .set_value(&balance_key(account), bincode::serialize(&100u64)?)
+                        .await?;
+                }
+            }
+
+            let mut rollup = Rollup::new(state_manager.clone())?;
+            let diff = if parallel {
+                rollup.process_block_parallel(block.clone()).await?
+            } else {
+                rollup.process_block(block.clone()).await?
+            };
+            diffs.push(diff);
+        }
+
+        assert_eq!(diffs[0], diffs[1]);
+        Ok(())
+    }
+}
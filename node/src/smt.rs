@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Tree depth: one level per bit of a key's path
+pub const DEPTH: usize = 256;
+
+/// Hash of an empty subtree at any depth
+pub const EMPTY: [u8; 32] = [0u8; 32];
+
+/// Position of `key` in the tree
+pub fn key_path(key: &[u8]) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+/// Leaf committing to `value` at `path`
+pub fn leaf_hash(path: &[u8; 32], value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(path);
+    hasher.update(Sha256::digest(value));
+    hasher.finalize().into()
+}
+
+/// Parent of two subtrees; two empty subtrees make an empty subtree
+pub fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    if left == &EMPTY && right == &EMPTY {
+        return EMPTY;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Bit `depth` of `path`, most significant first; set means the right child
+fn bit(path: &[u8; 32], depth: usize) -> bool {
+    path[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+/// First `depth` bits of `path`, identifying the node at `depth` above it
+fn prefix(path: &[u8; 32], depth: usize) -> [u8; 32] {
+    let (bytes, bits) = (depth / 8, depth % 8);
+    let mut prefix = [0u8; 32];
+    prefix[..bytes].copy_from_slice(&path[..bytes]);
+    if bits > 0 {
+        prefix[bytes] = path[bytes] & !(0xff >> bits);
+    }
+    prefix
+}
+
+/// Prefix of the sibling of the node at `depth + 1` above `path`
+fn sibling_prefix(path: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut prefix = prefix(path, depth + 1);
+    prefix[depth / 8] ^= 0x80 >> (depth % 8);
+    prefix
+}
+
+/// Storage key of the node at `depth` with `prefix`
+pub fn node_key(depth: usize, prefix: &[u8; 32]) -> Vec<u8> {
+    [&(depth as u16).to_be_bytes()[..], &prefix[..]].concat()
+}
+
+/// Inclusion or exclusion proof of one key
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtProof {
+    /// Bit `d` is set if the sibling at depth `d + 1` is not empty
+    pub bitmap: [u8; 32],
+    /// Non-empty siblings, from the root down
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl SmtProof {
+    /// Root of the tree in which the key at `path` holds `value`, `None`
+    /// meaning absent, and every other leaf is as when the proof was taken
+    pub fn root(&self, path: &[u8; 32], value: Option<&[u8]>) -> Result<[u8; 32]> {
+        let mut hash = value.map_or(EMPTY, |value| leaf_hash(path, value));
+        let mut siblings = self.siblings.iter().rev();
+        for depth in (0..DEPTH).rev() {
+            let sibling = if bit(&self.bitmap, depth) {
+                *siblings
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Proof is missing siblings"))?
+            } else {
+                EMPTY
+            };
+            hash = if bit(path, depth) {
+                node_hash(&sibling, &hash)
+            } else {
+                node_hash(&hash, &sibling)
+            };
+        }
+        if siblings.next().is_some() {
+            return Err(anyhow::anyhow!("Proof has unused siblings"));
+        }
+        Ok(hash)
+    }
+}
+
+/// Sparse Merkle tree over a node store, with updates buffered in memory
+/// until they are written back with [`SparseMerkleTree::into_nodes`]
+pub struct SparseMerkleTree<F> {
+    read: F,
+    nodes: HashMap<(usize, [u8; 32]), [u8; 32]>,
+}
+
+impl<F> SparseMerkleTree<F>
+where
+    F: Fn(usize, &[u8; 32]) -> Result<[u8; 32]>,
+{
+    /// `read` returns the stored node at a depth and prefix, [`EMPTY`] if absent
+    pub fn new(read: F) -> Self {
+        Self {
+            read,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn node(&self, depth: usize, prefix: &[u8; 32]) -> Result<[u8; 32]> {
+        match self.nodes.get(&(depth, *prefix)) {
+            Some(hash) => Ok(*hash),
+            None => (self.read)(depth, prefix),
+        }
+    }
+
+    pub fn root(&self) -> Result<[u8; 32]> {
+        self.node(0, &EMPTY)
+    }
+
+    /// Set `key` to `value`, `None` removing it
+    pub fn update(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let path = key_path(key);
+        let mut hash = value.map_or(EMPTY, |value| leaf_hash(&path, value));
+        self.nodes.insert((DEPTH, path), hash);
+
+        for depth in (0..DEPTH).rev() {
+            let sibling = self.node(depth + 1, &sibling_prefix(&path, depth))?;
+            hash = if bit(&path, depth) {
+                node_hash(&sibling, &hash)
+            } else {
+                node_hash(&hash, &sibling)
+            };
+            self.nodes.insert((depth, prefix(&path, depth)), hash);
+        }
+        Ok(())
+    }
+
+    pub fn proof(&self, key: &[u8]) -> Result<SmtProof> {
+        let path = key_path(key);
+        let mut proof = SmtProof {
+            bitmap: [0u8; 32],
+            siblings: Vec::new(),
+        };
+        for depth in 0..DEPTH {
+            let sibling = self.node(depth + 1, &sibling_prefix(&path, depth))?;
+            if sibling != EMPTY {
+                proof.bitmap[depth / 8] |= 0x80 >> (depth % 8);
+                proof.siblings.push(sibling);
+            }
+        }
+        Ok(proof)
+    }
+
+    /// Nodes changed by updates as `(storage key, hash)`; empty nodes should
+    /// be deleted from the store
+    pub fn into_nodes(self) -> Vec<(Vec<u8>, [u8; 32])> {
+        self.nodes
+            .into_iter()
+            .map(|((depth, prefix), hash)| (node_key(depth, &prefix), hash))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proofs_track_updates() -> Result<()> {
+        let mut tree = SparseMerkleTree::new(|_, _| Ok(EMPTY));
+        assert_eq!(tree.root()?, EMPTY);
+
+        tree.update(b"a", Some(b"1"))?;
+        tree.update(b"b", Some(b"2"))?;
+        let root = tree.root()?;
+
+        let proof = tree.proof(b"a")?;
+        assert_eq!(proof.root(&key_path(b"a"), Some(b"1"))?, root);
+        assert_ne!(proof.root(&key_path(b"a"), Some(b"3"))?, root);
+
+        // Exclusion proof, and the root after inserting through it
+        let proof = tree.proof(b"c")?;
+        assert_eq!(proof.root(&key_path(b"c"), None)?, root);
+        let inserted = proof.root(&key_path(b"c"), Some(b"3"))?;
+        tree.update(b"c", Some(b"3"))?;
+        assert_eq!(tree.root()?, inserted);
+
+        // Removing every key empties the tree again
+        for key in [&b"a"[..], b"b", b"c"] {
+            tree.update(key, None)?;
+        }
+        assert_eq!(tree.root()?, EMPTY);
+
+        Ok(())
+    }
+}
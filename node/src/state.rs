@@ -1,19 +1,63 @@
 use anyhow::Result;
-use rocksdb::{ColumnFamilyDescriptor, Options, DB};
+use bincode::Options as _;
+use rocksdb::{
+    checkpoint::Checkpoint, ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch,
+    DB,
+};
 use serde;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
+use crate::smt::{node_key, SmtProof, SparseMerkleTree, EMPTY};
+use crate::types::Block;
+
 pub struct StateManager {
     db: DB,
     current_root: StateRoot,
+    pending_journal: Vec<JournalEntry>,
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct StateRoot {
     pub root: [u8; 32],
     pub height: u64,
 }
 
+/// Value a key held before it was overwritten, used to undo a block
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    pub key: Vec<u8>,
+    pub previous: Option<Vec<u8>>,
+}
+
+/// Change to a single state key
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KeyChange {
+    pub key: Vec<u8>,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+}
+
+/// Net effect of a block on state: every key whose value changed, in order of
+/// first write, with its value before and after the block
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateDiff {
+    pub previous_root: StateRoot,
+    pub root: StateRoot,
+    pub changes: Vec<KeyChange>,
+}
+
+impl StateDiff {
+    /// Compact varint encoding used for storage and L1 posting
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::DefaultOptions::new().serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(bincode::DefaultOptions::new().deserialize(data)?)
+    }
+}
+
 impl StateManager {
     pub fn new(path: &impl AsRef<Path>) -> Result<Self> {
         let mut opts = Options::default();
@@ -22,8 +66,19 @@ impl StateManager {
 
         let cf_roots = ColumnFamilyDescriptor::new("roots", Options::default());
         let cf_data = ColumnFamilyDescriptor::new("data", Options::default());
+        let cf_blocks = ColumnFamilyDescriptor::new("blocks", Options::default());
+        let cf_journal = ColumnFamilyDescriptor::new("journal", Options::default());
+        let cf_diffs = ColumnFamilyDescriptor::new("diffs", Options::default());
+        let cf_meta = ColumnFamilyDescriptor::new("meta", Options::default());
+        let cf_smt = ColumnFamilyDescriptor::new("smt", Options::default());
 
-        let db = DB::open_cf_descriptors(&opts, path, vec![cf_roots, cf_data])?;
+        let db = DB::open_cf_descriptors(
+            &opts,
+            path,
+            vec![
+                cf_roots, cf_data, cf_blocks, cf_journal, cf_diffs, cf_meta, cf_smt,
+            ],
+        )?;
 
         let current_root = match db.get_cf(db.cf_handle("roots").unwrap(), "current")? {
             Some(data) => bincode::deserialize(&data)?,
@@ -33,7 +88,11 @@ impl StateManager {
             },
         };
 
-        Ok(Self { db, current_root })
+        Ok(Self {
+            db,
+            current_root,
+            pending_journal: Vec::new(),
+        })
     }
 
     pub async fn get_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -43,7 +102,12 @@ impl StateManager {
 
     pub async fn set_value(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         let cf_data = self.db.cf_handle("data").unwrap();
+        let previous = self.db.get_cf(cf_data, key)?;
         self.db.put_cf(cf_data, key, value)?;
+        self.pending_journal.push(JournalEntry {
+            key: key.to_vec(),
+            previous,
+        });
         Ok(())
     }
 
@@ -58,6 +122,317 @@ impl StateManager {
     pub fn get_current_root(&self) -> &StateRoot {
         &self.current_root
     }
+
+    /// Commit all writes made since the last commit as `block`, advancing the head.
+    ///
+    /// The new root is the root of a sparse Merkle tree over the whole state, so
+    /// the value of any key can be proven against it. The block's [`StateDiff`]
+    /// is stored next to it and returned.
+    pub async fn commit_block(&mut self, block: &Block) -> Result<StateDiff> {
+        self.commit(block, None).await
+    }
+
+    /// Commit the genesis block together with the hash of the configuration it
+    /// was built from, so a database never holds one without the other
+    pub async fn commit_genesis(&mut self, block: &Block, hash: [u8; 32]) -> Result<StateDiff> {
+        self.commit(block, Some(hash)).await
+    }
+
+    async fn commit(&mut self, block: &Block, genesis_hash: Option<[u8; 32]>) -> Result<StateDiff> {
+        let tree = self.pending_tree().await?;
+        let root = StateRoot {
+            root: tree.root()?,
+            height: block.number,
+        };
+        let nodes = tree.into_nodes();
+
+        let diff = StateDiff {
+            previous_root: self.current_root.clone(),
+            root: root.clone(),
+            changes: self.pending_changes().await?,
+        };
+
+        let height_key = block.number.to_be_bytes();
+        let cf_roots = self.db.cf_handle("roots").unwrap();
+        let cf_blocks = self.db.cf_handle("blocks").unwrap();
+        let cf_journal = self.db.cf_handle("journal").unwrap();
+        let cf_diffs = self.db.cf_handle("diffs").unwrap();
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(cf_blocks, height_key, bincode::serialize(block)?);
+        batch.put_cf(
+            cf_journal,
+            height_key,
+            bincode::serialize(&self.pending_journal)?,
+        );
+        batch.put_cf(cf_diffs, height_key, diff.encode()?);
+        batch.put_cf(cf_roots, height_key, bincode::serialize(&root)?);
+        batch.put_cf(cf_roots, "current", bincode::serialize(&root)?);
+        if let Some(hash) = genesis_hash {
+            batch.put_cf(cf_roots, "genesis", bincode::serialize(&hash)?);
+        }
+        self.write_nodes(&mut batch, nodes);
+        self.db.write(batch)?;
+
+        self.pending_journal.clear();
+        self.current_root = root;
+        Ok(diff)
+    }
+
+    /// State tree as of the last committed block
+    pub fn tree(&self) -> SparseMerkleTree<impl Fn(usize, &[u8; 32]) -> Result<[u8; 32]> + '_> {
+        let cf_smt = self.db.cf_handle("smt").unwrap();
+        SparseMerkleTree::new(move |depth, prefix| {
+            match self.db.get_cf(cf_smt, node_key(depth, prefix))? {
+                Some(data) => Ok(data.as_slice().try_into()?),
+                None => Ok(EMPTY),
+            }
+        })
+    }
+
+    /// State tree including the writes made since the last committed block
+    pub async fn pending_tree(
+        &self,
+    ) -> Result<SparseMerkleTree<impl Fn(usize, &[u8; 32]) -> Result<[u8; 32]> + '_>> {
+        let mut tree = self.tree();
+        let mut updated = HashSet::new();
+        for entry in &self.pending_journal {
+            if updated.insert(entry.key.as_slice()) {
+                tree.update(&entry.key, self.get_value(&entry.key).await?.as_deref())?;
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Proof of the committed value of `key` against the current root
+    pub fn prove(&self, key: &[u8]) -> Result<SmtProof> {
+        self.tree().proof(key)
+    }
+
+    fn write_nodes(&self, batch: &mut WriteBatch, nodes: Vec<(Vec<u8>, [u8; 32])>) {
+        let cf_smt = self.db.cf_handle("smt").unwrap();
+        for (key, hash) in nodes {
+            if hash == EMPTY {
+                batch.delete_cf(cf_smt, key);
+            } else {
+                batch.put_cf(cf_smt, key, hash);
+            }
+        }
+    }
+
+    /// Collapse the pending journal into one change per key, dropping keys that
+    /// ended up with their original value
+    async fn pending_changes(&self) -> Result<Vec<KeyChange>> {
+        let mut first_write: HashMap<&[u8], &Option<Vec<u8>>> = HashMap::new();
+        let mut order = Vec::new();
+        for entry in &self.pending_journal {
+            if !first_write.contains_key(entry.key.as_slice()) {
+                first_write.insert(&entry.key, &entry.previous);
+                order.push(&entry.key);
+            }
+        }
+
+        let mut changes = Vec::with_capacity(order.len());
+        for key in order {
+            let old = first_write[key.as_slice()].clone();
+            let new = self.get_value(key).await?;
+            if old != new {
+                changes.push(KeyChange {
+                    key: key.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// State diff stored for the block at `height`
+    pub fn get_state_diff(&self, height: u64) -> Result<Option<StateDiff>> {
+        let cf_diffs = self.db.cf_handle("diffs").unwrap();
+        match self.db.get_cf(cf_diffs, height.to_be_bytes())? {
+            Some(data) => Ok(Some(StateDiff::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes made since the last committed block, oldest first
+    pub fn pending_journal(&self) -> &[JournalEntry] {
+        &self.pending_journal
+    }
+
+    /// Copy the database to `path`, which must not exist yet, and open the copy.
+    ///
+    /// Fails while there are uncommitted writes, since their journal only lives
+    /// in memory and could not be undone in the copy.
+    pub fn checkpoint(&self, path: &impl AsRef<Path>) -> Result<StateManager> {
+        if !self.pending_journal.is_empty() {
+            return Err(anyhow::anyhow!("Cannot checkpoint with uncommitted writes"));
+        }
+        Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        StateManager::new(path)
+    }
+
+    /// Undo writes that have not been committed as part of a block
+    pub async fn discard_pending(&mut self) -> Result<()> {
+        let journal = std::mem::take(&mut self.pending_journal);
+        self.undo(&journal)
+    }
+
+    /// Revert state to `height`, returning the reverted blocks in ascending order.
+    ///
+    /// Uncommitted writes are discarded as well. The whole rollback lands in a
+    /// single write, so a crash leaves the state at either its start or its end.
+    pub async fn rollback_to(&mut self, height: u64) -> Result<Vec<Block>> {
+        if height > self.current_root.height {
+            return Err(anyhow::anyhow!(
+                "Cannot roll back to height {} above head {}",
+                height,
+                self.current_root.height
+            ));
+        }
+
+        let target = self
+            .get_root_at(height)?
+            .ok_or_else(|| anyhow::anyhow!("No state root stored for height {}", height))?;
+
+        // Undo newest first, so every key ends up with the value it held
+        // before its oldest reverted write
+        let mut restored: HashMap<Vec<u8>, Option<Vec<u8>>> = HashMap::new();
+        for entry in self.pending_journal.iter().rev() {
+            restored.insert(entry.key.clone(), entry.previous.clone());
+        }
+
+        let cf_data = self.db.cf_handle("data").unwrap();
+        let cf_roots = self.db.cf_handle("roots").unwrap();
+        let cf_blocks = self.db.cf_handle("blocks").unwrap();
+        let cf_journal = self.db.cf_handle("journal").unwrap();
+        let cf_diffs = self.db.cf_handle("diffs").unwrap();
+        let mut batch = WriteBatch::default();
+        let mut reverted = Vec::new();
+        for number in (height + 1..=self.current_root.height).rev() {
+            let height_key = number.to_be_bytes();
+            let journal: Vec<JournalEntry> = match self.db.get_cf(cf_journal, height_key)? {
+                Some(data) => bincode::deserialize(&data)?,
+                None => return Err(anyhow::anyhow!("Missing journal for height {}", number)),
+            };
+            for entry in journal.iter().rev() {
+                restored.insert(entry.key.clone(), entry.previous.clone());
+            }
+
+            if let Some(block) = self.get_block(number)? {
+                reverted.push(block);
+            }
+
+            batch.delete_cf(cf_journal, height_key);
+            batch.delete_cf(cf_blocks, height_key);
+            batch.delete_cf(cf_diffs, height_key);
+            batch.delete_cf(cf_roots, height_key);
+        }
+
+        let mut tree = self.tree();
+        for (key, value) in &restored {
+            tree.update(key, value.as_deref())?;
+        }
+        if tree.root()? != target.root {
+            return Err(anyhow::anyhow!(
+                "State tree does not match the root at height {}",
+                height
+            ));
+        }
+        self.write_nodes(&mut batch, tree.into_nodes());
+
+        for (key, value) in &restored {
+            match value {
+                Some(value) => batch.put_cf(cf_data, key, value),
+                None => batch.delete_cf(cf_data, key),
+            }
+        }
+        batch.put_cf(cf_roots, "current", bincode::serialize(&target)?);
+        self.db.write(batch)?;
+
+        self.pending_journal.clear();
+        self.current_root = target;
+        reverted.reverse();
+        Ok(reverted)
+    }
+
+    /// State root committed at `height`; height 0 is the empty initial state
+    pub fn get_root_at(&self, height: u64) -> Result<Option<StateRoot>> {
+        let cf_roots = self.db.cf_handle("roots").unwrap();
+        match self.db.get_cf(cf_roots, height.to_be_bytes())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None if height == 0 => Ok(Some(StateRoot {
+                root: [0u8; 32],
+                height: 0,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Hash of the genesis configuration this database was initialized from
+    pub fn get_genesis_hash(&self) -> Result<Option<[u8; 32]>> {
+        let cf_roots = self.db.cf_handle("roots").unwrap();
+        match self.db.get_cf(cf_roots, "genesis")? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Node bookkeeping that is not part of the L2 state and is never rolled back
+    pub fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let cf_meta = self.db.cf_handle("meta").unwrap();
+        Ok(self.db.get_cf(cf_meta, key)?)
+    }
+
+    pub fn set_metadata(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let cf_meta = self.db.cf_handle("meta").unwrap();
+        self.db.put_cf(cf_meta, key, value)?;
+        Ok(())
+    }
+
+    pub fn delete_metadata(&mut self, key: &[u8]) -> Result<()> {
+        let cf_meta = self.db.cf_handle("meta").unwrap();
+        self.db.delete_cf(cf_meta, key)?;
+        Ok(())
+    }
+
+    /// First metadata entry whose key sorts at or after `from`
+    pub fn seek_metadata(&self, from: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let cf_meta = self.db.cf_handle("meta").unwrap();
+        match self
+            .db
+            .iterator_cf(cf_meta, IteratorMode::From(from, Direction::Forward))
+            .next()
+        {
+            Some(entry) => {
+                let (key, value) = entry?;
+                Ok(Some((key.to_vec(), value.to_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_block(&self, height: u64) -> Result<Option<Block>> {
+        let cf_blocks = self.db.cf_handle("blocks").unwrap();
+        match self.db.get_cf(cf_blocks, height.to_be_bytes())? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn undo(&self, journal: &[JournalEntry]) -> Result<()> {
+        let cf_data = self.db.cf_handle("data").unwrap();
+        let mut batch = WriteBatch::default();
+        for entry in journal.iter().rev() {
+            match &entry.previous {
+                Some(value) => batch.put_cf(cf_data, &entry.key, value),
+                None => batch.delete_cf(cf_data, &entry.key),
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -87,4 +462,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_rollback_restores_values() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut state = StateManager::new(&temp_dir)?;
+
+        state.set_value(b"a", b"1".to_vec()).await?;
+        let first = state.commit_block(&Block::new(1, [0u8; 32], vec![], 0)).await?.root;
+
+        state.set_value(b"a", b"2".to_vec()).await?;
+        state.set_value(b"b", b"3".to_vec()).await?;
+        state.commit_block(&Block::new(2, [0u8; 32], vec![], 0)).await?;
+
+        let reverted = state.rollback_to(1).await?;
+        assert_eq!(reverted.len(), 1);
+        assert_eq!(reverted[0].number, 2);
+        assert_eq!(state.get_value(b"a").await?, Some(b"1".to_vec()));
+        assert_eq!(state.get_value(b"b").await?, None);
+        assert_eq!(state.get_current_root(), &first);
+        assert!(state.get_block(2)?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_diff() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let mut state = StateManager::new(&temp_dir)?;
+
+        state.set_value(b"a", b"1".to_vec()).await?;
+        state.set_value(b"b", b"1".to_vec()).await?;
+        state
+            .commit_block(&Block::new(1, [0u8; 32], vec![], 0))
+            .await?;
+
+        state.set_value(b"a", b"2".to_vec()).await?;
+        state.set_value(b"a", b"3".to_vec()).await?;
+        state.set_value(b"b", b"2".to_vec()).await?;
+        state.set_value(b"b", b"1".to_vec()).await?;
+        state.set_value(b"c", b"4".to_vec()).await?;
+        let diff = state
+            .commit_block(&Block::new(2, [0u8; 32], vec![], 0))
+            .await?;
+
+        assert_eq!(diff.previous_root.height, 1);
+        assert_eq!(&diff.root, state.get_current_root());
+        assert_eq!(
+            diff.changes,
+            vec![
+                KeyChange {
+                    key: b"a".to_vec(),
+                    old: Some(b"1".to_vec()),
+                    new: Some(b"3".to_vec()),
+                },
+                KeyChange {
+                    key: b"c".to_vec(),
+                    old: None,
+                    new: Some(b"4".to_vec()),
+                },
+            ]
+        );
+
+        assert_eq!(state.get_state_diff(2)?, Some(diff.clone()));
+        assert_eq!(StateDiff::decode(&diff.encode()?)?, diff);
+
+        state.rollback_to(1).await?;
+        assert!(state.get_state_diff(2)?.is_none());
+
+        Ok(())
+    }
 }
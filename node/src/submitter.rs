@@ -0,0 +1,662 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::batch::{split_payload, Batch, BatchCommitment, BatchData, MAX_CHUNK_DATA_BYTES};
+use crate::state::StateManager;
+
+/// Metadata key holding the highest L2 height whose root was confirmed on L1
+const COMMITTED_HEIGHT_KEY: &[u8] = b"l1:committed_height";
+/// Prefix of per-batch submission records, keyed by the batch end height
+const BATCH_RECORD_PREFIX: &[u8] = b"l1:batch:";
+
+/// Largest encoded batch posted inline in `update_state_root`; anything larger
+/// is split into `post_batch_data` chunks. Leaves room in the transaction for
+/// the commitment and the co-signing validators' signatures.
+pub const MAX_INLINE_BATCH_BYTES: usize = 400;
+
+/// Outcome of a submitted L1 transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfirmationStatus {
+    /// Not yet observed at the requested commitment
+    Pending,
+    Confirmed,
+    /// Landed but the program rejected it
+    Failed(String),
+}
+
+/// The subset of L1 interaction the batch submitter needs.
+///
+/// Implemented over `solana-client` by [`crate::l1::SolanaL1Client`] and by
+/// in-memory mocks in tests.
+#[async_trait]
+pub trait L1Client: Send + Sync {
+    /// Send an `update_state_root` transaction, returning its signature
+    async fn submit_state_root(&self, commitment: BatchCommitment, data: Vec<u8>)
+        -> Result<String>;
+
+    /// Send a `post_batch_data` transaction carrying one batch chunk
+    async fn post_batch_data(&self, data: Vec<u8>) -> Result<String>;
+
+    /// Whether this validator is the program's leader for the next batch, the
+    /// only one `update_state_root` accepts right now
+    async fn is_leader(&self) -> Result<bool>;
+
+    async fn confirmation_status(&self, signature: &str) -> Result<ConfirmationStatus>;
+
+    /// Most recent root accepted by the program, `None` before the first batch
+    async fn latest_root(&self) -> Result<Option<PostedRoot>>;
+}
+
+/// A state root recorded on L1 by `update_state_root`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PostedRoot {
+    pub batch_index: u64,
+    pub state_root: [u8; 32],
+    /// Hash of the batch data the root was posted with
+    pub data_hash: [u8; 32],
+    /// L1 cluster time the root was recorded
+    pub submitted_at: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchSubmitterConfig {
+    /// Upper bound on the number of L2 blocks in one batch
+    pub max_blocks_per_batch: u64,
+    /// Delay between checks for new blocks
+    pub poll_interval: Duration,
+    /// Submission attempts per batch before giving up until the next poll
+    pub max_attempts: u32,
+    /// Backoff after the first failed attempt, doubled on every retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Interval between confirmation checks
+    pub confirmation_poll_interval: Duration,
+    /// How long to wait for a submitted transaction before resubmitting
+    pub confirmation_timeout: Duration,
+}
+
+impl Default for BatchSubmitterConfig {
+    fn default() -> Self {
+        Self {
+            max_blocks_per_batch: 64,
+            poll_interval: Duration::from_secs(10),
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            confirmation_poll_interval: Duration::from_secs(1),
+            confirmation_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Batch whose state root was confirmed on L1
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchRecord {
+    pub start_height: u64,
+    pub end_height: u64,
+    pub state_root: [u8; 32],
+    /// Signature of the `update_state_root` transaction, empty if the root was
+    /// found already recorded on L1 after a restart
+    pub signature: String,
+    /// Size of the encoded batch
+    pub data_len: usize,
+    /// Number of `post_batch_data` chunks, zero for an inline batch
+    pub chunks: usize,
+    /// L1 time the program recorded the root; its challenge window starts here
+    pub posted_at: i64,
+}
+
+/// A single L1 write performed by the submitter
+enum L1Write<'a> {
+    StateRoot(BatchCommitment, &'a [u8]),
+    BatchData(&'a [u8]),
+}
+
+/// Periodically posts compressed ranges of L2 blocks and their resulting state
+/// root to the `solana_oasis` program.
+pub struct BatchSubmitter<C: L1Client> {
+    client: C,
+    state: Arc<RwLock<StateManager>>,
+    config: BatchSubmitterConfig,
+}
+
+impl<C: L1Client> BatchSubmitter<C> {
+    pub fn new(client: C, state: Arc<RwLock<StateManager>>, config: BatchSubmitterConfig) -> Self {
+        Self {
+            client,
+            state,
+            config,
+        }
+    }
+
+    /// Submit batches until the task is dropped
+    pub async fn run(&mut self) {
+        loop {
+            match self.submit_next_batch().await {
+                // Keep draining while there is a backlog
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(e) => log::error!("Batch submission failed: {}", e),
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Highest L2 height whose state root has been confirmed on L1
+    pub async fn committed_height(&self) -> Result<u64> {
+        committed_height(&*self.state.read().await)
+    }
+
+    /// Post the next range of uncommitted blocks, if any, and wait for it to be
+    /// confirmed.
+    pub async fn submit_next_batch(&mut self) -> Result<Option<BatchRecord>> {
+        self.revert_orphaned_batches().await?;
+        let batch = {
+            let state = self.state.read().await;
+            let committed = committed_height(&state)?;
+            let head = state.get_current_root().height;
+            if head <= committed {
+                return Ok(None);
+            }
+
+            let end = head.min(committed + self.config.max_blocks_per_batch);
+            Batch::from_state(&state, committed + 1, end)?
+        };
+        let (start, end) = (batch.header.start_height, batch.header.end_height);
+        if !self.client.is_leader().await? {
+            log::debug!(
+                "Waiting for this validator's turn to post blocks {}..={}",
+                start,
+                end
+            );
+            return Ok(None);
+        }
+        let state_root = batch.header.state_root;
+        let encoded = batch.encode()?;
+
+        let (data, chunks) = if encoded.len() <= MAX_INLINE_BATCH_BYTES {
+            (BatchData::Inline(encoded.clone()), 0)
+        } else {
+            // Post chunks oldest first, each linking to its predecessor
+            let chunks = split_payload(&encoded, MAX_CHUNK_DATA_BYTES)?;
+            let count = chunks.len();
+            let chain_hash = chunks.last().expect("Batch has at least one chunk").link();
+            let mut previous = None;
+            for mut chunk in chunks {
+                chunk.previous = previous.take();
+                let signature = self
+                    .submit_with_retry(L1Write::BatchData(&chunk.encode()?))
+                    .await?;
+                previous = Some(signature);
+            }
+            let data = BatchData::Chunked {
+                chain_hash,
+                last_chunk: previous.expect("Batch has at least one chunk"),
+            };
+            (data, count)
+        };
+
+        let payload = data.encode()?;
+        let commitment = batch.commitment(&payload)?;
+        let signature = self
+            .submit_with_retry(L1Write::StateRoot(commitment, &payload))
+            .await?;
+        // The challenge window runs on the cluster clock from when the program
+        // recorded the root
+        let posted_at = match self.posted_root(&commitment).await? {
+            Some(root) => root.submitted_at,
+            None => {
+                log::warn!("Root of blocks {}..={} was superseded on L1", start, end);
+                chrono::Utc::now().timestamp()
+            }
+        };
+        let record = BatchRecord {
+            start_height: start,
+            end_height: end,
+            state_root,
+            signature,
+            data_len: encoded.len(),
+            chunks,
+            posted_at,
+        };
+
+        let mut state = self.state.write().await;
+        let record_key = [BATCH_RECORD_PREFIX, &end.to_be_bytes()[..]].concat();
+        state.set_metadata(&record_key, bincode::serialize(&record)?)?;
+        state.set_metadata(COMMITTED_HEIGHT_KEY, bincode::serialize(&end)?)?;
+
+        log::info!(
+            "Committed L2 blocks {}..={} to L1 in {}",
+            start,
+            end,
+            record.signature
+        );
+        Ok(Some(record))
+    }
+
+    /// Forget batch records whose blocks a reorg reverted, so the replacement
+    /// blocks are posted again instead of being skipped as committed
+    async fn revert_orphaned_batches(&self) -> Result<()> {
+        {
+            let state = self.state.read().await;
+            if is_canonical(&state, committed_height(&state)?)? {
+                return Ok(());
+            }
+        }
+
+        let mut state = self.state.write().await;
+        let old = committed_height(&state)?;
+        let mut committed = old;
+        while !is_canonical(&state, committed)? {
+            let record = batch_record(&state, committed)?.expect("Orphaned batch has a record");
+            let record_key = [BATCH_RECORD_PREFIX, &committed.to_be_bytes()[..]].concat();
+            state.delete_metadata(&record_key)?;
+            committed = record.start_height - 1;
+        }
+        state.set_metadata(COMMITTED_HEIGHT_KEY, bincode::serialize(&committed)?)?;
+
+        log::warn!(
+            "Reorg reverted committed L2 blocks {}..={}, resubmitting them",
+            committed + 1,
+            old
+        );
+        Ok(())
+    }
+
+    /// Submit until a transaction is confirmed, backing off exponentially between
+    /// failed attempts
+    async fn submit_with_retry(&self, write: L1Write<'_>) -> Result<String> {
+        let mut backoff = self.config.initial_backoff;
+        let mut last_error = None;
+        let mut last_signature = None;
+
+        for attempt in 1..=self.config.max_attempts {
+            // A root whose confirmation timed out, or that was sent before a
+            // restart, may have landed anyway; posting it again would fail
+            if let L1Write::StateRoot(commitment, _) = &write {
+                if self.posted_root(commitment).await?.is_some() {
+                    return Ok(last_signature.take().unwrap_or_default());
+                }
+            }
+
+            match self.submit_and_confirm(&write, &mut last_signature).await {
+                Ok(signature) => return Ok(signature),
+                Err(e) => {
+                    log::warn!(
+                        "L1 submission attempt {}/{} failed: {}",
+                        attempt,
+                        self.config.max_attempts,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+
+            if attempt < self.config.max_attempts {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.config.max_backoff);
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No submission attempts configured")))
+    }
+
+    /// L1's latest root, if it is the one `commitment` posts
+    async fn posted_root(&self, commitment: &BatchCommitment) -> Result<Option<PostedRoot>> {
+        Ok(self.client.latest_root().await?.filter(|root| {
+            root.state_root == commitment.new_root && root.data_hash == commitment.data_hash
+        }))
+    }
+
+    async fn submit_and_confirm(
+        &self,
+        write: &L1Write<'_>,
+        last_signature: &mut Option<String>,
+    ) -> Result<String> {
+        let signature = match write {
+            L1Write::StateRoot(commitment, data) => {
+                self.client
+                    .submit_state_root(*commitment, data.to_vec())
+                    .await?
+            }
+            L1Write::BatchData(data) => self.client.post_batch_data(data.to_vec()).await?,
+        };
+        *last_signature = Some(signature.clone());
+
+        let deadline = tokio::time::Instant::now() + self.config.confirmation_timeout;
+        loop {
+            match self.client.confirmation_status(&signature).await? {
+                ConfirmationStatus::Confirmed => return Ok(signature),
+                ConfirmationStatus::Failed(reason) => {
+                    return Err(anyhow::anyhow!(
+                        "Transaction {} failed: {}",
+                        signature,
+                        reason
+                    ))
+                }
+                ConfirmationStatus::Pending => {}
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                return Err(anyhow::anyhow!(
+                    "Transaction {} was not confirmed in time",
+                    signature
+                ));
+            }
+            tokio::time::sleep(self.config.confirmation_poll_interval).await;
+        }
+    }
+}
+
+/// Highest L2 height whose state root has been confirmed on L1
+pub fn committed_height(state: &StateManager) -> Result<u64> {
+    match state.get_metadata(COMMITTED_HEIGHT_KEY)? {
+        Some(data) => Ok(bincode::deserialize(&data)?),
+        None => Ok(0),
+    }
+}
+
+/// Submission record of the batch ending at `end_height`
+pub fn batch_record(state: &StateManager, end_height: u64) -> Result<Option<BatchRecord>> {
+    let key = [BATCH_RECORD_PREFIX, &end_height.to_be_bytes()[..]].concat();
+    match state.get_metadata(&key)? {
+        Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether the batch ending at `end_height`, if any, still matches the local chain
+fn is_canonical(state: &StateManager, end_height: u64) -> Result<bool> {
+    let Some(record) = batch_record(state, end_height)? else {
+        return Ok(true);
+    };
+    let root = state.get_root_at(end_height)?;
+    Ok(root.map(|root| root.root) == Some(record.state_root))
+}
+
+/// Submission record of the batch containing `height`, if it was posted
+pub fn covering_batch(state: &StateManager, height: u64) -> Result<Option<BatchRecord>> {
+    // Records are keyed by big-endian end height, so the first one at or after
+    // `height` is the only candidate
+    let from = [BATCH_RECORD_PREFIX, &height.to_be_bytes()[..]].concat();
+    match state.seek_metadata(&from)? {
+        Some((key, data)) if key.starts_with(BATCH_RECORD_PREFIX) => {
+            let record: BatchRecord = bincode::deserialize(&data)?;
+            Ok((record.start_height <= height).then_some(record))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{data_hash, fetch_batch, PostedDataSource};
+    use crate::rollup::Rollup;
+    use crate::types::{balance_key, Block, Transaction};
+    use sha2::{Digest, Sha256};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    /// Mock RPC that rejects the first `failures` sends and reports every landed
+    /// transaction as pending for `pending_polls` checks
+    #[derive(Default)]
+    struct MockL1 {
+        failures: AtomicUsize,
+        pending_polls: AtomicUsize,
+        /// Another validator leads the next batch
+        follower: AtomicBool,
+        submitted: Mutex<Vec<(BatchCommitment, Vec<u8>)>>,
+        chunks: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl L1Client for Arc<MockL1> {
+        async fn submit_state_root(
+            &self,
+            commitment: BatchCommitment,
+            data: Vec<u8>,
+        ) -> Result<String> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(anyhow::anyhow!("Blockhash not found"));
+            }
+            let mut submitted = self.submitted.lock().unwrap();
+            submitted.push((commitment, data));
+            Ok(format!("sig{}", submitted.len()))
+        }
+
+        async fn post_batch_data(&self, data: Vec<u8>) -> Result<String> {
+            let mut chunks = self.chunks.lock().unwrap();
+            chunks.push(data);
+            Ok(format!("chunk{}", chunks.len()))
+        }
+
+        async fn is_leader(&self) -> Result<bool> {
+            Ok(!self.follower.load(Ordering::SeqCst))
+        }
+
+        async fn confirmation_status(&self, _signature: &str) -> Result<ConfirmationStatus> {
+            if self.pending_polls.load(Ordering::SeqCst) > 0 {
+                self.pending_polls.fetch_sub(1, Ordering::SeqCst);
+                return Ok(ConfirmationStatus::Pending);
+            }
+            Ok(ConfirmationStatus::Confirmed)
+        }
+
+        async fn latest_root(&self) -> Result<Option<PostedRoot>> {
+            let submitted = self.submitted.lock().unwrap();
+            Ok(submitted.last().map(|(commitment, _)| PostedRoot {
+                batch_index: submitted.len() as u64 - 1,
+                state_root: commitment.new_root,
+                data_hash: commitment.data_hash,
+                submitted_at: 0,
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl PostedDataSource for MockL1 {
+        async fn posted_data(&self, signature: &str) -> Result<Vec<u8>> {
+            let posted =
+                |prefix: &str| -> Option<usize> { signature.strip_prefix(prefix)?.parse().ok() };
+            let data = if let Some(n) = posted("sig") {
+                self.submitted
+                    .lock()
+                    .unwrap()
+                    .get(n - 1)
+                    .map(|(_, data)| data.clone())
+            } else if let Some(n) = posted("chunk") {
+                self.chunks.lock().unwrap().get(n - 1).cloned()
+            } else {
+                None
+            };
+            data.ok_or_else(|| anyhow::anyhow!("Unknown signature {}", signature))
+        }
+    }
+
+    fn test_config() -> BatchSubmitterConfig {
+        BatchSubmitterConfig {
+            max_blocks_per_batch: 2,
+            poll_interval: Duration::from_millis(1),
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            confirmation_poll_interval: Duration::from_millis(1),
+            confirmation_timeout: Duration::from_secs(1),
+        }
+    }
+
+    async fn produce_blocks(state: Arc<RwLock<StateManager>>, count: u64) -> Result<()> {
+        let mut rollup = Rollup::new(state)?;
+        for number in 1..=count {
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![], number as i64))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submits_ranges_and_persists_progress() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        produce_blocks(state.clone(), 3).await?;
+
+        let mock = Arc::new(MockL1::default());
+        mock.pending_polls.store(2, Ordering::SeqCst);
+        let mut submitter = BatchSubmitter::new(mock.clone(), state.clone(), test_config());
+
+        // Nothing is posted until it is this validator's turn
+        mock.follower.store(true, Ordering::SeqCst);
+        assert!(submitter.submit_next_batch().await?.is_none());
+        assert!(mock.submitted.lock().unwrap().is_empty());
+        mock.follower.store(false, Ordering::SeqCst);
+
+        let first = submitter.submit_next_batch().await?.unwrap();
+        assert_eq!((first.start_height, first.end_height), (1, 2));
+        let second = submitter.submit_next_batch().await?.unwrap();
+        assert_eq!((second.start_height, second.end_height), (3, 3));
+        assert!(submitter.submit_next_batch().await?.is_none());
+
+        let state = state.read().await;
+        assert_eq!(committed_height(&state)?, 3);
+        assert_eq!(batch_record(&state, 2)?, Some(first));
+        let submitted = mock.submitted.lock().unwrap();
+        assert_eq!(submitted.len(), 2);
+        assert_eq!(submitted[1].0.new_root, state.get_root_at(3)?.unwrap().root);
+        assert_eq!(submitted[1].0.transaction_count, 0);
+        // Each commitment builds on the previous one and covers the posted data
+        assert_eq!(submitted[1].0.previous_root, submitted[0].0.new_root);
+        assert_eq!(submitted[1].0.data_hash, data_hash(&submitted[1].1));
+
+        // Small batches are posted inline and decode back to the blocks
+        let BatchData::Inline(data) = BatchData::decode(&submitted[1].1)? else {
+            panic!("Expected an inline batch");
+        };
+        let batch = Batch::decode(&data)?;
+        assert_eq!(
+            batch.to_blocks()[0].hash(),
+            state.get_block(3)?.unwrap().hash()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_with_backoff() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        produce_blocks(state.clone(), 2).await?;
+
+        let mock = Arc::new(MockL1::default());
+        mock.failures.store(2, Ordering::SeqCst);
+        let config = BatchSubmitterConfig {
+            max_blocks_per_batch: 1,
+            ..test_config()
+        };
+        let mut submitter = BatchSubmitter::new(mock.clone(), state.clone(), config.clone());
+        assert!(submitter.submit_next_batch().await?.is_some());
+        assert_eq!(submitter.committed_height().await?, 1);
+
+        // Exhausting every attempt leaves the range uncommitted
+        mock.failures.store(3, Ordering::SeqCst);
+        assert!(submitter.submit_next_batch().await.is_err());
+        assert_eq!(submitter.committed_height().await?, 1);
+
+        // A root that lands after its confirmation timed out is not posted twice
+        mock.pending_polls.store(usize::MAX, Ordering::SeqCst);
+        let mut submitter = BatchSubmitter::new(
+            mock.clone(),
+            state.clone(),
+            BatchSubmitterConfig {
+                confirmation_timeout: Duration::from_millis(1),
+                ..config
+            },
+        );
+        let record = submitter.submit_next_batch().await?.unwrap();
+        assert_eq!(record.signature, "sig2");
+        assert_eq!(mock.submitted.lock().unwrap().len(), 2);
+        assert_eq!(submitter.committed_height().await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resubmits_batches_reverted_by_reorg() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        produce_blocks(state.clone(), 2).await?;
+
+        let mock = Arc::new(MockL1::default());
+        let config = BatchSubmitterConfig {
+            max_blocks_per_batch: 1,
+            ..test_config()
+        };
+        let mut submitter = BatchSubmitter::new(mock.clone(), state.clone(), config);
+        while submitter.submit_next_batch().await?.is_some() {}
+        assert_eq!(submitter.committed_height().await?, 2);
+
+        // Block 2 is replaced by one with a different state
+        {
+            let mut state = state.write().await;
+            state.rollback_to(1).await?;
+            state.set_value(b"reorg", vec![1]).await?;
+            state
+                .commit_block(&Block::new(2, [0u8; 32], vec![], 3))
+                .await?;
+        }
+
+        let record = submitter.submit_next_batch().await?.unwrap();
+        assert_eq!((record.start_height, record.end_height), (2, 2));
+        let state = state.read().await;
+        assert_eq!(record.state_root, state.get_root_at(2)?.unwrap().root);
+        assert_eq!(batch_record(&state, 2)?, Some(record));
+        assert!(batch_record(&state, 1)?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_large_batches_are_posted_in_linked_chunks() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        let alice = [1u8; 32];
+        state
+            .write()
+            .await
+            .set_value(&balance_key(&alice), bincode::serialize(&1_000u64)?)
+            .await?;
+        // Distinct hashed recipients keep the batch from compressing away
+        let transactions = (0u64..40)
+            .map(|i| Transaction::Transfer {
+                from: alice,
+                to: Sha256::digest(i.to_le_bytes()).into(),
+                amount: 1,
+            })
+            .collect();
+        Rollup::new(state.clone())?
+            .process_block(Block::new(1, [0u8; 32], transactions, 1))
+            .await?;
+
+        let mock = Arc::new(MockL1::default());
+        let mut submitter = BatchSubmitter::new(mock.clone(), state.clone(), test_config());
+        let record = submitter.submit_next_batch().await?.unwrap();
+        assert!(record.chunks > 1);
+        assert_eq!(mock.chunks.lock().unwrap().len(), record.chunks);
+
+        // Validators rebuild the batch from the chunks the commitment links to
+        let batch = fetch_batch(&*mock, &record.signature).await?;
+        let state = state.read().await;
+        assert_eq!(batch, Batch::from_state(&state, 1, 1)?);
+        assert_eq!(record.data_len, batch.encode()?.len());
+
+        Ok(())
+    }
+}
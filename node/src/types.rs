@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
@@ -41,13 +42,149 @@ impl Block {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Transaction {
     Transfer {
         from: [u8; 32],
         to: [u8; 32],
         amount: u64,
     },
+    /// Credit for funds locked in the L1 bridge, injected by the deposit watcher
+    Deposit {
+        /// Position of the deposit in the L1 bridge, naming the receipt that
+        /// backs it; a deposit is credited at most once
+        nonce: u64,
+        recipient: [u8; 32],
+        /// L2 asset of the deposited mint, see [`token_id`]
+        token: [u8; 32],
+        amount: u64,
+    },
+    /// Transaction a user queued on L1 to bypass the sequencer.
+    ///
+    /// Including it always succeeds once: if `transaction` does not decode, is
+    /// invalid or is not authorized by `sender`, only the inclusion marker is
+    /// written.
+    Forced {
+        /// Position in the L1 forced-transaction queue
+        index: u64,
+        /// L1 account that queued the transaction
+        sender: [u8; 32],
+        /// Bincode-encoded transaction exactly as queued on L1
+        transaction: Vec<u8>,
+    },
+    /// Burn `amount` of the L2 asset of `token` from `from` so that
+    /// `recipient` can claim it from the L1 bridge with a proof of the
+    /// withdrawal's marker in a finalized root
+    Withdraw {
+        from: [u8; 32],
+        /// L1 account the bridge pays
+        recipient: [u8; 32],
+        /// L1 mint of the withdrawn token, [`NATIVE_MINT`] for SOL
+        token: [u8; 32],
+        amount: u64,
+        /// Chosen by the sender; the same withdrawal can only be made once
+        nonce: u64,
+    },
+}
+
+impl Transaction {
+    /// State keys the transaction reads or writes
+    pub fn access_list(&self) -> Vec<Vec<u8>> {
+        match self {
+            Transaction::Transfer { from, to, .. } => vec![balance_key(from), balance_key(to)],
+            Transaction::Deposit {
+                nonce,
+                recipient,
+                token,
+                ..
+            } => vec![
+                deposit_key(&deposit_id(*nonce)),
+                token_balance_key(token, recipient),
+            ],
+            Transaction::Forced {
+                index, transaction, ..
+            } => {
+                let mut keys = vec![forced_key(*index)];
+                if let Ok(inner) = bincode::deserialize::<Transaction>(transaction) {
+                    keys.extend(inner.access_list());
+                }
+                keys
+            }
+            Transaction::Withdraw {
+                from,
+                recipient,
+                token,
+                amount,
+                nonce,
+            } => vec![
+                token_balance_key(&token_id(token), from),
+                withdrawal_key(&withdrawal_hash(from, recipient, token, *amount, *nonce)),
+            ],
+        }
+    }
+}
+
+/// State key holding the L2 balance of `account`
+pub fn balance_key(account: &[u8; 32]) -> Vec<u8> {
+    [&b"balance:"[..], account.as_ref()].concat()
+}
+
+/// State key holding the L2 balance of `account` in bridged asset `token`
+pub fn token_balance_key(token: &[u8; 32], account: &[u8; 32]) -> Vec<u8> {
+    [&b"token_balance:"[..], token.as_ref(), account.as_ref()].concat()
+}
+
+/// Mint the bridge reports native SOL deposits and withdrawals under
+pub const NATIVE_MINT: Pubkey = solana_sdk::pubkey!("So11111111111111111111111111111111111111112");
+
+/// L2 asset identifier of the L1 mint `mint`
+pub fn token_id(mint: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"oasis:token");
+    hasher.update(mint);
+    hasher.finalize().into()
+}
+
+/// State key marking forced transaction `index` as included
+pub fn forced_key(index: u64) -> Vec<u8> {
+    [&b"forced:"[..], &index.to_be_bytes()[..]].concat()
+}
+
+/// State key marking an L1 deposit as credited
+pub fn deposit_key(deposit_id: &[u8; 32]) -> Vec<u8> {
+    [&b"deposit:"[..], deposit_id.as_ref()].concat()
+}
+
+/// Identifier the bridge reports its `nonce`-th deposit under
+pub fn deposit_id(nonce: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"oasis:deposit");
+    hasher.update(nonce.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// State key marking a withdrawal as made, holding its amount
+pub fn withdrawal_key(withdrawal_hash: &[u8; 32]) -> Vec<u8> {
+    [&b"withdrawal:"[..], withdrawal_hash.as_ref()].concat()
+}
+
+/// Identifier of a withdrawal, binding the L2 sender and everything the L1
+/// bridge pays out on
+pub fn withdrawal_hash(
+    from: &[u8; 32],
+    recipient: &[u8; 32],
+    token: &[u8; 32],
+    amount: u64,
+    nonce: u64,
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"oasis:withdrawal");
+    hasher.update(from);
+    hasher.update(recipient);
+    hasher.update(token);
+    hasher.update(amount.to_le_bytes());
+    hasher.update(nonce.to_le_bytes());
+    hasher.finalize().into()
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -0,0 +1,1222 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::{broadcast, RwLock};
+
+use crate::batch::{fetch_batch, transactions_root, Batch, PostedDataSource};
+use crate::dispute::{
+    build_trace, next_move, step_proof, DisputeAccount, DisputeClient, DisputeMove, DisputeOpened,
+    DisputeStatus, FraudProofSubmitted, Trace,
+};
+use crate::forced::{check_forced_inclusion, record_forced, ForcedTransaction};
+use crate::rollup::Rollup;
+use crate::state::StateManager;
+use crate::types::Transaction;
+use crate::watcher::{
+    event_discriminator, program_event_data, DepositReceiptSource, L1EventSource, L1WatcherConfig,
+};
+
+/// Metadata key holding the highest L1 slot scanned for posted roots
+const VALIDATOR_SLOT_KEY: &[u8] = b"validator:slot";
+/// Metadata key holding the index of the next batch to verify
+const VALIDATOR_BATCH_KEY: &[u8] = b"validator:next_batch";
+/// Prefix of the L1 signatures that posted each batch, keyed by batch index
+const BATCH_SIGNATURE_PREFIX: &[u8] = b"validator:signature:";
+/// Prefix of the disputes this node takes part in, keyed by batch index and
+/// challenger
+const DISPUTE_PREFIX: &[u8] = b"validator:dispute:";
+
+/// Capacity of the alert notification channel
+const ALERT_CHANNEL_CAPACITY: usize = 64;
+
+/// `StateRootUpdated` event emitted by `update_state_root`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootUpdate {
+    pub previous_root: [u8; 32],
+    pub new_root: [u8; 32],
+    /// Unix time of the update; the challenge window starts here
+    pub timestamp: i64,
+    pub validator: [u8; 32],
+    pub batch_index: u64,
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u64,
+}
+
+impl RootUpdate {
+    /// Decode a `StateRootUpdated` Anchor event payload
+    pub fn decode_event(data: &[u8]) -> Option<Self> {
+        let fields = data.strip_prefix(&event_discriminator("StateRootUpdated")[..])?;
+        if fields.len() != 152 {
+            return None;
+        }
+
+        Some(Self {
+            previous_root: fields[0..32].try_into().ok()?,
+            new_root: fields[32..64].try_into().ok()?,
+            timestamp: i64::from_le_bytes(fields[64..72].try_into().ok()?),
+            validator: fields[72..104].try_into().ok()?,
+            batch_index: u64::from_le_bytes(fields[104..112].try_into().ok()?),
+            transactions_root: fields[112..144].try_into().ok()?,
+            transaction_count: u64::from_le_bytes(fields[144..152].try_into().ok()?),
+        })
+    }
+}
+
+/// Condition an operator should be paged for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidatorAlert {
+    /// Re-execution disagrees with the root posted on L1
+    RootMismatch {
+        batch_index: u64,
+        claimed_root: [u8; 32],
+        /// `None` if a transaction of the batch fails to execute
+        computed_root: Option<[u8; 32]>,
+    },
+    /// The posted batch data does not match the transactions committed on L1;
+    /// the batch is disputed without being executed
+    CommitmentMismatch {
+        batch_index: u64,
+    },
+    /// The posted batch data cannot be fetched or decoded; the batch is
+    /// disputed without being executed
+    DataUnavailable {
+        batch_index: u64,
+        error: String,
+    },
+    DisputeOpened {
+        batch_index: u64,
+        signature: String,
+    },
+    /// Opening or playing a dispute failed; it is retried next poll
+    DisputeFailed {
+        batch_index: u64,
+        error: String,
+    },
+    DisputeResolved {
+        batch_index: u64,
+        won: bool,
+    },
+    /// A fraud proof slashed `validator` and rolled the chain back to before
+    /// batch `batch_index`
+    RootReverted {
+        batch_index: u64,
+        validator: [u8; 32],
+        restored_root: [u8; 32],
+    },
+    /// A bad root was found after its challenge period ended
+    ChallengeWindowMissed {
+        batch_index: u64,
+        deadline: i64,
+    },
+    /// A batch leaves out forced transactions that were due before it was
+    /// posted; it is disputed like a wrong root
+    ForcedInclusionViolated {
+        batch_index: u64,
+        error: String,
+    },
+    /// A batch builds on a root this validator rejected or never reached
+    UnknownParent {
+        batch_index: u64,
+        previous_root: [u8; 32],
+    },
+}
+
+/// Counters exported by the validator service
+#[derive(Debug, Default)]
+pub struct ValidatorMetrics {
+    pub batches_verified: AtomicU64,
+    pub blocks_executed: AtomicU64,
+    pub root_mismatches: AtomicU64,
+    pub disputes_opened: AtomicU64,
+    pub dispute_moves: AtomicU64,
+    pub disputes_won: AtomicU64,
+    pub disputes_lost: AtomicU64,
+    pub dispute_failures: AtomicU64,
+    pub missed_challenges: AtomicU64,
+    /// Index of the last batch whose root was verified, plus one
+    pub verified_batch_count: AtomicU64,
+}
+
+/// Re-executes every batch posted to the `solana_oasis` program against its
+/// own copy of the L2 state, built only from L1 data, and disputes roots that
+/// do not match.
+///
+/// It also plays every dispute it takes part in, including disputes opened
+/// against roots this node posted, until they are resolved.
+pub struct BatchValidator<C> {
+    client: C,
+    program_id: String,
+    state: Arc<RwLock<StateManager>>,
+    rollup: Rollup,
+    config: L1WatcherConfig,
+    metrics: Arc<ValidatorMetrics>,
+    alerts: broadcast::Sender<ValidatorAlert>,
+    /// Re-executed batches of the active disputes, `None` for batches whose
+    /// posted data is unusable
+    traces: HashMap<u64, (Option<Batch>, Trace)>,
+}
+
+impl<C> BatchValidator<C>
+where
+    C: L1EventSource + PostedDataSource + DisputeClient + DepositReceiptSource,
+{
+    /// `state` must be initialized from the same genesis as the chain and must
+    /// not be shared with the sequencer
+    pub fn new(
+        client: C,
+        program_id: String,
+        state: Arc<RwLock<StateManager>>,
+        config: L1WatcherConfig,
+    ) -> Result<Self> {
+        let (alerts, _) = broadcast::channel(ALERT_CHANNEL_CAPACITY);
+        Ok(Self {
+            client,
+            program_id,
+            rollup: Rollup::new(state.clone())?,
+            state,
+            config,
+            metrics: Arc::new(ValidatorMetrics::default()),
+            alerts,
+            traces: HashMap::new(),
+        })
+    }
+
+    pub fn metrics(&self) -> Arc<ValidatorMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Subscribe to alerts
+    pub fn subscribe_alerts(&self) -> broadcast::Receiver<ValidatorAlert> {
+        self.alerts.subscribe()
+    }
+
+    /// Verify posted batches until the task is dropped
+    pub async fn run(&mut self) {
+        loop {
+            if let Err(e) = self.poll().await {
+                log::error!("Batch validation failed: {}", e);
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Verify batches whose roots were posted in confirmed slots after the
+    /// cursor, then make the next move in every active dispute. Returns the
+    /// number of batches checked.
+    pub async fn poll(&mut self) -> Result<usize> {
+        let checked = self.scan().await?;
+        self.play_disputes().await?;
+        Ok(checked)
+    }
+
+    async fn scan(&mut self) -> Result<usize> {
+        let latest = self.client.latest_slot().await?;
+        let Some(confirmed) = latest.checked_sub(self.config.confirmations) else {
+            return Ok(0);
+        };
+
+        let cursor = read_u64(&*self.state.read().await, VALIDATOR_SLOT_KEY)?;
+        if confirmed <= cursor {
+            return Ok(0);
+        }
+        let to = confirmed.min(cursor + self.config.max_slots_per_poll);
+        let transactions = self
+            .client
+            .program_logs(&self.program_id, cursor + 1, to)
+            .await?;
+
+        let signer = self.client.signer();
+        let mut checked = 0;
+        for logs in &transactions {
+            for data in program_event_data(&self.program_id, &logs.logs) {
+                if let Some(opened) = DisputeOpened::decode_event(&data) {
+                    if opened.asserter == signer || opened.challenger == signer {
+                        self.track_dispute(opened.batch_index, &opened.challenger)
+                            .await?;
+                    }
+                    continue;
+                }
+                if let Some(reverted) = FraudProofSubmitted::decode_event(&data) {
+                    self.revert(&reverted).await?;
+                    continue;
+                }
+                // Queued transactions are tracked in this node's own state so
+                // batches can be held to their deadlines
+                if let Some(forced) = ForcedTransaction::decode_event(&data) {
+                    record_forced(&mut *self.state.write().await, &forced)?;
+                    continue;
+                }
+                let Some(update) = RootUpdate::decode_event(&data) else {
+                    continue;
+                };
+                // Batches verified before an interrupted poll are not redone
+                let next = read_u64(&*self.state.read().await, VALIDATOR_BATCH_KEY)?;
+                if update.batch_index < next {
+                    continue;
+                }
+
+                self.verify_batch(&update, &logs.signature, logs.slot)
+                    .await?;
+                self.state.write().await.set_metadata(
+                    VALIDATOR_BATCH_KEY,
+                    bincode::serialize(&(update.batch_index + 1))?,
+                )?;
+                checked += 1;
+            }
+        }
+
+        self.state
+            .write()
+            .await
+            .set_metadata(VALIDATOR_SLOT_KEY, bincode::serialize(&to)?)?;
+        Ok(checked)
+    }
+
+    /// Re-execute the batch posted by `signature` in L1 slot `slot` and dispute
+    /// its root if it does not match, the batch censors forced transactions or
+    /// its data does not reproduce the committed transactions
+    async fn verify_batch(
+        &mut self,
+        update: &RootUpdate,
+        signature: &str,
+        slot: u64,
+    ) -> Result<()> {
+        self.state.write().await.set_metadata(
+            &indexed_key(BATCH_SIGNATURE_PREFIX, update.batch_index),
+            signature.as_bytes().to_vec(),
+        )?;
+        let batch = match fetch_batch(&self.client, signature).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                log::error!(
+                    "Data posted for batch {} is unavailable: {}",
+                    update.batch_index,
+                    e
+                );
+                self.alert(ValidatorAlert::DataUnavailable {
+                    batch_index: update.batch_index,
+                    error: e.to_string(),
+                });
+                self.metrics.root_mismatches.fetch_add(1, Ordering::Relaxed);
+                return self.challenge(update).await;
+            }
+        };
+
+        let head = self.state.read().await.get_current_root().clone();
+        if batch.header.start_height != head.height + 1 || batch.header.previous_root != head.root {
+            log::warn!(
+                "Batch {} builds on root {} which is not the validated head",
+                update.batch_index,
+                hex::encode(batch.header.previous_root)
+            );
+            self.alert(ValidatorAlert::UnknownParent {
+                batch_index: update.batch_index,
+                previous_root: batch.header.previous_root,
+            });
+            return Ok(());
+        }
+
+        let transactions: Vec<_> = batch.transactions().cloned().collect();
+        if transactions_root(&transactions)? != update.transactions_root
+            || transactions.len() as u64 != update.transaction_count
+        {
+            log::error!(
+                "Data posted for batch {} does not match its committed transactions",
+                update.batch_index
+            );
+            self.alert(ValidatorAlert::CommitmentMismatch {
+                batch_index: update.batch_index,
+            });
+            self.metrics.root_mismatches.fetch_add(1, Ordering::Relaxed);
+            return self.challenge(update).await;
+        }
+
+        let mut blocks = batch.to_blocks();
+        let mut computed_root = Some(head.root);
+        if let Some(nonce) = unbacked_deposit(&self.client, &transactions).await? {
+            log::warn!(
+                "Batch {} credits deposit {} without a matching bridge receipt",
+                update.batch_index,
+                nonce
+            );
+            blocks.clear();
+            computed_root = None;
+        }
+        for block in blocks {
+            let number = block.number;
+            match self.rollup.process_block(block).await {
+                Ok(diff) => {
+                    self.metrics.blocks_executed.fetch_add(1, Ordering::Relaxed);
+                    computed_root = Some(diff.root.root);
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Block {} of batch {} is invalid: {}",
+                        number,
+                        update.batch_index,
+                        e
+                    );
+                    computed_root = None;
+                    break;
+                }
+            }
+        }
+
+        if computed_root != Some(update.new_root) {
+            log::error!(
+                "Root {} posted for batch {} does not match re-execution",
+                hex::encode(update.new_root),
+                update.batch_index
+            );
+            self.alert(ValidatorAlert::RootMismatch {
+                batch_index: update.batch_index,
+                claimed_root: update.new_root,
+                computed_root,
+            });
+        } else if let Err(e) = check_forced_inclusion(&*self.state.read().await, slot).await {
+            // A correct root over a censoring batch is disputed all the same
+            log::error!("Batch {} is invalid: {}", update.batch_index, e);
+            self.alert(ValidatorAlert::ForcedInclusionViolated {
+                batch_index: update.batch_index,
+                error: e.to_string(),
+            });
+        } else {
+            if batch.header.state_root != update.new_root {
+                log::warn!(
+                    "Batch {} header root differs from the root posted with it",
+                    update.batch_index
+                );
+            }
+            self.metrics
+                .batches_verified
+                .fetch_add(1, Ordering::Relaxed);
+            self.metrics
+                .verified_batch_count
+                .store(update.batch_index + 1, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.metrics.root_mismatches.fetch_add(1, Ordering::Relaxed);
+
+        // Later batches must build on the last valid root, not on this one
+        self.state
+            .write()
+            .await
+            .rollback_to(batch.header.start_height - 1)
+            .await?;
+
+        self.challenge(update).await
+    }
+
+    /// Open a dispute over `update` if its challenge window is still open
+    async fn challenge(&mut self, update: &RootUpdate) -> Result<()> {
+        let deadline = update
+            .timestamp
+            .saturating_add(self.client.challenge_period().await?);
+        if chrono::Utc::now().timestamp() > deadline {
+            self.metrics
+                .missed_challenges
+                .fetch_add(1, Ordering::Relaxed);
+            self.alert(ValidatorAlert::ChallengeWindowMissed {
+                batch_index: update.batch_index,
+                deadline,
+            });
+            return Ok(());
+        }
+
+        match self.client.open_dispute(update.batch_index).await {
+            Ok(signature) => {
+                log::info!(
+                    "Opened dispute over batch {} in {}",
+                    update.batch_index,
+                    signature
+                );
+                self.metrics.disputes_opened.fetch_add(1, Ordering::Relaxed);
+                self.track_dispute(update.batch_index, &self.client.signer())
+                    .await?;
+                self.alert(ValidatorAlert::DisputeOpened {
+                    batch_index: update.batch_index,
+                    signature,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                // The batch is verified again, and the dispute reopened, next poll
+                self.metrics
+                    .dispute_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                self.alert(ValidatorAlert::DisputeFailed {
+                    batch_index: update.batch_index,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Follow a fraud proof: batches from the reverted one on are posted
+    /// again under the same indices, and disputes over them are void and
+    /// settled
+    async fn revert(&mut self, reverted: &FraudProofSubmitted) -> Result<()> {
+        log::warn!(
+            "Fraud proof slashed {} and reverted batch {}",
+            hex::encode(reverted.validator),
+            reverted.batch_index
+        );
+        self.alert(ValidatorAlert::RootReverted {
+            batch_index: reverted.batch_index,
+            validator: reverted.validator,
+            restored_root: reverted.restored_root,
+        });
+
+        // Only reachable if this node accepted a batch L1 proved invalid
+        if self.state.read().await.get_current_root().root != reverted.restored_root {
+            let batch = self.posted_batch(reverted.batch_index).await?;
+            log::error!(
+                "Rolling back accepted batch {} to height {}",
+                reverted.batch_index,
+                batch.header.start_height - 1
+            );
+            self.rollup
+                .rollback_to(batch.header.start_height - 1)
+                .await?;
+        }
+
+        let mut state = self.state.write().await;
+        if read_u64(&state, VALIDATOR_BATCH_KEY)? > reverted.batch_index {
+            state.set_metadata(
+                VALIDATOR_BATCH_KEY,
+                bincode::serialize(&reverted.batch_index)?,
+            )?;
+        }
+        let mut voided = Vec::new();
+        while let Some((key, _)) =
+            state.seek_metadata(&indexed_key(DISPUTE_PREFIX, reverted.batch_index))?
+        {
+            let Some((batch_index, challenger)) = parse_dispute_key(&key) else {
+                break;
+            };
+            // The fraud proof closed the dispute it was submitted for
+            if (batch_index, challenger) != (reverted.batch_index, reverted.challenger) {
+                voided.push((batch_index, challenger));
+            }
+            self.traces.remove(&batch_index);
+            state.delete_metadata(&key)?;
+        }
+        drop(state);
+
+        // Settling only returns rent and releases stake, so a failure is not
+        // worth retrying
+        for (batch_index, challenger) in voided {
+            if let Err(e) = self
+                .client
+                .settle_stale_dispute(batch_index, &challenger)
+                .await
+            {
+                log::warn!("Could not settle dispute over batch {}: {}", batch_index, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn track_dispute(&self, batch_index: u64, challenger: &[u8; 32]) -> Result<()> {
+        self.state
+            .write()
+            .await
+            .set_metadata(&dispute_key(batch_index, challenger), Vec::new())
+    }
+
+    /// Make the next move in every tracked dispute. A failed move is alerted
+    /// and retried next poll without holding up the other disputes.
+    async fn play_disputes(&mut self) -> Result<()> {
+        let mut from = DISPUTE_PREFIX.to_vec();
+        loop {
+            let entry = self.state.read().await.seek_metadata(&from)?;
+            let Some((batch_index, challenger)) =
+                entry.and_then(|(key, _)| parse_dispute_key(&key))
+            else {
+                return Ok(());
+            };
+
+            if let Err(e) = self.play_dispute(batch_index, &challenger).await {
+                log::error!("Dispute over batch {} failed: {}", batch_index, e);
+                self.metrics
+                    .dispute_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                self.alert(ValidatorAlert::DisputeFailed {
+                    batch_index,
+                    error: e.to_string(),
+                });
+            }
+
+            // The smallest key after this one
+            from = [&dispute_key(batch_index, &challenger)[..], &[0]].concat();
+        }
+    }
+
+    async fn play_dispute(&mut self, batch_index: u64, challenger: &[u8; 32]) -> Result<()> {
+        let Some(dispute) = self.client.dispute(batch_index, challenger).await? else {
+            // Not visible at our commitment level yet
+            return Ok(());
+        };
+        let signer = self.client.signer();
+
+        if dispute.status != DisputeStatus::Active {
+            let won =
+                (dispute.status == DisputeStatus::ChallengerWon) == (dispute.challenger == signer);
+            // Winning as challenger only pays off once the fraud proof lands;
+            // a failed submission is retried next poll
+            if won && dispute.challenger == signer {
+                let signature = self.client.submit_fraud_proof(batch_index).await?;
+                log::info!(
+                    "Submitted fraud proof for batch {} in {}",
+                    batch_index,
+                    signature
+                );
+            }
+            let counter = if won {
+                &self.metrics.disputes_won
+            } else {
+                &self.metrics.disputes_lost
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            self.alert(ValidatorAlert::DisputeResolved { batch_index, won });
+
+            self.traces.remove(&batch_index);
+            self.state
+                .write()
+                .await
+                .delete_metadata(&dispute_key(batch_index, challenger))?;
+            return Ok(());
+        }
+
+        if !self.traces.contains_key(&batch_index) {
+            let posted = match self.posted_batch(batch_index).await {
+                Ok(batch) => commits_to(&batch, &dispute)?.then_some(batch),
+                Err(_) if dispute.challenger == signer => None,
+                Err(e) => return Err(e),
+            };
+            let entry = match posted {
+                Some(batch) => {
+                    let trace =
+                        build_trace(&*self.state.read().await, &batch, &tempfile::tempdir()?)
+                            .await?;
+                    (Some(batch), trace)
+                }
+                // Without the committed transactions the challenger can only
+                // reject the asserter's claims and wait for it to prove them
+                None if dispute.challenger == signer => (None, Trace::default()),
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "Data posted for batch {} does not match its commitment",
+                        batch_index
+                    ))
+                }
+            };
+            self.traces.insert(batch_index, entry);
+        }
+        let (batch, trace) = &self.traces[&batch_index];
+
+        let now = chrono::Utc::now().timestamp();
+        let Some(next) = next_move(&dispute, &signer, trace, now) else {
+            return Ok(());
+        };
+        let signature = match &next {
+            DisputeMove::Bisect(midpoint_root) => {
+                self.client
+                    .bisect_dispute(batch_index, challenger, *midpoint_root)
+                    .await?
+            }
+            DisputeMove::Respond { agree } => {
+                self.client.respond_dispute(batch_index, *agree).await?
+            }
+            DisputeMove::ProveStep => {
+                let batch = batch
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Batch {} is unavailable", batch_index))?;
+                let proof = step_proof(
+                    &*self.state.read().await,
+                    batch,
+                    dispute.agreed_step,
+                    &tempfile::tempdir()?,
+                )
+                .await?;
+                self.client
+                    .prove_step(batch_index, challenger, &proof)
+                    .await?
+            }
+            DisputeMove::Timeout => self.client.timeout_dispute(batch_index, challenger).await?,
+        };
+
+        log::info!(
+            "Played {:?} in the dispute over batch {} in {}",
+            next,
+            batch_index,
+            signature
+        );
+        self.metrics.dispute_moves.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Batch `batch_index` as posted on L1
+    async fn posted_batch(&self, batch_index: u64) -> Result<Batch> {
+        let signature = self
+            .state
+            .read()
+            .await
+            .get_metadata(&indexed_key(BATCH_SIGNATURE_PREFIX, batch_index))?
+            .ok_or_else(|| anyhow::anyhow!("Batch {} was never seen on L1", batch_index))?;
+        fetch_batch(&self.client, &String::from_utf8(signature)?).await
+    }
+
+    fn alert(&self, alert: ValidatorAlert) {
+        log::warn!("Validator alert: {:?}", alert);
+        // Having no subscribers is not an error
+        let _ = self.alerts.send(alert);
+    }
+}
+
+/// Whether `batch` holds the transactions `dispute` bisects over
+fn commits_to(batch: &Batch, dispute: &DisputeAccount) -> Result<bool> {
+    let transactions: Vec<_> = batch.transactions().cloned().collect();
+    Ok(
+        transactions_root(&transactions)? == dispute.transactions_root
+            && transactions.len() as u64 == dispute.transaction_count,
+    )
+}
+
+fn indexed_key(prefix: &[u8], index: u64) -> Vec<u8> {
+    [prefix, &index.to_be_bytes()[..]].concat()
+}
+
+fn dispute_key(batch_index: u64, challenger: &[u8; 32]) -> Vec<u8> {
+    [&indexed_key(DISPUTE_PREFIX, batch_index)[..], challenger].concat()
+}
+
+/// Batch index and challenger of a [`dispute_key`]
+fn parse_dispute_key(key: &[u8]) -> Option<(u64, [u8; 32])> {
+    let rest = key.strip_prefix(DISPUTE_PREFIX)?;
+    Some((
+        u64::from_be_bytes(rest.get(..8)?.try_into().ok()?),
+        rest.get(8..)?.try_into().ok()?,
+    ))
+}
+
+/// Nonce of the first deposit in `transactions` that does not credit exactly
+/// what its bridge receipt records, if any
+pub(crate) async fn unbacked_deposit(
+    source: &impl DepositReceiptSource,
+    transactions: &[Transaction],
+) -> Result<Option<u64>> {
+    for transaction in transactions {
+        let Transaction::Deposit { nonce, .. } = transaction else {
+            continue;
+        };
+        let receipt = source.deposit_receipt(*nonce).await?;
+        if !receipt.is_some_and(|receipt| receipt.backs(transaction)) {
+            return Ok(Some(*nonce));
+        }
+    }
+    Ok(None)
+}
+
+/// Index of the first posted batch `state` has not verified yet, i.e. the one
+/// building on its head
+pub(crate) fn next_batch_index(state: &StateManager) -> Result<u64> {
+    read_u64(state, VALIDATOR_BATCH_KEY)
+}
+
+fn read_u64(state: &StateManager, key: &[u8]) -> Result<u64> {
+    match state.get_metadata(key)? {
+        Some(data) => Ok(bincode::deserialize(&data)?),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::BatchData;
+    use crate::dispute::StepProof;
+    use crate::genesis::Genesis;
+    use crate::types::{token_id, Block};
+    use crate::watcher::{DepositReceipt, ProgramLogs, PROGRAM_DATA_PREFIX};
+    use async_trait::async_trait;
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    const OASIS: &str = "oasis11111111111111111111111111111111111111";
+
+    #[derive(Default)]
+    struct MockL1 {
+        logs: Vec<ProgramLogs>,
+        posted: HashMap<String, Vec<u8>>,
+        receipts: HashMap<u64, DepositReceipt>,
+        disputes: Mutex<Vec<u64>>,
+        settled: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl L1EventSource for MockL1 {
+        async fn latest_slot(&self) -> Result<u64> {
+            // Every emitted transaction is confirmed, later ones are not yet
+            Ok(10 * self.logs.len() as u64 + 15)
+        }
+
+        async fn program_logs(
+            &self,
+            _program_id: &str,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<ProgramLogs>> {
+            Ok(self
+                .logs
+                .iter()
+                .filter(|logs| logs.slot >= from && logs.slot <= to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[async_trait]
+    impl PostedDataSource for MockL1 {
+        async fn posted_data(&self, signature: &str) -> Result<Vec<u8>> {
+            self.posted
+                .get(signature)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Unknown signature {}", signature))
+        }
+    }
+
+    #[async_trait]
+    impl DepositReceiptSource for MockL1 {
+        async fn deposit_receipt(&self, nonce: u64) -> Result<Option<DepositReceipt>> {
+            Ok(self.receipts.get(&nonce).cloned())
+        }
+    }
+
+    #[async_trait]
+    impl DisputeClient for MockL1 {
+        fn signer(&self) -> [u8; 32] {
+            [6u8; 32]
+        }
+
+        async fn challenge_period(&self) -> Result<i64> {
+            Ok(3_600)
+        }
+
+        async fn dispute(
+            &self,
+            _batch_index: u64,
+            _challenger: &[u8; 32],
+        ) -> Result<Option<DisputeAccount>> {
+            Ok(None)
+        }
+
+        async fn open_dispute(&self, batch_index: u64) -> Result<String> {
+            self.disputes.lock().unwrap().push(batch_index);
+            Ok("dispute".to_string())
+        }
+
+        async fn bisect_dispute(
+            &self,
+            _batch_index: u64,
+            _challenger: &[u8; 32],
+            _root: [u8; 32],
+        ) -> Result<String> {
+            Err(anyhow::anyhow!("Unexpected move"))
+        }
+
+        async fn respond_dispute(&self, _batch_index: u64, _agree: bool) -> Result<String> {
+            Err(anyhow::anyhow!("Unexpected move"))
+        }
+
+        async fn prove_step(
+            &self,
+            _batch_index: u64,
+            _challenger: &[u8; 32],
+            _proof: &StepProof,
+        ) -> Result<String> {
+            Err(anyhow::anyhow!("Unexpected move"))
+        }
+
+        async fn timeout_dispute(
+            &self,
+            _batch_index: u64,
+            _challenger: &[u8; 32],
+        ) -> Result<String> {
+            Err(anyhow::anyhow!("Unexpected move"))
+        }
+
+        async fn submit_fraud_proof(&self, _batch_index: u64) -> Result<String> {
+            Err(anyhow::anyhow!("Unexpected move"))
+        }
+
+        async fn settle_stale_dispute(
+            &self,
+            batch_index: u64,
+            _challenger: &[u8; 32],
+        ) -> Result<String> {
+            self.settled.lock().unwrap().push(batch_index);
+            Ok("settle".to_string())
+        }
+    }
+
+    impl MockL1 {
+        /// Post `batch` as batch `index` claiming `root`, returning the
+        /// signature its data is fetched by
+        fn post(&mut self, index: u64, batch: &Batch, root: [u8; 32]) -> Result<String> {
+            let signature = format!("root{}-{}", index, self.logs.len());
+            let posted = BatchData::Inline(batch.encode()?).encode()?;
+            let commitment = batch.commitment(&posted)?;
+            self.posted.insert(signature.clone(), posted);
+
+            let mut data = event_discriminator("StateRootUpdated").to_vec();
+            data.extend_from_slice(&batch.header.previous_root);
+            data.extend_from_slice(&root);
+            data.extend_from_slice(&chrono::Utc::now().timestamp().to_le_bytes());
+            data.extend_from_slice(&[5u8; 32]);
+            data.extend_from_slice(&index.to_le_bytes());
+            data.extend_from_slice(&commitment.transactions_root);
+            data.extend_from_slice(&commitment.transaction_count.to_le_bytes());
+            self.emit(signature.clone(), data);
+            Ok(signature)
+        }
+
+        /// Queue `transaction` for forced inclusion by `deadline_slot`
+        fn queue(
+            &mut self,
+            index: u64,
+            transaction: &Transaction,
+            deadline_slot: u64,
+        ) -> Result<()> {
+            let transaction = bincode::serialize(transaction)?;
+            let slot = 10 * (self.logs.len() as u64 + 1);
+            let mut data = event_discriminator("ForcedTransactionQueued").to_vec();
+            data.extend_from_slice(&index.to_le_bytes());
+            data.extend_from_slice(&[1u8; 32]);
+            data.extend_from_slice(&(transaction.len() as u32).to_le_bytes());
+            data.extend_from_slice(&transaction);
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(&deadline_slot.to_le_bytes());
+            self.emit(format!("forced{}", index), data);
+            Ok(())
+        }
+
+        /// Accept another challenger's fraud proof against batch `index`,
+        /// restoring `root`
+        fn revert(&mut self, index: u64, root: [u8; 32]) {
+            let mut data = event_discriminator("FraudProofSubmitted").to_vec();
+            data.extend_from_slice(&[8u8; 32]);
+            data.extend_from_slice(&[5u8; 32]);
+            data.extend_from_slice(&index.to_le_bytes());
+            data.extend_from_slice(&root);
+            data.extend_from_slice(&[0u8; 24]);
+            self.emit(format!("fraud{}", index), data);
+        }
+
+        fn emit(&mut self, signature: String, data: Vec<u8>) {
+            self.logs.push(ProgramLogs {
+                slot: 10 * (self.logs.len() as u64 + 1),
+                signature,
+                logs: vec![
+                    format!("Program {} invoke [1]", OASIS),
+                    format!(
+                        "{}{}",
+                        PROGRAM_DATA_PREFIX,
+                        base64::engine::general_purpose::STANDARD.encode(data)
+                    ),
+                    format!("Program {} success", OASIS),
+                ],
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disputes_invalid_root() -> Result<()> {
+        let alice = [1u8; 32];
+        let mut genesis = Genesis::default();
+        genesis.balances.insert(hex::encode(alice), 1_000);
+
+        // The sequencer's chain
+        let sequencer_dir = tempdir()?;
+        let mut manager = StateManager::new(&sequencer_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let sequencer = Arc::new(RwLock::new(manager));
+        let mut rollup = Rollup::new(sequencer.clone())?;
+        for number in 1..=2 {
+            let tx = Transaction::Transfer {
+                from: alice,
+                to: [2u8; 32],
+                amount: 10,
+            };
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![tx], 0))
+                .await?;
+        }
+
+        let mut l1 = MockL1::default();
+        let first = Batch::from_state(&*sequencer.read().await, 1, 1)?;
+        let second = Batch::from_state(&*sequencer.read().await, 2, 2)?;
+        l1.post(0, &first, first.header.state_root)?;
+        l1.post(1, &second, [9u8; 32])?;
+
+        let validator_dir = tempdir()?;
+        let mut manager = StateManager::new(&validator_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let state = Arc::new(RwLock::new(manager));
+        let mut validator = BatchValidator::new(
+            l1,
+            OASIS.to_string(),
+            state.clone(),
+            L1WatcherConfig {
+                confirmations: 10,
+                ..L1WatcherConfig::default()
+            },
+        )?;
+        let mut alerts = validator.subscribe_alerts();
+
+        assert_eq!(validator.poll().await?, 2);
+        assert_eq!(validator.poll().await?, 0);
+
+        let metrics = validator.metrics();
+        assert_eq!(metrics.batches_verified.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.root_mismatches.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.disputes_opened.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            alerts.try_recv()?,
+            ValidatorAlert::RootMismatch {
+                batch_index: 1,
+                claimed_root: [9u8; 32],
+                computed_root: Some(second.header.state_root),
+            }
+        );
+        assert_eq!(*validator.client.disputes.lock().unwrap(), vec![1]);
+
+        // The rejected batch is not applied
+        assert_eq!(state.read().await.get_current_root().height, 1);
+
+        // Once another challenger's fraud proof lands, ours is settled and the
+        // honest batch reuses index 1
+        validator.client.revert(1, first.header.state_root);
+        validator
+            .client
+            .post(1, &second, second.header.state_root)?;
+        assert_eq!(validator.poll().await?, 1);
+        assert_eq!(*validator.client.settled.lock().unwrap(), vec![1]);
+        assert_eq!(metrics.batches_verified.load(Ordering::Relaxed), 2);
+        assert_eq!(state.read().await.get_current_root().height, 2);
+        assert!(state
+            .read()
+            .await
+            .get_metadata(&dispute_key(1, &[6u8; 32]))?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disputes_batch_censoring_forced_transaction() -> Result<()> {
+        let alice = [1u8; 32];
+        let mut genesis = Genesis::default();
+        genesis.balances.insert(hex::encode(alice), 1_000);
+
+        let sequencer_dir = tempdir()?;
+        let mut manager = StateManager::new(&sequencer_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let sequencer = Arc::new(RwLock::new(manager));
+        let transfer = Transaction::Transfer {
+            from: alice,
+            to: [2u8; 32],
+            amount: 10,
+        };
+        Rollup::new(sequencer.clone())?
+            .process_block(Block::new(1, [0u8; 32], vec![transfer.clone()], 0))
+            .await?;
+
+        // Queued at slot 10 and due by slot 15, but the batch posted at slot
+        // 20 leaves it out while claiming the root of what it did execute
+        let mut l1 = MockL1::default();
+        l1.queue(0, &transfer, 15)?;
+        let batch = Batch::from_state(&*sequencer.read().await, 1, 1)?;
+        l1.post(0, &batch, batch.header.state_root)?;
+
+        let validator_dir = tempdir()?;
+        let mut manager = StateManager::new(&validator_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let state = Arc::new(RwLock::new(manager));
+        let mut validator = BatchValidator::new(
+            l1,
+            OASIS.to_string(),
+            state.clone(),
+            L1WatcherConfig {
+                confirmations: 10,
+                ..L1WatcherConfig::default()
+            },
+        )?;
+        let mut alerts = validator.subscribe_alerts();
+
+        assert_eq!(validator.poll().await?, 1);
+        assert!(matches!(
+            alerts.try_recv()?,
+            ValidatorAlert::ForcedInclusionViolated { batch_index: 0, .. }
+        ));
+        let metrics = validator.metrics();
+        assert_eq!(metrics.batches_verified.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.root_mismatches.load(Ordering::Relaxed), 1);
+        assert_eq!(*validator.client.disputes.lock().unwrap(), vec![0]);
+        assert_eq!(state.read().await.get_current_root().height, 0);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_disputes_batch_with_unusable_data() -> Result<()> {
+        let alice = [1u8; 32];
+        let mut genesis = Genesis::default();
+        genesis.balances.insert(hex::encode(alice), 1_000);
+
+        let sequencer_dir = tempdir()?;
+        let mut manager = StateManager::new(&sequencer_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let sequencer = Arc::new(RwLock::new(manager));
+        let mut rollup = Rollup::new(sequencer.clone())?;
+        for number in 1..=2 {
+            let tx = Transaction::Transfer {
+                from: alice,
+                to: [2u8; 32],
+                amount: 10 * number,
+            };
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![tx], 0))
+                .await?;
+        }
+
+        // Batch 0 posts data that does not decode, batch 1 posts the data of
+        // another batch than the one it commits to
+        let mut l1 = MockL1::default();
+        let first = Batch::from_state(&*sequencer.read().await, 1, 1)?;
+        let second = Batch::from_state(&*sequencer.read().await, 2, 2)?;
+        let signature = l1.post(0, &first, first.header.state_root)?;
+        l1.posted.insert(signature, vec![0xff; 8]);
+        let signature = l1.post(1, &second, second.header.state_root)?;
+        let other = BatchData::Inline(first.encode()?).encode()?;
+        l1.posted.insert(signature, other);
+
+        let validator_dir = tempdir()?;
+        let mut manager = StateManager::new(&validator_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let state = Arc::new(RwLock::new(manager));
+        let mut validator = BatchValidator::new(
+            l1,
+            OASIS.to_string(),
+            state.clone(),
+            L1WatcherConfig {
+                confirmations: 10,
+                ..L1WatcherConfig::default()
+            },
+        )?;
+        let mut alerts = validator.subscribe_alerts();
+
+        // Neither batch holds up the ones after it
+        assert_eq!(validator.poll().await?, 2);
+        assert_eq!(validator.poll().await?, 0);
+        assert_eq!(read_u64(&*state.read().await, VALIDATOR_BATCH_KEY)?, 2);
+
+        assert!(matches!(
+            alerts.try_recv()?,
+            ValidatorAlert::DataUnavailable { batch_index: 0, .. }
+        ));
+        assert!(matches!(
+            alerts.try_recv()?,
+            ValidatorAlert::DisputeOpened { batch_index: 0, .. }
+        ));
+        assert_eq!(
+            alerts.try_recv()?,
+            ValidatorAlert::CommitmentMismatch { batch_index: 1 }
+        );
+        let metrics = validator.metrics();
+        assert_eq!(metrics.batches_verified.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.root_mismatches.load(Ordering::Relaxed), 2);
+        assert_eq!(*validator.client.disputes.lock().unwrap(), vec![0, 1]);
+        assert_eq!(state.read().await.get_current_root().height, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disputes_deposit_beyond_its_receipt() -> Result<()> {
+        let mint = [7u8; 32];
+        let deposit = |nonce: u64, amount: u64| Transaction::Deposit {
+            nonce,
+            recipient: [1u8; 32],
+            token: token_id(&mint),
+            amount,
+        };
+        let genesis = Genesis::default();
+
+        // The sequencer credits the first deposit as locked and the second
+        // ten times over
+        let sequencer_dir = tempdir()?;
+        let mut manager = StateManager::new(&sequencer_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let sequencer = Arc::new(RwLock::new(manager));
+        let mut rollup = Rollup::new(sequencer.clone())?;
+        for (number, transaction) in [(1, deposit(0, 100)), (2, deposit(1, 1_000))] {
+            rollup
+                .process_block(Block::new(number, [0u8; 32], vec![transaction], 0))
+                .await?;
+        }
+
+        let mut l1 = MockL1::default();
+        for nonce in 0..2 {
+            l1.receipts.insert(
+                nonce,
+                DepositReceipt {
+                    depositor: [5u8; 32],
+                    mint,
+                    amount: 100,
+                    l2_recipient: [1u8; 32],
+                    nonce,
+                },
+            );
+        }
+        let first = Batch::from_state(&*sequencer.read().await, 1, 1)?;
+        let second = Batch::from_state(&*sequencer.read().await, 2, 2)?;
+        l1.post(0, &first, first.header.state_root)?;
+        l1.post(1, &second, second.header.state_root)?;
+
+        let validator_dir = tempdir()?;
+        let mut manager = StateManager::new(&validator_dir)?;
+        genesis.initialize(&mut manager).await?;
+        let state = Arc::new(RwLock::new(manager));
+        let mut validator = BatchValidator::new(
+            l1,
+            OASIS.to_string(),
+            state.clone(),
+            L1WatcherConfig {
+                confirmations: 10,
+                ..L1WatcherConfig::default()
+            },
+        )?;
+        let mut alerts = validator.subscribe_alerts();
+
+        assert_eq!(validator.poll().await?, 2);
+        assert_eq!(
+            alerts.try_recv()?,
+            ValidatorAlert::RootMismatch {
+                batch_index: 1,
+                claimed_root: second.header.state_root,
+                computed_root: None,
+            }
+        );
+        let metrics = validator.metrics();
+        assert_eq!(metrics.batches_verified.load(Ordering::Relaxed), 1);
+        assert_eq!(*validator.client.disputes.lock().unwrap(), vec![1]);
+        assert_eq!(state.read().await.get_current_root().height, 1);
+
+        Ok(())
+    }
+}
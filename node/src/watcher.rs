@@ -0,0 +1,478 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::rollup::Rollup;
+use crate::state::StateManager;
+use crate::types::{deposit_id, deposit_key, token_id, Transaction};
+
+/// Metadata key holding the highest L1 slot whose deposits are all credited
+const DEPOSIT_SLOT_KEY: &[u8] = b"l1:deposit_slot";
+
+/// Prefix of the log line carrying a serialized Anchor event
+pub(crate) const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// Logs of one successful L1 transaction that invoked the watched program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramLogs {
+    pub slot: u64,
+    pub signature: String,
+    pub logs: Vec<String>,
+}
+
+/// The subset of L1 interaction the deposit watcher needs.
+///
+/// Implemented over `solana-client` by [`crate::l1::SolanaL1Client`] and by
+/// in-memory mocks in tests.
+#[async_trait]
+pub trait L1EventSource: Send + Sync {
+    /// Most recent slot observed at `confirmed` commitment
+    async fn latest_slot(&self) -> Result<u64>;
+
+    /// Logs of successful transactions invoking `program_id` in slots
+    /// `from..=to`, in slot order
+    async fn program_logs(&self, program_id: &str, from: u64, to: u64) -> Result<Vec<ProgramLogs>>;
+}
+
+/// `DepositEvent` emitted by the `oasis_bridge` program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepositEvent {
+    pub depositor: [u8; 32],
+    /// L1 mint of the deposited token
+    pub mint: [u8; 32],
+    pub amount: u64,
+    pub l2_recipient: [u8; 32],
+    pub timestamp: i64,
+    /// Id the deposit is credited under, derived by the bridge from its
+    /// deposit count
+    pub deposit_id: [u8; 32],
+    /// Position of the deposit, naming its receipt
+    pub nonce: u64,
+}
+
+impl DepositEvent {
+    /// Borsh size of the event fields, excluding the discriminator
+    const LEN: usize = 32 + 32 + 8 + 32 + 8 + 32 + 8;
+
+    /// Decode an Anchor event payload: discriminator followed by the Borsh fields
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != 8 + Self::LEN {
+            return None;
+        }
+        let (discriminator, fields) = data.split_at(8);
+        if discriminator != event_discriminator("DepositEvent") {
+            return None;
+        }
+
+        Some(Self {
+            depositor: fields[0..32].try_into().ok()?,
+            mint: fields[32..64].try_into().ok()?,
+            amount: u64::from_le_bytes(fields[64..72].try_into().ok()?),
+            l2_recipient: fields[72..104].try_into().ok()?,
+            timestamp: i64::from_le_bytes(fields[104..112].try_into().ok()?),
+            deposit_id: fields[112..144].try_into().ok()?,
+            nonce: u64::from_le_bytes(fields[144..152].try_into().ok()?),
+        })
+    }
+}
+
+/// `DepositReceipt` account the `oasis_bridge` program keeps of each deposit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepositReceipt {
+    pub depositor: [u8; 32],
+    /// L1 mint of the deposited token
+    pub mint: [u8; 32],
+    pub amount: u64,
+    pub l2_recipient: [u8; 32],
+    pub nonce: u64,
+}
+
+impl DepositReceipt {
+    /// Borsh size of the account fields read, excluding the discriminator
+    const LEN: usize = 32 + 32 + 8 + 32 + 8;
+
+    /// Decode the account data, including the 8-byte discriminator
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let fields = data.get(8..8 + Self::LEN)?;
+        let mut hasher = Sha256::new();
+        hasher.update(b"account:DepositReceipt");
+        if data[..8] != hasher.finalize()[..8] {
+            return None;
+        }
+
+        Some(Self {
+            depositor: fields[0..32].try_into().ok()?,
+            mint: fields[32..64].try_into().ok()?,
+            amount: u64::from_le_bytes(fields[64..72].try_into().ok()?),
+            l2_recipient: fields[72..104].try_into().ok()?,
+            nonce: u64::from_le_bytes(fields[104..112].try_into().ok()?),
+        })
+    }
+
+    /// Whether `transaction` credits exactly the deposit this receipt records
+    pub fn backs(&self, transaction: &Transaction) -> bool {
+        matches!(
+            transaction,
+            Transaction::Deposit {
+                nonce,
+                recipient,
+                token,
+                amount,
+            } if *nonce == self.nonce
+                && *recipient == self.l2_recipient
+                && *token == token_id(&self.mint)
+                && *amount == self.amount
+        )
+    }
+}
+
+/// Read access to the receipts of the `oasis_bridge` program
+#[async_trait]
+pub trait DepositReceiptSource: Send + Sync {
+    /// Receipt of the `nonce`-th deposit, `None` if the bridge holds none
+    async fn deposit_receipt(&self, nonce: u64) -> Result<Option<DepositReceipt>>;
+}
+
+/// Anchor event discriminator: first 8 bytes of `sha256("event:<name>")`
+pub fn event_discriminator(name: &str) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("event:{}", name).as_bytes());
+    let hash = hasher.finalize();
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+/// Raw Anchor event payloads emitted directly by `program_id`, in emission
+/// order.
+///
+/// Event data is only attributed to the program at the top of the invoke stack,
+/// so a different program logging a look-alike payload is ignored.
+pub fn program_event_data(program_id: &str, logs: &[String]) -> Vec<Vec<u8>> {
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for line in logs {
+        if let Some(data) = line.strip_prefix(PROGRAM_DATA_PREFIX) {
+            if stack.last() != Some(&program_id) {
+                continue;
+            }
+            if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(data) {
+                events.push(bytes);
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => stack.push(program),
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    events
+}
+
+/// Deposit events emitted directly by `program_id`, in emission order
+pub fn parse_deposit_events(program_id: &str, logs: &[String]) -> Vec<DepositEvent> {
+    program_event_data(program_id, logs)
+        .iter()
+        .filter_map(|data| DepositEvent::decode(data))
+        .collect()
+}
+
+/// Polling behaviour shared by the L1 event watchers
+#[derive(Clone, Debug)]
+pub struct L1WatcherConfig {
+    /// Slots an event must be buried under before it is acted upon
+    pub confirmations: u64,
+    /// Delay between L1 polls
+    pub poll_interval: Duration,
+    /// Upper bound on the slot range fetched in one poll
+    pub max_slots_per_poll: u64,
+}
+
+impl Default for L1WatcherConfig {
+    fn default() -> Self {
+        Self {
+            confirmations: 32,
+            poll_interval: Duration::from_secs(2),
+            max_slots_per_poll: 1_000,
+        }
+    }
+}
+
+/// Polls the `oasis_bridge` program logs and queues a [`Transaction::Deposit`]
+/// for every `DepositEvent` once it is `confirmations` slots deep.
+///
+/// Each deposit is credited exactly once: the executor rejects a deposit id
+/// already marked in state, the watcher never queues an id twice, and the
+/// persisted slot cursor only moves past a deposit once its credit is part of
+/// a committed block. After a restart, confirmed but uncredited deposits are
+/// found again from the cursor and re-queued.
+pub struct DepositWatcher<S: L1EventSource> {
+    source: S,
+    bridge_program_id: String,
+    state: Arc<RwLock<StateManager>>,
+    rollup: Arc<Mutex<Rollup>>,
+    config: L1WatcherConfig,
+    /// Deposits queued in the mempool but not yet committed
+    queued: HashSet<[u8; 32]>,
+}
+
+impl<S: L1EventSource> DepositWatcher<S> {
+    pub fn new(
+        source: S,
+        bridge_program_id: String,
+        state: Arc<RwLock<StateManager>>,
+        rollup: Arc<Mutex<Rollup>>,
+        config: L1WatcherConfig,
+    ) -> Self {
+        Self {
+            source,
+            bridge_program_id,
+            state,
+            rollup,
+            config,
+            queued: HashSet::new(),
+        }
+    }
+
+    /// Poll for deposits until the task is dropped
+    pub async fn run(&mut self) {
+        loop {
+            if let Err(e) = self.poll().await {
+                log::error!("Deposit watcher poll failed: {}", e);
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Scan confirmed slots after the cursor, queue new deposits and advance the
+    /// cursor past every deposit already credited. Returns the number of
+    /// deposits queued.
+    pub async fn poll(&mut self) -> Result<usize> {
+        let latest = self.source.latest_slot().await?;
+        let Some(confirmed) = latest.checked_sub(self.config.confirmations) else {
+            return Ok(0);
+        };
+
+        let cursor = deposit_slot(&*self.state.read().await)?;
+        if confirmed <= cursor {
+            return Ok(0);
+        }
+        let from = cursor + 1;
+        let to = confirmed.min(cursor + self.config.max_slots_per_poll);
+
+        let transactions = self
+            .source
+            .program_logs(&self.bridge_program_id, from, to)
+            .await?;
+
+        let mut new_cursor = to;
+        let mut deposits = Vec::new();
+        {
+            let state = self.state.read().await;
+            for logs in &transactions {
+                let events = parse_deposit_events(&self.bridge_program_id, &logs.logs);
+                for event in events {
+                    let id = deposit_id(event.nonce);
+                    if state.get_value(&deposit_key(&id)).await?.is_some() {
+                        self.queued.remove(&id);
+                        continue;
+                    }
+
+                    // Not credited yet; the cursor must not move past it
+                    new_cursor = new_cursor.min(logs.slot - 1);
+                    if self.queued.insert(id) {
+                        deposits.push(Transaction::Deposit {
+                            nonce: event.nonce,
+                            recipient: event.l2_recipient,
+                            token: token_id(&event.mint),
+                            amount: event.amount,
+                        });
+                    }
+                }
+            }
+        }
+
+        let count = deposits.len();
+        if count > 0 {
+            let mut rollup = self.rollup.lock().await;
+            for deposit in deposits {
+                rollup.submit_transaction(deposit);
+            }
+            log::info!("Queued {} L1 deposits from slots {}..={}", count, from, to);
+        }
+
+        if new_cursor > cursor {
+            self.state
+                .write()
+                .await
+                .set_metadata(DEPOSIT_SLOT_KEY, bincode::serialize(&new_cursor)?)?;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Highest L1 slot whose deposits have all been credited on L2
+pub fn deposit_slot(state: &StateManager) -> Result<u64> {
+    match state.get_metadata(DEPOSIT_SLOT_KEY)? {
+        Some(data) => Ok(bincode::deserialize(&data)?),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{token_balance_key, Block};
+    use tempfile::tempdir;
+
+    const BRIDGE: &str = "Bridge1111111111111111111111111111111111111";
+    const MINT: [u8; 32] = [6u8; 32];
+
+    struct MockSource {
+        latest_slot: std::sync::Mutex<u64>,
+        transactions: Vec<ProgramLogs>,
+    }
+
+    #[async_trait]
+    impl L1EventSource for Arc<MockSource> {
+        async fn latest_slot(&self) -> Result<u64> {
+            Ok(*self.latest_slot.lock().unwrap())
+        }
+
+        async fn program_logs(
+            &self,
+            _program_id: &str,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<ProgramLogs>> {
+            Ok(self
+                .transactions
+                .iter()
+                .filter(|logs| logs.slot >= from && logs.slot <= to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn deposit_logs(program_id: &str, recipient: [u8; 32], amount: u64) -> Vec<String> {
+        let mut data = event_discriminator("DepositEvent").to_vec();
+        data.extend_from_slice(&[5u8; 32]);
+        data.extend_from_slice(&MINT);
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&recipient);
+        data.extend_from_slice(&0i64.to_le_bytes());
+        data.extend_from_slice(&deposit_id(0));
+        data.extend_from_slice(&0u64.to_le_bytes());
+
+        vec![
+            format!("Program {} invoke [1]", program_id),
+            "Program log: Instruction: Deposit".to_string(),
+            format!(
+                "{}{}",
+                PROGRAM_DATA_PREFIX,
+                base64::engine::general_purpose::STANDARD.encode(data)
+            ),
+            format!("Program {} success", program_id),
+        ]
+    }
+
+    #[test]
+    fn test_parse_only_bridge_events() {
+        let mut logs = deposit_logs(BRIDGE, [1u8; 32], 10);
+        logs.extend(deposit_logs(
+            "Other11111111111111111111111111111111111111",
+            [2u8; 32],
+            20,
+        ));
+
+        let events = parse_deposit_events(BRIDGE, &logs);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].l2_recipient, [1u8; 32]);
+        assert_eq!(events[0].amount, 10);
+    }
+
+    #[tokio::test]
+    async fn test_deposits_credited_exactly_once() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let state = Arc::new(RwLock::new(StateManager::new(&temp_dir)?));
+        let rollup = Arc::new(Mutex::new(Rollup::new(state.clone())?));
+        let source = Arc::new(MockSource {
+            latest_slot: std::sync::Mutex::new(12),
+            transactions: vec![ProgramLogs {
+                slot: 10,
+                signature: "sig1".to_string(),
+                logs: deposit_logs(BRIDGE, [1u8; 32], 500),
+            }],
+        });
+        let config = L1WatcherConfig {
+            confirmations: 5,
+            ..L1WatcherConfig::default()
+        };
+        let mut watcher = DepositWatcher::new(
+            source.clone(),
+            BRIDGE.to_string(),
+            state.clone(),
+            rollup.clone(),
+            config.clone(),
+        );
+
+        // Not deep enough yet
+        assert_eq!(watcher.poll().await?, 0);
+
+        *source.latest_slot.lock().unwrap() = 20;
+        assert_eq!(watcher.poll().await?, 1);
+        // Still uncommitted: neither queued again nor skipped by the cursor
+        assert_eq!(watcher.poll().await?, 0);
+        assert_eq!(deposit_slot(&*state.read().await)?, 9);
+
+        // A restarted watcher re-queues the uncredited deposit
+        let mut restarted = DepositWatcher::new(
+            source,
+            BRIDGE.to_string(),
+            state.clone(),
+            rollup.clone(),
+            config,
+        );
+        assert_eq!(restarted.poll().await?, 1);
+
+        let transactions = rollup.lock().await.take_pending(usize::MAX);
+        assert_eq!(transactions.len(), 2);
+        rollup
+            .lock()
+            .await
+            .process_block(Block::new(1, [0u8; 32], transactions[..1].to_vec(), 0))
+            .await?;
+
+        assert_eq!(restarted.poll().await?, 0);
+        assert_eq!(deposit_slot(&*state.read().await)?, 15);
+        let balance = state
+            .read()
+            .await
+            .get_value(&token_balance_key(&token_id(&MINT), &[1u8; 32]))
+            .await?;
+        assert_eq!(balance, Some(bincode::serialize(&500u64)?));
+
+        // Crediting the same deposit twice is rejected
+        assert!(rollup
+            .lock()
+            .await
+            .process_block(Block::new(2, [0u8; 32], transactions[1..].to_vec(), 0))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
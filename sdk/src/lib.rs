@@ -1,20 +1,98 @@
+use serde::{Deserialize, Serialize};
 use solana_client::rpc_client::RpcClient;
+use solana_sdk::{clock::Clock, hash::hashv, pubkey::Pubkey, sysvar};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum SdkError {
     #[error("Failed to process SDK request")]
     ProcessingError,
+    #[error("Invalid account data: {0}")]
+    InvalidAccountData(&'static str),
 }
 
 pub trait RpcClientTrait {
     fn get_version(&self) -> Result<String, Box<dyn std::error::Error>>;
+    fn get_account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+    fn account_exists(&self, pubkey: &Pubkey) -> Result<bool, Box<dyn std::error::Error>>;
 }
 
 impl RpcClientTrait for RpcClient {
     fn get_version(&self) -> Result<String, Box<dyn std::error::Error>> {
         Ok(self.get_version()?.to_string())
     }
+
+    fn get_account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(self.get_account_data(pubkey)?)
+    }
+
+    fn account_exists(&self, pubkey: &Pubkey) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self
+            .get_account_with_commitment(pubkey, self.commitment())?
+            .value
+            .is_some())
+    }
+}
+
+/// Settlement status of an L2 state root
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinalityStatus {
+    /// Not posted to L1 yet
+    Pending,
+    /// Posted and still inside the challenge period
+    Posted,
+    /// Posted and no longer challengeable; withdrawals may use it
+    Finalized,
+}
+
+/// Status of the root posted for one batch
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootFinality {
+    pub batch_index: u64,
+    pub status: FinalityStatus,
+    pub state_root: Option<[u8; 32]>,
+    /// Unix time the root becomes final, once posted
+    pub finalizes_at: Option<i64>,
+}
+
+/// Address of the `StateRootRecord` PDA holding the root of `batch_index`
+pub fn root_record_address(
+    program_id: &Pubkey,
+    state_account: &Pubkey,
+    batch_index: u64,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"root", state_account.as_ref(), &batch_index.to_le_bytes()],
+        program_id,
+    )
+    .0
+}
+
+/// Address of the nullifier PDA the bridge creates when it pays out the
+/// withdrawal `withdrawal_hash`
+pub fn withdrawal_nullifier_address(
+    bridge_program_id: &Pubkey,
+    withdrawal_hash: &[u8; 32],
+) -> Pubkey {
+    Pubkey::find_program_address(&[b"nullifier", withdrawal_hash.as_ref()], bridge_program_id).0
+}
+
+/// Address of the nullifier PDA the bridge creates when `owner` withdraws its
+/// balance of `mint` through the escape hatch
+pub fn escape_nullifier_address(
+    bridge_program_id: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Pubkey {
+    let escape_hash = hashv(&[b"oasis:escape", owner.as_ref(), mint.as_ref()]).to_bytes();
+    withdrawal_nullifier_address(bridge_program_id, &escape_hash)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, SdkError> {
+    data.get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(SdkError::InvalidAccountData("truncated account"))
 }
 
 pub struct SolanaOasisSdk {
@@ -38,6 +116,96 @@ impl SolanaOasisSdk {
             .get_version()
             .map_err(|_| SdkError::ProcessingError)
     }
+
+    fn account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>, SdkError> {
+        self.client
+            .get_account_data(pubkey)
+            .map_err(|_| SdkError::ProcessingError)
+    }
+
+    /// Whether the bridge already paid out the withdrawal `withdrawal_hash`
+    pub fn is_withdrawal_claimed(
+        &self,
+        bridge_program_id: &Pubkey,
+        withdrawal_hash: &[u8; 32],
+    ) -> Result<bool, SdkError> {
+        self.client
+            .account_exists(&withdrawal_nullifier_address(
+                bridge_program_id,
+                withdrawal_hash,
+            ))
+            .map_err(|_| SdkError::ProcessingError)
+    }
+
+    /// Whether `owner` already withdrew its balance of `mint` through the
+    /// bridge's escape hatch
+    pub fn is_escape_claimed(
+        &self,
+        bridge_program_id: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<bool, SdkError> {
+        self.client
+            .account_exists(&escape_nullifier_address(bridge_program_id, owner, mint))
+            .map_err(|_| SdkError::ProcessingError)
+    }
+
+    /// Whether the root of `batch_index` is pending, posted or finalized on L1
+    pub fn get_root_finality(
+        &self,
+        program_id: &Pubkey,
+        state_account: &Pubkey,
+        batch_index: u64,
+    ) -> Result<RootFinality, SdkError> {
+        // StateAccount: discriminator, authority, validators vec, state_root,
+        // last_update, challenge_period, min_stake, forced_inclusion_slots,
+        // forced_tx_count, batch_count
+        let state = self.account_data(state_account)?;
+        let validators = state
+            .get(40..44)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(SdkError::InvalidAccountData("truncated state account"))?
+            as usize;
+        let offset = 44 + 32 * validators + 32 + 8;
+        let challenge_period = read_u64(&state, offset)? as i64;
+        let batch_count = read_u64(&state, offset + 32)?;
+
+        if batch_index >= batch_count {
+            return Ok(RootFinality {
+                batch_index,
+                status: FinalityStatus::Pending,
+                state_root: None,
+                finalizes_at: None,
+            });
+        }
+
+        // StateRootRecord: discriminator, state, batch_index, state_root,
+        // validator, submitted_at, bump
+        let record =
+            self.account_data(&root_record_address(program_id, state_account, batch_index))?;
+        let state_root: [u8; 32] = record
+            .get(48..80)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(SdkError::InvalidAccountData("truncated root record"))?;
+        let submitted_at = read_u64(&record, 112)? as i64;
+        let finalizes_at = submitted_at.saturating_add(challenge_period);
+
+        let clock: Clock = bincode::deserialize(&self.account_data(&sysvar::clock::id())?)
+            .map_err(|_| SdkError::InvalidAccountData("clock sysvar"))?;
+        let status = if clock.unix_timestamp >= finalizes_at {
+            FinalityStatus::Finalized
+        } else {
+            FinalityStatus::Posted
+        };
+
+        Ok(RootFinality {
+            batch_index,
+            status,
+            state_root: Some(state_root),
+            finalizes_at: Some(finalizes_at),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -48,6 +216,8 @@ mod tests {
     mock! {
         RpcClient {
             fn get_version(&self) -> Result<String, Box<dyn std::error::Error>>;
+            fn get_account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+            fn account_exists(&self, pubkey: &Pubkey) -> Result<bool, Box<dyn std::error::Error>>;
         }
     }
 
@@ -55,6 +225,14 @@ mod tests {
         fn get_version(&self) -> Result<String, Box<dyn std::error::Error>> {
             self.get_version()
         }
+
+        fn get_account_data(&self, pubkey: &Pubkey) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            self.get_account_data(pubkey)
+        }
+
+        fn account_exists(&self, pubkey: &Pubkey) -> Result<bool, Box<dyn std::error::Error>> {
+            self.account_exists(pubkey)
+        }
     }
 
     #[test]
@@ -67,4 +245,83 @@ mod tests {
         let sdk = SolanaOasisSdk::with_client(Box::new(mock_client));
         assert!(sdk.get_version().is_ok());
     }
+
+    #[test]
+    fn test_root_finality() {
+        let program_id = Pubkey::new_unique();
+        let state_account = Pubkey::new_unique();
+        let record_address = root_record_address(&program_id, &state_account, 0);
+
+        // One validator, challenge period of 100 seconds, one posted batch
+        let mut state = vec![0u8; 8 + 32];
+        state.extend_from_slice(&1u32.to_le_bytes());
+        state.extend_from_slice(&[0u8; 32 + 32 + 8]);
+        state.extend_from_slice(&100i64.to_le_bytes());
+        state.extend_from_slice(&[0u8; 24]);
+        state.extend_from_slice(&1u64.to_le_bytes());
+
+        let mut record = vec![0u8; 8 + 32 + 8];
+        record.extend_from_slice(&[7u8; 32]);
+        record.extend_from_slice(&[0u8; 32]);
+        record.extend_from_slice(&1_000i64.to_le_bytes());
+        record.push(255);
+
+        let clock = |unix_timestamp| {
+            bincode::serialize(&Clock {
+                unix_timestamp,
+                ..Clock::default()
+            })
+            .unwrap()
+        };
+        let mut mock_client = MockRpcClient::new();
+        let mut now = vec![clock(1_099), clock(1_100)].into_iter();
+        mock_client
+            .expect_get_account_data()
+            .returning(move |pubkey| {
+                if *pubkey == state_account {
+                    Ok(state.clone())
+                } else if *pubkey == record_address {
+                    Ok(record.clone())
+                } else {
+                    Ok(now.next().unwrap())
+                }
+            });
+
+        let sdk = SolanaOasisSdk::with_client(Box::new(mock_client));
+        let posted = sdk
+            .get_root_finality(&program_id, &state_account, 0)
+            .unwrap();
+        assert_eq!(posted.status, FinalityStatus::Posted);
+        assert_eq!(posted.state_root, Some([7u8; 32]));
+        assert_eq!(posted.finalizes_at, Some(1_100));
+
+        let finalized = sdk
+            .get_root_finality(&program_id, &state_account, 0)
+            .unwrap();
+        assert_eq!(finalized.status, FinalityStatus::Finalized);
+
+        let pending = sdk
+            .get_root_finality(&program_id, &state_account, 1)
+            .unwrap();
+        assert_eq!(pending.status, FinalityStatus::Pending);
+    }
+
+    #[test]
+    fn test_withdrawal_claimed() {
+        let bridge_program_id = Pubkey::new_unique();
+        let claimed = withdrawal_nullifier_address(&bridge_program_id, &[1u8; 32]);
+
+        let mut mock_client = MockRpcClient::new();
+        mock_client
+            .expect_account_exists()
+            .returning(move |pubkey| Ok(*pubkey == claimed));
+
+        let sdk = SolanaOasisSdk::with_client(Box::new(mock_client));
+        assert!(sdk
+            .is_withdrawal_claimed(&bridge_program_id, &[1u8; 32])
+            .unwrap());
+        assert!(!sdk
+            .is_withdrawal_claimed(&bridge_program_id, &[2u8; 32])
+            .unwrap());
+    }
 }